@@ -0,0 +1,14 @@
+// Write the TCP state machine as a Graphviz file. Render it with
+// `cargo run --example state_graph -- tcp_states.dot && dot -Tsvg tcp_states.dot -o tcp_states.svg`
+
+use net::tcp::states;
+use std::{env, fs, process};
+
+fn main() {
+    let path = env::args().nth(1).unwrap_or_else(|| "tcp_states.dot".to_string());
+    if let Err(e) = fs::write(&path, states::dot_graph()) {
+        eprintln!("error writing {path}: {e}");
+        process::exit(1);
+    }
+    println!("wrote {} transitions to {path}", states::TRANSITIONS.len());
+}
@@ -0,0 +1,81 @@
+// Brings up a TUN interface and connects to a TCP server on the host through it, with the
+// handshake and data built by this crate. The kernel thinks 10.77.0.2 is a host on the tun0
+// link, so it answers us instead of resetting the connection.
+//
+// Needs CAP_NET_ADMIN: `sudo -E cargo run --features tun --example tun_connect [-- <ifname>]`
+
+use net::packet::TcpSegment;
+use net::socket::packet_io::PacketIo;
+use net::socket::tun::TunDevice;
+use net::tcp::byte_stream::ByteStream;
+use net::tcp::flow_key::FlowKey;
+use net::tcp::sender::TcpSender;
+use net::tcp::tcp_flags::TcpFlags;
+use net::tcp::tcp_header::TcpHeader;
+use net::tcp::wrap32::Wrap32;
+use std::error::Error;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddrV4, TcpListener};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const HOST_IP: Ipv4Addr = Ipv4Addr::new(10, 77, 0, 1); // The kernel's end of the link
+const LOCAL: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 77, 0, 2), 40000); // Ours
+
+/// The next TCP segment from `server`, skipping anything else the kernel sends down the link
+fn recv_from(tun: &mut TunDevice, server: SocketAddrV4) -> io::Result<TcpSegment> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut buf = [0u8; 2048];
+    while Instant::now() < deadline {
+        let Ok(len) = tun.recv(&mut buf, Duration::from_millis(100)) else { continue };
+        if let Ok(segment) = TcpSegment::parse(&buf[..len]) {
+            if segment.src() == server {
+                return Ok(segment);
+            }
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::TimedOut, format!("nothing from {server}")))
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let ifname = std::env::args().nth(1).unwrap_or_else(|| "tun0".to_string());
+    let mut tun = TunDevice::open(&ifname)?;
+    tun.configure(HOST_IP, 24)?;
+    println!("{} is up as {HOST_IP}/24, we are {}", tun.name(), LOCAL.ip());
+
+    // The test server: echoes one line back
+    let listener = TcpListener::bind((HOST_IP, 0))?;
+    let server = SocketAddrV4::new(HOST_IP, listener.local_addr()?.port());
+    thread::spawn(move || -> io::Result<()> {
+        let (mut stream, peer) = listener.accept()?;
+        let mut line = [0u8; 64];
+        let len = stream.read(&mut line)?;
+        println!("server: {peer} sent {:?}", String::from_utf8_lossy(&line[..len]));
+        stream.write_all(&line[..len])
+    });
+
+    let isn = Wrap32::new(rand::random());
+    let mut sender = TcpSender::new(isn, ByteStream::new(4096));
+    sender.set_flow(FlowKey::new(LOCAL, server));
+    let header = |seq_no, ack_no, flags| TcpHeader { seq_no, ack_no, flags, ..TcpHeader::new(LOCAL.port(), server.port()) };
+
+    tun.send(&sender.build_packet(&header(isn, Wrap32::new(0), TcpFlags::SYN))?)?;
+    let syn_ack = recv_from(&mut tun, server)?;
+    println!("client: {}", net::packet::summary(&syn_ack.iph, &syn_ack.tcph));
+
+    let server_next = syn_ack.seq_no() + Wrap32::new(1);
+    let message = b"hello over tun\n";
+    let data = TcpHeader { payload: message[..].into(), ..header(isn + Wrap32::new(1), server_next, TcpFlags::ACK | TcpFlags::PSH) };
+    tun.send(&sender.build_packet(&data)?)?;
+    let echo = loop {
+        let segment = recv_from(&mut tun, server)?;
+        if !segment.payload().is_empty() {
+            break segment;
+        }
+    };
+    println!("client: echoed {:?}", String::from_utf8_lossy(echo.payload()));
+
+    let end = isn + Wrap32::new(1 + message.len() as u32);
+    tun.send(&sender.build_packet(&header(end, server_next + Wrap32::new(echo.payload().len() as u32), TcpFlags::RST))?)?;
+    Ok(())
+}
@@ -0,0 +1,290 @@
+// Compare two JSON benchmark reports and fail on regressions:
+//
+//     cargo run --release --features bench --bin byte_stream_speed_test -- --json > baseline.json
+//     (make changes)
+//     cargo run --release --features bench --bin byte_stream_speed_test -- --json > candidate.json
+//     cargo run --features bench --bin bench_compare -- baseline.json candidate.json [--threshold 5]
+//
+// A report looks like
+//     {"workloads": [{"name": "byte_stream", "params": {"capacity": 32768}, "metrics": {"throughput_gbps": 14.5}}]}
+// Workloads are matched by name and params. Exits 1 if any metric regressed beyond the threshold.
+
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+
+const DEFAULT_THRESHOLD: f64 = 5.0; // Percent
+
+/// One benchmark run: what was measured, with which parameters, and the results
+#[derive(Debug, Clone, PartialEq)]
+pub struct Workload {
+    pub name: String,
+    pub params: BTreeMap<String, String>,
+    pub metrics: BTreeMap<String, f64>,
+}
+
+impl Workload {
+    /// The key workloads are matched by: `name(k=v, ...)` with params sorted by key
+    pub fn signature(&self) -> String {
+        let params: Vec<String> = self.params.iter().map(|(k, v)| format!("{k}={v}")).collect();
+        format!("{}({})", self.name, params.join(", "))
+    }
+}
+
+/// Parse a JSON report into its workloads
+pub fn parse_report(json: &str) -> Result<Vec<Workload>, String> {
+    let report: Value = serde_json::from_str(json).map_err(|e| format!("invalid JSON: {e}"))?;
+    let workloads = report["workloads"].as_array().ok_or("missing \"workloads\" array")?;
+
+    workloads
+        .iter()
+        .map(|workload| {
+            let name = workload["name"].as_str().ok_or("workload without a \"name\"")?;
+            let params = match workload["params"].as_object() {
+                Some(params) => params
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.as_str().map_or_else(|| v.to_string(), str::to_string)))
+                    .collect(),
+                None => BTreeMap::new(),
+            };
+            let metrics = workload["metrics"]
+                .as_object()
+                .ok_or_else(|| format!("{name}: missing \"metrics\""))?
+                .iter()
+                .map(|(k, v)| Ok((k.clone(), v.as_f64().ok_or_else(|| format!("{name}: {k} is not a number"))?)))
+                .collect::<Result<_, String>>()?;
+            Ok(Workload { name: name.to_string(), params, metrics })
+        })
+        .collect()
+}
+
+/// Allocation counts and latencies get worse as they grow. Everything else is a rate
+pub fn higher_is_better(metric: &str) -> bool {
+    !(metric.contains("alloc") || metric.contains("latency") || metric.ends_with("_ns"))
+}
+
+/// The change from `baseline` to `candidate` in percent. Positive means the value grew
+pub fn delta_percent(baseline: f64, candidate: f64) -> f64 {
+    if baseline == 0.0 {
+        return if candidate == 0.0 { 0.0 } else { f64::INFINITY.copysign(candidate) };
+    }
+    (candidate - baseline) / baseline.abs() * 100.0
+}
+
+/// Did a metric get worse by more than `threshold` percent?
+pub fn is_regression(metric: &str, delta: f64, threshold: f64) -> bool {
+    if higher_is_better(metric) {
+        delta < -threshold
+    } else {
+        delta > threshold
+    }
+}
+
+/// One metric of one workload present in both reports
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub workload: String,
+    pub metric: String,
+    pub baseline: f64,
+    pub candidate: f64,
+    pub delta: f64,
+    pub regressed: bool,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Comparison {
+    pub rows: Vec<Row>,
+    pub missing: Vec<String>, // Workloads only in the baseline
+    pub extra: Vec<String>,   // Workloads only in the candidate
+}
+
+impl Comparison {
+    pub fn regressions(&self) -> usize {
+        self.rows.iter().filter(|row| row.regressed).count()
+    }
+}
+
+/// Match workloads by signature and compare every metric they share
+pub fn compare(baseline: &[Workload], candidate: &[Workload], threshold: f64) -> Comparison {
+    let by_signature = |workloads: &[Workload]| -> BTreeMap<String, Workload> {
+        workloads.iter().map(|w| (w.signature(), w.clone())).collect()
+    };
+    let (baseline, candidate) = (by_signature(baseline), by_signature(candidate));
+
+    let mut comparison = Comparison::default();
+    for (signature, base) in &baseline {
+        let Some(cand) = candidate.get(signature) else {
+            comparison.missing.push(signature.clone());
+            continue;
+        };
+        for (metric, &base_value) in &base.metrics {
+            let Some(&cand_value) = cand.metrics.get(metric) else {
+                continue;
+            };
+            let delta = delta_percent(base_value, cand_value);
+            comparison.rows.push(Row {
+                workload: signature.clone(),
+                metric: metric.clone(),
+                baseline: base_value,
+                candidate: cand_value,
+                delta,
+                regressed: is_regression(metric, delta, threshold),
+            });
+        }
+    }
+    comparison.extra = candidate.keys().filter(|s| !baseline.contains_key(*s)).cloned().collect();
+    comparison
+}
+
+/// Render the comparison as a table followed by unmatched workloads
+pub fn render(comparison: &Comparison) -> String {
+    let width = comparison.rows.iter().map(|row| row.workload.len()).max().unwrap_or(8).max(8);
+    let mut out = format!(
+        "{:<width$}  {:<18}  {:>12}  {:>12}  {:>9}\n",
+        "workload", "metric", "baseline", "candidate", "delta"
+    );
+    for row in &comparison.rows {
+        let flag = if row.regressed { "  REGRESSED" } else { "" };
+        out.push_str(&format!(
+            "{:<width$}  {:<18}  {:>12.3}  {:>12.3}  {:>+8.2}%{flag}\n",
+            row.workload, row.metric, row.baseline, row.candidate, row.delta
+        ));
+    }
+    for signature in &comparison.missing {
+        out.push_str(&format!("missing from candidate: {signature}\n"));
+    }
+    for signature in &comparison.extra {
+        out.push_str(&format!("new in candidate: {signature}\n"));
+    }
+    out
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let threshold = match args.iter().position(|a| a == "--threshold") {
+        Some(i) => args.get(i + 1).and_then(|t| t.parse::<f64>().ok()),
+        None => Some(DEFAULT_THRESHOLD),
+    };
+    let files: Vec<&String> = args
+        .iter()
+        .enumerate()
+        .filter(|&(i, a)| !a.starts_with("--") && (i == 0 || args[i - 1] != "--threshold"))
+        .map(|(_, a)| a)
+        .collect();
+
+    let (Some(threshold), [baseline, candidate]) = (threshold, files.as_slice()) else {
+        eprintln!("Usage: bench_compare <baseline.json> <candidate.json> [--threshold <percent>]");
+        std::process::exit(2);
+    };
+
+    let load = |path: &str| fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|json| parse_report(&json));
+    let (baseline, candidate) = match (load(baseline), load(candidate)) {
+        (Ok(baseline), Ok(candidate)) => (baseline, candidate),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Failed to read report: {e}");
+            std::process::exit(2);
+        }
+    };
+
+    let comparison = compare(&baseline, &candidate, threshold);
+    print!("{}", render(&comparison));
+
+    let regressions = comparison.regressions();
+    if regressions > 0 {
+        eprintln!("{regressions} metric(s) regressed by more than {threshold}%");
+        std::process::exit(1);
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(throughput: f64, allocations: f64) -> String {
+        format!(
+            r#"{{"workloads": [
+                {{"name": "byte_stream", "params": {{"capacity": 32768, "read_size": 128}},
+                  "metrics": {{"throughput_gbps": {throughput}, "allocations": {allocations}}}}},
+                {{"name": "reassembler", "params": {{"backend": "ring"}}, "metrics": {{"goodput_gbps": 10.0}}}}
+            ]}}"#
+        )
+    }
+
+    #[test]
+    fn test_parse_report() {
+        let workloads = parse_report(&report(14.5, 0.0)).unwrap();
+        assert_eq!(workloads.len(), 2);
+        assert_eq!(workloads[0].signature(), "byte_stream(capacity=32768, read_size=128)");
+        assert_eq!(workloads[1].signature(), "reassembler(backend=ring)");
+        assert_eq!(workloads[0].metrics["throughput_gbps"], 14.5);
+    }
+
+    #[test]
+    fn test_parse_report_errors() {
+        assert!(parse_report("not json").unwrap_err().starts_with("invalid JSON"));
+        assert!(parse_report("{}").unwrap_err().contains("workloads"));
+        let bad_metric = r#"{"workloads": [{"name": "x", "metrics": {"gbps": "fast"}}]}"#;
+        assert_eq!(parse_report(bad_metric).unwrap_err(), "x: gbps is not a number");
+    }
+
+    #[test]
+    fn test_signature_ignores_param_order() {
+        let a = r#"{"workloads": [{"name": "w", "params": {"a": 1, "b": 2}, "metrics": {}}]}"#;
+        let b = r#"{"workloads": [{"name": "w", "params": {"b": 2, "a": 1}, "metrics": {}}]}"#;
+        assert_eq!(parse_report(a).unwrap()[0].signature(), parse_report(b).unwrap()[0].signature());
+    }
+
+    #[test]
+    fn test_threshold_math() {
+        assert_eq!(delta_percent(10.0, 9.0), -10.0);
+        assert_eq!(delta_percent(0.0, 0.0), 0.0);
+        assert_eq!(delta_percent(0.0, 3.0), f64::INFINITY);
+
+        // Throughput must not drop, allocations must not grow
+        assert!(is_regression("throughput_gbps", -5.1, 5.0));
+        assert!(!is_regression("throughput_gbps", -5.0, 5.0));
+        assert!(!is_regression("throughput_gbps", 50.0, 5.0));
+        assert!(is_regression("allocations", 5.1, 5.0));
+        assert!(!is_regression("allocations", -50.0, 5.0));
+    }
+
+    #[test]
+    fn test_compare_flags_regressions() {
+        let baseline = parse_report(&report(14.5, 0.0)).unwrap();
+        let candidate = parse_report(&report(13.0, 2.0)).unwrap();
+        let comparison = compare(&baseline, &candidate, DEFAULT_THRESHOLD);
+
+        assert_eq!(comparison.rows.len(), 3);
+        assert_eq!(comparison.regressions(), 2);
+        let rendered = render(&comparison);
+        assert_eq!(rendered.matches("REGRESSED").count(), 2);
+        assert!(rendered.contains("-10.34%"));
+
+        // The same drop passes with a looser threshold, but allocations from zero never do
+        assert_eq!(compare(&baseline, &candidate, 20.0).regressions(), 1);
+    }
+
+    #[test]
+    fn test_compare_missing_and_extra_workloads() {
+        let baseline = parse_report(&report(14.5, 0.0)).unwrap();
+        let candidate = parse_report(
+            r#"{"workloads": [
+                {"name": "byte_stream", "params": {"capacity": 32768, "read_size": 128}, "metrics": {"throughput_gbps": 14.6}},
+                {"name": "reassembler", "params": {"backend": "tree"}, "metrics": {"goodput_gbps": 9.0}}
+            ]}"#,
+        )
+        .unwrap();
+        let comparison = compare(&baseline, &candidate, DEFAULT_THRESHOLD);
+
+        assert_eq!(comparison.missing, vec!["reassembler(backend=ring)"]);
+        assert_eq!(comparison.extra, vec!["reassembler(backend=tree)"]);
+        assert_eq!(comparison.rows.len(), 1); // Only the metric both sides report
+        assert_eq!(comparison.regressions(), 0);
+
+        let rendered = render(&comparison);
+        assert!(rendered.contains("missing from candidate: reassembler(backend=ring)"));
+        assert!(rendered.contains("new in candidate: reassembler(backend=tree)"));
+    }
+}
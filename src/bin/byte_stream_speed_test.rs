@@ -3,7 +3,7 @@ use rand::prelude::StdRng;
 use rand::{RngCore, SeedableRng};
 use std::collections::VecDeque;
 use std::io;
-use std::io::{Error, ErrorKind, Read, Write};
+use std::io::{Error, Read, Write};
 use std::time::Instant;
 
 fn speed_test(
@@ -12,7 +12,7 @@ fn speed_test(
     random_seed: usize,
     write_size: usize,
     read_size: usize,
-) -> io::Result<()> {
+) -> io::Result<f64> {
     // Generate random data
     let mut rng = StdRng::seed_from_u64(random_seed as u64);
     let mut data = vec![0u8; input_len];
@@ -31,6 +31,7 @@ fn speed_test(
     // Set up ByteStream and output buffer
     let mut stream = ByteStream::new(capacity);
     let mut output_buffer = Vec::with_capacity(input_len);
+    let mut read_buf = vec![0u8; read_size];
 
     // Start timer
     let t0 = Instant::now();
@@ -48,7 +49,8 @@ fn speed_test(
             }
         }
 
-        stream.read_to_end(&mut output_buffer)?;
+        let n = stream.read(&mut read_buf)?;
+        output_buffer.extend_from_slice(&read_buf[..n]);
     }
 
     // Stop timer
@@ -56,10 +58,7 @@ fn speed_test(
 
     // Validate data
     if data != output_buffer {
-        return Err(Error::new(
-            ErrorKind::Other,
-            "Data written does not equal data read :(",
-        ));
+        return Err(Error::other("Data written does not equal data read :("));
     }
 
     // Calculate throughput
@@ -68,14 +67,66 @@ fn speed_test(
     let bits_per_sec = bytes_per_sec * 8.0;
     let gigabits_per_sec = bits_per_sec / 1e9;
 
-    println!(
-        "ByteStream with capacity={capacity}, write_size={write_size}, \
-        read_size={read_size} reached {gigabits_per_sec:.2 } Gbit/s",
-    );
+    Ok(gigabits_per_sec)
+}
+
+fn peek_speed_test(capacity: usize, peek_size: usize, iterations: usize) -> io::Result<()> {
+    // Fill the stream so that its contents wrap around the end of the ring
+    let mut stream = ByteStream::new(capacity);
+    let data: Vec<u8> = (0..capacity).map(|i| (i % 251) as u8).collect();
+    stream.write_all(&data)?;
+    stream.pop_output(capacity / 2);
+    stream.write_all(&data[..capacity / 2])?;
+
+    // Peek with a fresh Vec each time
+    let t0 = Instant::now();
+    let mut checksum = 0u64;
+    for _ in 0..iterations {
+        let peeked = stream.peek_output(peek_size);
+        checksum += peeked[peek_size - 1] as u64;
+    }
+    let alloc_duration = t0.elapsed();
+
+    // Peek into a reused buffer
+    let mut buf = vec![0u8; peek_size];
+    let t0 = Instant::now();
+    let mut checksum_into = 0u64;
+    for _ in 0..iterations {
+        stream.peek_into(&mut buf);
+        checksum_into += buf[peek_size - 1] as u64;
+    }
+    let into_duration = t0.elapsed();
+
+    if checksum != checksum_into {
+        return Err(Error::other("peek_output and peek_into disagree :("));
+    }
+
+    let bytes = (peek_size * iterations) as f64;
+    for (path, duration) in [("peek_output", alloc_duration), ("peek_into", into_duration)] {
+        let gigabits_per_sec = bytes * 8.0 / duration.as_secs_f64() / 1e9;
+        println!("ByteStream {path} with capacity={capacity}, peek_size={peek_size} reached {gigabits_per_sec:.2} Gbit/s");
+    }
 
     Ok(())
 }
 
+/// Print the report for `bench_compare`
+#[cfg(feature = "bench")]
+fn print_json_report(capacity: usize, write_size: usize, read_size: usize, gigabits_per_sec: f64) {
+    let report = serde_json::json!({"workloads": [{
+        "name": "byte_stream",
+        "params": {"capacity": capacity, "write_size": write_size, "read_size": read_size},
+        "metrics": {"throughput_gbps": gigabits_per_sec},
+    }]});
+    println!("{report}");
+}
+
+#[cfg(not(feature = "bench"))]
+fn print_json_report(_: usize, _: usize, _: usize, _: f64) {
+    eprintln!("--json needs the `bench` feature");
+    std::process::exit(1);
+}
+
 fn main() {
     let input_len = 1e7 as usize; // 10 MB
     let capacity = 32768; // 32 KB
@@ -83,11 +134,32 @@ fn main() {
     let write_size = 1500; // MTU 1500 bytes
     let read_size = 128;
 
-    if let Err(e) = speed_test(input_len, capacity, random_seed, write_size, read_size) {
-        eprintln!("Speed test failed: {e}");
-        std::process::exit(1);
+    // `--json` prints a report for `bench_compare` instead of the human-readable results
+    let json = std::env::args().skip(1).any(|a| a == "--json");
+
+    let gigabits_per_sec = match speed_test(input_len, capacity, random_seed, write_size, read_size) {
+        Ok(gigabits_per_sec) => gigabits_per_sec,
+        Err(e) => {
+            eprintln!("Speed test failed: {e}");
+            std::process::exit(1);
+        }
     };
 
+    if json {
+        print_json_report(capacity, write_size, read_size, gigabits_per_sec);
+        return;
+    }
+
+    println!(
+        "ByteStream with capacity={capacity}, write_size={write_size}, \
+        read_size={read_size} reached {gigabits_per_sec:.2} Gbit/s",
+    );
+
+    if let Err(e) = peek_speed_test(capacity, write_size, 100_000) {
+        eprintln!("Peek speed test failed: {e}");
+        std::process::exit(1);
+    }
+
     // Result:
-    // ByteStream with capacity=32768, write_size=1500, read_size=128 reached 15.40 Gbit/s
+    // ByteStream with capacity=32768, write_size=1500, read_size=128 reached 14.49 Gbit/s
 }
@@ -0,0 +1,64 @@
+use net::packet::checksum;
+use rand::RngCore;
+use std::hint::black_box;
+use std::time::Instant;
+
+/// The 16-bits-at-a-time loop the headers used before `packet::checksum`
+fn checksum_16bit(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|chunk| {
+            if chunk.len() == 2 {
+                u16::from_be_bytes([chunk[0], chunk[1]]) as u32
+            } else {
+                (chunk[0] as u32) << 8
+            }
+        })
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Checksum `buf` `iterations` times. Returns Gbit/s
+fn speed_test(path: &str, buf: &[u8], iterations: usize) -> f64 {
+    let t0 = Instant::now();
+    for _ in 0..iterations {
+        match path {
+            "16bit" => black_box(checksum_16bit(black_box(buf))),
+            _ => black_box(checksum::checksum(black_box(buf))),
+        };
+    }
+    let duration = t0.elapsed();
+    (buf.len() * iterations * 8) as f64 / duration.as_secs_f64() / 1e9
+}
+
+fn main() {
+    // `--json` prints a report for `bench_compare` instead of the human-readable results
+    let json = std::env::args().skip(1).any(|a| a == "--json");
+
+    let mut workloads = Vec::new();
+    for len in [20, 40, 1500, 9000] {
+        let mut buf = vec![0u8; len];
+        rand::thread_rng().fill_bytes(&mut buf);
+        let iterations = (1 << 30) / len / 8;
+
+        for path in ["16bit", "wide"] {
+            let gbits_per_sec = speed_test(path, &buf, iterations);
+            if json {
+                workloads.push(serde_json::json!({
+                    "name": "checksum",
+                    "params": {"path": path, "len": len},
+                    "metrics": {"gbits_per_sec": gbits_per_sec},
+                }));
+            } else {
+                println!("Checksum ({path}) of {len} bytes reached {gbits_per_sec:.2} Gbit/s");
+            }
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::json!({ "workloads": workloads }));
+    }
+}
@@ -0,0 +1,215 @@
+use net::ip::ip_header::IpHeader;
+use net::packet;
+use net::packet::errors::HeaderError;
+use net::tcp::byte_stream::ByteStream;
+use net::tcp::flow_key::FlowKey;
+use net::tcp::negotiation::{self, HandshakeOffer, Negotiated};
+use net::tcp::sender::TcpSender;
+use net::tcp::tcp_flags::TcpFlags;
+use net::tcp::tcp_header::TcpHeader;
+use net::tcp::wrap32::Wrap32;
+use bytes::Bytes;
+use std::io;
+use std::io::Error;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::Instant;
+
+const CLIENT_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+const SERVER_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+const SERVER_PORT: u16 = 80;
+const SYN_OPTIONS: [u8; 4] = [0x02, 0x04, 0x05, 0xb4]; // MSS 1460
+const SAMPLES: usize = 1024; // Final ACKs kept per path, spread across the whole run
+
+/// Build the IP header for a packet from `src` to `dst` carrying a TCP segment of `tcp_len` bytes
+fn ip_header(src: Ipv4Addr, dst: Ipv4Addr, tcp_len: usize) -> IpHeader {
+    IpHeader {
+        version: 4,
+        ihl: 5,
+        total_len: (20 + tcp_len) as u16,
+        ttl: 64,
+        protocol: 6,
+        src_ip: src,
+        dst_ip: dst,
+        ..IpHeader::default()
+    }
+}
+
+/// Build a header-only TCP segment
+fn tcp_header(src_port: u16, dst_port: u16, seq: u32, ack: u32, flags: TcpFlags, options: &[u8]) -> TcpHeader {
+    TcpHeader {
+        src_port,
+        dst_port,
+        seq_no: Wrap32::new(seq),
+        ack_no: Wrap32::new(ack),
+        data_offset: 5 + (options.len() / 4) as u8,
+        flags,
+        window: 65535,
+        options: options.to_vec().into(),
+        ..TcpHeader::default()
+    }
+}
+
+/// One handshake where every packet gets freshly allocated headers and buffers
+fn handshake_alloc(client_port: u16, keep: bool, packets: &mut Vec<Vec<u8>>) -> Result<(), HeaderError> {
+    let client_isn = (client_port as u32).wrapping_mul(7919);
+    let server_isn = (client_port as u32).wrapping_mul(104729);
+
+    // Client -> SYN
+    let syn = tcp_header(client_port, SERVER_PORT, client_isn, 0, TcpFlags::SYN, &SYN_OPTIONS);
+    let syn = packet::wrap(&ip_header(CLIENT_IP, SERVER_IP, 24), &syn)?;
+    let (_, syn) = packet::unwrap(&syn)?;
+
+    // Server -> SYN-ACK
+    let flags = TcpFlags::SYN | TcpFlags::ACK;
+    let syn_ack = tcp_header(SERVER_PORT, syn.src_port, server_isn, (syn.seq_no + Wrap32::new(syn.seq_len() as u32)).value(), flags, &SYN_OPTIONS);
+    let syn_ack = packet::wrap(&ip_header(SERVER_IP, CLIENT_IP, 24), &syn_ack)?;
+    let (_, syn_ack) = packet::unwrap(&syn_ack)?;
+
+    // Client -> ACK
+    let ack = tcp_header(client_port, SERVER_PORT, client_isn.wrapping_add(1), (syn_ack.seq_no + Wrap32::new(syn_ack.seq_len() as u32)).value(), TcpFlags::ACK, &[]);
+    let ack = packet::wrap(&ip_header(CLIENT_IP, SERVER_IP, 20), &ack)?;
+    packet::unwrap(&ack)?;
+
+    if keep {
+        packets.push(ack);
+    }
+    Ok(())
+}
+
+/// Each end's `TcpSender`, whose header template and packet buffer are reused across handshakes.
+/// Only the per-connection fields change.
+struct Templates {
+    client: TcpSender,
+    server: TcpSender,
+    rx: (IpHeader, TcpHeader),
+}
+
+impl Templates {
+    fn new() -> Self {
+        Templates {
+            client: TcpSender::new(Wrap32::new(0), ByteStream::new(0)),
+            server: TcpSender::new(Wrap32::new(0), ByteStream::new(0)),
+            rx: (IpHeader::default(), TcpHeader::default()),
+        }
+    }
+
+    /// One handshake using the templates. Zero allocation apart from copying the rx options.
+    fn handshake(&mut self, client_port: u16, keep: bool, packets: &mut Vec<Vec<u8>>) -> Result<(), HeaderError> {
+        let client_isn = (client_port as u32).wrapping_mul(7919);
+        let server_isn = (client_port as u32).wrapping_mul(104729);
+        let (rx_ip, rx_tcp) = &mut self.rx;
+        let flow = FlowKey::new(SocketAddrV4::new(CLIENT_IP, client_port), SocketAddrV4::new(SERVER_IP, SERVER_PORT));
+        self.client.set_flow(flow);
+        self.server.set_flow(flow.reversed());
+        let options = Bytes::from_static(&SYN_OPTIONS);
+
+        // Client -> SYN
+        let syn = self.client.build_control_reused(Wrap32::new(client_isn), Wrap32::new(0), TcpFlags::SYN, 65535, options.clone())?;
+        packet::unwrap_from(syn, rx_ip, rx_tcp)?;
+
+        // Server -> SYN-ACK
+        let flags = TcpFlags::SYN | TcpFlags::ACK;
+        let ack_no = rx_tcp.seq_no + Wrap32::new(rx_tcp.seq_len() as u32);
+        let syn_ack = self.server.build_control_reused(Wrap32::new(server_isn), ack_no, flags, 65535, options)?;
+        packet::unwrap_from(syn_ack, rx_ip, rx_tcp)?;
+
+        // Client -> ACK
+        let ack_no = rx_tcp.seq_no + Wrap32::new(rx_tcp.seq_len() as u32);
+        let ack = self.client.build_control_reused(Wrap32::new(client_isn.wrapping_add(1)), ack_no, TcpFlags::ACK, 65535, Bytes::new())?;
+        packet::unwrap_from(ack, rx_ip, rx_tcp)?;
+
+        if keep {
+            packets.push(ack.to_vec());
+        }
+        Ok(())
+    }
+}
+
+/// Handshakes per second, and a sample of the final ACKs for comparing the two paths
+fn speed_test(num_conns: usize, templates: bool) -> io::Result<(f64, Vec<Vec<u8>>)> {
+    let stride = num_conns.div_ceil(SAMPLES).max(1);
+    let mut packets = Vec::with_capacity(SAMPLES);
+    let mut reused = Templates::new();
+
+    let t0 = Instant::now();
+    for i in 0..num_conns {
+        let client_port = 1024 + (i % 60000) as u16;
+        let keep = i % stride == 0;
+        let result = if templates {
+            reused.handshake(client_port, keep, &mut packets)
+        } else {
+            handshake_alloc(client_port, keep, &mut packets)
+        };
+        result.map_err(|e| Error::other(e.to_string()))?;
+    }
+    let duration = t0.elapsed();
+
+    Ok((num_conns as f64 / duration.as_secs_f64(), packets))
+}
+
+/// `packet` with its IP id and IP header checksum zeroed. The senders number their packets, so
+/// these are the only bytes allowed to differ between the two paths
+fn masked(packet: &[u8]) -> Vec<u8> {
+    let mut packet = packet.to_vec();
+    packet[4..6].fill(0);
+    packet[10..12].fill(0);
+    packet
+}
+
+fn negotiated_json(negotiated: &Negotiated) -> serde_json::Value {
+    serde_json::json!({
+        "mss": negotiated.mss,
+        "snd_wscale": negotiated.snd_wscale,
+        "rcv_wscale": negotiated.rcv_wscale,
+        "sack": negotiated.sack,
+        "timestamps": negotiated.timestamps,
+    })
+}
+
+fn main() {
+    // `--json` prints a report for `bench_compare` instead of the human-readable results
+    let json = std::env::args().skip(1).any(|a| a == "--json");
+    let num_conns = 1_000_000;
+
+    // Both ends are our own stack, so they must agree on every negotiated parameter
+    let offer = HandshakeOffer::default();
+    let negotiated = negotiation::check_handshake(&offer, &offer).map_err(|e| Error::other(e.to_string()));
+
+    // Run both paths and check that they put identical final ACKs on the wire, apart from the IP id
+    let result = negotiated.and_then(|negotiated| {
+        let (alloc_rate, alloc) = speed_test(num_conns, false)?;
+        let (templates_rate, templates) = speed_test(num_conns, true)?;
+        if templates.len() != alloc.len() || templates.iter().zip(&alloc).any(|(a, b)| masked(a) != masked(b)) {
+            return Err(Error::other("Template path produced different packets :("));
+        }
+        Ok((negotiated, [("alloc", alloc_rate), ("templates", templates_rate)]))
+    });
+
+    let ((client, server), rates) = match result {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Speed test failed: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    if json {
+        let workloads: Vec<_> = rates
+            .iter()
+            .map(|(path, rate)| {
+                serde_json::json!({
+                    "name": "conn_setup",
+                    "params": {"path": path, "conns": num_conns},
+                    "metrics": {"handshakes_per_sec": rate},
+                })
+            })
+            .collect();
+        let negotiated = serde_json::json!({ "client": negotiated_json(&client), "server": negotiated_json(&server) });
+        println!("{}", serde_json::json!({ "workloads": workloads, "negotiated": negotiated }));
+    } else {
+        for (path, rate) in rates {
+            println!("Handshakes ({path}) n={num_conns} reached {rate:.0} handshakes/s");
+        }
+        println!("Negotiated (both ends): {client:?}");
+    }
+}
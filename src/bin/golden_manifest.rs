@@ -0,0 +1,108 @@
+use net::ip::ip_header::IpHeader;
+use net::ip::ip_flags::IpFlags;
+use net::packet;
+use net::packet::errors::HeaderError;
+use std::fs;
+use std::io;
+use std::io::Error;
+use std::path::Path;
+
+/// Is the packet an IP fragment? Fragments only carry part of a TCP segment
+pub fn is_fragment(iph: &IpHeader) -> bool {
+    iph.flags.contains(IpFlags::MF) || iph.frag_offset != 0
+}
+
+/// The manifest fields of a packet, in manifest order. Fragments only have IP fields.
+pub fn fields(packet: &[u8]) -> Result<Vec<(&'static str, String)>, HeaderError> {
+    let iph = IpHeader::parse(packet)?;
+    let ip_flags: Vec<&str> = iph.flags.iter_names().map(|(name, _)| name).collect();
+
+    let mut fields = vec![
+        ("tos", iph.tos.to_string()),
+        ("len", iph.total_len.to_string()),
+        ("id", iph.id.to_string()),
+        ("ip_flags", join_or_dash(&ip_flags)),
+        ("frag", iph.frag_offset.to_string()),
+        ("ttl", iph.ttl.to_string()),
+        ("src", iph.src_ip.to_string()),
+        ("dst", iph.dst_ip.to_string()),
+    ];
+    if is_fragment(&iph) {
+        return Ok(fields);
+    }
+
+    let (_, tcph) = packet::unwrap(packet)?;
+    let tcp_flags: Vec<&str> = tcph.flags.iter_names().map(|(name, _)| name).collect();
+    fields.extend([
+        ("sport", tcph.src_port.to_string()),
+        ("dport", tcph.dst_port.to_string()),
+        ("seq", tcph.seq_no.value().to_string()),
+        ("ack", tcph.ack_no.value().to_string()),
+        ("off", tcph.data_offset.to_string()),
+        ("tcp_flags", join_or_dash(&tcp_flags)),
+        ("win", tcph.window.to_string()),
+        ("urg", tcph.urgent.to_string()),
+        ("options", if tcph.options.is_empty() { "-".to_string() } else { hex::encode(&tcph.options) }),
+        ("payload_len", tcph.payload.len().to_string()),
+    ]);
+    Ok(fields)
+}
+
+/// Render one manifest line: `name key=value key=value ...`
+pub fn manifest_line(name: &str, fields: &[(&str, String)]) -> String {
+    let pairs: Vec<String> = fields.iter().map(|(k, v)| format!("{k}={v}")).collect();
+    format!("{name} {}", pairs.join(" "))
+}
+
+/// Decode a `.hex` packet file. Whitespace is ignored
+pub fn read_hex(path: &Path) -> io::Result<Vec<u8>> {
+    let text = fs::read_to_string(path)?;
+    let hex: String = text.split_whitespace().collect();
+    hex::decode(hex).map_err(|e| Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Render the manifest for every `.hex` packet in `dir`, sorted by name
+pub fn render_manifest(dir: &Path) -> io::Result<String> {
+    let mut names: Vec<String> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str()?.strip_suffix(".hex").map(str::to_string))
+        .collect();
+    names.sort();
+
+    let mut manifest = String::from("# Generated by `cargo run --bin golden_manifest -- --write`\n");
+    for name in names {
+        let packet = read_hex(&dir.join(format!("{name}.hex")))?;
+        let fields = fields(&packet).map_err(|e| Error::new(io::ErrorKind::InvalidData, format!("{name}: {e}")))?;
+        manifest.push_str(&manifest_line(&name, &fields));
+        manifest.push('\n');
+    }
+    Ok(manifest)
+}
+
+fn join_or_dash(names: &[&str]) -> String {
+    if names.is_empty() {
+        "-".to_string()
+    } else {
+        names.join("|")
+    }
+}
+
+fn main() {
+    // Print the manifest for tests/golden, or overwrite tests/golden/manifest.txt with `--write`
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let write = std::env::args().any(|a| a == "--write");
+
+    let result = render_manifest(&dir).and_then(|manifest| {
+        if write {
+            fs::write(dir.join("manifest.txt"), manifest)
+        } else {
+            print!("{manifest}");
+            Ok(())
+        }
+    });
+
+    if let Err(e) = result {
+        eprintln!("Failed to render manifest: {e}");
+        std::process::exit(1);
+    }
+}
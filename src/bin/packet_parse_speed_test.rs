@@ -0,0 +1,136 @@
+use net::ip::ip_header::IpHeader;
+use net::packet;
+use net::tcp::byte_stream::ByteStream;
+use net::tcp::reassembler::Reassembler;
+use net::tcp::receiver::{ReceiverStats, TcpReceiver};
+use net::tcp::tcp_header::TcpHeader;
+use net::tcp::wrap32::Wrap32;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io;
+use std::io::{Error, Read};
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Counts every allocation, so each path can report allocations per packet
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const MSS: usize = 1460;
+
+/// `num_packets` full-sized data segments of one flow, in order
+fn data_packets(num_packets: usize) -> Vec<Vec<u8>> {
+    (0..num_packets)
+        .map(|i| {
+            let iph = IpHeader {
+                version: 4,
+                ihl: 5,
+                total_len: (40 + MSS) as u16,
+                ttl: 64,
+                protocol: 6,
+                src_ip: Ipv4Addr::new(10, 0, 0, 1),
+                dst_ip: Ipv4Addr::new(10, 0, 0, 2),
+                ..IpHeader::default()
+            };
+            let tcph = TcpHeader {
+                src_port: 80,
+                dst_port: 50000,
+                seq_no: Wrap32::new((i * MSS) as u32),
+                data_offset: 5,
+                window: 65535,
+                payload: vec![i as u8; MSS].into(),
+                ..TcpHeader::default()
+            };
+            packet::wrap(&iph, &tcph).unwrap()
+        })
+        .collect()
+}
+
+/// Run one path over every packet. Returns packets/s, allocations per packet, and the receiver's
+/// counters, which only the `recv_packet` path updates
+fn speed_test(path: &str, packets: &[Vec<u8>]) -> io::Result<(f64, f64, ReceiverStats)> {
+    let mut rx = TcpReceiver::new(Wrap32::new(0), Reassembler::with_ring_buffer(ByteStream::new(1 << 16)));
+    let mut buf = vec![0u8; MSS];
+    let mut checksum = 0u64;
+
+    let allocs_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let t0 = Instant::now();
+    for packet in packets {
+        match path {
+            "unwrap" => {
+                let (_, tcph) = packet::unwrap(packet).map_err(Error::other)?;
+                checksum += tcph.payload[0] as u64;
+            }
+            "unwrap_ref" => {
+                let (_, tcph) = packet::unwrap_ref(packet).map_err(Error::other)?;
+                checksum += tcph.payload()[0] as u64;
+            }
+            _ => {
+                rx.recv_packet(packet)?;
+                rx.stream_mut().read_exact(&mut buf)?;
+                checksum += buf[0] as u64;
+            }
+        }
+    }
+    let duration = t0.elapsed();
+    let allocs = ALLOCATIONS.load(Ordering::Relaxed) - allocs_before;
+
+    let expected: u64 = (0..packets.len()).map(|i| i as u8 as u64).sum();
+    if checksum != expected {
+        return Err(Error::other(format!("{path} read the wrong payloads")));
+    }
+
+    let packets_per_sec = packets.len() as f64 / duration.as_secs_f64();
+    Ok((packets_per_sec, allocs as f64 / packets.len() as f64, *rx.stats()))
+}
+
+fn main() {
+    let num_packets = 200_000;
+    let packets = data_packets(num_packets);
+
+    // `--json` prints a report for `bench_compare` instead of the human-readable results
+    let json = std::env::args().skip(1).any(|a| a == "--json");
+
+    let mut workloads = Vec::new();
+    for path in ["unwrap", "unwrap_ref", "recv_packet"] {
+        let (packets_per_sec, allocs_per_packet, stats) = match speed_test(path, &packets) {
+            Ok(result) => result,
+            Err(e) => {
+                eprintln!("Speed test failed: {e}");
+                std::process::exit(1);
+            }
+        };
+
+        if json {
+            workloads.push(serde_json::json!({
+                "name": "packet_parse",
+                "params": {"path": path, "num_packets": num_packets, "mss": MSS},
+                "metrics": {"packets_per_sec": packets_per_sec, "allocations_per_packet": allocs_per_packet},
+            }));
+        } else {
+            println!("Parse ({path}) reached {packets_per_sec:.0} packets/s with {allocs_per_packet:.2} allocations/packet");
+            if path == "recv_packet" {
+                println!("Receiver stats: {stats}");
+            }
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::json!({ "workloads": workloads }));
+    }
+}
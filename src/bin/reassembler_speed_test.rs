@@ -1,3 +1,4 @@
+use bytes::Bytes;
 use net::tcp::byte_stream::ByteStream;
 use net::tcp::reassembler::Reassembler;
 use rand::rngs::StdRng;
@@ -7,14 +8,15 @@ use std::io;
 use std::io::{Error, ErrorKind, Read};
 use std::time::Instant;
 
-fn speed_test(num_chunks: usize, capacity: usize, random_seed: usize) -> io::Result<()> {
+fn speed_test(num_chunks: usize, capacity: usize, random_seed: usize, ring: bool) -> io::Result<f64> {
     // Generate random data
     let mut rng = StdRng::seed_from_u64(random_seed as u64);
     let mut data = vec![0u8; num_chunks * capacity];
     rng.fill_bytes(&mut data);
+    let data = Bytes::from(data);
 
-    // Split data up into segments
-    let mut chunks: VecDeque<(usize, &[u8], bool)> = VecDeque::new();
+    // Split data up into segments. Slicing `Bytes` shares the underlying buffer
+    let mut chunks: VecDeque<(usize, Bytes, bool)> = VecDeque::new();
     for i in (0..data.len()).step_by(capacity) {
         for offset in [2, 0, 1] {
             let start = i + offset;
@@ -22,14 +24,18 @@ fn speed_test(num_chunks: usize, capacity: usize, random_seed: usize) -> io::Res
                 continue; // Skip if start exceeds data length
             }
             let end = usize::min(start + capacity * 2, data.len());
-            let segment = data.get(start..end).unwrap_or(&[]);
+            let segment = data.slice(start..end);
             let is_last = end >= data.len();
             chunks.push_back((start, segment, is_last));
         }
     }
 
     // Set up Reassembler and output buffer
-    let mut ra = Reassembler::new(ByteStream::new(capacity));
+    let mut ra = if ring {
+        Reassembler::with_ring_buffer(ByteStream::new(capacity))
+    } else {
+        Reassembler::new(ByteStream::new(capacity))
+    };
     let mut output_buffer = Vec::with_capacity(data.len());
     let mut buf = [0u8; 4096]; // Reusable buffer
 
@@ -38,7 +44,7 @@ fn speed_test(num_chunks: usize, capacity: usize, random_seed: usize) -> io::Res
 
     // Run simulation
     while let Some((seq_num, segment, is_last)) = chunks.pop_front() {
-        ra.insert(seq_num, segment, is_last)?;
+        ra.insert_bytes(seq_num, segment, is_last)?;
 
         loop {
             match ra.read(&mut buf) {
@@ -57,17 +63,11 @@ fn speed_test(num_chunks: usize, capacity: usize, random_seed: usize) -> io::Res
     let duration = t0.elapsed();
 
     if !ra.get_output().eof() {
-        return Err(Error::new(
-            ErrorKind::Other,
-            "Reassembler did not close ByteStream when finished",
-        ));
+        return Err(Error::other("Reassembler did not close ByteStream when finished"));
     }
 
     if data != output_buffer {
-        return Err(Error::new(
-            ErrorKind::Other,
-            "Mismatch between data written and data read",
-        ));
+        return Err(Error::other("Mismatch between data written and data read"));
     }
 
     // Calculate throughput
@@ -76,11 +76,29 @@ fn speed_test(num_chunks: usize, capacity: usize, random_seed: usize) -> io::Res
     let bits_per_sec = bytes_per_sec * 8.0;
     let gigabits_per_sec = bits_per_sec / 1e9;
 
-    println!(
-        "Reassembler to ByteStream with capacity={capacity} reached {gigabits_per_sec:.2} Gbit/s"
-    );
+    Ok(gigabits_per_sec)
+}
+
+/// Print the report for `bench_compare`, with the goodput of each backend
+#[cfg(feature = "bench")]
+fn print_json_report(capacity: usize, num_chunks: usize, results: &[(&str, f64)]) {
+    let workloads: Vec<_> = results
+        .iter()
+        .map(|(backend, gigabits_per_sec)| {
+            serde_json::json!({
+                "name": "reassembler",
+                "params": {"backend": backend, "capacity": capacity, "num_chunks": num_chunks},
+                "metrics": {"goodput_gbps": gigabits_per_sec},
+            })
+        })
+        .collect();
+    println!("{}", serde_json::json!({ "workloads": workloads }));
+}
 
-    Ok(())
+#[cfg(not(feature = "bench"))]
+fn print_json_report(_: usize, _: usize, _: &[(&str, f64)]) {
+    eprintln!("--json needs the `bench` feature");
+    std::process::exit(1);
 }
 
 fn main() {
@@ -88,9 +106,37 @@ fn main() {
     let capacity = 1500;
     let random_seed = 1370;
 
-    if let Err(e) = speed_test(num_chunks, capacity, random_seed) {
-        eprintln!("Speed test failed: {e}");
-        std::process::exit(1);
+    // `--ring` benchmarks the ring buffer backend, `--compare` benchmarks both.
+    // `--json` prints a report for `bench_compare` instead of the human-readable results
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let json = args.iter().any(|a| a == "--json");
+    let backends = if args.iter().any(|a| a == "--compare") {
+        vec![false, true]
+    } else {
+        vec![args.iter().any(|a| a == "--ring")]
+    };
+
+    let mut results = Vec::new();
+    for ring in backends {
+        let gigabits_per_sec = match speed_test(num_chunks, capacity, random_seed, ring) {
+            Ok(gigabits_per_sec) => gigabits_per_sec,
+            Err(e) => {
+                eprintln!("Speed test failed: {e}");
+                std::process::exit(1);
+            }
+        };
+
+        let backend = if ring { "ring" } else { "tree" };
+        if !json {
+            println!(
+                "Reassembler ({backend}) to ByteStream with capacity={capacity} reached {gigabits_per_sec:.2} Gbit/s"
+            );
+        }
+        results.push((backend, gigabits_per_sec));
+    }
+
+    if json {
+        print_json_report(capacity, num_chunks, &results);
     }
 
     // Result:
@@ -0,0 +1,74 @@
+use net::socket::rawsocket::{self, BATCH_BUF_LEN};
+use nix::sys::socket::{bind, getsockname, socket, AddressFamily, SockFlag, SockType, SockaddrIn};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::time::{Duration, Instant};
+
+const PACKET_LEN: usize = 1400;
+const BURST: usize = 64; // Fits the default 208 KiB receive buffer
+
+/// A UDP socket bound to an ephemeral loopback port
+fn udp_socket() -> (OwnedFd, SocketAddrV4) {
+    let fd = socket(AddressFamily::Inet, SockType::Datagram, SockFlag::empty(), None).unwrap();
+    bind(fd.as_raw_fd(), &SockaddrIn::from(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))).unwrap();
+    let addr = SocketAddrV4::from(getsockname::<SockaddrIn>(fd.as_raw_fd()).unwrap());
+    (fd, addr)
+}
+
+/// Receive `rounds` bursts of `BURST` packets, reading with `batch` buffers per call (1 means
+/// plain `recv_packet_timeout`). Each burst is queued before the reads start, like packets that
+/// piled up while the receiver was busy. Returns (packets/s, syscalls per packet), counting a
+/// poll and a read for each receive call.
+fn speed_test(rounds: usize, batch: usize) -> (f64, f64) {
+    let (rx, rx_addr) = udp_socket();
+    let (tx, _) = udp_socket();
+    let packet = [0xabu8; PACKET_LEN];
+    let mut bufs = vec![[0u8; BATCH_BUF_LEN]; batch];
+
+    let mut elapsed = Duration::ZERO;
+    let (mut received, mut calls) = (0usize, 0usize);
+    for _ in 0..rounds {
+        for _ in 0..BURST {
+            rawsocket::send_packet(&tx, &packet, rx_addr).unwrap();
+        }
+
+        let t0 = Instant::now();
+        let mut pending = BURST;
+        while pending > 0 {
+            calls += 1;
+            let got = if batch == 1 {
+                rawsocket::recv_packet_timeout(&rx, &mut bufs[0], Duration::from_secs(1)).map(|_| 1).unwrap()
+            } else {
+                rawsocket::recv_batch(&rx, &mut bufs, Duration::from_secs(1)).unwrap().len()
+            };
+            assert!(got > 0, "burst lost packets");
+            pending -= got;
+        }
+        elapsed += t0.elapsed();
+        received += BURST;
+    }
+    (received as f64 / elapsed.as_secs_f64(), (2 * calls) as f64 / received as f64)
+}
+
+fn main() {
+    // `--json` prints a report for `bench_compare` instead of the human-readable results
+    let json = std::env::args().skip(1).any(|a| a == "--json");
+
+    let mut workloads = Vec::new();
+    for batch in [1, 8, 32, 64] {
+        let (packets_per_sec, syscalls_per_packet) = speed_test(2000, batch);
+        if json {
+            workloads.push(serde_json::json!({
+                "name": "recv_batch",
+                "params": {"batch": batch, "burst": BURST, "packet_len": PACKET_LEN},
+                "metrics": {"packets_per_sec": packets_per_sec, "syscalls_per_packet": syscalls_per_packet},
+            }));
+        } else {
+            println!("Batch of {batch}: {packets_per_sec:.0} packets/s, {syscalls_per_packet:.3} syscalls per packet");
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::json!({ "workloads": workloads }));
+    }
+}
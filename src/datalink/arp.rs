@@ -0,0 +1,238 @@
+use crate::datalink::ethernet::{MacAddr, ETHERTYPE_IPV4};
+use crate::packet::errors::HeaderError;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+const HTYPE_ETHERNET: u16 = 1;
+
+/// How long a learned mapping stays usable
+pub const ENTRY_TTL: Duration = Duration::from_secs(30);
+/// How long to wait for a reply before asking again
+pub const REQUEST_INTERVAL: Duration = Duration::from_secs(5);
+/// Packets held per unresolved address. Past this the oldest is dropped, like a full NIC queue
+const MAX_PENDING: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArpOp {
+    Request = 1,
+    Reply = 2,
+}
+
+/// An ARP message for IPv4 over Ethernet (RFC 826), the only kind we speak
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArpMessage {
+    pub op: ArpOp,
+    pub sender_mac: MacAddr,
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: MacAddr, // Zero in a request; that's what is being asked
+    pub target_ip: Ipv4Addr,
+}
+
+impl ArpMessage {
+    pub const LEN: usize = 28;
+
+    /// Who has `target_ip`? Tell `sender_ip`
+    pub fn request(sender_mac: MacAddr, sender_ip: Ipv4Addr, target_ip: Ipv4Addr) -> Self {
+        ArpMessage { op: ArpOp::Request, sender_mac, sender_ip, target_mac: MacAddr::default(), target_ip }
+    }
+
+    /// The reply to this request from `our_mac`, which owns the target address
+    pub fn reply(&self, our_mac: MacAddr) -> Self {
+        ArpMessage {
+            op: ArpOp::Reply,
+            sender_mac: our_mac,
+            sender_ip: self.target_ip,
+            target_mac: self.sender_mac,
+            target_ip: self.sender_ip,
+        }
+    }
+
+    pub fn serialize(&self, buf: &mut [u8]) -> Result<usize, HeaderError> {
+        if buf.len() < Self::LEN {
+            return Err(HeaderError::BufferTooSmall { expected: Self::LEN, found: buf.len() });
+        }
+        buf[0..2].copy_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+        buf[2..4].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        buf[4] = 6; // Hardware address length
+        buf[5] = 4; // Protocol address length
+        buf[6..8].copy_from_slice(&(self.op as u16).to_be_bytes());
+        buf[8..14].copy_from_slice(&self.sender_mac.0);
+        buf[14..18].copy_from_slice(&self.sender_ip.octets());
+        buf[18..24].copy_from_slice(&self.target_mac.0);
+        buf[24..28].copy_from_slice(&self.target_ip.octets());
+        Ok(Self::LEN)
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::LEN] {
+        let mut buf = [0u8; Self::LEN];
+        let _ = self.serialize(&mut buf); // Can't fail: the buffer is exactly LEN
+        buf
+    }
+
+    /// Parse an ARP message. Trailing bytes, such as Ethernet padding to the 60 byte minimum, are
+    /// ignored.
+    pub fn parse(buf: &[u8]) -> Result<Self, HeaderError> {
+        if buf.len() < Self::LEN {
+            return Err(HeaderError::BufferTooSmall { expected: Self::LEN, found: buf.len() });
+        }
+        let u16_at = |at: usize| u16::from_be_bytes([buf[at], buf[at + 1]]);
+        if u16_at(0) != HTYPE_ETHERNET || u16_at(2) != ETHERTYPE_IPV4 || buf[4] != 6 || buf[5] != 4 {
+            let (htype, ptype, hlen, plen) = (u16_at(0), u16_at(2), buf[4], buf[5]);
+            return Err(HeaderError::UnsupportedArp(format!("htype {htype} ptype {ptype:#06x} hlen {hlen} plen {plen}")));
+        }
+        let op = match u16_at(6) {
+            1 => ArpOp::Request,
+            2 => ArpOp::Reply,
+            op => return Err(HeaderError::UnsupportedArp(format!("opcode {op}"))),
+        };
+        let mac = |at: usize| MacAddr([buf[at], buf[at + 1], buf[at + 2], buf[at + 3], buf[at + 4], buf[at + 5]]);
+        let ip = |at: usize| Ipv4Addr::new(buf[at], buf[at + 1], buf[at + 2], buf[at + 3]);
+        Ok(ArpMessage { op, sender_mac: mac(8), sender_ip: ip(14), target_mac: mac(18), target_ip: ip(24) })
+    }
+}
+
+#[derive(Debug)]
+struct Pending {
+    packets: Vec<Vec<u8>>, // Waiting for the address, oldest first
+    last_request: Instant, // When we last asked for it
+}
+
+/// Learned IPv4 to Ethernet mappings, plus the packets held until an address resolves
+#[derive(Debug, Default)]
+pub struct ArpCache {
+    entries: HashMap<Ipv4Addr, (MacAddr, Instant)>, // Mapping and when it was learned
+    pending: HashMap<Ipv4Addr, Pending>,
+}
+
+impl ArpCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The MAC for `ip`, unless it's unknown or older than `ENTRY_TTL`
+    pub fn lookup(&self, ip: Ipv4Addr, now: Instant) -> Option<MacAddr> {
+        match self.entries.get(&ip) {
+            Some(&(mac, learned)) if now.duration_since(learned) < ENTRY_TTL => Some(mac),
+            _ => None,
+        }
+    }
+
+    /// Learn (or refresh) a mapping. Returns the packets that were waiting on `ip`, to be sent now
+    pub fn insert(&mut self, ip: Ipv4Addr, mac: MacAddr, now: Instant) -> Vec<Vec<u8>> {
+        self.entries.insert(ip, (mac, now));
+        self.pending.remove(&ip).map(|p| p.packets).unwrap_or_default()
+    }
+
+    /// Hold `packet` until `ip` resolves. Returns true if a request should go out: the first time
+    /// we wait on `ip`, or once the last request has gone unanswered for `REQUEST_INTERVAL`.
+    pub fn enqueue(&mut self, ip: Ipv4Addr, packet: Vec<u8>, now: Instant) -> bool {
+        let mut ask = false;
+        let pending = self.pending.entry(ip).or_insert_with(|| {
+            ask = true;
+            Pending { packets: Vec::new(), last_request: now }
+        });
+        if !ask && now.duration_since(pending.last_request) >= REQUEST_INTERVAL {
+            pending.last_request = now;
+            ask = true;
+        }
+        if pending.packets.len() == MAX_PENDING {
+            pending.packets.remove(0);
+        }
+        pending.packets.push(packet);
+        ask
+    }
+
+    /// How many packets are waiting on `ip`
+    pub fn pending(&self, ip: Ipv4Addr) -> usize {
+        self.pending.get(&ip).map_or(0, |p| p.packets.len())
+    }
+
+    /// Forget expired mappings
+    pub fn evict_expired(&mut self, now: Instant) {
+        self.entries.retain(|_, &mut (_, learned)| now.duration_since(learned) < ENTRY_TTL);
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUR_MAC: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x02]);
+    const OUR_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+    const GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+    #[test]
+    fn test_request_bytes() {
+        let request = ArpMessage::request(OUR_MAC, OUR_IP, GATEWAY);
+        let expected = "00010800060400010200000000020a0000020000000000000a000001";
+        assert_eq!(hex::encode(request.to_bytes()), expected);
+        assert_eq!(ArpMessage::parse(&hex::decode(expected).unwrap()), Ok(request));
+
+        // Ethernet pads short frames out to 60 bytes
+        let mut padded = request.to_bytes().to_vec();
+        padded.resize(46, 0);
+        assert_eq!(ArpMessage::parse(&padded), Ok(request));
+    }
+
+    #[test]
+    fn test_reply_swaps_roles() {
+        let gateway_mac = MacAddr([0x02, 0, 0, 0, 0, 0x01]);
+        let reply = ArpMessage::request(OUR_MAC, OUR_IP, GATEWAY).reply(gateway_mac);
+        assert_eq!(
+            reply,
+            ArpMessage { op: ArpOp::Reply, sender_mac: gateway_mac, sender_ip: GATEWAY, target_mac: OUR_MAC, target_ip: OUR_IP }
+        );
+        assert_eq!(hex::encode(&reply.to_bytes()[6..8]), "0002");
+        assert_eq!(ArpMessage::parse(&reply.to_bytes()), Ok(reply));
+    }
+
+    #[test]
+    fn test_parse_rejects() {
+        let mut buf = ArpMessage::request(OUR_MAC, OUR_IP, GATEWAY).to_bytes();
+        assert_eq!(ArpMessage::parse(&buf[..27]), Err(HeaderError::BufferTooSmall { expected: 28, found: 27 }));
+        buf[7] = 3; // RARP request
+        assert_eq!(ArpMessage::parse(&buf), Err(HeaderError::UnsupportedArp("opcode 3".into())));
+        buf[3] = 0xdd; // IPv6
+        assert!(matches!(ArpMessage::parse(&buf), Err(HeaderError::UnsupportedArp(_))));
+    }
+
+    #[test]
+    fn test_cache_queues_until_resolved() {
+        let mut cache = ArpCache::new();
+        let start = Instant::now();
+        assert_eq!(cache.lookup(GATEWAY, start), None);
+
+        // One request for the first packet, none for the next until the interval passes
+        assert!(cache.enqueue(GATEWAY, b"one".to_vec(), start));
+        assert!(!cache.enqueue(GATEWAY, b"two".to_vec(), start + Duration::from_secs(1)));
+        assert!(cache.enqueue(GATEWAY, b"three".to_vec(), start + REQUEST_INTERVAL));
+        assert_eq!(cache.pending(GATEWAY), 3);
+
+        let mac = MacAddr([0x02, 0, 0, 0, 0, 0x01]);
+        let flushed = cache.insert(GATEWAY, mac, start + REQUEST_INTERVAL);
+        assert_eq!(flushed, [b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]);
+        assert_eq!(cache.pending(GATEWAY), 0);
+        assert_eq!(cache.lookup(GATEWAY, start + REQUEST_INTERVAL), Some(mac));
+
+        // Expires after the TTL
+        let later = start + REQUEST_INTERVAL + ENTRY_TTL;
+        assert_eq!(cache.lookup(GATEWAY, later), None);
+        cache.evict_expired(later);
+        assert!(cache.entries.is_empty());
+    }
+
+    #[test]
+    fn test_pending_queue_is_bounded() {
+        let mut cache = ArpCache::new();
+        let now = Instant::now();
+        for i in 0..MAX_PENDING + 2 {
+            cache.enqueue(GATEWAY, vec![i as u8], now);
+        }
+        let flushed = cache.insert(GATEWAY, MacAddr::BROADCAST, now);
+        assert_eq!(flushed.len(), MAX_PENDING);
+        assert_eq!(flushed[0], [2]); // The two oldest were dropped
+    }
+}
@@ -0,0 +1,100 @@
+use crate::packet::errors::HeaderError;
+use std::fmt;
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
+
+/// A 48-bit Ethernet address
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    pub const BROADCAST: MacAddr = MacAddr([0xff; 6]);
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+/// An Ethernet II header. No 802.1Q tag, and no FCS, which the NIC adds and strips
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EthernetHeader {
+    pub dst_mac: MacAddr,
+    pub src_mac: MacAddr,
+    pub ethertype: u16, // What the payload is, e.g. `ETHERTYPE_IPV4`
+}
+
+impl EthernetHeader {
+    pub const LEN: usize = 14;
+
+    /// Serialize the header into the front of `buf`. Returns `LEN`
+    pub fn serialize(&self, buf: &mut [u8]) -> Result<usize, HeaderError> {
+        if buf.len() < Self::LEN {
+            return Err(HeaderError::BufferTooSmall { expected: Self::LEN, found: buf.len() });
+        }
+        buf[0..6].copy_from_slice(&self.dst_mac.0);
+        buf[6..12].copy_from_slice(&self.src_mac.0);
+        buf[12..14].copy_from_slice(&self.ethertype.to_be_bytes());
+        Ok(Self::LEN)
+    }
+
+    /// Parse the header at the front of a frame. The payload is `&frame[EthernetHeader::LEN..]`
+    pub fn parse(frame: &[u8]) -> Result<Self, HeaderError> {
+        if frame.len() < Self::LEN {
+            return Err(HeaderError::BufferTooSmall { expected: Self::LEN, found: frame.len() });
+        }
+        let mac = |at: usize| MacAddr([frame[at], frame[at + 1], frame[at + 2], frame[at + 3], frame[at + 4], frame[at + 5]]);
+        Ok(EthernetHeader { dst_mac: mac(0), src_mac: mac(6), ethertype: u16::from_be_bytes([frame[12], frame[13]]) })
+    }
+
+    /// A new frame of this header followed by `payload`
+    pub fn frame(&self, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0u8; Self::LEN + payload.len()];
+        frame[..6].copy_from_slice(&self.dst_mac.0);
+        frame[6..12].copy_from_slice(&self.src_mac.0);
+        frame[12..14].copy_from_slice(&self.ethertype.to_be_bytes());
+        frame[Self::LEN..].copy_from_slice(payload);
+        frame
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_and_parse() {
+        let header = EthernetHeader {
+            dst_mac: MacAddr::BROADCAST,
+            src_mac: MacAddr([0x02, 0, 0, 0xab, 0xcd, 0xef]),
+            ethertype: ETHERTYPE_ARP,
+        };
+        let frame = header.frame(b"payload");
+        assert_eq!(hex::encode(&frame[..EthernetHeader::LEN]), "ffffffffffff020000abcdef0806");
+        assert_eq!(&frame[EthernetHeader::LEN..], b"payload");
+        assert_eq!(EthernetHeader::parse(&frame), Ok(header));
+
+        let mut buf = [0u8; 14];
+        assert_eq!(header.serialize(&mut buf), Ok(14));
+        assert_eq!(buf[..], frame[..14]);
+        assert_eq!(header.serialize(&mut buf[..13]), Err(HeaderError::BufferTooSmall { expected: 14, found: 13 }));
+        assert_eq!(EthernetHeader::parse(&frame[..13]), Err(HeaderError::BufferTooSmall { expected: 14, found: 13 }));
+    }
+
+    #[test]
+    fn test_mac_display() {
+        assert_eq!(MacAddr([0x02, 0, 0, 0xab, 0xcd, 0xef]).to_string(), "02:00:00:ab:cd:ef");
+        assert!(MacAddr::BROADCAST.is_broadcast());
+        assert!(!MacAddr::default().is_broadcast());
+    }
+}
@@ -1,2 +1,3 @@
-mod arp;
-mod ethernet;
+pub mod arp;
+pub mod ethernet;
+pub mod tap;
@@ -0,0 +1,262 @@
+// IP packets over an Ethernet link, e.g. a TAP device. Unlike TUN, a TAP interface hands us
+// frames and expects a host on the other end: we answer ARP for our address and resolve the next
+// hop before anything goes out.
+
+use crate::datalink::arp::{ArpCache, ArpMessage, ArpOp};
+use crate::datalink::ethernet::{EthernetHeader, MacAddr, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use crate::socket::packet_io::{self, PacketIo};
+use nix::errno::Errno;
+use std::io;
+use std::mem;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+/// Largest frame we read: an Ethernet header and a maximum size IP packet
+const MAX_FRAME_LEN: usize = EthernetHeader::LEN + u16::MAX as usize;
+
+/// An IP-level `PacketIo` on top of `link`, a `PacketIo` that carries whole Ethernet frames
+#[derive(Debug)]
+pub struct TapIo<L> {
+    link: L,
+    mac: MacAddr,              // Ours, as the source of every frame
+    ip: Ipv4Addr,              // Ours, which we answer ARP requests for
+    netmask: u32,              // Addresses inside it are sent to directly
+    gateway: Option<Ipv4Addr>, // Next hop for everything else
+    arp: ArpCache,
+    frame: Vec<u8>,            // Receive buffer
+}
+
+impl<L: PacketIo> TapIo<L> {
+    /// Act as host `ip/prefix_len` with hardware address `mac` on `link`. Packets leaving the
+    /// subnet go through `gateway`; without one they fail with ENETUNREACH.
+    pub fn new(link: L, mac: MacAddr, ip: Ipv4Addr, prefix_len: u8, gateway: Option<Ipv4Addr>) -> Self {
+        TapIo {
+            link,
+            mac,
+            ip,
+            netmask: u32::MAX.checked_shl(32 - prefix_len.min(32) as u32).unwrap_or(0),
+            gateway,
+            arp: ArpCache::new(),
+            frame: vec![0u8; MAX_FRAME_LEN],
+        }
+    }
+
+    pub fn mac(&self) -> MacAddr {
+        self.mac
+    }
+
+    pub fn ip(&self) -> Ipv4Addr {
+        self.ip
+    }
+
+    pub fn arp(&self) -> &ArpCache {
+        &self.arp
+    }
+
+    pub fn link(&self) -> &L {
+        &self.link
+    }
+
+    /// Where a packet for `dst` goes first: straight there inside our subnet, else the gateway
+    fn next_hop(&self, dst: Ipv4Addr) -> io::Result<Ipv4Addr> {
+        if u32::from(dst) & self.netmask == u32::from(self.ip) & self.netmask {
+            return Ok(dst);
+        }
+        Ok(self.gateway.ok_or(Errno::ENETUNREACH)?)
+    }
+
+    fn send_frame(&mut self, dst_mac: MacAddr, ethertype: u16, payload: &[u8]) -> io::Result<()> {
+        let header = EthernetHeader { dst_mac, src_mac: self.mac, ethertype };
+        self.link.send(&header.frame(payload))?;
+        Ok(())
+    }
+
+    /// Learn the sender's mapping, send whatever was waiting on it, and answer requests for us
+    fn handle_arp(&mut self, payload: &[u8]) -> io::Result<()> {
+        let Ok(message) = ArpMessage::parse(payload) else { return Ok(()) };
+        if message.sender_ip.is_unspecified() {
+            return Ok(()); // An address probe (RFC 5227), nothing to learn
+        }
+        for packet in self.arp.insert(message.sender_ip, message.sender_mac, Instant::now()) {
+            self.send_frame(message.sender_mac, ETHERTYPE_IPV4, &packet)?;
+        }
+        if message.op == ArpOp::Request && message.target_ip == self.ip {
+            self.send_frame(message.sender_mac, ETHERTYPE_ARP, &message.reply(self.mac).to_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Deal with one received frame. Returns the length of the IP packet copied into `buf`, if
+    /// the frame carried one for us.
+    fn on_frame(&mut self, frame: &[u8], buf: &mut [u8]) -> io::Result<Option<usize>> {
+        let Ok(header) = EthernetHeader::parse(frame) else { return Ok(None) };
+        if header.dst_mac != self.mac && !header.dst_mac.is_broadcast() {
+            return Ok(None);
+        }
+        let payload = &frame[EthernetHeader::LEN..];
+        match header.ethertype {
+            ETHERTYPE_ARP => self.handle_arp(payload).map(|()| None),
+            ETHERTYPE_IPV4 if payload.len() >= 20 => {
+                // Short packets arrive padded to the 60 byte minimum frame; trim to the IP length
+                let total_len = u16::from_be_bytes([payload[2], payload[3]]) as usize;
+                let packet = payload.get(..total_len).unwrap_or(payload);
+                packet_io::deliver(packet, buf).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl<L: PacketIo> PacketIo for TapIo<L> {
+    /// Frame and send an IPv4 packet. If the next hop isn't resolved yet the packet is held, an ARP
+    /// request goes out, and it's still reported as sent, the way the kernel queues it.
+    fn send(&mut self, packet: &[u8]) -> io::Result<usize> {
+        if packet.len() < 20 || packet[0] >> 4 != 4 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "not an IPv4 packet"));
+        }
+        let dst = Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]);
+        let hop = self.next_hop(dst)?;
+        let now = Instant::now();
+        match self.arp.lookup(hop, now) {
+            Some(mac) => self.send_frame(mac, ETHERTYPE_IPV4, packet)?,
+            None => {
+                if self.arp.enqueue(hop, packet.to_vec(), now) {
+                    let request = ArpMessage::request(self.mac, self.ip, hop);
+                    self.send_frame(MacAddr::BROADCAST, ETHERTYPE_ARP, &request.to_bytes())?;
+                }
+            }
+        }
+        Ok(packet.len())
+    }
+
+    /// Receive the next IPv4 packet addressed to our MAC, handling any ARP traffic on the way
+    fn recv(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(packet_io::timed_out());
+            }
+            // Borrow the buffer out so `on_frame` can take `&mut self`
+            let mut frame = mem::take(&mut self.frame);
+            let delivered = match self.link.recv(&mut frame, remaining) {
+                Ok(len) => self.on_frame(&frame[..len], buf),
+                Err(e) => Err(e),
+            };
+            self.frame = frame;
+            if let Some(len) = delivered? {
+                return Ok(len);
+            }
+        }
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::packet_io::LoopbackIo;
+
+    const OUR_MAC: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x02]);
+    const OUR_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+    const PEER_MAC: MacAddr = MacAddr([0x02, 0, 0, 0, 0, 0x01]);
+    const PEER_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+    const TIMEOUT: Duration = Duration::from_millis(200);
+
+    /// A minimal IPv4 header from `src` to `dst`, no payload
+    fn ip_packet(src: Ipv4Addr, dst: Ipv4Addr) -> Vec<u8> {
+        let mut packet = vec![0x45, 0, 0, 20, 0, 0, 0x40, 0, 64, 6, 0, 0];
+        packet.extend(src.octets());
+        packet.extend(dst.octets());
+        packet
+    }
+
+    /// The next frame on the wire, split into header and payload
+    fn recv_frame(wire: &mut LoopbackIo) -> (EthernetHeader, Vec<u8>) {
+        let mut buf = [0u8; 2048];
+        let len = wire.recv(&mut buf, TIMEOUT).unwrap();
+        (EthernetHeader::parse(&buf[..len]).unwrap(), buf[EthernetHeader::LEN..len].to_vec())
+    }
+
+    fn tap() -> (TapIo<LoopbackIo>, LoopbackIo) {
+        let (link, wire) = LoopbackIo::pair();
+        (TapIo::new(link, OUR_MAC, OUR_IP, 24, Some(PEER_IP)), wire)
+    }
+
+    #[test]
+    fn test_queued_packets_flush_on_reply() {
+        let (mut tap, mut wire) = tap();
+        let first = ip_packet(OUR_IP, PEER_IP);
+        let second = ip_packet(OUR_IP, Ipv4Addr::new(8, 8, 8, 8)); // Via the gateway, the same hop
+        assert_eq!(tap.send(&first).unwrap(), 20);
+        assert_eq!(tap.send(&second).unwrap(), 20);
+
+        // Only one broadcast request for both
+        let (header, payload) = recv_frame(&mut wire);
+        assert_eq!((header.dst_mac, header.src_mac, header.ethertype), (MacAddr::BROADCAST, OUR_MAC, ETHERTYPE_ARP));
+        let request = ArpMessage::parse(&payload).unwrap();
+        assert_eq!(request, ArpMessage::request(OUR_MAC, OUR_IP, PEER_IP));
+        assert_eq!(wire.queued(), 0);
+        assert_eq!(tap.arp().pending(PEER_IP), 2);
+
+        // The reply arrives while we wait for packets, which sends both in order
+        let reply = EthernetHeader { dst_mac: OUR_MAC, src_mac: PEER_MAC, ethertype: ETHERTYPE_ARP };
+        wire.send(&reply.frame(&request.reply(PEER_MAC).to_bytes())).unwrap();
+        let mut buf = [0u8; 2048];
+        assert_eq!(tap.recv(&mut buf, TIMEOUT).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        for expected in [first, second] {
+            let (header, payload) = recv_frame(&mut wire);
+            assert_eq!((header.dst_mac, header.ethertype), (PEER_MAC, ETHERTYPE_IPV4));
+            assert_eq!(payload, expected);
+        }
+
+        // Now resolved, later packets go straight out
+        tap.send(&ip_packet(OUR_IP, PEER_IP)).unwrap();
+        assert_eq!(recv_frame(&mut wire).0.dst_mac, PEER_MAC);
+    }
+
+    #[test]
+    fn test_answers_requests_for_our_address() {
+        let (mut tap, mut wire) = tap();
+        let broadcast = EthernetHeader { dst_mac: MacAddr::BROADCAST, src_mac: PEER_MAC, ethertype: ETHERTYPE_ARP };
+        wire.send(&broadcast.frame(&ArpMessage::request(PEER_MAC, PEER_IP, Ipv4Addr::new(10, 0, 0, 9)).to_bytes())).unwrap();
+        wire.send(&broadcast.frame(&ArpMessage::request(PEER_MAC, PEER_IP, OUR_IP).to_bytes())).unwrap();
+
+        // Then an IP packet, padded like a minimum size frame
+        let mut packet = ip_packet(PEER_IP, OUR_IP);
+        packet.resize(46, 0);
+        let unicast = EthernetHeader { dst_mac: OUR_MAC, src_mac: PEER_MAC, ethertype: ETHERTYPE_IPV4 };
+        wire.send(&unicast.frame(&packet)).unwrap();
+
+        let mut buf = [0u8; 2048];
+        assert_eq!(tap.recv(&mut buf, TIMEOUT).unwrap(), 20);
+        assert_eq!(buf[..20], packet[..20]);
+
+        // Only the request for us was answered, unicast
+        let (header, payload) = recv_frame(&mut wire);
+        assert_eq!((header.dst_mac, header.src_mac), (PEER_MAC, OUR_MAC));
+        let reply = ArpMessage::parse(&payload).unwrap();
+        assert_eq!((reply.op, reply.sender_mac, reply.sender_ip, reply.target_ip), (ArpOp::Reply, OUR_MAC, OUR_IP, PEER_IP));
+        assert_eq!(wire.queued(), 0);
+        assert_eq!(tap.arp().lookup(PEER_IP, Instant::now()), Some(PEER_MAC)); // Learned from the request
+    }
+
+    #[test]
+    fn test_drops_frames_for_other_hosts() {
+        let (mut tap, mut wire) = tap();
+        let other = EthernetHeader { dst_mac: MacAddr([0x02, 0, 0, 0, 0, 0x09]), src_mac: PEER_MAC, ethertype: ETHERTYPE_IPV4 };
+        wire.send(&other.frame(&ip_packet(PEER_IP, Ipv4Addr::new(10, 0, 0, 9)))).unwrap();
+        let mut buf = [0u8; 2048];
+        assert_eq!(tap.recv(&mut buf, TIMEOUT).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn test_no_route_without_gateway() {
+        let (link, _wire) = LoopbackIo::pair();
+        let mut tap = TapIo::new(link, OUR_MAC, OUR_IP, 24, None);
+        let err = tap.send(&ip_packet(OUR_IP, Ipv4Addr::new(8, 8, 8, 8))).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(Errno::ENETUNREACH as i32));
+        assert_eq!(tap.send(&[0x60; 40]).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
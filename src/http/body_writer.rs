@@ -0,0 +1,225 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[cfg(feature = "mmap")]
+use memmap2::MmapMut;
+#[cfg(feature = "mmap")]
+use std::io::{Error, ErrorKind};
+
+/// Writes a response body to a file. With a known Content-Length (and the `mmap` feature) the
+/// file is preallocated and mapped, otherwise it falls back to buffered File I/O.
+#[derive(Debug)]
+pub enum BodyWriter {
+    #[cfg(feature = "mmap")]
+    Mapped(MmapWriter),
+    Buffered(BufWriter<File>),
+}
+
+impl BodyWriter {
+    /// Create the output file at `path`, truncating any existing file
+    pub fn create(path: impl AsRef<Path>, content_length: Option<u64>) -> io::Result<Self> {
+        #[cfg(feature = "mmap")]
+        if let Some(len) = content_length.filter(|&len| len > 0) {
+            return Ok(BodyWriter::Mapped(MmapWriter::create(path, len)?));
+        }
+
+        #[cfg(not(feature = "mmap"))]
+        let _ = content_length; // Only the mapped writer needs the length
+        Ok(BodyWriter::Buffered(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Flush the body to disk and return the number of bytes in the file.
+    /// A mapped body shorter than its Content-Length is truncated and reported as `UnexpectedEof`.
+    pub fn finish(self) -> io::Result<u64> {
+        match self {
+            #[cfg(feature = "mmap")]
+            BodyWriter::Mapped(writer) => writer.finish(),
+            BodyWriter::Buffered(mut writer) => {
+                writer.flush()?;
+                writer.get_ref().metadata().map(|m| m.len())
+            }
+        }
+    }
+}
+
+impl Write for BodyWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            #[cfg(feature = "mmap")]
+            BodyWriter::Mapped(writer) => writer.write(buf),
+            BodyWriter::Buffered(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            #[cfg(feature = "mmap")]
+            BodyWriter::Mapped(writer) => writer.flush(),
+            BodyWriter::Buffered(writer) => writer.flush(),
+        }
+    }
+}
+
+/// A file preallocated to the body length and mapped into memory. Bytes can be written
+/// sequentially through `Write`, or at exact offsets for disjoint range downloads.
+#[cfg(feature = "mmap")]
+#[derive(Debug)]
+pub struct MmapWriter {
+    file: File,
+    map: MmapMut,
+    pos: usize,        // Cursor for sequential writes
+    high_water: usize, // One past the highest byte written
+    written: usize,    // Total bytes written, assuming disjoint regions
+}
+
+#[cfg(feature = "mmap")]
+impl MmapWriter {
+    /// Create the file at `path` and preallocate it to `len` bytes
+    pub fn create(path: impl AsRef<Path>, len: u64) -> io::Result<Self> {
+        let file = File::options().read(true).write(true).create(true).truncate(true).open(path)?;
+        file.set_len(len)?;
+
+        // Safety: the file was just created and truncated by us, and is not shared
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        Ok(MmapWriter {
+            file,
+            map,
+            pos: 0,
+            high_water: 0,
+            written: 0,
+        })
+    }
+
+    /// Write `buf` at byte `offset` of the body
+    pub fn write_at(&mut self, offset: usize, buf: &[u8]) -> io::Result<()> {
+        let end = offset
+            .checked_add(buf.len())
+            .filter(|&end| end <= self.map.len())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "body longer than Content-Length"))?;
+
+        self.map[offset..end].copy_from_slice(buf);
+        self.high_water = self.high_water.max(end);
+        self.written += buf.len();
+        Ok(())
+    }
+
+    /// Flush the mapping. If fewer bytes than the Content-Length arrived, the file is truncated
+    /// to the highest byte written and `UnexpectedEof` is returned.
+    pub fn finish(self) -> io::Result<u64> {
+        self.map.flush()?;
+        let expected = self.map.len();
+        if self.written >= expected {
+            return Ok(expected as u64);
+        }
+
+        let (file, high_water, written) = (self.file, self.high_water, self.written);
+        drop(self.map);
+        file.set_len(high_water as u64)?;
+        Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            format!("body ended after {written} of {expected} bytes"),
+        ))
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl Write for MmapWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_at(self.pos, buf)?;
+        self.pos += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.map.flush()
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::Hasher;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("net-body-writer-{}-{name}", std::process::id()))
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn hash(data: &[u8]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(data);
+        hasher.finish()
+    }
+
+    /// Write `data` through a `BodyWriter` in MTU-sized chunks and hash the resulting file
+    fn write_and_hash(name: &str, data: &[u8], content_length: Option<u64>) -> u64 {
+        let path = temp_path(name);
+        let mut writer = BodyWriter::create(&path, content_length).unwrap();
+        for chunk in data.chunks(1460) {
+            writer.write_all(chunk).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), data.len() as u64);
+
+        let digest = hash(&std::fs::read(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        digest
+    }
+
+    #[test]
+    fn test_buffered_writer() {
+        let data = pattern(64 << 20);
+        assert_eq!(write_and_hash("buffered", &data, None), hash(&data));
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mapped_writer_matches_buffered() {
+        let data = pattern(64 << 20);
+        let len = Some(data.len() as u64);
+        assert_eq!(write_and_hash("mapped", &data, len), write_and_hash("unmapped", &data, None));
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mapped_writer_disjoint_ranges() {
+        let path = temp_path("ranges");
+        let mut writer = MmapWriter::create(&path, 10).unwrap();
+        writer.write_at(5, b"fghij").unwrap();
+        writer.write_at(0, b"abcde").unwrap();
+        assert_eq!(writer.finish().unwrap(), 10);
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"abcdefghij");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mapped_writer_short_body() {
+        let path = temp_path("short");
+        let mut writer = BodyWriter::create(&path, Some(100)).unwrap();
+        writer.write_all(b"only forty bytes of a hundred byte body!").unwrap();
+
+        let err = writer.finish().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 40);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mapped_writer_long_body() {
+        let path = temp_path("long");
+        let mut writer = BodyWriter::create(&path, Some(4)).unwrap();
+        let err = writer.write_all(b"too long").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,3 +1,4 @@
+pub mod body_writer;
 pub mod client;
 pub mod request;
 pub mod response;
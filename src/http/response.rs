@@ -0,0 +1,130 @@
+/// How the end of a response body is delimited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyFraming {
+    ContentLength(usize),
+    Chunked,
+    UntilClose, // No length information; the body ends when the server closes
+}
+
+/// Determine the body framing of a response with `status` to a request that was HEAD or not.
+/// RFC 9112 section 6.3: a HEAD response, 1xx, 204 and 304 never have a body, whatever the headers
+/// say, so they're framed as zero length. Otherwise the headers decide.
+pub fn body_framing(status: u16, head_request: bool, headers: &[(&str, &str)]) -> BodyFraming {
+    if head_request || (100..200).contains(&status) || status == 204 || status == 304 {
+        return BodyFraming::ContentLength(0);
+    }
+    if let Some(te) = header(headers, "Transfer-Encoding") {
+        if te.rsplit(',').next().is_some_and(|last| last.trim().eq_ignore_ascii_case("chunked")) {
+            return BodyFraming::Chunked;
+        }
+        return BodyFraming::UntilClose;
+    }
+    match header(headers, "Content-Length").map(|v| v.trim().parse::<usize>()) {
+        Some(Ok(len)) => BodyFraming::ContentLength(len),
+        _ => BodyFraming::UntilClose,
+    }
+}
+
+/// Can the connection carry another request after this response?
+///
+/// `version` and `status` are from the response status line, e.g. "HTTP/1.1" and 200, and
+/// `head_request` is whether the request was HEAD. `body_complete` is whether the framed body
+/// was read to its end (final chunk, or all Content-Length bytes).
+pub fn will_reuse(version: &str, status: u16, head_request: bool, headers: &[(&str, &str)], body_complete: bool) -> bool {
+    let connection = header(headers, "Connection").unwrap_or("");
+    let has_token = |token: &str| connection.split(',').any(|t| t.trim().eq_ignore_ascii_case(token));
+
+    let persistent = match version {
+        "HTTP/1.1" => !has_token("close"),
+        "HTTP/1.0" => has_token("keep-alive"), // HTTP/1.0 closes by default
+        _ => false,
+    };
+    if !persistent || !body_complete {
+        return false;
+    }
+
+    match body_framing(status, head_request, headers) {
+        BodyFraming::ContentLength(_) | BodyFraming::Chunked => true,
+        BodyFraming::UntilClose => false,
+    }
+}
+
+/// Case-insensitive header lookup. Returns the first match
+fn header<'a>(headers: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|&(_, v)| v)
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_body_framing() {
+        assert_eq!(body_framing(200, false, &[("Content-Length", "42")]), BodyFraming::ContentLength(42));
+        assert_eq!(body_framing(200, false, &[("transfer-encoding", "gzip, chunked")]), BodyFraming::Chunked);
+        assert_eq!(body_framing(200, false, &[("Transfer-Encoding", "gzip")]), BodyFraming::UntilClose);
+        assert_eq!(body_framing(200, false, &[("Content-Length", "abc")]), BodyFraming::UntilClose);
+        assert_eq!(body_framing(200, false, &[]), BodyFraming::UntilClose);
+
+        // Transfer-Encoding overrides Content-Length
+        let headers = [("Content-Length", "10"), ("Transfer-Encoding", "chunked")];
+        assert_eq!(body_framing(200, false, &headers), BodyFraming::Chunked);
+    }
+
+    #[test]
+    fn test_reuse_content_length_complete() {
+        assert!(will_reuse("HTTP/1.1", 200, false, &[("Content-Length", "5")], true));
+        assert!(!will_reuse("HTTP/1.1", 200, false, &[("Content-Length", "5")], false));
+    }
+
+    #[test]
+    fn test_reuse_chunked_complete() {
+        assert!(will_reuse("HTTP/1.1", 200, false, &[("Transfer-Encoding", "chunked")], true));
+        assert!(!will_reuse("HTTP/1.1", 200, false, &[("Transfer-Encoding", "chunked")], false));
+    }
+
+    #[test]
+    fn test_no_reuse_on_connection_close() {
+        let headers = [("Content-Length", "5"), ("Connection", "Close")];
+        assert!(!will_reuse("HTTP/1.1", 200, false, &headers, true));
+    }
+
+    #[test]
+    fn test_no_reuse_read_until_close() {
+        assert!(!will_reuse("HTTP/1.1", 200, false, &[], true));
+    }
+
+    #[test]
+    fn test_http10_default_close() {
+        assert!(!will_reuse("HTTP/1.0", 200, false, &[("Content-Length", "5")], true));
+
+        let headers = [("Content-Length", "5"), ("Connection", "keep-alive")];
+        assert!(will_reuse("HTTP/1.0", 200, false, &headers, true));
+    }
+
+    #[test]
+    fn test_bodyless_statuses() {
+        // No Content-Length, which would otherwise mean reading until close
+        for status in [100, 103, 204, 304] {
+            assert_eq!(body_framing(status, false, &[]), BodyFraming::ContentLength(0), "status {status}");
+            assert!(will_reuse("HTTP/1.1", status, false, &[], true), "status {status}");
+        }
+        // Even when the headers claim a body
+        assert_eq!(body_framing(304, false, &[("Content-Length", "42")]), BodyFraming::ContentLength(0));
+        assert_eq!(body_framing(204, false, &[("Transfer-Encoding", "chunked")]), BodyFraming::ContentLength(0));
+    }
+
+    #[test]
+    fn test_head_response() {
+        // Content-Length is the size a GET would get, not what follows
+        assert_eq!(body_framing(200, true, &[("Content-Length", "42")]), BodyFraming::ContentLength(0));
+        assert_eq!(body_framing(200, true, &[]), BodyFraming::ContentLength(0));
+        assert!(will_reuse("HTTP/1.1", 200, true, &[], true));
+        assert!(!will_reuse("HTTP/1.1", 200, false, &[], true));
+    }
+}
@@ -0,0 +1,29 @@
+/// The ECN codepoint in the low two bits of the TOS byte (RFC 3168)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ecn {
+    NotEct = 0b00, // Not ECN-capable
+    Ect1 = 0b01,   // ECN-capable, codepoint 1
+    Ect0 = 0b10,   // ECN-capable, codepoint 0
+    Ce = 0b11,     // Congestion experienced
+}
+
+impl Ecn {
+    /// Take the low two bits of a TOS byte
+    pub fn from_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0b00 => Ecn::NotEct,
+            0b01 => Ecn::Ect1,
+            0b10 => Ecn::Ect0,
+            _ => Ecn::Ce,
+        }
+    }
+
+    pub fn bits(self) -> u8 {
+        self as u8
+    }
+
+    /// Was the packet sent by an ECN-capable transport?
+    pub fn is_ect(self) -> bool {
+        self != Ecn::NotEct
+    }
+}
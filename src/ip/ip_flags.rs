@@ -1,4 +1,8 @@
+use crate::packet::errors::ParseFlagsError;
+use crate::tcp::tcp_flags::{parse_names, write_names};
 use bitflags::bitflags;
+use std::fmt;
+use std::str::FromStr;
 
 bitflags! {
     // Bit positions [ RF, DF, MF, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0 ]
@@ -24,11 +28,43 @@ impl IpFlags {
     }
 }
 
+/// Compact form for logs, e.g. "DF" or "DF,MF". No flags prints as "."
+impl fmt::Display for IpFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_names(f, self.iter_names().map(|(name, _)| name))
+    }
+}
+
+/// Parse "DF|MF", "df,mf" or "." for none. Case-insensitive
+impl FromStr for IpFlags {
+    type Err = ParseFlagsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_names(s)
+    }
+}
+
+/// Serialize as a list of names, e.g. `["DF"]`
+#[cfg(feature = "serde")]
+impl serde::Serialize for IpFlags {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter_names().map(|(name, _)| name))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for IpFlags {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::tcp::tcp_flags::deserialize_names(deserializer)
+    }
+}
+
 // -- Unit tests --
 
 #[cfg(test)]
 mod tests {
     use crate::ip::ip_flags::IpFlags;
+    use crate::packet::errors::ParseFlagsError;
 
     #[test]
     fn test_ip_flags() {
@@ -39,4 +75,17 @@ mod tests {
         let combined = IpFlags::RF | IpFlags::DF | IpFlags::MF;
         assert_eq!(combined.bits(), 0b1110000000000000);
     }
+
+    #[test]
+    fn test_display_and_from_str() {
+        assert_eq!(IpFlags::empty().to_string(), ".");
+        assert_eq!((IpFlags::MF | IpFlags::DF).to_string(), "DF,MF");
+        assert_eq!("df|mf".parse(), Ok(IpFlags::DF | IpFlags::MF));
+        assert_eq!("XF".parse::<IpFlags>(), Err(ParseFlagsError::UnknownFlag("XF".into())));
+
+        for bits in 0..8u16 {
+            let flags = IpFlags::from_bits_retain(bits << 13);
+            assert_eq!(flags.to_string().parse(), Ok(flags));
+        }
+    }
 }
@@ -1,11 +1,16 @@
+use crate::ip::ecn::Ecn;
 use crate::ip::ip_flags::IpFlags;
 use std::net::Ipv4Addr;
 use crate::packet::errors::HeaderError;
+use crate::packet::checksum::{self, PseudoHeader};
+use crate::packet::header_ref::IpHeaderRef;
+use crate::packet::parse_options::{ChecksumStatus, ParseOptions};
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(deny_unknown_fields))]
 pub struct IpHeader {
     pub version: u8, // Always 4 for IPv4
-    pub ihl: u8,     // Always 5 since we have no options
+    pub ihl: u8,     // Header length in 32-bit words. 5 unless there are options
     pub tos: u8,     // Always 0 when we send out, can be 8 when receiving from server
     pub total_len: u16,
     pub id: u16,
@@ -16,16 +21,27 @@ pub struct IpHeader {
     pub checksum: u16,
     pub src_ip: Ipv4Addr,
     pub dst_ip: Ipv4Addr,
+    pub options: Vec<u8>, // Raw IP options, zero padded to a multiple of 4 on serialize
 }
 
 impl IpHeader {
-    /// Serialize an `IPHeader` into a byte array of size 20.
+    /// The serialized header length: 20 bytes plus the options padded to a multiple of 4
+    pub fn header_len(&self) -> usize {
+        20 + self.options.len().next_multiple_of(4)
+    }
+
+    /// Serialize an `IPHeader` into a byte array of size `header_len()`. The IHL is derived from
+    /// the options.
     pub fn serialize(&self, buf: &mut [u8]) -> Result<usize, HeaderError> {
-        if buf.len() < 20 {
-            return Err(HeaderError::BufferTooSmall { expected: 20, found: buf.len() })
+        let header_len = self.header_len();
+        if header_len > 60 {
+            return Err(HeaderError::OptionsTooLong(self.options.len()))
+        }
+        if buf.len() < header_len {
+            return Err(HeaderError::BufferTooSmall { expected: header_len, found: buf.len() })
         }
 
-        buf[0] = (self.version << 4) | self.ihl;
+        buf[0] = (self.version << 4) | (header_len / 4) as u8;
         buf[1] = self.tos;
         buf[2..4].copy_from_slice(&self.total_len.to_be_bytes());
         buf[4..6].copy_from_slice(&self.id.to_be_bytes());
@@ -36,64 +52,73 @@ impl IpHeader {
         buf[10..12].fill(0); // Set checksum to 0 initially
         buf[12..16].copy_from_slice(&self.src_ip.octets());
         buf[16..20].copy_from_slice(&self.dst_ip.octets());
+        buf[20..20 + self.options.len()].copy_from_slice(&self.options);
+        buf[20 + self.options.len()..header_len].fill(0); // End of options list padding
 
-        let checksum = Self::checksum(&buf[0..20]);
+        let checksum = Self::checksum(&buf[0..header_len]);
         buf[10..12].copy_from_slice(&checksum.to_be_bytes());
 
-        Ok(20)
+        Ok(header_len)
     }
 
-    /// Parse a byte array into an `IPHeader`.
+    /// Parse a byte array holding a whole IPv4 packet into an `IPHeader`. Consumes `ihl * 4`
+    /// bytes, including any options. The version, IHL and total length are validated.
     pub fn parse(buf: &[u8]) -> Result<Self, HeaderError> {
-        if buf.len() < 20 {
-            return Err(HeaderError::BufferTooSmall { expected: 20, found: buf.len() })
-        }
+        IpHeaderRef::parse(buf).map(|iph| iph.to_owned())
+    }
 
-        if Self::checksum(&buf[0..20]) != 0 {
-            return Err(HeaderError::BadChecksum("IP".to_string()))
-        };
+    /// Like `parse`, but a bad checksum is only an error if `options.verify_ip_checksum` is set
+    pub fn parse_with(buf: &[u8], options: &ParseOptions) -> Result<(Self, ChecksumStatus), HeaderError> {
+        IpHeaderRef::parse_with(buf, options).map(|(iph, status)| (iph.to_owned(), status))
+    }
+
+    /// The Differentiated Services codepoint, the high six bits of `tos`
+    pub fn dscp(&self) -> u8 {
+        self.tos >> 2
+    }
+
+    pub fn set_dscp(&mut self, dscp: u8) {
+        self.tos = (dscp << 2) | (self.tos & 0b11);
+    }
+
+    /// The ECN codepoint, the low two bits of `tos`
+    pub fn ecn(&self) -> Ecn {
+        Ecn::from_bits(self.tos)
+    }
+
+    pub fn set_ecn(&mut self, ecn: Ecn) {
+        self.tos = (self.tos & !0b11) | ecn.bits();
+    }
 
-        let version = buf[0] >> 4;
-        let ihl = buf[0] & 0x0f;
-        let tos = buf[1];
-        let total_len = u16::from_be_bytes([buf[2], buf[3]]);
-        let id = u16::from_be_bytes([buf[4], buf[5]]);
-        let combo_flags = u16::from_be_bytes([buf[6], buf[7]]);
-        let (flags, frag_offset) = IpFlags::unpack(combo_flags);
-        let ttl = buf[8];
-        let protocol = buf[9];
-        let checksum = u16::from_be_bytes([buf[10], buf[11]]);
-        let src_ip = Ipv4Addr::new(buf[12], buf[13], buf[14], buf[15]);
-        let dst_ip = Ipv4Addr::new(buf[16], buf[17], buf[18], buf[19]);
-
-        Ok(IpHeader {
-            version,
-            ihl,
-            tos,
-            total_len,
-            id,
-            flags,
-            frag_offset,
-            ttl,
-            protocol,
-            checksum,
-            src_ip,
-            dst_ip,
-        })
+    /// Set the TTL of an already-serialized packet in `buf`, fixing the header checksum
+    /// incrementally (RFC 1624) instead of summing the whole header again
+    pub fn patch_ttl(buf: &mut [u8], ttl: u8) -> Result<(), HeaderError> {
+        // TTL shares its 16-bit word with the protocol
+        let protocol = *buf.get(9).ok_or(HeaderError::BufferTooSmall { expected: 20, found: buf.len() })?;
+        checksum::patch(buf, 10, 8, &[ttl, protocol])
+    }
+
+    /// Set the identification of an already-serialized packet in `buf`, fixing the header
+    /// checksum incrementally
+    pub fn patch_id(buf: &mut [u8], id: u16) -> Result<(), HeaderError> {
+        checksum::patch(buf, 10, 4, &id.to_be_bytes())
     }
 
     /// Compute the checksum for an `IPHeader` (Ipv4).
     /// Wiki: https://en.wikipedia.org/wiki/IPv4_header_checksum.
     pub fn checksum(data: &[u8]) -> u16 {
-        // Sum every 2 bytes as a 16-bit value
-        let sum: u32 = data
-            .chunks(2)
-            .map(|chunk| u16::from_be_bytes([chunk[0], chunk[1]]) as u32)
-            .sum();
+        checksum::checksum(data)
+    }
+}
 
-        // Fold the carry bits
-        let folded = (sum & 0xffff) + (sum >> 16);
-        !(folded as u16)
+impl PseudoHeader for IpHeader {
+    fn segment_len(&self) -> Option<usize> {
+        (self.total_len as usize).checked_sub(self.ihl as usize * 4)
+    }
+
+    /// RFC 793: addresses, zero, protocol, 16-bit TCP length
+    fn pseudo_header_sum(&self, segment_len: usize) -> u64 {
+        checksum::sum(&self.src_ip.octets()) + checksum::sum(&self.dst_ip.octets()) + self.protocol as u64 + segment_len as u64
     }
 }
 
@@ -112,6 +137,7 @@ impl Default for IpHeader {
             checksum: 0,
             src_ip: Ipv4Addr::new(0,0,0,0),
             dst_ip: Ipv4Addr::new(0,0,0,0),
+            options: Vec::new(),
         }
     }
 }
@@ -138,6 +164,7 @@ mod tests {
             checksum: 54134,
             src_ip: Ipv4Addr::new(10, 110, 208, 106),
             dst_ip: Ipv4Addr::new(204, 44, 192, 60),
+            options: Vec::new(),
         };
 
         let mut buf = vec![0u8; 64];
@@ -153,8 +180,7 @@ mod tests {
 
     #[test]
     fn test_ip_header_from_bytes() {
-        let ip_bytes = hex::decode(test_utils::get_ip_hex()).unwrap();
-        let iph = IpHeader::parse(&ip_bytes).unwrap();
+        let iph = test_utils::get_ip_header();
 
         assert_eq!(iph.version, 4);
         assert_eq!(iph.ihl, 5);
@@ -169,4 +195,115 @@ mod tests {
         assert_eq!(iph.src_ip, Ipv4Addr::new(10, 110, 208, 106));
         assert_eq!(iph.dst_ip, Ipv4Addr::new(204, 44, 192, 60));
     }
+
+    /// A header carrying a Router Alert option (RFC 2113): type 0x94, length 4, value 0
+    fn router_alert_header() -> IpHeader {
+        IpHeader {
+            version: 4,
+            ihl: 6,
+            total_len: 24,
+            ttl: 1,
+            protocol: 2,
+            src_ip: Ipv4Addr::new(10, 0, 0, 1),
+            dst_ip: Ipv4Addr::new(224, 0, 0, 22),
+            options: vec![0x94, 0x04, 0x00, 0x00],
+            ..IpHeader::default()
+        }
+    }
+
+    #[test]
+    fn test_ip_header_options_roundtrip() {
+        let header = router_alert_header();
+        let mut buf = vec![0u8; 24];
+        assert_eq!(header.serialize(&mut buf).unwrap(), 24);
+        assert_eq!(buf[0], 0x46); // IHL 6
+        assert_eq!(&buf[20..24], &[0x94, 0x04, 0x00, 0x00]);
+        assert_eq!(IpHeader::checksum(&buf), 0);
+
+        let parsed = IpHeader::parse(&buf).unwrap();
+        assert_eq!(parsed.ihl, 6);
+        assert_eq!(parsed.options, header.options);
+        assert_eq!(parsed.src_ip, header.src_ip);
+    }
+
+    #[test]
+    fn test_ip_header_options_padded() {
+        let header = IpHeader {
+            version: 4,
+            total_len: 24,
+            options: vec![0x01, 0x01, 0x01], // Three NOPs
+            ..IpHeader::default()
+        };
+        assert_eq!(header.header_len(), 24);
+
+        let mut buf = vec![0xffu8; 24];
+        header.serialize(&mut buf).unwrap();
+        assert_eq!(&buf[20..24], &[0x01, 0x01, 0x01, 0x00]);
+        assert_eq!(IpHeader::parse(&buf).unwrap().options, vec![0x01, 0x01, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn test_ip_header_options_errors() {
+        let header = router_alert_header();
+        let mut buf = vec![0u8; 24];
+        assert_eq!(
+            header.serialize(&mut buf[..20]),
+            Err(HeaderError::BufferTooSmall { expected: 24, found: 20 })
+        );
+
+        header.serialize(&mut buf).unwrap();
+        assert_eq!(IpHeader::parse(&buf[..22]), Err(HeaderError::BufferTooSmall { expected: 24, found: 22 }));
+
+        buf[22] ^= 0xff; // Options are covered by the checksum
+        assert!(matches!(IpHeader::parse(&buf), Err(HeaderError::BadChecksum(_))));
+
+
+        let too_long = IpHeader { options: vec![1; 41], ..IpHeader::default() };
+        assert_eq!(too_long.serialize(&mut [0u8; 64]), Err(HeaderError::OptionsTooLong(41)));
+    }
+
+    #[test]
+    fn test_ip_header_dscp_ecn() {
+        let mut header = test_utils::get_ip_header();
+        header.set_dscp(46); // Expedited Forwarding
+        assert_eq!(header.tos, 0xb8);
+
+        for ecn in [Ecn::NotEct, Ecn::Ect1, Ecn::Ect0, Ecn::Ce] {
+            header.set_ecn(ecn);
+            let mut buf = vec![0u8; 64];
+            header.serialize(&mut buf).unwrap();
+            assert_eq!(buf[1], 0xb8 | ecn.bits());
+
+            let parsed = IpHeader::parse(&buf).unwrap();
+            assert_eq!(parsed.ecn(), ecn);
+            assert_eq!(parsed.dscp(), 46);
+        }
+    }
+
+    #[test]
+    fn test_ip_header_patch_ttl_and_id() {
+        let mut buf = hex::decode(test_utils::get_ip_hex()).unwrap();
+        buf.extend(hex::decode(test_utils::get_tcp_hex()).unwrap());
+
+        // Every TTL, including the ones that wrap the checksum's carry
+        for ttl in (0..=u8::MAX).rev().chain(0..=u8::MAX) {
+            IpHeader::patch_ttl(&mut buf, ttl).unwrap();
+            assert_eq!(IpHeader::checksum(&buf[..20]), 0, "ttl {ttl}");
+        }
+        for id in [1, 0x7fff, 0x8000, 0xfffe, 0xffff, 0, 0x1234] {
+            IpHeader::patch_id(&mut buf, id).unwrap();
+            assert_eq!(IpHeader::checksum(&buf[..20]), 0, "id {id:#06x}");
+        }
+
+        let iph = IpHeader::parse(&buf).unwrap();
+        assert_eq!((iph.ttl, iph.id, iph.protocol), (u8::MAX, 0x1234, 6));
+
+        // Matches a full re-serialization
+        let mut expected = [0u8; 20];
+        iph.serialize(&mut expected).unwrap();
+        assert_eq!(buf[..20], expected);
+
+        assert_eq!(IpHeader::patch_ttl(&mut buf[..9], 1), Err(HeaderError::BufferTooSmall { expected: 20, found: 9 }));
+        assert_eq!(IpHeader::patch_id(&mut buf[..11], 1), Err(HeaderError::BufferTooSmall { expected: 12, found: 11 }));
+    }
 }
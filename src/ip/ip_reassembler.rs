@@ -0,0 +1,324 @@
+use crate::ip::ip_flags::IpFlags;
+use crate::ip::ip_header::IpHeader;
+use crate::packet::errors::HeaderError;
+use std::collections::{BTreeMap, HashMap};
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
+
+/// Linux's `ipfrag_time`
+pub const DEFAULT_FRAGMENT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Linux's `ipfrag_high_thresh`
+pub const DEFAULT_MAX_BUFFERED_BYTES: usize = 4 * 1024 * 1024;
+
+/// The fields that tie the fragments of one datagram together (RFC 791)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FragmentKey {
+    pub src_ip: Ipv4Addr,
+    pub dst_ip: Ipv4Addr,
+    pub id: u16,
+    pub protocol: u8,
+}
+
+impl From<&IpHeader> for FragmentKey {
+    fn from(iph: &IpHeader) -> Self {
+        FragmentKey { src_ip: iph.src_ip, dst_ip: iph.dst_ip, id: iph.id, protocol: iph.protocol }
+    }
+}
+
+/// Reassembly counters
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReassemblyStats {
+    pub fragments_received: u64,
+    pub datagrams_reassembled: u64,
+    pub timeouts: u64,        // Datagrams discarded because a fragment never arrived
+    pub cap_drops: u64,       // Fragments dropped because the buffer cap was reached
+    pub malformed_drops: u64, // Fragments that can't belong to a valid datagram
+}
+
+/// The fragments of one datagram received so far
+#[derive(Debug)]
+struct PendingDatagram {
+    header: Option<IpHeader>,            // From the fragment at offset 0
+    fragments: BTreeMap<usize, Vec<u8>>, // Byte offset -> fragment data
+    payload_len: Option<usize>,          // Known once the last fragment (MF clear) arrives
+    buffered: usize,
+    first_seen: Instant,
+}
+
+impl PendingDatagram {
+    /// The first fragment's header and the payload, once every byte up to `payload_len` is in
+    fn assemble(&self) -> Option<(IpHeader, Vec<u8>)> {
+        let payload_len = self.payload_len?;
+        let header = self.header.clone()?;
+
+        let mut payload = vec![0u8; payload_len];
+        let mut covered = 0;
+        for (&offset, data) in &self.fragments {
+            if offset > covered {
+                return None; // A hole
+            }
+            let end = offset + data.len();
+            if end > covered {
+                payload[covered..end].copy_from_slice(&data[covered - offset..]);
+                covered = end;
+            }
+        }
+        (covered == payload_len).then_some((header, payload))
+    }
+}
+
+/// Buffers inbound IPv4 fragments until their datagram is complete
+#[derive(Debug)]
+pub struct IpReassembler {
+    pending: HashMap<FragmentKey, PendingDatagram>,
+    timeout: Duration,   // How long a datagram may wait for its missing fragments
+    max_buffered: usize, // Cap on fragment bytes across all datagrams
+    buffered: usize,
+    stats: ReassemblyStats,
+}
+
+impl IpReassembler {
+    pub fn new(timeout: Duration, max_buffered: usize) -> Self {
+        IpReassembler {
+            pending: HashMap::new(),
+            timeout,
+            max_buffered,
+            buffered: 0,
+            stats: ReassemblyStats::default(),
+        }
+    }
+
+    /// Feed one IP packet received at `now`. Unfragmented packets pass straight through. Returns the
+    /// datagram's header and payload once all of its fragments are in. The returned header
+    /// describes the whole datagram, so the payload can go to `TcpHeader::parse` as usual.
+    pub fn push(&mut self, packet: &[u8], now: Instant) -> Result<Option<(IpHeader, Vec<u8>)>, HeaderError> {
+        self.expire(now);
+
+        let iph = IpHeader::parse(packet)?;
+        let data = &packet[iph.header_len()..iph.total_len as usize];
+        let more_fragments = iph.flags.contains(IpFlags::MF);
+        if !more_fragments && iph.frag_offset == 0 {
+            return Ok(Some((iph, data.to_vec())));
+        }
+
+        self.stats.fragments_received += 1;
+        let offset = iph.frag_offset as usize * 8;
+        let end = offset + data.len();
+        // All but the last fragment carry a multiple of 8 bytes, and no datagram exceeds 64 KB
+        if (more_fragments && !data.len().is_multiple_of(8)) || end + iph.header_len() > u16::MAX as usize {
+            self.stats.malformed_drops += 1;
+            return Ok(None);
+        }
+        if self.buffered + data.len() > self.max_buffered {
+            self.stats.cap_drops += 1;
+            return Ok(None);
+        }
+
+        let key = FragmentKey::from(&iph);
+        let datagram = self.pending.entry(key).or_insert_with(|| PendingDatagram {
+            header: None,
+            fragments: BTreeMap::new(),
+            payload_len: None,
+            buffered: 0,
+            first_seen: now,
+        });
+
+        // A second last fragment disagreeing on the length, or data past the end, is bogus
+        let payload_len = if more_fragments { datagram.payload_len } else { Some(end) };
+        let buffered_end = datagram.fragments.iter().map(|(&offset, data)| offset + data.len()).max().unwrap_or(0);
+        let conflicting = datagram.payload_len.is_some_and(|len| Some(len) != payload_len);
+        if conflicting || payload_len.is_some_and(|len| end > len || buffered_end > len) {
+            self.stats.malformed_drops += 1;
+            return Ok(None);
+        }
+        datagram.payload_len = payload_len;
+        if offset == 0 {
+            datagram.header = Some(iph.clone());
+        }
+
+        // Keep the longer copy if a fragment is retransmitted at the same offset
+        let previous = datagram.fragments.get(&offset).map_or(0, Vec::len);
+        if data.len() > previous {
+            datagram.fragments.insert(offset, data.to_vec());
+            datagram.buffered += data.len() - previous;
+            self.buffered += data.len() - previous;
+        }
+
+        let Some((mut header, payload)) = datagram.assemble() else {
+            return Ok(None);
+        };
+        self.buffered -= datagram.buffered;
+        self.pending.remove(&key);
+        self.stats.datagrams_reassembled += 1;
+
+        header.total_len = (header.header_len() + payload.len()) as u16;
+        header.flags.remove(IpFlags::MF);
+        Ok(Some((header, payload)))
+    }
+
+    /// Discard datagrams that have waited longer than the timeout. Returns how many were dropped
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let timeout = self.timeout;
+        let before = self.pending.len();
+        let mut freed = 0;
+        self.pending.retain(|_, datagram| {
+            let alive = now.saturating_duration_since(datagram.first_seen) < timeout;
+            if !alive {
+                freed += datagram.buffered;
+            }
+            alive
+        });
+
+        let expired = before - self.pending.len();
+        self.buffered -= freed;
+        self.stats.timeouts += expired as u64;
+        expired
+    }
+
+    /// Fragment bytes waiting for the rest of their datagram
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered
+    }
+
+    /// Datagrams with at least one fragment missing
+    pub fn pending_datagrams(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn stats(&self) -> &ReassemblyStats {
+        &self.stats
+    }
+}
+
+impl Default for IpReassembler {
+    fn default() -> Self {
+        Self::new(DEFAULT_FRAGMENT_TIMEOUT, DEFAULT_MAX_BUFFERED_BYTES)
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet;
+    use crate::packet::test_utils;
+    use crate::tcp::tcp_header::TcpHeader;
+
+    /// The giant_payload packet, split into three fragments
+    fn giant_fragments() -> (Vec<u8>, Vec<Vec<u8>>) {
+        let ip_bytes = hex::decode(test_utils::get_ip_hex_with_payload()).unwrap();
+        let tcp_bytes = hex::decode(test_utils::get_tcp_hex_with_payload()).unwrap();
+        let payload = hex::decode(test_utils::giant_payload()).unwrap();
+        let packet = [ip_bytes, tcp_bytes, payload].concat();
+
+        let (mut iph, tcph) = packet::unwrap(&packet).unwrap();
+        iph.flags.remove(IpFlags::DF);
+        let fragments = packet::wrap_fragmented(&iph, &tcph, 600).unwrap();
+        assert_eq!(fragments.len(), 3);
+        (packet, fragments)
+    }
+
+    #[test]
+    fn test_reassemble_out_of_order() {
+        let (packet, fragments) = giant_fragments();
+        let mut defrag = IpReassembler::default();
+        let now = Instant::now();
+
+        assert_eq!(defrag.push(&fragments[2], now).unwrap(), None);
+        assert_eq!(defrag.push(&fragments[0], now).unwrap(), None);
+        assert_eq!(defrag.pending_datagrams(), 1);
+        assert_eq!(defrag.buffered_bytes(), 576 + 254);
+
+        let (iph, segment) = defrag.push(&fragments[1], now).unwrap().unwrap();
+        assert_eq!(segment, packet[20..]);
+        assert_eq!(iph.total_len, 1426);
+        assert_eq!(iph.frag_offset, 0);
+        assert!(!iph.flags.contains(IpFlags::MF));
+
+        // The reassembled segment passes the TCP checksum
+        let (_, expected) = packet::unwrap(&packet).unwrap();
+        assert_eq!(TcpHeader::parse(&segment, &iph).unwrap(), expected);
+
+        assert_eq!(defrag.pending_datagrams(), 0);
+        assert_eq!(defrag.buffered_bytes(), 0);
+        assert_eq!(defrag.stats().fragments_received, 3);
+        assert_eq!(defrag.stats().datagrams_reassembled, 1);
+    }
+
+    #[test]
+    fn test_unfragmented_passes_through() {
+        let (packet, _) = giant_fragments();
+        let (iph, segment) = IpReassembler::default().push(&packet, Instant::now()).unwrap().unwrap();
+        assert_eq!(iph.total_len, 1426);
+        assert_eq!(segment, packet[20..]);
+    }
+
+    #[test]
+    fn test_duplicate_fragments() {
+        let (packet, fragments) = giant_fragments();
+        let mut defrag = IpReassembler::default();
+        let now = Instant::now();
+
+        for i in [0, 0, 2, 2] {
+            assert_eq!(defrag.push(&fragments[i], now).unwrap(), None);
+        }
+        assert_eq!(defrag.buffered_bytes(), 576 + 254);
+        let (_, segment) = defrag.push(&fragments[1], now).unwrap().unwrap();
+        assert_eq!(segment, packet[20..]);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let (_, fragments) = giant_fragments();
+        let mut defrag = IpReassembler::new(Duration::from_secs(30), DEFAULT_MAX_BUFFERED_BYTES);
+        let t0 = Instant::now();
+
+        defrag.push(&fragments[0], t0).unwrap();
+        defrag.push(&fragments[1], t0 + Duration::from_secs(29)).unwrap();
+        assert_eq!(defrag.expire(t0 + Duration::from_secs(29)), 0);
+
+        // The last fragment is too late, and starts a new datagram on its own
+        assert_eq!(defrag.push(&fragments[2], t0 + Duration::from_secs(30)).unwrap(), None);
+        assert_eq!(defrag.stats().timeouts, 1);
+        assert_eq!(defrag.pending_datagrams(), 1);
+        assert_eq!(defrag.buffered_bytes(), 254);
+    }
+
+    #[test]
+    fn test_buffer_cap() {
+        let (_, fragments) = giant_fragments();
+        let mut defrag = IpReassembler::new(DEFAULT_FRAGMENT_TIMEOUT, 1000);
+        let now = Instant::now();
+
+        defrag.push(&fragments[0], now).unwrap();
+        defrag.push(&fragments[1], now).unwrap(); // 576 + 576 > 1000
+        assert_eq!(defrag.stats().cap_drops, 1);
+        assert_eq!(defrag.buffered_bytes(), 576);
+    }
+
+    #[test]
+    fn test_malformed_fragments() {
+        let (_, fragments) = giant_fragments();
+        let mut defrag = IpReassembler::default();
+        let now = Instant::now();
+
+        // A middle fragment whose length isn't a multiple of 8
+        let mut iph = IpHeader::parse(&fragments[1]).unwrap();
+        iph.total_len -= 1;
+        let mut short = fragments[1][..fragments[1].len() - 1].to_vec();
+        iph.serialize(&mut short).unwrap();
+        assert_eq!(defrag.push(&short, now).unwrap(), None);
+
+        // Data past the end set by the last fragment
+        defrag.push(&fragments[2], now).unwrap();
+        let mut iph = IpHeader::parse(&fragments[1]).unwrap();
+        iph.frag_offset = 200;
+        let mut beyond = fragments[1].clone();
+        iph.serialize(&mut beyond).unwrap();
+        assert_eq!(defrag.push(&beyond, now).unwrap(), None);
+
+        assert_eq!(defrag.stats().malformed_drops, 2);
+        assert_eq!(defrag.buffered_bytes(), 254);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::packet::errors::HeaderError;
+use crate::packet::checksum::{self, PseudoHeader};
+use std::net::Ipv6Addr;
+
+/// The fixed 40-byte IPv6 header. Extension headers are not supported
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ipv6Header {
+    pub version: u8,       // Always 6 for IPv6
+    pub traffic_class: u8, // DSCP and ECN, like the IPv4 TOS byte
+    pub flow_label: u32,   // 20 bits
+    pub payload_len: u16,  // Bytes after this header
+    pub next_header: u8,   // 6 for TCP
+    pub hop_limit: u8,     // Always 64 when we send out
+    pub src_ip: Ipv6Addr,
+    pub dst_ip: Ipv6Addr,
+}
+
+impl Ipv6Header {
+    pub const LEN: usize = 40;
+
+    /// Serialize an `Ipv6Header` into a byte array of size 40.
+    pub fn serialize(&self, buf: &mut [u8]) -> Result<usize, HeaderError> {
+        if buf.len() < Self::LEN {
+            return Err(HeaderError::BufferTooSmall { expected: Self::LEN, found: buf.len() })
+        }
+
+        let word = (self.version as u32) << 28 | (self.traffic_class as u32) << 20 | (self.flow_label & 0xfffff);
+        buf[0..4].copy_from_slice(&word.to_be_bytes());
+        buf[4..6].copy_from_slice(&self.payload_len.to_be_bytes());
+        buf[6] = self.next_header;
+        buf[7] = self.hop_limit;
+        buf[8..24].copy_from_slice(&self.src_ip.octets());
+        buf[24..40].copy_from_slice(&self.dst_ip.octets());
+
+        Ok(Self::LEN)
+    }
+
+    /// Parse a byte array holding a whole IPv6 packet into an `Ipv6Header`.
+    pub fn parse(buf: &[u8]) -> Result<Self, HeaderError> {
+        if buf.len() < Self::LEN {
+            return Err(HeaderError::BufferTooSmall { expected: Self::LEN, found: buf.len() })
+        }
+
+        let word = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        let version = (word >> 28) as u8;
+        if version != 6 {
+            return Err(HeaderError::InvalidVersion(version))
+        }
+
+        let payload_len = u16::from_be_bytes([buf[4], buf[5]]);
+        let total_len = Self::LEN + payload_len as usize;
+        if total_len > buf.len() {
+            return Err(HeaderError::TruncatedPacket { claimed: total_len, actual: buf.len() })
+        }
+
+        let mut src = [0u8; 16];
+        let mut dst = [0u8; 16];
+        src.copy_from_slice(&buf[8..24]);
+        dst.copy_from_slice(&buf[24..40]);
+
+        Ok(Ipv6Header {
+            version,
+            traffic_class: (word >> 20) as u8,
+            flow_label: word & 0xfffff,
+            payload_len,
+            next_header: buf[6],
+            hop_limit: buf[7],
+            src_ip: Ipv6Addr::from(src),
+            dst_ip: Ipv6Addr::from(dst),
+        })
+    }
+}
+
+impl Default for Ipv6Header {
+    fn default() -> Self {
+        Ipv6Header {
+            version: 6,
+            traffic_class: 0,
+            flow_label: 0,
+            payload_len: 0,
+            next_header: 6,
+            hop_limit: 64,
+            src_ip: Ipv6Addr::UNSPECIFIED,
+            dst_ip: Ipv6Addr::UNSPECIFIED,
+        }
+    }
+}
+
+impl PseudoHeader for Ipv6Header {
+    fn segment_len(&self) -> Option<usize> {
+        Some(self.payload_len as usize)
+    }
+
+    /// RFC 8200 section 8.1: addresses, 32-bit upper-layer length, zeros, next header
+    fn pseudo_header_sum(&self, segment_len: usize) -> u64 {
+        checksum::sum(&self.src_ip.octets())
+            + checksum::sum(&self.dst_ip.octets())
+            + checksum::sum(&(segment_len as u32).to_be_bytes())
+            + self.next_header as u64
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::test_utils;
+
+    #[test]
+    fn test_ipv6_header_from_bytes() {
+        let packet = hex::decode(test_utils::get_ipv6_syn_hex()).unwrap();
+        let ip6h = Ipv6Header::parse(&packet).unwrap();
+
+        assert_eq!(ip6h.version, 6);
+        assert_eq!(ip6h.traffic_class, 0);
+        assert_eq!(ip6h.flow_label, 0x33b72);
+        assert_eq!(ip6h.payload_len, 40);
+        assert_eq!(ip6h.next_header, 6);
+        assert_eq!(ip6h.hop_limit, 64);
+        assert_eq!(ip6h.src_ip, Ipv6Addr::LOCALHOST);
+        assert_eq!(ip6h.dst_ip, Ipv6Addr::LOCALHOST);
+    }
+
+    #[test]
+    fn test_ipv6_header_roundtrip() {
+        let packet = hex::decode(test_utils::get_ipv6_syn_hex()).unwrap();
+        let ip6h = Ipv6Header::parse(&packet).unwrap();
+
+        let mut buf = [0u8; 40];
+        assert_eq!(ip6h.serialize(&mut buf).unwrap(), 40);
+        assert_eq!(buf, packet[..40]);
+    }
+
+    #[test]
+    fn test_ipv6_header_errors() {
+        let packet = hex::decode(test_utils::get_ipv6_syn_hex()).unwrap();
+        assert_eq!(
+            Ipv6Header::parse(&packet[..39]),
+            Err(HeaderError::BufferTooSmall { expected: 40, found: 39 })
+        );
+        assert_eq!(
+            Ipv6Header::parse(&packet[..70]),
+            Err(HeaderError::TruncatedPacket { claimed: 80, actual: 70 })
+        );
+
+        let mut v4 = packet.clone();
+        v4[0] = 0x45;
+        assert_eq!(Ipv6Header::parse(&v4), Err(HeaderError::InvalidVersion(4)));
+    }
+}
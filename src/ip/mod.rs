@@ -1,2 +1,5 @@
+pub mod ecn;
 pub mod ip_flags;
 pub mod ip_header;
+pub mod ip_reassembler;
+pub mod ipv6_header;
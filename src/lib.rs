@@ -1,7 +1,8 @@
 pub mod datalink;
 pub mod http;
 pub mod ip;
-mod packet;
+pub mod metrics;
+pub mod packet;
 pub mod router;
 pub mod socket;
 pub mod tcp;
@@ -0,0 +1,184 @@
+use crate::metrics::registry::{global, Registry};
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How long a scraper may take to send its request or read the response
+pub const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Render the global registry in the Prometheus text exposition format
+pub fn render_prometheus() -> String {
+    match global().lock() {
+        Ok(registry) => registry.render(),
+        Err(poisoned) => poisoned.into_inner().render(),
+    }
+}
+
+/// Serve the registry over plain HTTP on `listener`. Blocks forever.
+/// This endpoint uses the kernel TCP stack and is out-of-band from the raw socket stack.
+pub fn serve(listener: &TcpListener, registry: &Mutex<Registry>) -> io::Result<()> {
+    serve_with_timeout(listener, registry, SCRAPE_TIMEOUT)
+}
+
+/// Failures the exporter survived, counted in `metrics_exporter_errors_total`
+#[derive(Debug, Default)]
+struct ExporterErrors {
+    accept: u64,     // Transient accept errors like EMFILE or ECONNABORTED
+    connection: u64, // Scrapes that failed or timed out partway
+}
+
+/// Like `serve`, with each connection given `timeout` to send its request and to take the
+/// response, so a stalled scraper can't hold up the ones behind it. A zero `timeout` means none.
+pub fn serve_with_timeout(listener: &TcpListener, registry: &Mutex<Registry>, timeout: Duration) -> io::Result<()> {
+    let timeout = Some(timeout).filter(|timeout| !timeout.is_zero());
+    let mut errors = ExporterErrors::default();
+    for stream in listener.incoming() {
+        // A misbehaving scraper or a failed accept only costs that connection, and is counted
+        match stream.map(|stream| scrape(stream, registry, timeout)) {
+            Ok(Ok(())) => continue,
+            Ok(Err(_)) => errors.connection += 1,
+            Err(_) => errors.accept += 1,
+        }
+        record_errors(registry, &errors);
+    }
+    Ok(())
+}
+
+/// Answer one scraper within `timeout`
+fn scrape(stream: TcpStream, registry: &Mutex<Registry>, timeout: Option<Duration>) -> io::Result<()> {
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+    handle_connection(stream, registry)
+}
+
+/// Publish the exporter's own error counts, so the next scrape shows them
+fn record_errors(registry: &Mutex<Registry>, errors: &ExporterErrors) {
+    let mut registry = match registry.lock() {
+        Ok(registry) => registry,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let help = "Scrapes the metrics exporter failed to serve";
+    for (stage, count) in [("accept", errors.accept), ("connection", errors.connection)] {
+        // Only fails if something else registered the name as a gauge
+        let _ = registry.counter("metrics_exporter_errors_total", help, &[("stage", stage)], count);
+    }
+}
+
+/// Answer a single scrape request with the rendered registry
+pub fn handle_connection(mut stream: TcpStream, registry: &Mutex<Registry>) -> io::Result<()> {
+    // Consume the request headers. Every path gets the metrics
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line == "\r\n" || line == "\n" {
+            break;
+        }
+    }
+
+    let body = match registry.lock() {
+        Ok(registry) => registry.render(),
+        Err(poisoned) => poisoned.into_inner().render(),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\n\
+        Content-Type: text/plain; version=0.0.4\r\n\
+        Content-Length: {}\r\n\
+        Connection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::thread;
+
+    #[test]
+    fn test_render_prometheus_global() {
+        global()
+            .lock()
+            .unwrap()
+            .counter("exporter_test_total", "Test counter", &[("conn", "9")], 3)
+            .unwrap();
+        assert!(render_prometheus().contains("exporter_test_total{conn=\"9\"} 3\n"));
+    }
+
+    #[test]
+    fn test_http_endpoint() {
+        let mut registry = Registry::new();
+        registry.counter("tcp_segments_sent_total", "Segments sent", &[("conn", "3")], 12).unwrap();
+        let expected_body = registry.render();
+        let registry = Mutex::new(registry);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, &registry).unwrap();
+        });
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        server.join().unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}", expected_body.len())));
+        assert_eq!(body, expected_body);
+    }
+
+    #[test]
+    fn test_stalled_scraper_times_out() {
+        let mut registry = Registry::new();
+        registry.counter("x_total", "x", &[], 1).unwrap();
+        let registry: &'static Mutex<Registry> = Box::leak(Box::new(Mutex::new(registry)));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve_with_timeout(&listener, registry, Duration::from_millis(100)));
+
+        // Connects and never sends a request
+        let _stalled = TcpStream::connect(addr).unwrap();
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.ends_with("x_total 1\n"));
+
+        // The stalled scrape was counted before this one was answered
+        assert!(response.contains("metrics_exporter_errors_total{stage=\"connection\"} 1\n"));
+        assert!(response.contains("metrics_exporter_errors_total{stage=\"accept\"} 0\n"));
+    }
+
+    #[test]
+    fn test_zero_timeout_keeps_serving() {
+        let registry: &'static Mutex<Registry> = Box::leak(Box::new(Mutex::new(Registry::new())));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve_with_timeout(&listener, registry, Duration::ZERO));
+
+        // No timeout rather than an error that ends the loop
+        for _ in 0..2 {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            client.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        }
+    }
+}
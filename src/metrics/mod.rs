@@ -0,0 +1,9 @@
+pub mod exporter;
+pub mod registry;
+
+// -- Re-export public structs --
+
+pub use crate::metrics::exporter::render_prometheus;
+pub use crate::metrics::registry::global;
+pub use crate::metrics::registry::MetricError;
+pub use crate::metrics::registry::Registry;
@@ -0,0 +1,241 @@
+use crate::tcp::receiver::ReceiverStats;
+use crate::tcp::sender::SenderStats;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+}
+
+#[derive(Debug, PartialEq, Error)]
+pub enum MetricError {
+    #[error("Metric {name} is a {registered:?}, not a {requested:?}")]
+    KindMismatch { name: String, registered: MetricKind, requested: MetricKind },
+}
+
+#[derive(Debug)]
+struct Metric {
+    kind: MetricKind,
+    help: String,
+    samples: BTreeMap<String, f64>, // key = rendered label set, e.g. `{conn="3"}`
+}
+
+/// A registry of named counters and gauges. Names are kept sorted for stable output.
+#[derive(Debug, Default)]
+pub struct Registry {
+    metrics: BTreeMap<String, Metric>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// Set a counter sample to `value`. Fails if `name` is already registered as a gauge
+    pub fn counter(&mut self, name: &str, help: &str, labels: &[(&str, &str)], value: u64) -> Result<(), MetricError> {
+        self.set(name, help, MetricKind::Counter, labels, value as f64)
+    }
+
+    /// Set a gauge sample to `value`. Fails if `name` is already registered as a counter
+    pub fn gauge(&mut self, name: &str, help: &str, labels: &[(&str, &str)], value: f64) -> Result<(), MetricError> {
+        self.set(name, help, MetricKind::Gauge, labels, value)
+    }
+
+    /// Record every `ReceiverStats` counter for the connection labelled `conn`
+    pub fn record_receiver_stats(&mut self, conn: &str, stats: &ReceiverStats) -> Result<(), MetricError> {
+        let labels = [("conn", conn)];
+        let counters = [
+            ("tcp_segments_received_total", "Segments handed to the receiver", stats.segments_received),
+            ("tcp_bytes_delivered_total", "Bytes delivered in order", stats.bytes_delivered),
+            ("tcp_duplicate_segments_total", "Segments carrying no new data", stats.duplicate_segments),
+            ("tcp_out_of_order_segments_total", "Segments buffered ahead of a gap", stats.out_of_order_segments),
+            ("tcp_bad_checksum_drops_total", "Packets dropped for a bad checksum", stats.bad_checksum_drops),
+            ("tcp_out_of_window_drops_total", "Segments beyond the receive window", stats.out_of_window_drops),
+            ("tcp_inconsistent_bytes_total", "Overlapping bytes that differed", stats.inconsistent_bytes),
+            ("tcp_syn_received_total", "SYN segments received", stats.syn_count),
+            ("tcp_duplicate_syns_total", "Retransmitted SYNs with the known ISN", stats.duplicate_syns),
+            ("tcp_conflicting_syns_total", "SYNs with a conflicting ISN", stats.conflicting_syns),
+            ("tcp_fin_received_total", "FIN segments received", stats.fin_count),
+            ("tcp_segment_cap_drops_total", "Out-of-order segments over the segment cap", stats.segment_cap_drops),
+            ("tcp_window_updates_total", "ACKs requested because a read reopened the window", stats.window_updates),
+        ];
+
+        for (name, help, value) in counters {
+            self.counter(name, help, &labels, value)?;
+        }
+        Ok(())
+    }
+
+    /// Record every `SenderStats` counter for the connection labelled `conn`
+    pub fn record_sender_stats(&mut self, conn: &str, stats: &SenderStats) -> Result<(), MetricError> {
+        let labels = [("conn", conn)];
+        self.counter("tcp_segments_sent_total", "Segments sent", &labels, stats.segments_sent)?;
+        self.counter("tcp_bytes_sent_total", "Payload bytes sent", &labels, stats.bytes_sent)
+    }
+
+    /// Remove every metric from the registry
+    pub fn clear(&mut self) {
+        self.metrics.clear();
+    }
+
+    /// Render the registry in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, metric) in &self.metrics {
+            let kind = match metric.kind {
+                MetricKind::Counter => "counter",
+                MetricKind::Gauge => "gauge",
+            };
+            let _ = writeln!(out, "# HELP {name} {}", metric.help);
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            for (labels, value) in &metric.samples {
+                let _ = writeln!(out, "{name}{labels} {}", render_value(*value));
+            }
+        }
+        out
+    }
+
+    fn set(&mut self, name: &str, help: &str, kind: MetricKind, labels: &[(&str, &str)], value: f64) -> Result<(), MetricError> {
+        let metric = self.metrics.entry(name.to_string()).or_insert_with(|| Metric {
+            kind,
+            help: help.to_string(),
+            samples: BTreeMap::new(),
+        });
+        if metric.kind != kind {
+            return Err(MetricError::KindMismatch { name: name.to_string(), registered: metric.kind, requested: kind });
+        }
+        metric.samples.insert(render_labels(labels), value);
+        Ok(())
+    }
+}
+
+/// The process-wide registry
+pub fn global() -> &'static Mutex<Registry> {
+    static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(Registry::new()))
+}
+
+/// Render a sample value. Prometheus spells the non-finite ones `+Inf`, `-Inf` and `NaN`
+fn render_value(value: f64) -> String {
+    match value {
+        f64::INFINITY => "+Inf".to_string(),
+        f64::NEG_INFINITY => "-Inf".to_string(),
+        v if v.is_nan() => "NaN".to_string(),
+        v => v.to_string(),
+    }
+}
+
+/// Render labels as `{k1="v1",k2="v2"}`, or an empty string if there are none
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+
+    let pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| {
+            let escaped = v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{k}=\"{escaped}\"")
+        })
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_empty() {
+        assert_eq!(Registry::new().render(), "");
+    }
+
+    #[test]
+    fn test_render_counters_and_gauges() {
+        let mut registry = Registry::new();
+        registry.counter("tcp_segments_sent_total", "Segments sent", &[("conn", "3")], 42).unwrap();
+        registry.counter("tcp_segments_sent_total", "Segments sent", &[("conn", "1")], 7).unwrap();
+        registry.gauge("tcp_window_bytes", "Advertised window", &[], 1024.5).unwrap();
+
+        let expected = "\
+# HELP tcp_segments_sent_total Segments sent
+# TYPE tcp_segments_sent_total counter
+tcp_segments_sent_total{conn=\"1\"} 7
+tcp_segments_sent_total{conn=\"3\"} 42
+# HELP tcp_window_bytes Advertised window
+# TYPE tcp_window_bytes gauge
+tcp_window_bytes 1024.5
+";
+        assert_eq!(registry.render(), expected);
+    }
+
+    #[test]
+    fn test_counter_overwrites_sample() {
+        let mut registry = Registry::new();
+        registry.counter("x_total", "x", &[("conn", "1")], 1).unwrap();
+        registry.counter("x_total", "x", &[("conn", "1")], 2).unwrap();
+        assert!(registry.render().contains("x_total{conn=\"1\"} 2\n"));
+        assert!(!registry.render().contains("x_total{conn=\"1\"} 1\n"));
+    }
+
+    #[test]
+    fn test_label_escaping() {
+        let mut registry = Registry::new();
+        registry.counter("x_total", "x", &[("peer", "a\"b\\c\nd")], 1).unwrap();
+        assert!(registry.render().contains(r#"x_total{peer="a\"b\\c\nd"} 1"#));
+    }
+
+    #[test]
+    fn test_record_receiver_stats() {
+        let stats = ReceiverStats {
+            segments_received: 5,
+            duplicate_segments: 2,
+            ..ReceiverStats::default()
+        };
+
+        let mut registry = Registry::new();
+        registry.record_receiver_stats("0", &stats).unwrap();
+        let rendered = registry.render();
+        assert!(rendered.contains("# TYPE tcp_segments_received_total counter\n"));
+        assert!(rendered.contains("tcp_segments_received_total{conn=\"0\"} 5\n"));
+        assert!(rendered.contains("tcp_duplicate_segments_total{conn=\"0\"} 2\n"));
+        assert!(rendered.contains("tcp_fin_received_total{conn=\"0\"} 0\n"));
+    }
+
+    #[test]
+    fn test_record_sender_stats() {
+        let mut registry = Registry::new();
+        registry.record_sender_stats("2", &SenderStats { segments_sent: 3, bytes_sent: 1460 }).unwrap();
+        let rendered = registry.render();
+        assert!(rendered.contains("# TYPE tcp_bytes_sent_total counter\n"));
+        assert!(rendered.contains("tcp_segments_sent_total{conn=\"2\"} 3\n"));
+        assert!(rendered.contains("tcp_bytes_sent_total{conn=\"2\"} 1460\n"));
+    }
+
+    #[test]
+    fn test_kind_mismatch() {
+        let mut registry = Registry::new();
+        registry.counter("x_total", "x", &[], 1).unwrap();
+        let err = registry.gauge("x_total", "x", &[], 2.0).unwrap_err();
+        assert_eq!(err, MetricError::KindMismatch { name: "x_total".into(), registered: MetricKind::Counter, requested: MetricKind::Gauge });
+        assert!(registry.render().contains("x_total 1\n")); // Unchanged
+    }
+
+    #[test]
+    fn test_non_finite_values() {
+        let mut registry = Registry::new();
+        registry.gauge("a", "a", &[], f64::INFINITY).unwrap();
+        registry.gauge("b", "b", &[], f64::NEG_INFINITY).unwrap();
+        registry.gauge("c", "c", &[], f64::NAN).unwrap();
+        let rendered = registry.render();
+        assert!(rendered.contains("a +Inf\n"));
+        assert!(rendered.contains("b -Inf\n"));
+        assert!(rendered.contains("c NaN\n"));
+    }
+}
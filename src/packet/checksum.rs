@@ -0,0 +1,203 @@
+// The Internet checksum (RFC 1071) shared by the IPv4 and TCP headers. `sum` adds 8 bytes per
+// step into a u64. Each step adds the two 32-bit halves of a word, so the accumulator can't
+// overflow for any realistic buffer, and carries are folded only once at the end.
+
+use crate::packet::errors::HeaderError;
+
+/// The IP header fields covered by the TCP checksum. Implemented by the IPv4 and IPv6 headers,
+/// so TCP can checksum a segment without knowing which one it travels in.
+pub trait PseudoHeader {
+    /// The TCP segment length claimed by the IP header, or `None` if the IP header is inconsistent
+    fn segment_len(&self) -> Option<usize>;
+
+    /// The unfolded sum of the pseudo-header's 16-bit words for a segment of `segment_len` bytes.
+    /// `segment_len` is the whole segment, header and payload.
+    fn pseudo_header_sum(&self, segment_len: usize) -> u64;
+}
+
+/// The unfolded one's complement sum of `data` as big-endian 16-bit words. An odd last byte is
+/// padded with a zero byte.
+pub fn sum(data: &[u8]) -> u64 {
+    let mut acc = 0u64;
+
+    let mut words = data.chunks_exact(8);
+    for word in &mut words {
+        let word = u64::from_be_bytes([word[0], word[1], word[2], word[3], word[4], word[5], word[6], word[7]]);
+        acc += (word >> 32) + (word & 0xffff_ffff);
+    }
+
+    let mut tail = words.remainder().chunks_exact(2);
+    for pair in &mut tail {
+        acc += u16::from_be_bytes([pair[0], pair[1]]) as u64;
+    }
+    if let [last] = tail.remainder() {
+        acc += (*last as u64) << 8;
+    }
+    acc
+}
+
+/// Fold the carries of an unfolded sum back into 16 bits. A single fold can itself carry
+pub fn fold(mut sum: u64) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+/// The checksum of `data`: the complement of its folded sum. Zero when `data` already contains a
+/// correct checksum.
+pub fn checksum(data: &[u8]) -> u16 {
+    !fold(sum(data))
+}
+
+/// Like `checksum`, starting from `initial`, e.g. a pseudo-header sum
+pub fn checksum_with(initial: u64, data: &[u8]) -> u16 {
+    !fold(initial + sum(data))
+}
+
+/// RFC 1624 eqn. 3, `HC' = ~(~HC + ~m + m')`: the checksum after a field changes from
+/// `old_bytes` to `new_bytes`, without summing the rest of the data again. Both must be the same
+/// length and start at an even offset into the checksummed data.
+pub fn incremental_update(old_checksum: u16, old_bytes: &[u8], new_bytes: &[u8]) -> u16 {
+    debug_assert_eq!(old_bytes.len(), new_bytes.len());
+    // ~m, word by word. An odd last byte is padded like in `sum`, and its zero pad cancels out
+    let old_complement: u64 = old_bytes
+        .chunks(2)
+        .map(|chunk| !u16::from_be_bytes([chunk[0], chunk.get(1).copied().unwrap_or(0)]) as u64)
+        .sum();
+    !fold(!old_checksum as u64 + old_complement + sum(new_bytes))
+}
+
+/// Overwrite the field at `field_at` in an already-checksummed `buf` with `new_bytes`, and fix the
+/// checksum stored at `checksum_at` to match
+pub(crate) fn patch(buf: &mut [u8], checksum_at: usize, field_at: usize, new_bytes: &[u8]) -> Result<(), HeaderError> {
+    let end = (field_at + new_bytes.len()).max(checksum_at + 2);
+    if buf.len() < end {
+        return Err(HeaderError::BufferTooSmall { expected: end, found: buf.len() })
+    }
+
+    let old_checksum = u16::from_be_bytes([buf[checksum_at], buf[checksum_at + 1]]);
+    let field = &mut buf[field_at..field_at + new_bytes.len()];
+    let new_checksum = incremental_update(old_checksum, field, new_bytes);
+    field.copy_from_slice(new_bytes);
+    buf[checksum_at..checksum_at + 2].copy_from_slice(&new_checksum.to_be_bytes());
+    Ok(())
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+
+    /// The original 16-bits-at-a-time loop the headers used
+    fn reference(data: &[u8]) -> u16 {
+        let mut sum: u32 = data
+            .chunks(2)
+            .map(|chunk| {
+                if chunk.len() == 2 {
+                    u16::from_be_bytes([chunk[0], chunk[1]]) as u32
+                } else {
+                    (chunk[0] as u32) << 8
+                }
+            })
+            .sum();
+        while sum >> 16 != 0 {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+
+    #[test]
+    fn test_matches_reference_on_random_buffers() {
+        let mut rng = StdRng::seed_from_u64(0x1071);
+        let lengths = (0..=64).chain([255, 1499, 1500, 9000, 65535]);
+        for len in lengths {
+            for _ in 0..20 {
+                let mut buf = vec![0u8; len];
+                rng.fill_bytes(&mut buf);
+                assert_eq!(checksum(&buf), reference(&buf), "len {len}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_all_ones_and_unaligned_slices() {
+        // Worst case for carries
+        for len in [1, 7, 8, 9, 1500, 65535] {
+            let buf = vec![0xffu8; len];
+            assert_eq!(checksum(&buf), reference(&buf), "len {len}");
+        }
+
+        // Slices that don't start on an 8-byte boundary of their allocation
+        let buf: Vec<u8> = (0..100u8).collect();
+        for start in 0..8 {
+            assert_eq!(checksum(&buf[start..]), reference(&buf[start..]));
+        }
+    }
+
+    #[test]
+    fn test_rfc_1071_example() {
+        // RFC 1071 section 3: the words 0001 f203 f4f5 f6f7 sum to ddf2
+        let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+        assert_eq!(fold(sum(&data)), 0xddf2);
+        assert_eq!(checksum(&data), !0xddf2);
+        assert_eq!(checksum_with(sum(&data[..4]), &data[4..]), !0xddf2);
+    }
+
+    #[test]
+    fn test_incremental_update_matches_full_recompute() {
+        let mut rng = StdRng::seed_from_u64(0x1624);
+        for len in [2, 8, 20, 61, 1500] {
+            let mut buf = vec![0u8; len];
+            rng.fill_bytes(&mut buf);
+            let mut check = checksum(&buf);
+
+            for _ in 0..200 {
+                let at = (rng.next_u32() as usize % len) & !1;
+                let width = (len - at).min(1 + rng.next_u32() as usize % 4);
+                let mut new_bytes = vec![0u8; width];
+                rng.fill_bytes(&mut new_bytes);
+
+                check = incremental_update(check, &buf[at..at + width], &new_bytes);
+                buf[at..at + width].copy_from_slice(&new_bytes);
+                assert_eq!(check, checksum(&buf), "len {len} at {at} width {width}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_incremental_update_across_carries() {
+        // Field flips between the extremes, with the rest of the data chosen so the sum sits right
+        // next to a carry in either direction. All-zero data is left out: eqn. 3 then gives the
+        // other zero, 0x0000 for 0xffff, and no IP or TCP header is ever all zero.
+        for rest in [0x8000u16, 0x0001, 0x7fff, 0xfffe, 0xffff] {
+            for (old, new) in [(0x0000u16, 0xffffu16), (0xffff, 0x0000), (0x0001, 0xffff), (0xffff, 0x0001), (0x8000, 0x7fff)] {
+                let mut buf = [0u8; 4];
+                buf[0..2].copy_from_slice(&rest.to_be_bytes());
+                buf[2..4].copy_from_slice(&old.to_be_bytes());
+                let check = checksum(&buf);
+
+                let updated = incremental_update(check, &old.to_be_bytes(), &new.to_be_bytes());
+                buf[2..4].copy_from_slice(&new.to_be_bytes());
+                assert_eq!(updated, checksum(&buf), "rest {rest:#06x} {old:#06x} -> {new:#06x}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_patch() {
+        let mut buf = [0x12, 0x34, 0, 0, 0x56, 0x78];
+        let check = checksum(&buf);
+        buf[2..4].copy_from_slice(&check.to_be_bytes());
+
+        patch(&mut buf, 2, 4, &[0xff, 0xff]).unwrap();
+        assert_eq!(buf[4..6], [0xff, 0xff]);
+        assert_eq!(checksum(&buf), 0);
+
+        assert_eq!(patch(&mut buf, 2, 6, &[0]), Err(HeaderError::BufferTooSmall { expected: 7, found: 6 }));
+        assert_eq!(patch(&mut buf[..3], 2, 0, &[0]), Err(HeaderError::BufferTooSmall { expected: 4, found: 3 }));
+    }
+}
@@ -0,0 +1,73 @@
+use std::hint::black_box;
+
+/// Compare two byte strings in time that depends only on their lengths, never on where they
+/// differ. Use it for anything a peer could learn by timing, like signatures and cookies. Lengths
+/// are treated as public: inputs of different lengths return `false` straight away.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    // Fold every byte difference together instead of stopping at the first one
+    let diff = a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    black_box(diff) == 0
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ct_eq() {
+        assert!(ct_eq(b"", b""));
+        assert!(!ct_eq(b"", b"a"));
+        assert!(!ct_eq(b"abc", b"ab"));
+
+        for len in [1, 2, 15, 16, 17, 64, 1000] {
+            let a: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            assert!(ct_eq(&a, &a.clone()));
+
+            // A single flipped bit anywhere is noticed
+            for pos in [0, len / 2, len - 1] {
+                for bit in 0..8 {
+                    let mut b = a.clone();
+                    b[pos] ^= 1 << bit;
+                    assert!(!ct_eq(&a, &b), "len {len} pos {pos} bit {bit}");
+                }
+            }
+        }
+    }
+
+    /// Coarse smoke test: a mismatch in the first byte must not be much faster than a match.
+    /// Release builds are left out since the optimizer makes the timings too noisy to compare.
+    #[cfg(debug_assertions)]
+    #[test]
+    fn test_ct_eq_timing_smoke() {
+        use std::time::{Duration, Instant};
+
+        let a = vec![0x5au8; 4096];
+        let mut early = a.clone();
+        early[0] ^= 1;
+        let same = a.clone();
+
+        // The fastest of several batches filters out scheduler noise
+        let time = |b: &[u8]| -> Duration {
+            (0..7)
+                .map(|_| {
+                    let t0 = Instant::now();
+                    for _ in 0..50 {
+                        black_box(ct_eq(black_box(&a), black_box(b)));
+                    }
+                    t0.elapsed()
+                })
+                .min()
+                .unwrap_or_default()
+        };
+
+        let (equal, mismatch) = (time(&same), time(&early));
+        let ratio = mismatch.as_secs_f64() / equal.as_secs_f64();
+        assert!(ratio > 0.5, "first-byte mismatch took {mismatch:?}, equal inputs took {equal:?}");
+    }
+}
@@ -1,4 +1,3 @@
-use std::io;
 use thiserror::Error;
 
 #[derive(Debug, PartialEq, Error)]
@@ -8,4 +7,69 @@ pub enum HeaderError {
 
     #[error("Bad checksum")]
     BadChecksum(String),
-}
\ No newline at end of file
+
+    #[error("Segment length mismatch: IP header claims {expected} bytes, actual {found} bytes")]
+    LengthMismatch {expected: usize, found: usize},
+
+    #[error("Invalid TCP data offset: {0} words cannot hold the header and its options")]
+    InvalidDataOffset(u8),
+
+    #[error("Malformed TCP option: kind {kind} at offset {offset}")]
+    MalformedOption {kind: u8, offset: usize},
+
+    #[error("Invalid IP version: {0}, expected 4")]
+    InvalidVersion(u8),
+
+    #[error("Invalid IP header length: IHL {0} is below the minimum of 5")]
+    InvalidIhl(u8),
+
+    #[error("Invalid IP total length: {total_len} bytes, but the buffer has {buf_len} bytes")]
+    InvalidTotalLen {total_len: usize, buf_len: usize},
+
+    #[error("Truncated packet: IP header claims {claimed} bytes, actual {actual} bytes")]
+    TruncatedPacket {claimed: usize, actual: usize},
+
+    #[error("IP options too long: {0} bytes, at most 40 fit in the header")]
+    OptionsTooLong(usize),
+
+    #[error("Fragmentation needed: {len} byte packet exceeds MTU {mtu}, but DF is set")]
+    FragmentationNeeded {len: usize, mtu: usize},
+
+    #[error("MTU {0} is too small to carry any fragment data")]
+    MtuTooSmall(usize),
+
+    #[error("Unsupported ARP message: {0}")]
+    UnsupportedArp(String),
+
+    #[error("Unsupported ICMP message: {0}")]
+    UnsupportedIcmp(String),
+}
+#[derive(Debug, PartialEq, Error)]
+pub enum NegotiationError {
+    #[error(transparent)]
+    Header(#[from] HeaderError),
+
+    #[error("Endpoints disagree on negotiated parameters: {}", .0.join("; "))]
+    Mismatch(Vec<String>),
+}
+
+#[derive(Debug, PartialEq, Error)]
+pub enum ParseFlagsError {
+    #[error("Unknown flag: {0:?}")]
+    UnknownFlag(String),
+}
+
+#[derive(Debug, PartialEq, Error)]
+pub enum InterfaceError {
+    #[error("No such interface: {0}")]
+    NoSuchInterface(String),
+
+    #[error("Interface {0} has no IPv4 address")]
+    AddressNotAvailable(String),
+
+    #[error("No non-loopback IPv4 address found on any interface")]
+    NoLocalAddress,
+
+    #[error("Failed to list network interfaces: {0}")]
+    List(String),
+}
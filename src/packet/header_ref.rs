@@ -0,0 +1,360 @@
+use crate::ip::ecn::Ecn;
+use crate::ip::ip_flags::IpFlags;
+use crate::ip::ip_header::IpHeader;
+use crate::packet::errors::HeaderError;
+use crate::packet::parse_options::{ChecksumStatus, ParseOptions};
+use crate::packet::checksum::{self, PseudoHeader};
+use crate::tcp::tcp_flags::TcpFlags;
+use crate::tcp::tcp_header::{self, TcpHeader};
+use crate::tcp::tcp_options::{self, TcpOption};
+use crate::tcp::wrap32::Wrap32;
+use bytes::Bytes;
+use std::net::Ipv4Addr;
+
+/// A validated IPv4 header borrowed from a packet buffer. Fields are read from the buffer on
+/// access, so parsing never allocates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpHeaderRef<'a> {
+    buf: &'a [u8], // The whole datagram, trimmed to `total_len`
+}
+
+impl<'a> IpHeaderRef<'a> {
+    /// Parse a byte array holding a whole IPv4 packet. Validates the same things as
+    /// `IpHeader::parse`: version, IHL, total length and checksum.
+    pub fn parse(buf: &'a [u8]) -> Result<Self, HeaderError> {
+        Self::parse_with(buf, &ParseOptions::default()).map(|(iph, _)| iph)
+    }
+
+    /// Like `parse`, but a bad checksum is only an error if `options.verify_ip_checksum` is set
+    pub fn parse_with(buf: &'a [u8], options: &ParseOptions) -> Result<(Self, ChecksumStatus), HeaderError> {
+        if buf.len() < 20 {
+            return Err(HeaderError::BufferTooSmall { expected: 20, found: buf.len() })
+        }
+
+        let version = buf[0] >> 4;
+        if version != 4 {
+            return Err(HeaderError::InvalidVersion(version))
+        }
+        let ihl = buf[0] & 0x0f;
+        if ihl < 5 {
+            return Err(HeaderError::InvalidIhl(ihl))
+        }
+        let header_len = ihl as usize * 4;
+        if buf.len() < header_len {
+            return Err(HeaderError::BufferTooSmall { expected: header_len, found: buf.len() })
+        }
+
+        // The packet may be followed by link-layer padding, but never cut short
+        let total_len = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+        if total_len < header_len {
+            return Err(HeaderError::InvalidTotalLen { total_len, buf_len: buf.len() })
+        }
+        if total_len > buf.len() {
+            return Err(HeaderError::TruncatedPacket { claimed: total_len, actual: buf.len() })
+        }
+
+        let status = ChecksumStatus::of(IpHeader::checksum(&buf[0..header_len]) == 0);
+        if options.verify_ip_checksum && status != ChecksumStatus::Verified {
+            return Err(HeaderError::BadChecksum("IP".to_string()))
+        };
+
+        Ok((IpHeaderRef { buf: &buf[..total_len] }, status))
+    }
+
+    pub fn version(&self) -> u8 {
+        self.buf[0] >> 4
+    }
+
+    pub fn ihl(&self) -> u8 {
+        self.buf[0] & 0x0f
+    }
+
+    pub fn header_len(&self) -> usize {
+        self.ihl() as usize * 4
+    }
+
+    pub fn tos(&self) -> u8 {
+        self.buf[1]
+    }
+
+    pub fn dscp(&self) -> u8 {
+        self.tos() >> 2
+    }
+
+    pub fn ecn(&self) -> Ecn {
+        Ecn::from_bits(self.tos())
+    }
+
+    pub fn total_len(&self) -> u16 {
+        u16::from_be_bytes([self.buf[2], self.buf[3]])
+    }
+
+    pub fn id(&self) -> u16 {
+        u16::from_be_bytes([self.buf[4], self.buf[5]])
+    }
+
+    pub fn flags(&self) -> IpFlags {
+        IpFlags::unpack(u16::from_be_bytes([self.buf[6], self.buf[7]])).0
+    }
+
+    pub fn frag_offset(&self) -> u16 {
+        IpFlags::unpack(u16::from_be_bytes([self.buf[6], self.buf[7]])).1
+    }
+
+    pub fn ttl(&self) -> u8 {
+        self.buf[8]
+    }
+
+    pub fn protocol(&self) -> u8 {
+        self.buf[9]
+    }
+
+    pub fn checksum(&self) -> u16 {
+        u16::from_be_bytes([self.buf[10], self.buf[11]])
+    }
+
+    pub fn src_ip(&self) -> Ipv4Addr {
+        Ipv4Addr::new(self.buf[12], self.buf[13], self.buf[14], self.buf[15])
+    }
+
+    pub fn dst_ip(&self) -> Ipv4Addr {
+        Ipv4Addr::new(self.buf[16], self.buf[17], self.buf[18], self.buf[19])
+    }
+
+    /// Raw IP options, including any padding
+    pub fn options(&self) -> &'a [u8] {
+        &self.buf[20..self.header_len()]
+    }
+
+    /// Everything after the header, up to `total_len`
+    pub fn payload(&self) -> &'a [u8] {
+        &self.buf[self.header_len()..]
+    }
+
+    /// Copy the fields into an owned `IpHeader`
+    pub fn to_owned(&self) -> IpHeader {
+        IpHeader {
+            version: self.version(),
+            ihl: self.ihl(),
+            tos: self.tos(),
+            total_len: self.total_len(),
+            id: self.id(),
+            flags: self.flags(),
+            frag_offset: self.frag_offset(),
+            ttl: self.ttl(),
+            protocol: self.protocol(),
+            checksum: self.checksum(),
+            src_ip: self.src_ip(),
+            dst_ip: self.dst_ip(),
+            options: self.options().to_vec(),
+        }
+    }
+}
+
+impl PseudoHeader for IpHeaderRef<'_> {
+    fn segment_len(&self) -> Option<usize> {
+        (self.total_len() as usize).checked_sub(self.header_len())
+    }
+
+    /// RFC 793: addresses, zero, protocol, 16-bit TCP length
+    fn pseudo_header_sum(&self, segment_len: usize) -> u64 {
+        checksum::sum(&self.buf[12..20]) + self.protocol() as u64 + segment_len as u64
+    }
+}
+
+/// A validated TCP header borrowed from a packet buffer. Options and payload are slices into
+/// the buffer, so parsing never allocates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TcpHeaderRef<'a> {
+    buf: &'a [u8], // The whole segment: header, options and payload
+}
+
+impl<'a> TcpHeaderRef<'a> {
+    /// Parse a TCP segment. Validates the same things as `TcpHeader::parse`: the buffer must
+    /// hold exactly the segment length claimed by the IP header, the data offset must cover at
+    /// least the 20 byte header and fit in the buffer, and the checksum must match.
+    pub fn parse(buf: &'a [u8], iph: &impl PseudoHeader) -> Result<Self, HeaderError> {
+        Self::parse_with(buf, iph, &ParseOptions::default()).map(|(tcph, _)| tcph)
+    }
+
+    /// Like `parse`, but a bad checksum is only an error if `options.verify_tcp_checksum` is set
+    pub fn parse_with(
+        buf: &'a [u8],
+        iph: &impl PseudoHeader,
+        options: &ParseOptions,
+    ) -> Result<(Self, ChecksumStatus), HeaderError> {
+        if buf.len() < 20 {
+            return Err(HeaderError::BufferTooSmall { expected: 20, found: buf.len() })
+        }
+
+        // The pseudo-header length comes from the IP header, not from the buffer
+        let segment_len = iph
+            .segment_len()
+            .ok_or(HeaderError::LengthMismatch { expected: 0, found: buf.len() })?;
+        if segment_len != buf.len() {
+            return Err(HeaderError::LengthMismatch { expected: segment_len, found: buf.len() })
+        }
+
+        // Below 5 words the header would overlap its own fixed fields
+        let data_offset = buf[12] >> 4;
+        if data_offset < 5 {
+            return Err(HeaderError::InvalidDataOffset(data_offset))
+        }
+        let header_len = data_offset as usize * 4;
+        if buf.len() < header_len {
+            return Err(HeaderError::BufferTooSmall { expected: header_len, found: buf.len() })
+        }
+
+        let status = ChecksumStatus::of(TcpHeader::checksum_with_len(buf, iph, segment_len) == 0);
+        if options.verify_tcp_checksum && status != ChecksumStatus::Verified {
+            return Err(HeaderError::BadChecksum("TCP".to_string()))
+        }
+
+        Ok((TcpHeaderRef { buf }, status))
+    }
+
+    pub fn src_port(&self) -> u16 {
+        u16::from_be_bytes([self.buf[0], self.buf[1]])
+    }
+
+    pub fn dst_port(&self) -> u16 {
+        u16::from_be_bytes([self.buf[2], self.buf[3]])
+    }
+
+    pub fn seq_no(&self) -> Wrap32 {
+        Wrap32::new(u32::from_be_bytes([self.buf[4], self.buf[5], self.buf[6], self.buf[7]]))
+    }
+
+    pub fn ack_no(&self) -> Wrap32 {
+        Wrap32::new(u32::from_be_bytes([self.buf[8], self.buf[9], self.buf[10], self.buf[11]]))
+    }
+
+    pub fn data_offset(&self) -> u8 {
+        self.buf[12] >> 4
+    }
+
+    pub fn reserved(&self) -> u8 {
+        self.buf[12] & 0x0f
+    }
+
+    pub fn flags(&self) -> TcpFlags {
+        TcpFlags::from_bits_truncate(self.buf[13])
+    }
+
+    pub fn window(&self) -> u16 {
+        u16::from_be_bytes([self.buf[14], self.buf[15]])
+    }
+
+    pub fn checksum(&self) -> u16 {
+        u16::from_be_bytes([self.buf[16], self.buf[17]])
+    }
+
+    pub fn urgent(&self) -> u16 {
+        u16::from_be_bytes([self.buf[18], self.buf[19]])
+    }
+
+    fn header_len(&self) -> usize {
+        self.data_offset() as usize * 4
+    }
+
+    /// Raw TCP options
+    pub fn options(&self) -> &'a [u8] {
+        &self.buf[20..self.header_len()]
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        &self.buf[self.header_len()..]
+    }
+
+    pub fn payload_len(&self) -> usize {
+        self.buf.len() - self.header_len()
+    }
+
+    /// Like `TcpHeader::seq_len`
+    pub fn seq_len(&self) -> usize {
+        tcp_header::seq_len(self.flags(), self.payload_len())
+    }
+
+    /// Parse the raw options into typed options, like `TcpHeader::tcp_options`
+    pub fn tcp_options(&self) -> Result<Vec<TcpOption>, HeaderError> {
+        tcp_options::parse_options(self.options())
+    }
+
+    /// Copy the fields, options and payload into an owned `TcpHeader`
+    pub fn to_owned(&self) -> TcpHeader {
+        TcpHeader {
+            src_port: self.src_port(),
+            dst_port: self.dst_port(),
+            seq_no: self.seq_no(),
+            ack_no: self.ack_no(),
+            data_offset: self.data_offset(),
+            reserved: self.reserved(),
+            flags: self.flags(),
+            window: self.window(),
+            checksum: self.checksum(),
+            urgent: self.urgent(),
+            options: Bytes::copy_from_slice(self.options()),
+            payload: Bytes::copy_from_slice(self.payload()),
+        }
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet;
+    use crate::packet::test_utils;
+
+    fn fixture_packets() -> Vec<Vec<u8>> {
+        let syn = [test_utils::get_ip_hex(), test_utils::get_tcp_hex()].concat();
+        let data = [
+            test_utils::get_ip_hex_with_payload(),
+            test_utils::get_tcp_hex_with_payload(),
+            test_utils::giant_payload(),
+        ]
+        .concat();
+        vec![hex::decode(syn).unwrap(), hex::decode(data).unwrap()]
+    }
+
+    #[test]
+    fn test_views_match_owned_headers() {
+        for packet in fixture_packets() {
+            let (iph, tcph) = packet::unwrap(&packet).unwrap();
+            let (iph_ref, tcph_ref) = packet::unwrap_ref(&packet).unwrap();
+
+            assert_eq!(iph_ref.to_owned(), iph);
+            assert_eq!(tcph_ref.to_owned(), tcph);
+            assert_eq!(iph_ref.src_ip(), iph.src_ip);
+            assert_eq!(iph_ref.ecn(), iph.ecn());
+            assert_eq!(tcph_ref.seq_no(), tcph.seq_no);
+            assert_eq!(tcph_ref.flags(), tcph.flags);
+            assert_eq!(tcph_ref.tcp_options(), tcph.tcp_options());
+            assert_eq!(tcph_ref.payload(), &tcph.payload[..]);
+        }
+    }
+
+    #[test]
+    fn test_payload_borrows_packet() {
+        let packet = &fixture_packets()[1];
+        let (iph, tcph) = packet::unwrap_ref(packet).unwrap();
+
+        let offset = tcph.payload().as_ptr() as usize - packet.as_ptr() as usize;
+        assert_eq!(offset, iph.header_len() + tcph.data_offset() as usize * 4);
+        assert_eq!(iph.payload().len(), iph.total_len() as usize - 20);
+    }
+
+    #[test]
+    fn test_errors_match_owned_parse() {
+        for packet in fixture_packets() {
+            for len in 0..packet.len() {
+                assert_eq!(packet::unwrap_ref(&packet[..len]).unwrap_err(), packet::unwrap(&packet[..len]).unwrap_err());
+            }
+
+            let mut corrupted = packet.clone();
+            *corrupted.last_mut().unwrap() ^= 0xff;
+            assert_eq!(packet::unwrap_ref(&corrupted).unwrap_err(), HeaderError::BadChecksum("TCP".to_string()));
+        }
+    }
+}
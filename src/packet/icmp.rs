@@ -0,0 +1,313 @@
+// ICMPv4 (RFC 792): echo, and the two error messages a TCP connection cares about. An error quotes
+// the IP header and first 8 bytes of the packet that caused it, which is enough to recover the
+// TCP ports and match it to a connection.
+
+use crate::packet::checksum;
+use crate::packet::errors::HeaderError;
+use crate::tcp::flow_key::FlowKey;
+use crate::tcp::wrap32::Wrap32;
+use nix::errno::Errno;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+pub const PROTOCOL_ICMP: u8 = 1;
+const PROTOCOL_TCP: u8 = 6;
+
+const TYPE_ECHO_REPLY: u8 = 0;
+const TYPE_DEST_UNREACHABLE: u8 = 3;
+const TYPE_ECHO_REQUEST: u8 = 8;
+const TYPE_TIME_EXCEEDED: u8 = 11;
+
+// Destination Unreachable codes
+pub const CODE_NET_UNREACHABLE: u8 = 0;
+pub const CODE_HOST_UNREACHABLE: u8 = 1;
+pub const CODE_PROTOCOL_UNREACHABLE: u8 = 2;
+pub const CODE_PORT_UNREACHABLE: u8 = 3;
+pub const CODE_FRAGMENTATION_NEEDED: u8 = 4;
+
+/// How much of the offending packet an error quotes: its IP header plus 8 bytes
+const QUOTE_DATA_LEN: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IcmpMessage {
+    EchoRequest { id: u16, seq: u16, payload: Vec<u8> },
+    EchoReply { id: u16, seq: u16, payload: Vec<u8> },
+    /// `next_hop_mtu` is only meaningful for `CODE_FRAGMENTATION_NEEDED` (RFC 1191), else 0
+    DestinationUnreachable { code: u8, next_hop_mtu: u16, original: Vec<u8> },
+    TimeExceeded { code: u8, original: Vec<u8> },
+}
+
+/// What an ICMP error tells us about the packet that caused it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuotedPacket {
+    pub protocol: u8,
+    pub total_len: u16,    // Its IP total length, for PMTUD when the router gives no MTU
+    pub src: SocketAddrV4, // Ports are 0 unless the protocol is TCP
+    pub dst: SocketAddrV4,
+    pub seq_no: Wrap32,    // TCP only, else 0: which of our segments it was
+}
+
+impl IcmpMessage {
+    /// A Destination Unreachable error about `packet`, quoting its header and first 8 data bytes
+    pub fn unreachable(code: u8, next_hop_mtu: u16, packet: &[u8]) -> Self {
+        IcmpMessage::DestinationUnreachable { code, next_hop_mtu, original: quote(packet).to_vec() }
+    }
+
+    /// A Time Exceeded error about `packet`, e.g. code 0 when its TTL ran out in transit
+    pub fn time_exceeded(code: u8, packet: &[u8]) -> Self {
+        IcmpMessage::TimeExceeded { code, original: quote(packet).to_vec() }
+    }
+
+    pub fn icmp_type(&self) -> u8 {
+        match self {
+            IcmpMessage::EchoRequest { .. } => TYPE_ECHO_REQUEST,
+            IcmpMessage::EchoReply { .. } => TYPE_ECHO_REPLY,
+            IcmpMessage::DestinationUnreachable { .. } => TYPE_DEST_UNREACHABLE,
+            IcmpMessage::TimeExceeded { .. } => TYPE_TIME_EXCEEDED,
+        }
+    }
+
+    pub fn code(&self) -> u8 {
+        match self {
+            IcmpMessage::EchoRequest { .. } | IcmpMessage::EchoReply { .. } => 0,
+            IcmpMessage::DestinationUnreachable { code, .. } | IcmpMessage::TimeExceeded { code, .. } => *code,
+        }
+    }
+
+    /// The serialized length: the 8 byte header and the body
+    pub fn message_len(&self) -> usize {
+        8 + self.body().len()
+    }
+
+    fn body(&self) -> &[u8] {
+        match self {
+            IcmpMessage::EchoRequest { payload, .. } | IcmpMessage::EchoReply { payload, .. } => payload,
+            IcmpMessage::DestinationUnreachable { original, .. } | IcmpMessage::TimeExceeded { original, .. } => original,
+        }
+    }
+
+    /// Serialize into the front of `buf`, checksum included. Returns `message_len()`
+    pub fn serialize(&self, buf: &mut [u8]) -> Result<usize, HeaderError> {
+        let len = self.message_len();
+        if buf.len() < len {
+            return Err(HeaderError::BufferTooSmall { expected: len, found: buf.len() });
+        }
+        buf[0] = self.icmp_type();
+        buf[1] = self.code();
+        buf[2..4].fill(0);
+        match self {
+            IcmpMessage::EchoRequest { id, seq, .. } | IcmpMessage::EchoReply { id, seq, .. } => {
+                buf[4..6].copy_from_slice(&id.to_be_bytes());
+                buf[6..8].copy_from_slice(&seq.to_be_bytes());
+            }
+            IcmpMessage::DestinationUnreachable { next_hop_mtu, .. } => {
+                buf[4..6].fill(0);
+                buf[6..8].copy_from_slice(&next_hop_mtu.to_be_bytes());
+            }
+            IcmpMessage::TimeExceeded { .. } => buf[4..8].fill(0),
+        }
+        buf[8..len].copy_from_slice(self.body());
+        let checksum = checksum::checksum(&buf[..len]);
+        buf[2..4].copy_from_slice(&checksum.to_be_bytes());
+        Ok(len)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![0u8; self.message_len()];
+        let _ = self.serialize(&mut buf); // Can't fail: the buffer is exactly message_len()
+        buf
+    }
+
+    /// Parse an ICMP message, the payload of an IP packet with protocol 1. The checksum covers
+    /// the whole message and is always verified.
+    pub fn parse(buf: &[u8]) -> Result<Self, HeaderError> {
+        if buf.len() < 8 {
+            return Err(HeaderError::BufferTooSmall { expected: 8, found: buf.len() });
+        }
+        if checksum::checksum(buf) != 0 {
+            return Err(HeaderError::BadChecksum("ICMP".to_string()));
+        }
+        let u16_at = |at: usize| u16::from_be_bytes([buf[at], buf[at + 1]]);
+        let (code, body) = (buf[1], buf[8..].to_vec());
+        match buf[0] {
+            TYPE_ECHO_REQUEST => Ok(IcmpMessage::EchoRequest { id: u16_at(4), seq: u16_at(6), payload: body }),
+            TYPE_ECHO_REPLY => Ok(IcmpMessage::EchoReply { id: u16_at(4), seq: u16_at(6), payload: body }),
+            TYPE_DEST_UNREACHABLE => Ok(IcmpMessage::DestinationUnreachable { code, next_hop_mtu: u16_at(6), original: body }),
+            TYPE_TIME_EXCEEDED => Ok(IcmpMessage::TimeExceeded { code, original: body }),
+            other => Err(HeaderError::UnsupportedIcmp(format!("type {other} code {code}"))),
+        }
+    }
+
+    /// For an error message, the endpoints of the packet that caused it. `None` for echo, or if
+    /// too little of the packet was quoted to tell.
+    pub fn quoted(&self) -> Option<QuotedPacket> {
+        let original = match self {
+            IcmpMessage::DestinationUnreachable { original, .. } | IcmpMessage::TimeExceeded { original, .. } => original,
+            _ => return None,
+        };
+        if original.len() < 20 || original[0] >> 4 != 4 {
+            return None;
+        }
+        let ihl = (original[0] & 0x0f) as usize * 4;
+        if ihl < 20 {
+            return None;
+        }
+        let protocol = original[9];
+        let ip = |at: usize| Ipv4Addr::new(original[at], original[at + 1], original[at + 2], original[at + 3]);
+        let u16_at = |at: usize| u16::from_be_bytes([original[at], original[at + 1]]);
+        let (src_port, dst_port, seq_no) = match (protocol, original.get(ihl..ihl + 8)) {
+            (PROTOCOL_TCP, Some(tcp)) => {
+                (u16_at(ihl), u16_at(ihl + 2), Wrap32::new(u32::from_be_bytes([tcp[4], tcp[5], tcp[6], tcp[7]])))
+            }
+            (PROTOCOL_TCP, None) => return None,
+            _ => (0, 0, Wrap32::new(0)),
+        };
+        Some(QuotedPacket {
+            protocol,
+            total_len: u16_at(2),
+            src: SocketAddrV4::new(ip(12), src_port),
+            dst: SocketAddrV4::new(ip(16), dst_port),
+            seq_no,
+        })
+    }
+
+    /// The error this message means for the TCP connection `flow`, if it's about one of our
+    /// packets on it. Errors map the way the kernel reports them: port unreachable is
+    /// ECONNREFUSED, fragmentation needed EMSGSIZE, and TTL expiry EHOSTUNREACH.
+    pub fn connection_error(&self, flow: &FlowKey) -> Option<io::Error> {
+        let quoted = self.quoted()?;
+        if quoted.protocol != PROTOCOL_TCP || quoted.src != flow.local || quoted.dst != flow.remote {
+            return None;
+        }
+        let errno = match self {
+            IcmpMessage::DestinationUnreachable { code, .. } => match *code {
+                CODE_NET_UNREACHABLE => Errno::ENETUNREACH,
+                CODE_PROTOCOL_UNREACHABLE => Errno::ENOPROTOOPT,
+                CODE_PORT_UNREACHABLE => Errno::ECONNREFUSED,
+                CODE_FRAGMENTATION_NEEDED => Errno::EMSGSIZE,
+                _ => Errno::EHOSTUNREACH,
+            },
+            _ => Errno::EHOSTUNREACH,
+        };
+        Some(errno.into())
+    }
+}
+
+/// The connection error carried by `packet`, a received IPv4 packet, for the connection `flow`.
+/// `None` unless it's an ICMP error to our address about that connection; a receive loop can call
+/// this on every non-TCP packet it sees.
+pub fn connection_error(packet: &[u8], flow: &FlowKey) -> Option<io::Error> {
+    if packet.len() < 20 || packet[0] >> 4 != 4 || packet[9] != PROTOCOL_ICMP {
+        return None;
+    }
+    if Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]) != *flow.local.ip() {
+        return None;
+    }
+    let ihl = (packet[0] & 0x0f) as usize * 4;
+    let total_len = (u16::from_be_bytes([packet[2], packet[3]]) as usize).min(packet.len());
+    IcmpMessage::parse(packet.get(ihl..total_len)?).ok()?.connection_error(flow)
+}
+
+/// The IP header and first 8 data bytes of `packet`, what an ICMP error quotes
+fn quote(packet: &[u8]) -> &[u8] {
+    let ihl = packet.first().map_or(0, |b| (b & 0x0f) as usize * 4);
+    &packet[..packet.len().min(ihl + QUOTE_DATA_LEN)]
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::test_utils::{get_ip_hex, get_tcp_hex};
+
+    /// The wireshark SYN, from 10.110.208.106:50871 to 204.44.192.60:80
+    fn syn() -> Vec<u8> {
+        hex::decode([get_ip_hex(), get_tcp_hex()].concat()).unwrap()
+    }
+
+    fn syn_flow() -> FlowKey {
+        FlowKey::new(SocketAddrV4::new(Ipv4Addr::new(10, 110, 208, 106), 50871), SocketAddrV4::new(Ipv4Addr::new(204, 44, 192, 60), 80))
+    }
+
+    #[test]
+    fn test_echo_request_and_reply() {
+        // `ping -p 70696e67 -s 4`, id 0x1234, seq 1
+        let request = hex::decode("080006fa1234000170696e67").unwrap();
+        let message = IcmpMessage::parse(&request).unwrap();
+        assert_eq!(message, IcmpMessage::EchoRequest { id: 0x1234, seq: 1, payload: b"ping".to_vec() });
+        assert_eq!(message.to_bytes(), request);
+
+        let reply = hex::decode("00000efa1234000170696e67").unwrap();
+        let message = IcmpMessage::parse(&reply).unwrap();
+        assert_eq!(message, IcmpMessage::EchoReply { id: 0x1234, seq: 1, payload: b"ping".to_vec() });
+        assert_eq!(message.to_bytes(), reply);
+        assert_eq!(message.quoted(), None);
+    }
+
+    #[test]
+    fn test_port_unreachable() {
+        let fixture = hex::decode("0303f53a0000000045000040000040004006d3760a6ed06acc2cc03cc6b70050a4269c93").unwrap();
+        let message = IcmpMessage::parse(&fixture).unwrap();
+        assert_eq!(message, IcmpMessage::unreachable(CODE_PORT_UNREACHABLE, 0, &syn()));
+        assert_eq!(message.to_bytes(), fixture);
+        let flow = syn_flow();
+        let quoted = QuotedPacket { protocol: 6, total_len: 64, src: flow.local, dst: flow.remote, seq_no: Wrap32::new(0xa4269c93) };
+        assert_eq!(message.quoted(), Some(quoted));
+
+        // Too little quoted to find the sequence number
+        let IcmpMessage::DestinationUnreachable { mut original, .. } = message else { unreachable!() };
+        original.truncate(27);
+        assert_eq!(IcmpMessage::DestinationUnreachable { code: 3, next_hop_mtu: 0, original }.quoted(), None);
+    }
+
+    #[test]
+    fn test_fragmentation_needed() {
+        let fixture = hex::decode("0304ef5d000005dc45000040000040004006d3760a6ed06acc2cc03cc6b70050a4269c93").unwrap();
+        let message = IcmpMessage::parse(&fixture).unwrap();
+        assert_eq!(message, IcmpMessage::unreachable(CODE_FRAGMENTATION_NEEDED, 1500, &syn()));
+        assert_eq!(message.to_bytes(), fixture);
+    }
+
+    #[test]
+    fn test_time_exceeded() {
+        let fixture = hex::decode("0b00ed3d0000000045000040000040004006d3760a6ed06acc2cc03cc6b70050a4269c93").unwrap();
+        let message = IcmpMessage::parse(&fixture).unwrap();
+        assert_eq!(message, IcmpMessage::time_exceeded(0, &syn()));
+        assert_eq!(message.to_bytes(), fixture);
+        assert_eq!(message.connection_error(&syn_flow()).unwrap().raw_os_error(), Some(Errno::EHOSTUNREACH as i32));
+    }
+
+    #[test]
+    fn test_parse_rejects() {
+        let mut fixture = hex::decode("00000efa1234000170696e67").unwrap();
+        assert_eq!(IcmpMessage::parse(&fixture[..7]), Err(HeaderError::BufferTooSmall { expected: 8, found: 7 }));
+        fixture[11] ^= 1;
+        assert_eq!(IcmpMessage::parse(&fixture), Err(HeaderError::BadChecksum("ICMP".to_string())));
+
+        // A redirect, with a valid checksum
+        let mut redirect = hex::decode("0501000000000000").unwrap();
+        let sum = checksum::checksum(&redirect);
+        redirect[2..4].copy_from_slice(&sum.to_be_bytes());
+        assert_eq!(IcmpMessage::parse(&redirect), Err(HeaderError::UnsupportedIcmp("type 5 code 1".into())));
+    }
+
+    #[test]
+    fn test_connection_error_from_packet() {
+        // From the server back to us, protocol 1. The IP checksum is left zero; it isn't checked
+        let icmp = IcmpMessage::unreachable(CODE_PORT_UNREACHABLE, 0, &syn()).to_bytes();
+        let mut packet = hex::decode("4500003c0000000040010000cc2cc03c0a6ed06a").unwrap();
+        packet.extend(&icmp);
+
+        let flow = syn_flow();
+        let err = connection_error(&packet, &flow).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+
+        // Not about this connection, or not addressed to us
+        let other = FlowKey::new(flow.local, SocketAddrV4::new(*flow.remote.ip(), 443));
+        assert!(connection_error(&packet, &other).is_none());
+        let elsewhere = FlowKey::new(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 50871), flow.remote);
+        assert!(connection_error(&packet, &elsewhere).is_none());
+        assert!(connection_error(&syn(), &flow).is_none()); // TCP, not ICMP
+    }
+}
@@ -1,25 +1,78 @@
 pub mod tcp_over_ip;
 pub mod errors;
+pub mod checksum;
+pub mod constant_time;
+pub mod segment_expectation;
+pub mod header_ref;
+pub mod icmp;
+pub mod pcap;
+pub mod parse_options;
+pub mod segment;
+pub mod segment_record;
+pub mod summary;
+pub mod udp;
 
 // -- Re-export public structs --
 
 pub use crate::packet::tcp_over_ip::wrap_into;
 pub use crate::packet::tcp_over_ip::unwrap_from;
 pub use crate::packet::tcp_over_ip::wrap;
+pub use crate::packet::tcp_over_ip::wrap_fragmented;
+pub use crate::packet::tcp_over_ip::wrap_for_platform;
+pub use crate::packet::tcp_over_ip::to_host_byte_order;
+pub use crate::packet::tcp_over_ip::HDRINCL_HOST_BYTE_ORDER;
 pub use crate::packet::tcp_over_ip::unwrap;
+pub use crate::packet::tcp_over_ip::unwrap_ref;
+pub use crate::packet::tcp_over_ip::unwrap_with;
+pub use crate::packet::tcp_over_ip::wrap_into_v6;
+pub use crate::packet::tcp_over_ip::unwrap_from_v6;
+pub use crate::packet::tcp_over_ip::wrap_v6;
+pub use crate::packet::tcp_over_ip::unwrap_v6;
+pub use crate::packet::udp::wrap_udp;
+pub use crate::packet::udp::unwrap_udp;
+pub use crate::packet::udp::UdpHeader;
+pub use crate::packet::checksum::PseudoHeader;
+pub use crate::packet::constant_time::ct_eq;
+pub use crate::packet::parse_options::ChecksumReport;
+pub use crate::packet::parse_options::ChecksumStatus;
+pub use crate::packet::parse_options::ParseOptions;
+pub use crate::packet::icmp::IcmpMessage;
+pub use crate::packet::header_ref::IpHeaderRef;
+pub use crate::packet::header_ref::TcpHeaderRef;
+pub use crate::packet::segment_expectation::SegmentExpectation;
+pub use crate::packet::segment::TcpSegment;
+pub use crate::packet::segment_record::SegmentRecord;
+pub use crate::packet::summary::summary;
+pub use crate::packet::summary::summary_verbose;
+pub use crate::packet::summary::Summary;
 
 // -- Unit test helpers --
 
 #[cfg(test)]
 pub mod test_utils {
+    use crate::ip::ip_header::IpHeader;
+
     pub fn get_ip_hex() -> &'static str {
         "45000040000040004006d3760a6ed06acc2cc03c"
     }
 
+    /// The parsed IP header of the wireshark SYN. Parsing needs the whole packet
+    pub fn get_ip_header() -> IpHeader {
+        let packet = hex::decode([get_ip_hex(), get_tcp_hex()].concat()).unwrap();
+        IpHeader::parse(&packet).unwrap()
+    }
+
     pub fn get_tcp_hex() -> &'static str {
         "c6b70050a4269c9300000000b002ffff92970000020405b4010303060101080abb6879f80000000004020000"
     }
 
+    /// A SYN from [::1]:38886 to [::1]:8080, captured on loopback. Loopback leaves the TCP
+    /// checksum to offload, so it was filled in afterwards.
+    pub fn get_ipv6_syn_hex() -> &'static str {
+        "60033b7200280640000000000000000000000000000000010000000000000000000000000000000197e61f90\
+        76e57d6b00000000a002ffc489c700000204ffc40402080af9c51ed1000000000103030a"
+    }
+
     pub fn get_ip_hex_with_payload() -> &'static str {
         "45000592464440002a069de0cc2cc03c0a6ed06a"
     }
@@ -0,0 +1,51 @@
+/// Which checksums parsing enforces. The default rejects any bad checksum. Captures taken on the
+/// sending host usually have checksum offload, so their outgoing checksums are blank or partial.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions {
+    pub verify_ip_checksum: bool,  // Reject a bad IPv4 header checksum
+    pub verify_tcp_checksum: bool, // Reject a bad TCP checksum
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions { verify_ip_checksum: true, verify_tcp_checksum: true }
+    }
+}
+
+impl ParseOptions {
+    /// Accept bad checksums, e.g. to replay captures with offloaded checksums
+    pub fn lenient() -> Self {
+        ParseOptions { verify_ip_checksum: false, verify_tcp_checksum: false }
+    }
+}
+
+/// The outcome of a checksum check made while parsing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumStatus {
+    Verified, // Checked and correct
+    Failed,   // Wrong, but accepted because verification was off
+}
+
+impl ChecksumStatus {
+    pub(crate) fn of(valid: bool) -> Self {
+        if valid {
+            ChecksumStatus::Verified
+        } else {
+            ChecksumStatus::Failed
+        }
+    }
+}
+
+/// The checksum outcomes of parsing a whole TCP/IPv4 packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChecksumReport {
+    pub ip: ChecksumStatus,
+    pub tcp: ChecksumStatus,
+}
+
+impl ChecksumReport {
+    /// Were both checksums correct?
+    pub fn all_verified(&self) -> bool {
+        self.ip == ChecksumStatus::Verified && self.tcp == ChecksumStatus::Verified
+    }
+}
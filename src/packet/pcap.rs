@@ -0,0 +1,381 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const LINKTYPE_ETHERNET: u32 = 1; // Records start with a 14-byte Ethernet header
+pub const LINKTYPE_RAW: u32 = 101;    // Records are bare IPv4 or IPv6 packets
+
+const MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const MAGIC_NANOS: u32 = 0xa1b23c4d;
+const ETHERNET_HEADER_LEN: usize = 14;
+const SNAPLEN: u32 = 65535;
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads IP packets from a classic libpcap capture. Ethernet headers are stripped, so every
+/// record can go straight to `packet::unwrap`.
+#[derive(Debug)]
+pub struct PcapReader<R: Read> {
+    reader: R,
+    big_endian: bool, // The capture was written on a big-endian host
+    nanos: bool,      // Timestamps have nanosecond instead of microsecond resolution
+    linktype: u32,
+    record: Vec<u8>,  // The current record, reused between reads
+}
+
+impl PcapReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        PcapReader::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Read and check the global header
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0u8; 24];
+        reader.read_exact(&mut header)?;
+
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let (big_endian, nanos) = match magic {
+            MAGIC_MICROS => (false, false),
+            MAGIC_NANOS => (false, true),
+            _ if magic.swap_bytes() == MAGIC_MICROS => (true, false),
+            _ if magic.swap_bytes() == MAGIC_NANOS => (true, true),
+            _ => return Err(invalid_data(format!("not a pcap file: magic {magic:#010x}"))),
+        };
+
+        let mut pcap = PcapReader { reader, big_endian, nanos, linktype: 0, record: Vec::new() };
+        pcap.linktype = pcap.u32_at(&header, 20);
+        if pcap.linktype != LINKTYPE_RAW && pcap.linktype != LINKTYPE_ETHERNET {
+            return Err(invalid_data(format!("unsupported pcap linktype {}", pcap.linktype)));
+        }
+        Ok(pcap)
+    }
+
+    pub fn linktype(&self) -> u32 {
+        self.linktype
+    }
+
+    fn u32_at(&self, buf: &[u8], at: usize) -> u32 {
+        let bytes = [buf[at], buf[at + 1], buf[at + 2], buf[at + 3]];
+        if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    }
+
+    /// The next record's timestamp, since the Unix epoch, and its IP packet. `None` at the end of
+    /// the capture.
+    pub fn next_record(&mut self) -> io::Result<Option<(Duration, &[u8])>> {
+        let mut header = [0u8; 16];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let secs = self.u32_at(&header, 0) as u64;
+        let frac = self.u32_at(&header, 4);
+        let captured = self.u32_at(&header, 8) as usize;
+        if captured > SNAPLEN as usize * 4 {
+            return Err(invalid_data(format!("pcap record of {captured} bytes is implausibly large")));
+        }
+        let timestamp = if self.nanos {
+            Duration::new(secs, frac)
+        } else {
+            Duration::new(secs, 0) + Duration::from_micros(frac as u64)
+        };
+
+        self.record.resize(captured, 0);
+        self.reader.read_exact(&mut self.record)?;
+
+        let packet = if self.linktype == LINKTYPE_ETHERNET {
+            self.record
+                .get(ETHERNET_HEADER_LEN..)
+                .ok_or_else(|| invalid_data(format!("Ethernet record of {captured} bytes has no payload")))?
+        } else {
+            &self.record[..]
+        };
+        Ok(Some((timestamp, packet)))
+    }
+}
+
+/// Writes IP packets to a classic libpcap capture with microsecond timestamps
+#[derive(Debug)]
+pub struct PcapWriter<W: Write> {
+    writer: W,
+    linktype: u32,
+}
+
+impl PcapWriter<BufWriter<File>> {
+    pub fn create(path: impl AsRef<Path>, linktype: u32) -> io::Result<Self> {
+        PcapWriter::new(BufWriter::new(File::create(path)?), linktype)
+    }
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Write the global header. `linktype` is `LINKTYPE_RAW` or `LINKTYPE_ETHERNET`
+    pub fn new(mut writer: W, linktype: u32) -> io::Result<Self> {
+        if linktype != LINKTYPE_RAW && linktype != LINKTYPE_ETHERNET {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unsupported pcap linktype {linktype}")));
+        }
+
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&MAGIC_MICROS.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes()); // Version 2.4
+        header.extend_from_slice(&4u16.to_le_bytes());
+        header.extend_from_slice(&0i32.to_le_bytes()); // GMT offset
+        header.extend_from_slice(&0u32.to_le_bytes()); // Timestamp accuracy
+        header.extend_from_slice(&SNAPLEN.to_le_bytes());
+        header.extend_from_slice(&linktype.to_le_bytes());
+        writer.write_all(&header)?;
+
+        Ok(PcapWriter { writer, linktype })
+    }
+
+    /// Append an IP packet captured at `timestamp` since the Unix epoch. Ethernet captures get a
+    /// header with zeroed MACs and the EtherType taken from the IP version.
+    pub fn write_packet(&mut self, timestamp: Duration, packet: &[u8]) -> io::Result<()> {
+        let link_len = if self.linktype == LINKTYPE_ETHERNET { ETHERNET_HEADER_LEN } else { 0 };
+        let len = u32::try_from(packet.len() + link_len)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "packet too large for pcap"))?;
+
+        let mut header = [0u8; 16];
+        header[0..4].copy_from_slice(&(timestamp.as_secs() as u32).to_le_bytes());
+        header[4..8].copy_from_slice(&timestamp.subsec_micros().to_le_bytes());
+        header[8..12].copy_from_slice(&len.to_le_bytes());
+        header[12..16].copy_from_slice(&len.to_le_bytes());
+        self.writer.write_all(&header)?;
+
+        if link_len > 0 {
+            let ethertype: u16 = if packet.first().is_some_and(|b| b >> 4 == 6) { 0x86dd } else { 0x0800 };
+            let mut ethernet = [0u8; ETHERNET_HEADER_LEN];
+            ethernet[12..14].copy_from_slice(&ethertype.to_be_bytes());
+            self.writer.write_all(&ethernet)?;
+        }
+        self.writer.write_all(packet)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Flush and return the underlying writer
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// A capture of every raw packet a connection sends and receives, for debugging. Clones share
+/// one file, so a sender and receiver can record into the same capture. Disabled by default,
+/// which costs one `Option` check per packet.
+#[derive(Clone, Default)]
+pub struct PacketCapture {
+    inner: Option<Arc<Mutex<CaptureFile>>>,
+}
+
+struct CaptureFile {
+    writer: PcapWriter<Box<dyn Write + Send>>,
+    count: u64,
+    error: Option<io::ErrorKind>, // The first write error. Recording stops after it
+}
+
+impl PacketCapture {
+    /// Record into a new `LINKTYPE_RAW` pcap file at `path`
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::to_writer(BufWriter::new(File::create(path)?))
+    }
+
+    /// Record into any writer, e.g. an in-memory buffer
+    pub fn to_writer(writer: impl Write + Send + 'static) -> io::Result<Self> {
+        let writer = PcapWriter::new(Box::new(writer) as Box<dyn Write + Send>, LINKTYPE_RAW)?;
+        let file = CaptureFile { writer, count: 0, error: None };
+        Ok(PacketCapture { inner: Some(Arc::new(Mutex::new(file))) })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    /// Append a packet, timestamped now. Best effort: a failed write is remembered in `error`
+    /// and disables the capture rather than failing the connection.
+    pub fn record(&self, packet: &[u8]) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+        let Ok(mut file) = inner.lock() else {
+            return;
+        };
+        if file.error.is_some() {
+            return;
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        match file.writer.write_packet(now, packet) {
+            Ok(()) => file.count += 1,
+            Err(e) => file.error = Some(e.kind()),
+        }
+    }
+
+    /// How many packets were recorded
+    pub fn capture_count(&self) -> u64 {
+        self.inner.as_ref().and_then(|inner| inner.lock().ok()).map_or(0, |file| file.count)
+    }
+
+    /// The write error that stopped the capture, if any
+    pub fn error(&self) -> Option<io::ErrorKind> {
+        self.inner.as_ref().and_then(|inner| inner.lock().ok()).and_then(|file| file.error)
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        match self.inner.as_ref().map(|inner| inner.lock()) {
+            Some(Ok(mut file)) => file.writer.flush(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for CaptureFile {
+    fn drop(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+impl fmt::Debug for PacketCapture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PacketCapture")
+            .field("enabled", &self.is_enabled())
+            .field("count", &self.capture_count())
+            .finish()
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet;
+    use crate::packet::errors::HeaderError;
+    use crate::packet::parse_options::{ChecksumStatus, ParseOptions};
+    use crate::packet::test_utils;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    fn fixture_packets() -> Vec<Vec<u8>> {
+        let syn = [test_utils::get_ip_hex(), test_utils::get_tcp_hex()].concat();
+        let data = [
+            test_utils::get_ip_hex_with_payload(),
+            test_utils::get_tcp_hex_with_payload(),
+            test_utils::giant_payload(),
+        ]
+        .concat();
+        vec![hex::decode(syn).unwrap(), hex::decode(data).unwrap()]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for linktype in [LINKTYPE_RAW, LINKTYPE_ETHERNET] {
+            let mut writer = PcapWriter::new(Vec::new(), linktype).unwrap();
+            for (i, packet) in fixture_packets().iter().enumerate() {
+                writer.write_packet(Duration::new(1_700_000_000, i as u32 * 1000), packet).unwrap();
+            }
+            let capture = writer.into_inner().unwrap();
+
+            let mut reader = PcapReader::new(Cursor::new(capture)).unwrap();
+            assert_eq!(reader.linktype(), linktype);
+            for (i, packet) in fixture_packets().iter().enumerate() {
+                let (timestamp, record) = reader.next_record().unwrap().unwrap();
+                assert_eq!(timestamp, Duration::new(1_700_000_000, i as u32 * 1000));
+                assert_eq!(record, &packet[..]);
+            }
+            assert!(reader.next_record().unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let path = std::env::temp_dir().join(format!("net-pcap-test-{}.pcap", std::process::id()));
+        let mut writer = PcapWriter::create(&path, LINKTYPE_RAW).unwrap();
+        for packet in fixture_packets() {
+            writer.write_packet(Duration::ZERO, &packet).unwrap();
+        }
+        writer.flush().unwrap();
+        drop(writer);
+
+        let mut reader = PcapReader::open(&path).unwrap();
+        let mut count = 0;
+        while let Some((_, record)) = reader.next_record().unwrap() {
+            packet::unwrap(record).unwrap();
+            count += 1;
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn test_bundled_ethernet_capture() {
+        // Big-endian capture of one HTTP exchange, with short frames padded to 60 bytes
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/captures/http_exchange.pcap");
+        let mut reader = PcapReader::open(path).unwrap();
+        assert_eq!(reader.linktype(), LINKTYPE_ETHERNET);
+
+        let mut records = Vec::new();
+        while let Some((timestamp, record)) = reader.next_record().unwrap() {
+            let (iph, tcph, _) = packet::unwrap_with(record, &ParseOptions::lenient()).unwrap();
+            records.push((timestamp, iph.src_ip.to_string(), tcph.flags.to_string(), tcph.payload.len()));
+        }
+
+        assert_eq!(records.len(), 8);
+        assert_eq!(records[0], (Duration::new(1_700_000_000, 0), "192.168.1.10".into(), "ACK".into(), 0));
+        assert_eq!(records[1].3, 7);
+        assert_eq!(records[7].1, "93.184.216.34");
+        assert_eq!(records[7].2, "RST");
+        assert_eq!(records[3].0, Duration::new(1_700_000_000, 3_000_000));
+    }
+
+    #[test]
+    fn test_replay_offloaded_checksums() {
+        // Outgoing packets captured on the sending host carry whatever the NIC hadn't filled in yet
+        let mut offloaded = fixture_packets();
+        for packet in &mut offloaded {
+            packet[36..38].copy_from_slice(&[0x12, 0x34]);
+        }
+        let mut writer = PcapWriter::new(Vec::new(), LINKTYPE_RAW).unwrap();
+        for packet in &offloaded {
+            writer.write_packet(Duration::ZERO, packet).unwrap();
+        }
+        let capture = writer.into_inner().unwrap();
+
+        let mut reader = PcapReader::new(Cursor::new(capture)).unwrap();
+        let mut replayed = 0;
+        while let Some((_, record)) = reader.next_record().unwrap() {
+            assert_eq!(packet::unwrap(record).unwrap_err(), HeaderError::BadChecksum("TCP".to_string()));
+            let (_, tcph, report) = packet::unwrap_with(record, &ParseOptions::lenient()).unwrap();
+            assert_eq!(report.tcp, ChecksumStatus::Failed);
+            assert_eq!(tcph.checksum, 0x1234);
+            replayed += 1;
+        }
+        assert_eq!(replayed, 2);
+    }
+
+    #[test]
+    fn test_rejects_bad_files() {
+        let err = PcapReader::new(Cursor::new(vec![0u8; 24])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // A record cut short is an error, a clean end is not
+        let mut writer = PcapWriter::new(Vec::new(), LINKTYPE_RAW).unwrap();
+        writer.write_packet(Duration::ZERO, &fixture_packets()[0]).unwrap();
+        let mut capture = writer.into_inner().unwrap();
+        capture.truncate(capture.len() - 1);
+        let mut reader = PcapReader::new(Cursor::new(capture)).unwrap();
+        assert_eq!(reader.next_record().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        assert!(PcapWriter::new(Vec::new(), 42).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+use crate::ip::ip_header::IpHeader;
+use crate::packet;
+use crate::packet::errors::HeaderError;
+use crate::tcp::tcp_flags::TcpFlags;
+use crate::tcp::tcp_header::TcpHeader;
+use crate::tcp::wrap32::Wrap32;
+use std::net::SocketAddrV4;
+
+/// A whole TCP/IPv4 segment: both headers and the payload. Parse one from a received packet, or
+/// fill in the headers and `build` one to send.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TcpSegment {
+    pub iph: IpHeader,
+    pub tcph: TcpHeader,
+}
+
+impl TcpSegment {
+    pub fn new(iph: IpHeader, tcph: TcpHeader) -> Self {
+        TcpSegment { iph, tcph }
+    }
+
+    /// Parse a TCP/IPv4 packet, validating it like `packet::unwrap`
+    pub fn parse(packet: &[u8]) -> Result<Self, HeaderError> {
+        let (iph, tcph) = packet::unwrap(packet)?;
+        Ok(TcpSegment { iph, tcph })
+    }
+
+    /// Serialize into a new packet, like `packet::wrap`
+    pub fn build(&self) -> Result<Vec<u8>, HeaderError> {
+        packet::wrap(&self.iph, &self.tcph)
+    }
+
+    /// Serialize into the front of `buf`, like `packet::wrap_into`. Returns the packet length
+    pub fn build_into(&self, buf: &mut [u8]) -> Result<usize, HeaderError> {
+        packet::wrap_into(&self.iph, &self.tcph, buf)
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.tcph.payload
+    }
+
+    pub fn flags(&self) -> TcpFlags {
+        self.tcph.flags
+    }
+
+    pub fn seq_no(&self) -> Wrap32 {
+        self.tcph.seq_no
+    }
+
+    pub fn ack_no(&self) -> Wrap32 {
+        self.tcph.ack_no
+    }
+
+    /// Like `TcpHeader::seq_len`
+    pub fn seq_len(&self) -> usize {
+        self.tcph.seq_len()
+    }
+
+    /// The sender's address and port
+    pub fn src(&self) -> SocketAddrV4 {
+        SocketAddrV4::new(self.iph.src_ip, self.tcph.src_port)
+    }
+
+    /// The receiver's address and port
+    pub fn dst(&self) -> SocketAddrV4 {
+        SocketAddrV4::new(self.iph.dst_ip, self.tcph.dst_port)
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::test_utils;
+    use bytes::Bytes;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_parse_and_rebuild_wireshark_syn() {
+        let packet = hex::decode([test_utils::get_ip_hex(), test_utils::get_tcp_hex()].concat()).unwrap();
+        let segment = TcpSegment::parse(&packet).unwrap();
+
+        assert_eq!(segment.flags(), TcpFlags::SYN);
+        assert_eq!(segment.seq_no(), Wrap32::new(2753993875));
+        assert_eq!(segment.ack_no(), Wrap32::new(0));
+        assert_eq!(segment.seq_len(), 1);
+        assert!(segment.payload().is_empty());
+        assert_eq!(segment.src(), SocketAddrV4::new(Ipv4Addr::new(10, 110, 208, 106), 50871));
+        assert_eq!(segment.dst(), SocketAddrV4::new(Ipv4Addr::new(204, 44, 192, 60), 80));
+
+        assert_eq!(segment.build().unwrap(), packet);
+    }
+
+    #[test]
+    fn test_build_into_reused_buffer() {
+        let packet = hex::decode([test_utils::get_ip_hex(), test_utils::get_tcp_hex()].concat()).unwrap();
+        let mut segment = TcpSegment::parse(&packet).unwrap();
+        segment.tcph.flags = TcpFlags::ACK;
+        segment.tcph.options = Bytes::new();
+
+        let mut buf = [0u8; 1500];
+        for i in 0..1000u32 {
+            let len = (i % 100) as usize;
+            segment.tcph.seq_no = Wrap32::new(i * 100);
+            segment.tcph.payload = vec![i as u8; len].into();
+            segment.iph.total_len = (40 + len) as u16;
+            segment.iph.id = i as u16;
+
+            let n = segment.build_into(&mut buf).unwrap();
+            assert_eq!(n, 40 + len);
+            let parsed = TcpSegment::parse(&buf[..n]).unwrap();
+            assert_eq!((parsed.seq_no(), parsed.payload()), (segment.seq_no(), segment.payload()));
+        }
+
+        let needed = segment.build().unwrap().len();
+        let result = segment.build_into(&mut buf[..needed - 1]);
+        assert!(matches!(result, Err(HeaderError::BufferTooSmall { .. })), "{result:?}");
+    }
+
+    #[test]
+    fn test_parse_and_rebuild_with_payload() {
+        let packet = hex::decode(
+            [test_utils::get_ip_hex_with_payload(), test_utils::get_tcp_hex_with_payload(), test_utils::giant_payload()]
+                .concat(),
+        )
+        .unwrap();
+        let segment = TcpSegment::parse(&packet).unwrap();
+        assert_eq!(segment.payload().len(), packet.len() - 52);
+        assert_eq!(segment.seq_len(), segment.payload().len());
+        assert_eq!(TcpSegment::new(segment.iph.clone(), segment.tcph.clone()).build().unwrap(), packet);
+
+        assert!(TcpSegment::parse(&packet[..packet.len() - 1]).is_err());
+    }
+}
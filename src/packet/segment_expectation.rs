@@ -0,0 +1,265 @@
+use crate::packet;
+use crate::tcp::tcp_flags::TcpFlags;
+use crate::tcp::tcp_header::TcpHeader;
+use crate::tcp::wrap32::Wrap32;
+use std::fmt::Write;
+
+/// The expected fields of one emitted segment. Fields left unset match anything.
+/// `seq` is relative to our ISN and `ack` is relative to the peer's ISN.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SegmentExpectation {
+    flags: Option<TcpFlags>,
+    seq: Option<u32>,
+    ack: Option<u32>,
+    payload_len: Option<usize>,
+    window: Option<u16>,
+}
+
+impl SegmentExpectation {
+    /// Expect a segment carrying exactly `flags`
+    pub fn new(flags: TcpFlags) -> Self {
+        SegmentExpectation {
+            flags: Some(flags),
+            ..SegmentExpectation::default()
+        }
+    }
+
+    pub fn seq(mut self, seq: u32) -> Self {
+        self.seq = Some(seq);
+        self
+    }
+
+    pub fn ack(mut self, ack: u32) -> Self {
+        self.ack = Some(ack);
+        self
+    }
+
+    pub fn payload_len(mut self, len: usize) -> Self {
+        self.payload_len = Some(len);
+        self
+    }
+
+    pub fn window(mut self, window: u16) -> Self {
+        self.window = Some(window);
+        self
+    }
+}
+
+const COLUMNS: [&str; 5] = ["flags", "seq", "ack", "len", "win"];
+
+/// The rendered columns of an actual segment, relative to the ISNs
+fn actual_row(tcph: &TcpHeader, isn: Wrap32, peer_isn: Wrap32) -> [String; 5] {
+    let ack = if tcph.flags.contains(TcpFlags::ACK) {
+        tcph.ack_no.value().wrapping_sub(peer_isn.value()).to_string()
+    } else {
+        "-".to_string()
+    };
+    [
+        tcph.flags.to_string(),
+        tcph.seq_no.value().wrapping_sub(isn.value()).to_string(),
+        ack,
+        tcph.payload.len().to_string(),
+        tcph.window.to_string(),
+    ]
+}
+
+/// The rendered columns of an expectation. Unset fields render as `*`
+fn expected_row(exp: &SegmentExpectation) -> [String; 5] {
+    fn or_any<T: ToString>(value: Option<T>) -> String {
+        value.map_or("*".to_string(), |v| v.to_string())
+    }
+    [
+        or_any(exp.flags),
+        or_any(exp.seq),
+        or_any(exp.ack),
+        or_any(exp.payload_len),
+        or_any(exp.window),
+    ]
+}
+
+/// Compare emitted packets against expectations field by field. Returns `None` when they match,
+/// otherwise a table of both sides with differing fields marked `!`.
+pub fn diff_segments(
+    actual: &[Vec<u8>],
+    expected: &[SegmentExpectation],
+    isn: Wrap32,
+    peer_isn: Wrap32,
+) -> Option<String> {
+    let mut rows = Vec::new();
+    let mut first_divergence = None;
+
+    for i in 0..actual.len().max(expected.len()) {
+        let exp = expected.get(i).map(expected_row);
+        let act = actual.get(i).map(|packet| match packet::unwrap(packet) {
+            Ok((_, tcph)) => Ok(actual_row(&tcph, isn, peer_isn)),
+            Err(e) => Err(format!("unparseable: {e}")),
+        });
+
+        // A field differs unless the expectation is a wildcard or renders identically
+        let mut differs = [false; 5];
+        match (&exp, &act) {
+            (Some(exp), Some(Ok(act))) => {
+                for (col, (e, a)) in exp.iter().zip(act).enumerate() {
+                    differs[col] = e != "*" && e != a;
+                }
+            }
+            _ => differs = [true; 5],
+        }
+        if first_divergence.is_none() && differs.contains(&true) {
+            first_divergence = Some(i);
+        }
+        rows.push((i, exp, act, differs));
+    }
+
+    let first = first_divergence?;
+    let mut out = format!("segments differ, first divergence at #{first}\n");
+    let _ = write!(out, "{:>3}  {:<8}", "#", "side");
+    for col in COLUMNS {
+        let _ = write!(out, "  {col:<12}");
+    }
+    out.push('\n');
+
+    for (i, exp, act, differs) in rows {
+        let _ = write!(out, "{i:>3}  {:<8}", "expected");
+        match exp {
+            Some(cells) => cells.iter().for_each(|cell| {
+                let _ = write!(out, "  {cell:<12}");
+            }),
+            None => out.push_str("  (none)"),
+        }
+        let _ = write!(out, "\n{:>3}  {:<8}", "", "actual");
+        match act {
+            Some(Ok(cells)) => cells.iter().zip(differs).for_each(|(cell, differs)| {
+                let cell = if differs { format!("{cell}!") } else { cell.clone() };
+                let _ = write!(out, "  {cell:<12}");
+            }),
+            Some(Err(e)) => out.push_str(&format!("  ({e})")),
+            None => out.push_str("  (none)"),
+        }
+        out.push('\n');
+    }
+    Some(out)
+}
+
+/// Assert that emitted packets match a list of `SegmentExpectation`s, printing a field-by-field
+/// table on mismatch. Without ISNs, `seq` and `ack` are absolute.
+///
+/// `assert_segments!(packets, [exp1, exp2])` or `assert_segments!(packets, isn, peer_isn, [exp1])`
+#[macro_export]
+macro_rules! assert_segments {
+    ($actual:expr, [$($exp:expr),* $(,)?]) => {
+        $crate::assert_segments!(
+            $actual,
+            $crate::tcp::wrap32::Wrap32::new(0),
+            $crate::tcp::wrap32::Wrap32::new(0),
+            [$($exp),*]
+        )
+    };
+    ($actual:expr, $isn:expr, $peer_isn:expr, [$($exp:expr),* $(,)?]) => {
+        if let Some(diff) = $crate::packet::segment_expectation::diff_segments(
+            &$actual,
+            &[$($exp),*],
+            $isn,
+            $peer_isn,
+        ) {
+            panic!("{diff}");
+        }
+    };
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ip::ip_header::IpHeader;
+
+    const ISN: u32 = 1000;
+    const PEER_ISN: u32 = 5000;
+
+    fn packet(flags: TcpFlags, seq: u32, ack: u32, payload: &[u8]) -> Vec<u8> {
+        let iph = IpHeader {
+            version: 4,
+            ihl: 5,
+            total_len: (40 + payload.len()) as u16,
+            ttl: 64,
+            protocol: 6,
+            ..IpHeader::default()
+        };
+        let tcph = TcpHeader {
+            seq_no: Wrap32::new(seq),
+            ack_no: Wrap32::new(ack),
+            data_offset: 5,
+            flags,
+            window: 64240,
+            payload: payload.to_vec().into(),
+            ..TcpHeader::default()
+        };
+        packet::wrap(&iph, &tcph).unwrap()
+    }
+
+    /// Our side of a handshake, one request, and an active close
+    fn client_packets() -> Vec<Vec<u8>> {
+        vec![
+            packet(TcpFlags::SYN, ISN, 0, b""),
+            packet(TcpFlags::ACK, ISN + 1, PEER_ISN + 1, b""),
+            packet(TcpFlags::ACK | TcpFlags::PSH, ISN + 1, PEER_ISN + 1, b"GET / HTTP/1.1\r\n\r\n"),
+            packet(TcpFlags::ACK | TcpFlags::FIN, ISN + 19, PEER_ISN + 1, b""),
+            packet(TcpFlags::ACK, ISN + 20, PEER_ISN + 2, b""),
+        ]
+    }
+
+    #[test]
+    fn test_handshake_and_teardown_match() {
+        let (isn, peer_isn) = (Wrap32::new(ISN), Wrap32::new(PEER_ISN));
+        assert_segments!(client_packets(), isn, peer_isn, [
+            SegmentExpectation::new(TcpFlags::SYN).seq(0).payload_len(0),
+            SegmentExpectation::new(TcpFlags::ACK).seq(1).ack(1),
+            SegmentExpectation::new(TcpFlags::ACK | TcpFlags::PSH).seq(1).payload_len(18),
+            SegmentExpectation::new(TcpFlags::ACK | TcpFlags::FIN).seq(19).ack(1),
+            SegmentExpectation::new(TcpFlags::ACK).seq(20).ack(2).window(64240),
+        ]);
+    }
+
+    #[test]
+    fn test_absolute_form() {
+        assert_segments!([packet(TcpFlags::RST, 7, 0, b"")], [SegmentExpectation::new(TcpFlags::RST).seq(7)]);
+    }
+
+    #[test]
+    fn test_diff_marks_fields_and_first_divergence() {
+        let expected = [
+            SegmentExpectation::new(TcpFlags::SYN).seq(0),
+            SegmentExpectation::new(TcpFlags::ACK).seq(1).ack(2), // Off by one after the FIN
+        ];
+        let actual = client_packets();
+        let diff = diff_segments(&actual[..2], &expected, Wrap32::new(ISN), Wrap32::new(PEER_ISN)).unwrap();
+
+        let lines: Vec<&str> = diff.lines().collect();
+        assert_eq!(lines[0], "segments differ, first divergence at #1");
+        assert!(lines[1].starts_with("  #  side      flags"));
+        assert!(!lines[3].contains('!')); // Row 0 matches
+        assert!(lines[5].contains("1!"));
+        assert_eq!(lines[5].matches('!').count(), 1);
+    }
+
+    #[test]
+    fn test_diff_missing_and_extra_segments() {
+        let expected = [SegmentExpectation::new(TcpFlags::SYN)];
+        let actual = client_packets();
+        let diff = diff_segments(&actual[..2], &expected, Wrap32::new(ISN), Wrap32::new(PEER_ISN)).unwrap();
+        assert!(diff.starts_with("segments differ, first divergence at #1"));
+        assert!(diff.contains("(none)"));
+
+        let mut corrupt = actual[0].clone();
+        corrupt[30] ^= 0xff; // Breaks the TCP checksum
+        let diff = diff_segments(&[corrupt], &expected, Wrap32::new(0), Wrap32::new(0)).unwrap();
+        assert!(diff.contains("unparseable"));
+    }
+
+    #[test]
+    #[should_panic(expected = "first divergence at #0")]
+    fn test_assert_segments_panics() {
+        assert_segments!(client_packets(), [SegmentExpectation::new(TcpFlags::RST)]);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::ip::ip_header::IpHeader;
+use crate::packet;
+use crate::packet::errors::HeaderError;
+use crate::tcp::flow_key::Direction;
+use crate::tcp::tcp_header::TcpHeader;
+use std::time::Duration;
+
+/// One segment seen on a connection, for logging to JSON with the `serde` feature
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(deny_unknown_fields))]
+pub struct SegmentRecord {
+    pub timestamp: Duration, // Since the start of the capture or connection
+    pub direction: Direction,
+    pub iph: IpHeader,
+    pub tcph: TcpHeader,
+}
+
+impl SegmentRecord {
+    /// Parse a TCP/IPv4 packet into a record
+    pub fn from_packet(timestamp: Duration, direction: Direction, packet: &[u8]) -> Result<Self, HeaderError> {
+        let (iph, tcph) = packet::unwrap(packet)?;
+        Ok(SegmentRecord { timestamp, direction, iph, tcph })
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use crate::ip::ip_flags::IpFlags;
+    use crate::packet::test_utils;
+    use crate::tcp::tcp_flags::TcpFlags;
+    use crate::tcp::wrap32::Wrap32;
+    use serde_json::json;
+
+    fn wireshark_syn() -> SegmentRecord {
+        let packet = hex::decode([test_utils::get_ip_hex(), test_utils::get_tcp_hex()].concat()).unwrap();
+        SegmentRecord::from_packet(Duration::from_millis(1500), Direction::Outbound, &packet).unwrap()
+    }
+
+    #[test]
+    fn test_syn_json_shape() {
+        let value = serde_json::to_value(wireshark_syn()).unwrap();
+        let expected = json!({
+            "timestamp": {"secs": 1, "nanos": 500_000_000},
+            "direction": "outbound",
+            "iph": {
+                "version": 4,
+                "ihl": 5,
+                "tos": 0,
+                "total_len": 64,
+                "id": 0,
+                "flags": ["DF"],
+                "frag_offset": 0,
+                "ttl": 64,
+                "protocol": 6,
+                "checksum": 0xd376,
+                "src_ip": "10.110.208.106",
+                "dst_ip": "204.44.192.60",
+                "options": [],
+            },
+            "tcph": {
+                "src_port": 50871,
+                "dst_port": 80,
+                "seq_no": 2753993875u32,
+                "ack_no": 0,
+                "data_offset": 11,
+                "reserved": 0,
+                "flags": ["SYN"],
+                "window": 65535,
+                "checksum": 0x9297,
+                "urgent": 0,
+                "options": hex::decode("020405b4010303060101080abb6879f80000000004020000").unwrap(),
+                "payload": [],
+            },
+        });
+        assert_eq!(value, expected);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut record = wireshark_syn();
+        record.direction = Direction::Inbound;
+        record.tcph.flags = TcpFlags::all();
+        record.tcph.payload = b"hello".to_vec().into();
+        record.iph.flags = IpFlags::DF | IpFlags::MF;
+
+        let json = serde_json::to_string(&record).unwrap();
+        assert_eq!(serde_json::from_str::<SegmentRecord>(&json).unwrap(), record);
+
+        for bits in 0..=u8::MAX {
+            let flags = TcpFlags::from_bits_retain(bits);
+            let json = serde_json::to_string(&flags).unwrap();
+            assert_eq!(serde_json::from_str::<TcpFlags>(&json).unwrap(), flags);
+        }
+        assert_eq!(serde_json::to_string(&Wrap32::new(7)).unwrap(), "7");
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        let mut value = serde_json::to_value(wireshark_syn()).unwrap();
+        value["tcph"]["flags"] = json!(["SYN", "BOGUS"]);
+        let err = serde_json::from_value::<SegmentRecord>(value.clone()).unwrap_err();
+        assert!(err.to_string().contains("Unknown flag: \"BOGUS\""), "{err}");
+
+        // Raw bits aren't accepted in place of names
+        value["tcph"]["flags"] = json!(2);
+        assert!(serde_json::from_value::<SegmentRecord>(value.clone()).is_err());
+
+        value["tcph"]["flags"] = json!(["syn"]);
+        value["iph"]["ecn"] = json!(0);
+        let err = serde_json::from_value::<SegmentRecord>(value.clone()).unwrap_err();
+        assert!(err.to_string().contains("unknown field `ecn`"), "{err}");
+
+        value["iph"].as_object_mut().unwrap().remove("ecn");
+        value["extra"] = json!(true);
+        assert!(serde_json::from_value::<SegmentRecord>(value).is_err());
+    }
+}
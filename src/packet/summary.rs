@@ -0,0 +1,236 @@
+use crate::ip::ip_flags::IpFlags;
+use crate::ip::ip_header::IpHeader;
+use crate::tcp::tcp_flags::TcpFlags;
+use crate::tcp::tcp_header::TcpHeader;
+use std::fmt;
+
+/// tcpdump's one-letter flag codes, in its print order. ACK prints as "."
+const FLAG_CODES: [(TcpFlags, &str); 8] = [
+    (TcpFlags::FIN, "F"),
+    (TcpFlags::SYN, "S"),
+    (TcpFlags::RST, "R"),
+    (TcpFlags::PSH, "P"),
+    (TcpFlags::ACK, "."),
+    (TcpFlags::URG, "U"),
+    (TcpFlags::ECE, "E"),
+    (TcpFlags::CWR, "W"),
+];
+
+/// A one-line, tcpdump-style description of a TCP/IPv4 packet. Formats lazily, so it can be
+/// written straight to a log without building a `String` first.
+#[derive(Debug, Clone, Copy)]
+pub struct Summary<'a> {
+    pub iph: &'a IpHeader,
+    pub tcph: &'a TcpHeader,
+    pub verbose: bool, // Prefix the IP fields and show whether each checksum is correct
+}
+
+/// `10.0.0.1:50871 > 10.0.0.2:80 Flags [S], seq 1, win 65535, options [mss 1460], length 0`
+pub fn summary(iph: &IpHeader, tcph: &TcpHeader) -> String {
+    Summary { iph, tcph, verbose: false }.to_string()
+}
+
+/// Like `summary`, prefixed with the TOS, TTL, ID and flags of the IP header and with both
+/// checksums checked
+pub fn summary_verbose(iph: &IpHeader, tcph: &TcpHeader) -> String {
+    Summary { iph, tcph, verbose: true }.to_string()
+}
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (iph, tcph) = (self.iph, self.tcph);
+        if self.verbose {
+            write!(f, "IP (tos {:#x}, ttl {}, id {}, offset {}, flags [", iph.tos, iph.ttl, iph.id, iph.frag_offset as usize * 8)?;
+            if iph.flags.is_empty() {
+                f.write_str("none")?;
+            } else {
+                write!(f, "{}", iph.flags)?;
+            }
+            write!(f, "], proto {}, length {}, cksum {:#06x} ", iph.protocol, iph.total_len, iph.checksum)?;
+            write_checksum_status(f, iph.checksum, ip_checksum(iph))?;
+            f.write_str(") ")?;
+        }
+
+        write!(f, "{}:{} > {}:{} ", iph.src_ip, tcph.src_port, iph.dst_ip, tcph.dst_port)?;
+        write_flags(f, tcph.flags)?;
+        if self.verbose {
+            write!(f, ", cksum {:#06x} ", tcph.checksum)?;
+            write_checksum_status(f, tcph.checksum, tcp_checksum(iph, tcph))?;
+        }
+        write_segment(f, tcph)
+    }
+}
+
+/// `50871 > 80 Flags [S], seq 1, win 65535, length 0`
+impl fmt::Display for TcpHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} > {} ", self.src_port, self.dst_port)?;
+        write_flags(f, self.flags)?;
+        write_segment(f, self)
+    }
+}
+
+/// `10.0.0.1 > 10.0.0.2 proto 6, ttl 64, length 64`
+impl fmt::Display for IpHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} > {} proto {}, ttl {}, length {}", self.src_ip, self.dst_ip, self.protocol, self.ttl, self.total_len)?;
+        if self.flags.contains(IpFlags::MF) || self.frag_offset != 0 {
+            write!(f, ", frag offset {}", self.frag_offset as usize * 8)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_flags(f: &mut fmt::Formatter<'_>, flags: TcpFlags) -> fmt::Result {
+    f.write_str("Flags [")?;
+    if flags.is_empty() {
+        f.write_str("none")?;
+    }
+    for (flag, code) in FLAG_CODES {
+        if flags.contains(flag) {
+            f.write_str(code)?;
+        }
+    }
+    f.write_str("]")
+}
+
+/// Everything after the flags: sequence range, ack, window, urgent pointer, options and length
+fn write_segment(f: &mut fmt::Formatter<'_>, tcph: &TcpHeader) -> fmt::Result {
+    let seq = tcph.seq_no.value();
+    if tcph.payload.is_empty() {
+        write!(f, ", seq {seq}")?;
+    } else {
+        write!(f, ", seq {seq}:{}", seq.wrapping_add(tcph.payload_len() as u32))?;
+    }
+    if tcph.flags.contains(TcpFlags::ACK) {
+        write!(f, ", ack {}", tcph.ack_no.value())?;
+    }
+    write!(f, ", win {}", tcph.window)?;
+    if tcph.flags.contains(TcpFlags::URG) {
+        write!(f, ", urg {}", tcph.urgent)?;
+    }
+
+    if !tcph.options.is_empty() {
+        f.write_str(", options [")?;
+        match tcph.tcp_options() {
+            Ok(options) => {
+                for (i, option) in options.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{option}")?;
+                }
+            }
+            // Show what was on the wire rather than hiding a malformed options area
+            Err(_) => write!(f, "bad opts 0x{}", hex::encode(&tcph.options))?,
+        }
+        f.write_str("]")?;
+    }
+    write!(f, ", length {}", tcph.payload_len())
+}
+
+fn write_checksum_status(f: &mut fmt::Formatter<'_>, found: u16, expected: Option<u16>) -> fmt::Result {
+    match expected {
+        Some(expected) if expected == found => f.write_str("(correct)"),
+        Some(expected) => write!(f, "(incorrect -> {expected:#06x})"),
+        None => f.write_str("(unverified)"),
+    }
+}
+
+/// The checksum `iph` should carry, or `None` if it can't be serialized
+fn ip_checksum(iph: &IpHeader) -> Option<u16> {
+    let mut buf = [0u8; 60];
+    iph.serialize(&mut buf).ok()?;
+    Some(u16::from_be_bytes([buf[10], buf[11]]))
+}
+
+/// The checksum `tcph` should carry under `iph`'s pseudo-header
+fn tcp_checksum(iph: &IpHeader, tcph: &TcpHeader) -> Option<u16> {
+    let mut buf = vec![0u8; tcph.header_len() + tcph.payload_len()];
+    tcph.serialize(&mut buf, iph).ok()?;
+    Some(u16::from_be_bytes([buf[16], buf[17]]))
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet;
+    use crate::packet::test_utils;
+    use crate::tcp::wrap32::Wrap32;
+
+    fn wireshark_syn() -> (IpHeader, TcpHeader) {
+        let packet = hex::decode([test_utils::get_ip_hex(), test_utils::get_tcp_hex()].concat()).unwrap();
+        packet::unwrap(&packet).unwrap()
+    }
+
+    fn wireshark_data() -> (IpHeader, TcpHeader) {
+        let packet = [
+            test_utils::get_ip_hex_with_payload(),
+            test_utils::get_tcp_hex_with_payload(),
+            test_utils::giant_payload(),
+        ]
+        .concat();
+        packet::unwrap(&hex::decode(packet).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_summary_syn() {
+        let (iph, tcph) = wireshark_syn();
+        assert_eq!(
+            summary(&iph, &tcph),
+            "10.110.208.106:50871 > 204.44.192.60:80 Flags [S], seq 2753993875, win 65535, \
+            options [mss 1460,wscale 6,TS val 3144186360 ecr 0,sackOK], length 0"
+        );
+        assert_eq!(
+            tcph.to_string(),
+            "50871 > 80 Flags [S], seq 2753993875, win 65535, \
+            options [mss 1460,wscale 6,TS val 3144186360 ecr 0,sackOK], length 0"
+        );
+        assert_eq!(iph.to_string(), "10.110.208.106 > 204.44.192.60 proto 6, ttl 64, length 64");
+    }
+
+    #[test]
+    fn test_summary_data() {
+        let (iph, tcph) = wireshark_data();
+        assert_eq!(
+            summary(&iph, &tcph),
+            "204.44.192.60:80 > 10.110.208.106:50871 Flags [.], seq 1654659911:1654661285, \
+            ack 2753994376, win 235, options [TS val 3199819530 ecr 3144186437], length 1374"
+        );
+    }
+
+    #[test]
+    fn test_summary_verbose() {
+        let (iph, tcph) = wireshark_syn();
+        assert_eq!(
+            summary_verbose(&iph, &tcph),
+            "IP (tos 0x0, ttl 64, id 0, offset 0, flags [DF], proto 6, length 64, cksum 0xd376 (correct)) \
+            10.110.208.106:50871 > 204.44.192.60:80 Flags [S], cksum 0x9297 (correct), seq 2753993875, \
+            win 65535, options [mss 1460,wscale 6,TS val 3144186360 ecr 0,sackOK], length 0"
+        );
+
+        // A hand-built header has no checksum yet
+        let (iph, mut tcph) = wireshark_data();
+        tcph.checksum = 0;
+        assert!(summary_verbose(&iph, &tcph).contains("cksum 0x0000 (incorrect -> 0x71aa)"));
+    }
+
+    #[test]
+    fn test_summary_fallbacks() {
+        let (iph, mut tcph) = wireshark_syn();
+        tcph.flags = TcpFlags::FIN | TcpFlags::PSH | TcpFlags::ACK;
+        tcph.options = hex::decode("fd04abcd").unwrap().into();
+        assert!(summary(&iph, &tcph).contains("Flags [FP.], seq 2753993875, ack 0, win 65535, options [unknown-253 0xabcd]"));
+
+        // A malformed options area is shown raw
+        tcph.flags = TcpFlags::empty();
+        tcph.options = hex::decode("0201").unwrap().into();
+        assert!(summary(&iph, &tcph).contains("Flags [none], seq 2753993875, win 65535, options [bad opts 0x0201]"));
+
+        tcph.seq_no = Wrap32::new(u32::MAX);
+        tcph.payload = vec![0; 2].into();
+        assert!(summary(&iph, &tcph).contains("seq 4294967295:1,"));
+    }
+}
@@ -1,37 +1,207 @@
+use crate::ip::ip_flags::IpFlags;
 use crate::ip::ip_header::IpHeader;
+use crate::ip::ipv6_header::Ipv6Header;
 use crate::tcp::tcp_header::TcpHeader;
 use crate::packet::errors::HeaderError;
+use crate::packet::header_ref::{IpHeaderRef, TcpHeaderRef};
+use crate::packet::parse_options::{ChecksumReport, ParseOptions};
 
 /// Wrap an `IPHeader` and `TCPHeader` into a packet. Zero allocation.
 pub fn wrap_into(iph: &IpHeader, tcph: &TcpHeader, packet: &mut [u8]) -> Result<usize, HeaderError> {
-    let ip_len = iph.serialize(&mut packet[0..20])?;
-    let tcp_length = tcph.serialize(&mut packet[20..], iph)?;
+    let ip_len = iph.serialize(packet)?;
+    let tcp_length = tcph.serialize(&mut packet[ip_len..], iph)?;
     Ok(ip_len + tcp_length)
 }
 
 /// Wrap an `IPHeader` and `TCPHeader` into a packet. Allocs a new `Vec<u8>` for convenience.
+///
+/// ```
+/// use bytes::Bytes;
+/// use net::ip::ip_header::IpHeader;
+/// use net::packet;
+/// use net::tcp::tcp_flags::TcpFlags;
+/// use net::tcp::tcp_header::TcpHeader;
+/// use std::net::Ipv4Addr;
+///
+/// let iph = IpHeader {
+///     version: 4,
+///     ihl: 5,
+///     total_len: 45, // 20 + 20 + 5 bytes of payload
+///     ttl: 64,
+///     protocol: 6,
+///     src_ip: Ipv4Addr::new(10, 0, 0, 1),
+///     dst_ip: Ipv4Addr::new(10, 0, 0, 2),
+///     ..IpHeader::default()
+/// };
+/// let tcph = TcpHeader {
+///     src_port: 50000,
+///     dst_port: 80,
+///     data_offset: 5,
+///     flags: TcpFlags::PSH | TcpFlags::ACK,
+///     payload: Bytes::from_static(b"hello"),
+///     ..TcpHeader::default()
+/// };
+///
+/// let packet = packet::wrap(&iph, &tcph).unwrap();
+/// assert_eq!(packet.len(), 45);
+///
+/// let (parsed_iph, parsed_tcph) = packet::unwrap(&packet).unwrap();
+/// assert_eq!(parsed_iph.dst_ip, Ipv4Addr::new(10, 0, 0, 2));
+/// assert_eq!(parsed_tcph.payload, &b"hello"[..]);
+/// ```
 pub fn wrap(iph: &IpHeader, tcph: &TcpHeader) -> Result<Vec<u8>, HeaderError> {
-    let tcp_len = tcph.data_offset as usize * 4 + tcph.payload.len();
-    let total_len = 20 + tcp_len;
+    let tcp_len = tcph.header_len() + tcph.payload.len();
+    let total_len = iph.header_len() + tcp_len;
     let mut packet = vec![0u8; total_len];
 
     wrap_into(iph, tcph, &mut packet)?;
     Ok(packet)
 }
 
-/// Unwrap a packet into `IPHeader` and `TCPHeader` objects. Zero allocation.
+/// Whether raw sockets here want the IP total length and fragment offset in host byte order
+/// when IP_HDRINCL is set. Apple systems kept this quirk of the old BSD stacks; Linux, FreeBSD 11
+/// and later, and OpenBSD take network byte order like every other field.
+pub const HDRINCL_HOST_BYTE_ORDER: bool = cfg!(any(target_os = "macos", target_os = "ios"));
+
+/// Rewrite the total length and the flags and fragment offset of the IPv4 header at the front of
+/// `packet` from network to host byte order. On a little-endian host that swaps both fields'
+/// bytes, so applying it twice restores the packet; on a big-endian host it changes nothing.
+/// The checksum is left alone, as BSD kernels recompute it on send.
+pub fn to_host_byte_order(packet: &mut [u8]) -> Result<(), HeaderError> {
+    if packet.len() < 8 {
+        return Err(HeaderError::BufferTooSmall { expected: 8, found: packet.len() });
+    }
+    for field in [2, 6] {
+        let value = u16::from_be_bytes([packet[field], packet[field + 1]]);
+        packet[field..field + 2].copy_from_slice(&value.to_ne_bytes());
+    }
+    Ok(())
+}
+
+/// Like `wrap`, with the byte order fixups this platform's raw sockets need applied, for callers
+/// writing to a header-included socket themselves. `rawsocket::send_packet` applies them on its
+/// own, so give it packets from `wrap` instead.
+pub fn wrap_for_platform(iph: &IpHeader, tcph: &TcpHeader) -> Result<Vec<u8>, HeaderError> {
+    let mut packet = wrap(iph, tcph)?;
+    if HDRINCL_HOST_BYTE_ORDER {
+        to_host_byte_order(&mut packet)?;
+    }
+    Ok(packet)
+}
+
+/// Wrap an `IPHeader` and `TCPHeader` into IP fragments of at most `mtu` bytes each. Every fragment
+/// shares the `id` of `iph`. Returns a single packet if it fits, and errors instead of fragmenting if
+/// `DF` is set.
+pub fn wrap_fragmented(iph: &IpHeader, tcph: &TcpHeader, mtu: usize) -> Result<Vec<Vec<u8>>, HeaderError> {
+    let tcp_len = tcph.header_len() + tcph.payload.len();
+    let len = iph.header_len() + tcp_len;
+    if len <= mtu {
+        return Ok(vec![wrap(iph, tcph)?]);
+    }
+    if iph.flags.contains(IpFlags::DF) {
+        return Err(HeaderError::FragmentationNeeded { len, mtu })
+    }
+
+    // The TCP checksum covers the whole segment, so serialize it once before splitting it up
+    let mut segment = vec![0u8; tcp_len];
+    tcph.serialize(&mut segment, iph)?;
+
+    let mut frag_iph = iph.clone();
+    let mut fragments = Vec::new();
+    let mut offset = 0;
+    while offset < segment.len() {
+        if offset > 0 {
+            frag_iph.options = copied_options(&iph.options);
+        }
+        let header_len = frag_iph.header_len();
+
+        // Fragment offsets count 8-byte units, so all but the last fragment carry a multiple of 8
+        let max_data = mtu.saturating_sub(header_len) & !7;
+        if max_data == 0 {
+            return Err(HeaderError::MtuTooSmall(mtu))
+        }
+        let end = usize::min(offset + max_data, segment.len());
+
+        frag_iph.ihl = (header_len / 4) as u8;
+        frag_iph.total_len = (header_len + end - offset) as u16;
+        frag_iph.frag_offset = iph.frag_offset + (offset / 8) as u16;
+        frag_iph.flags.set(IpFlags::MF, end < segment.len() || iph.flags.contains(IpFlags::MF));
+
+        let mut packet = vec![0u8; header_len + end - offset];
+        frag_iph.serialize(&mut packet)?;
+        packet[header_len..].copy_from_slice(&segment[offset..end]);
+        fragments.push(packet);
+        offset = end;
+    }
+
+    Ok(fragments)
+}
+
+/// The IP options with the copied flag set. RFC 791 repeats only these in the fragments after the
+/// first.
+fn copied_options(options: &[u8]) -> Vec<u8> {
+    let mut copied = Vec::new();
+    let mut i = 0;
+    while i < options.len() {
+        match options[i] {
+            0 => break,  // End of options list
+            1 => i += 1, // No-op
+            kind => {
+                let remaining = options.len() - i;
+                let len = options.get(i + 1).map_or(remaining, |&len| (len as usize).clamp(2, remaining));
+                if kind & 0x80 != 0 {
+                    copied.extend_from_slice(&options[i..i + len]);
+                }
+                i += len;
+            }
+        }
+    }
+    copied
+}
+
+/// Unwrap a packet into `IPHeader` and `TCPHeader` objects. Zero allocation. The TCP header starts
+/// after `ihl * 4` bytes, and a packet shorter than its total length is a `TruncatedPacket` error.
 pub fn unwrap_from(packet: &[u8], iph: &mut IpHeader, tcph: &mut TcpHeader) -> Result<usize, HeaderError> {
-    let parsed_iph = IpHeader::parse(&packet[0..20])?;
+    let parsed_iph = IpHeader::parse(packet)?;
     let total_len = parsed_iph.total_len as usize;
+    let header_len = parsed_iph.ihl as usize * 4;
     *iph = parsed_iph;
 
-    let parsed_tcph = TcpHeader::parse(&packet[20..total_len], iph)?;
+    // `IpHeader::parse` checked that the total length fits in the packet. Trailing padding is dropped
+    let parsed_tcph = TcpHeader::parse(&packet[header_len..total_len], iph)?;
     *tcph = parsed_tcph;
 
     Ok(total_len)
 }
 
+/// Unwrap a packet into borrowed header views. Zero allocation and zero copy, for the receive
+/// hot path. Validates the same things as `unwrap_from`.
+pub fn unwrap_ref(packet: &[u8]) -> Result<(IpHeaderRef<'_>, TcpHeaderRef<'_>), HeaderError> {
+    let iph = IpHeaderRef::parse(packet)?;
+    let tcph = TcpHeaderRef::parse(iph.payload(), &iph)?;
+    Ok((iph, tcph))
+}
+
+/// Unpack a packet like `unwrap`, enforcing only the checksums `options` asks for. The report says
+/// which checksums were wrong but accepted.
+pub fn unwrap_with(packet: &[u8], options: &ParseOptions) -> Result<(IpHeader, TcpHeader, ChecksumReport), HeaderError> {
+    let (iph, ip) = IpHeaderRef::parse_with(packet, options)?;
+    let (tcph, tcp) = TcpHeaderRef::parse_with(iph.payload(), &iph, options)?;
+    Ok((iph.to_owned(), tcph.to_owned(), ChecksumReport { ip, tcp }))
+}
+
 /// Unpack a byte vector into an `IPHeader` and `TCPHeader`. Allocs new headers for convenience.
+///
+/// ```
+/// use net::packet;
+/// use net::packet::errors::HeaderError;
+///
+/// // An IPv4 header whose total length claims more bytes than the buffer holds
+/// let mut packet = vec![0x45, 0, 0, 60, 0, 0, 0x40, 0, 64, 6, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+/// packet.extend_from_slice(&[0; 20]);
+/// let err = packet::unwrap(&packet).unwrap_err();
+/// assert_eq!(err, HeaderError::TruncatedPacket { claimed: 60, actual: 40 });
+/// ```
 pub fn unwrap(packet: &[u8]) -> Result<(IpHeader, TcpHeader), HeaderError> {
     let mut iph = IpHeader::default();
     let mut tcph = TcpHeader::default();
@@ -40,17 +210,90 @@ pub fn unwrap(packet: &[u8]) -> Result<(IpHeader, TcpHeader), HeaderError> {
     Ok((iph, tcph))
 }
 
+/// The version nibble of a raw IP packet, to pick between the v4 and v6 entry points
+pub fn ip_version(packet: &[u8]) -> Option<u8> {
+    packet.first().map(|byte| byte >> 4)
+}
+
+/// Wrap an `Ipv6Header` and `TCPHeader` into a packet. Zero allocation.
+pub fn wrap_into_v6(ip6h: &Ipv6Header, tcph: &TcpHeader, packet: &mut [u8]) -> Result<usize, HeaderError> {
+    let ip_len = ip6h.serialize(packet)?;
+    let tcp_length = tcph.serialize(&mut packet[ip_len..], ip6h)?;
+    Ok(ip_len + tcp_length)
+}
+
+/// Wrap an `Ipv6Header` and `TCPHeader` into a packet. Allocs a new `Vec<u8>` for convenience.
+pub fn wrap_v6(ip6h: &Ipv6Header, tcph: &TcpHeader) -> Result<Vec<u8>, HeaderError> {
+    let tcp_len = tcph.header_len() + tcph.payload.len();
+    let mut packet = vec![0u8; Ipv6Header::LEN + tcp_len];
+
+    wrap_into_v6(ip6h, tcph, &mut packet)?;
+    Ok(packet)
+}
+
+/// Unwrap an IPv6 packet into `Ipv6Header` and `TCPHeader` objects. Zero allocation.
+pub fn unwrap_from_v6(packet: &[u8], ip6h: &mut Ipv6Header, tcph: &mut TcpHeader) -> Result<usize, HeaderError> {
+    *ip6h = Ipv6Header::parse(packet)?;
+    let total_len = Ipv6Header::LEN + ip6h.payload_len as usize;
+
+    *tcph = TcpHeader::parse(&packet[Ipv6Header::LEN..total_len], ip6h)?;
+    Ok(total_len)
+}
+
+/// Unpack an IPv6 packet into an `Ipv6Header` and `TCPHeader`. Allocs new headers for convenience.
+pub fn unwrap_v6(packet: &[u8]) -> Result<(Ipv6Header, TcpHeader), HeaderError> {
+    let mut ip6h = Ipv6Header::default();
+    let mut tcph = TcpHeader::default();
+
+    unwrap_from_v6(packet, &mut ip6h, &mut tcph)?;
+    Ok((ip6h, tcph))
+}
+
 // -- Unit tests --
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::ip::ip_flags::IpFlags;
+    use crate::packet::parse_options::ChecksumStatus;
     use crate::packet::test_utils;
     use crate::tcp::tcp_flags::TcpFlags;
     use std::net::Ipv4Addr;
     use crate::tcp::wrap32::Wrap32;
 
+    #[test]
+    fn test_to_host_byte_order() {
+        let mut packet = hex::decode([test_utils::get_ip_hex(), test_utils::get_tcp_hex()].concat()).unwrap();
+        let original = packet.clone();
+        to_host_byte_order(&mut packet).unwrap();
+
+        // total_len 64 and DF, read back as the host would
+        assert_eq!(u16::from_ne_bytes([packet[2], packet[3]]), 64);
+        assert_eq!(u16::from_ne_bytes([packet[6], packet[7]]), 0x4000);
+        let changed: Vec<usize> = (0..packet.len()).filter(|&i| packet[i] != original[i]).collect();
+        if cfg!(target_endian = "little") {
+            assert_eq!(changed, [2, 3, 6, 7]);
+        } else {
+            assert!(changed.is_empty());
+        }
+
+        to_host_byte_order(&mut packet).unwrap();
+        assert_eq!(packet, original);
+        let result = to_host_byte_order(&mut packet[..7]);
+        assert_eq!(result, Err(HeaderError::BufferTooSmall { expected: 8, found: 7 }));
+    }
+
+    #[test]
+    fn test_wrap_for_platform() {
+        let packet = hex::decode([test_utils::get_ip_hex(), test_utils::get_tcp_hex()].concat()).unwrap();
+        let (iph, tcph) = unwrap(&packet).unwrap();
+        let mut fixed = wrap_for_platform(&iph, &tcph).unwrap();
+        if HDRINCL_HOST_BYTE_ORDER {
+            to_host_byte_order(&mut fixed).unwrap();
+        }
+        assert_eq!(fixed, packet);
+    }
+
     #[test]
     fn test_pack() {
         let ip_bytes = hex::decode(test_utils::get_ip_hex_with_payload()).unwrap();
@@ -70,6 +313,7 @@ mod tests {
             checksum: 40416,
             src_ip: Ipv4Addr::new(204, 44, 192, 60),
             dst_ip: Ipv4Addr::new(10, 110, 208, 106),
+            options: Vec::new(),
         };
 
         let tcph = TcpHeader {
@@ -83,8 +327,8 @@ mod tests {
             window: 235,
             checksum: 29098,
             urgent: 0,
-            options: hex::decode("0101080abeb95f0abb687a45").unwrap(),
-            payload: payload.clone(),
+            options: hex::decode("0101080abeb95f0abb687a45").unwrap().into(),
+            payload: payload.clone().into(),
         };
 
         let packet = wrap(&iph, &tcph).unwrap();
@@ -163,6 +407,97 @@ mod tests {
         assert_eq!(err, HeaderError::BadChecksum("TCP".to_string()));
     }
 
+    #[test]
+    fn test_unwrap_with_lenient_checksums() {
+        let ip_bytes = hex::decode(test_utils::get_ip_hex_with_payload()).unwrap();
+        let mut tcp_bytes = hex::decode(test_utils::get_tcp_hex_with_payload()).unwrap();
+        tcp_bytes[16..18].fill(0); // Offloaded: the NIC would have filled this in
+        let payload = hex::decode(test_utils::giant_payload()).unwrap();
+        let packet = [ip_bytes, tcp_bytes, payload.clone()].concat();
+
+        // Strict by default, like `unwrap`
+        let err = unwrap_with(&packet, &ParseOptions::default()).unwrap_err();
+        assert_eq!(err, HeaderError::BadChecksum("TCP".to_string()));
+
+        let (iph, tcph, report) = unwrap_with(&packet, &ParseOptions::lenient()).unwrap();
+        assert_eq!(report, ChecksumReport { ip: ChecksumStatus::Verified, tcp: ChecksumStatus::Failed });
+        assert!(!report.all_verified());
+        assert_eq!(iph.total_len, 1426);
+        assert_eq!(tcph.checksum, 0);
+        assert_eq!(tcph.payload, payload);
+
+        // Only the IP checksum is enforced
+        let options = ParseOptions { verify_ip_checksum: true, verify_tcp_checksum: false };
+        assert!(unwrap_with(&packet, &options).is_ok());
+        let mut corrupt_ip = packet.clone();
+        corrupt_ip[10] ^= 0xff;
+        assert_eq!(unwrap_with(&corrupt_ip, &options).unwrap_err(), HeaderError::BadChecksum("IP".to_string()));
+        let (_, _, report) = unwrap_with(&corrupt_ip, &ParseOptions::lenient()).unwrap();
+        assert_eq!(report.ip, ChecksumStatus::Failed);
+
+        // Intact packets verify either way
+        let mut intact = packet.clone();
+        intact[36..38].copy_from_slice(&[0x71, 0xaa]);
+        assert!(unwrap_with(&intact, &ParseOptions::lenient()).unwrap().2.all_verified());
+    }
+
+    #[test]
+    fn test_unpack_packet_shorter_than_total_len() {
+        let ip_bytes = hex::decode(test_utils::get_ip_hex_with_payload()).unwrap();
+        let tcp_bytes = hex::decode(test_utils::get_tcp_hex_with_payload()).unwrap();
+        let payload = hex::decode(test_utils::giant_payload()).unwrap();
+
+        // Drop the last 10 bytes of the payload. The IP header still claims 1426 bytes
+        let packet = [ip_bytes, tcp_bytes, payload[..payload.len() - 10].to_vec()].concat();
+        let err = unwrap(&packet).unwrap_err();
+        assert_eq!(err, HeaderError::TruncatedPacket { claimed: 1426, actual: 1416 });
+    }
+
+    /// The wireshark SYN with one mutation applied and the IP checksum fixed up
+    fn mutated_syn(mutate: impl FnOnce(&mut [u8])) -> Vec<u8> {
+        let mut packet = hex::decode([test_utils::get_ip_hex(), test_utils::get_tcp_hex()].concat()).unwrap();
+        mutate(&mut packet);
+        packet[10..12].fill(0);
+        let checksum = IpHeader::checksum(&packet[..20]);
+        packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn test_unpack_invalid_version() {
+        let packet = mutated_syn(|p| p[0] = 0x65);
+        assert_eq!(unwrap(&packet).unwrap_err(), HeaderError::InvalidVersion(6));
+    }
+
+    #[test]
+    fn test_unpack_invalid_ihl() {
+        let packet = mutated_syn(|p| p[0] = 0x40);
+        assert_eq!(unwrap(&packet).unwrap_err(), HeaderError::InvalidIhl(0));
+    }
+
+    #[test]
+    fn test_unpack_total_len_smaller_than_header() {
+        let packet = mutated_syn(|p| p[2..4].copy_from_slice(&12u16.to_be_bytes()));
+        let err = unwrap(&packet).unwrap_err();
+        assert_eq!(err, HeaderError::InvalidTotalLen { total_len: 12, buf_len: 64 });
+    }
+
+    #[test]
+    fn test_unpack_total_len_larger_than_packet() {
+        let packet = mutated_syn(|p| p[2..4].copy_from_slice(&1500u16.to_be_bytes()));
+        let err = unwrap(&packet).unwrap_err();
+        assert_eq!(err, HeaderError::TruncatedPacket { claimed: 1500, actual: 64 });
+    }
+
+    #[test]
+    fn test_unpack_ignores_trailing_padding() {
+        let mut packet = mutated_syn(|_| {});
+        packet.extend_from_slice(&[0; 6]); // Ethernet minimum frame padding
+        let (iph, tcph) = unwrap(&packet).unwrap();
+        assert_eq!(iph.total_len, 64);
+        assert_eq!(tcph.flags, TcpFlags::SYN);
+    }
+
     // Difficult as fuck
     #[test]
     fn test_odd_tcp_segment_length() {
@@ -181,6 +516,7 @@ mod tests {
             checksum: 45243,
             src_ip: Ipv4Addr::new(204, 44, 192, 60),
             dst_ip: Ipv4Addr::new(192, 168, 1, 13),
+            options: Vec::new(),
         };
 
         let tcph = TcpHeader {
@@ -194,8 +530,8 @@ mod tests {
             window: 235,
             checksum: 47864,
             urgent: 0,
-            options: hex::decode("0101080afdc076540198f657").unwrap(),
-            payload,
+            options: hex::decode("0101080afdc076540198f657").unwrap().into(),
+            payload: payload.into(),
         };
 
         let packet = wrap(&iph, &tcph).unwrap();
@@ -206,4 +542,264 @@ mod tests {
         assert_eq!(iph.checksum, iph2.checksum);
         assert_eq!(tcph.checksum, tcph2.checksum);
     }
+
+    #[test]
+    fn test_wrap_unwrap_with_ip_options() {
+        let iph = IpHeader {
+            version: 4,
+            ihl: 6,
+            total_len: 24 + 20 + 5,
+            ttl: 64,
+            protocol: 6,
+            src_ip: Ipv4Addr::new(10, 0, 0, 1),
+            dst_ip: Ipv4Addr::new(10, 0, 0, 2),
+            options: vec![0x94, 0x04, 0x00, 0x00], // Router Alert
+            ..IpHeader::default()
+        };
+        let tcph = TcpHeader {
+            src_port: 40000,
+            dst_port: 80,
+            seq_no: Wrap32::new(1),
+            data_offset: 5,
+            flags: TcpFlags::ACK | TcpFlags::PSH,
+            window: 1024,
+            payload: b"hello".to_vec().into(),
+            ..TcpHeader::default()
+        };
+
+        let packet = wrap(&iph, &tcph).unwrap();
+        assert_eq!(packet.len(), 49);
+        assert_eq!(&packet[20..24], &[0x94, 0x04, 0x00, 0x00]);
+
+        // The TCP segment starts after the options and its checksum still validates
+        let (iph2, tcph2) = unwrap(&packet).unwrap();
+        assert_eq!(iph2.options, iph.options);
+        assert_eq!(tcph2.src_port, 40000);
+        assert_eq!(tcph2.payload, &b"hello"[..]);
+        assert_eq!(TcpHeader::checksum(&packet[24..], &iph2), 0);
+    }
+
+    #[test]
+    fn test_unpack_v6_syn() {
+        let packet = hex::decode(test_utils::get_ipv6_syn_hex()).unwrap();
+        assert_eq!(ip_version(&packet), Some(6));
+
+        let (ip6h, tcph) = unwrap_v6(&packet).unwrap();
+        assert_eq!(ip6h.payload_len, 40);
+        assert_eq!(tcph.src_port, 38886);
+        assert_eq!(tcph.dst_port, 8080);
+        assert_eq!(tcph.seq_no, Wrap32::new(0x76e57d6b));
+        assert_eq!(tcph.data_offset, 10);
+        assert_eq!(tcph.flags, TcpFlags::SYN);
+        assert_eq!(tcph.window, 65476);
+        assert_eq!(tcph.checksum, 0x89c7);
+    }
+
+    #[test]
+    fn test_v6_roundtrip() {
+        let packet = hex::decode(test_utils::get_ipv6_syn_hex()).unwrap();
+        let (ip6h, tcph) = unwrap_v6(&packet).unwrap();
+        assert_eq!(wrap_v6(&ip6h, &tcph).unwrap(), packet);
+    }
+
+    #[test]
+    fn test_v6_bad_checksum() {
+        let mut packet = hex::decode(test_utils::get_ipv6_syn_hex()).unwrap();
+        packet[8] ^= 0x01; // The source address is covered by the pseudo-header
+        assert_eq!(unwrap_v6(&packet).unwrap_err(), HeaderError::BadChecksum("TCP".to_string()));
+    }
+
+    #[test]
+    fn test_v4_entry_point_rejects_v6() {
+        let packet = hex::decode(test_utils::get_ipv6_syn_hex()).unwrap();
+        assert_eq!(unwrap(&packet).unwrap_err(), HeaderError::InvalidVersion(6));
+    }
+
+    fn fragmentable_headers(payload_len: usize) -> (IpHeader, TcpHeader) {
+        let iph = IpHeader {
+            version: 4,
+            ihl: 5,
+            id: 4242,
+            flags: IpFlags::empty(),
+            ttl: 64,
+            protocol: 6,
+            src_ip: Ipv4Addr::new(10, 0, 0, 1),
+            dst_ip: Ipv4Addr::new(10, 0, 0, 2),
+            ..IpHeader::default()
+        };
+        let tcph = TcpHeader {
+            src_port: 50871,
+            dst_port: 80,
+            seq_no: Wrap32::new(1000),
+            data_offset: 5,
+            payload: (0..payload_len).map(|i| (i % 251) as u8).collect(),
+            ..TcpHeader::default()
+        };
+        (iph, tcph)
+    }
+
+    /// Put the fragments back together in offset order, checking each IP header on the way
+    fn reassemble(fragments: &[Vec<u8>]) -> Vec<u8> {
+        let mut parsed: Vec<(IpHeader, &[u8])> = fragments
+            .iter()
+            .map(|fragment| {
+                let iph = IpHeader::parse(fragment).unwrap();
+                let data = &fragment[iph.header_len()..iph.total_len as usize];
+                (iph, data)
+            })
+            .collect();
+        parsed.sort_by_key(|(iph, _)| iph.frag_offset);
+
+        let mut segment = Vec::new();
+        for (iph, data) in parsed {
+            assert_eq!(iph.frag_offset as usize * 8, segment.len());
+            segment.extend_from_slice(data);
+        }
+        segment
+    }
+
+    #[test]
+    fn test_wrap_fragmented() {
+        let (iph, tcph) = fragmentable_headers(3980); // A 4000-byte segment
+        let fragments = wrap_fragmented(&iph, &tcph, 1500).unwrap();
+
+        assert_eq!(fragments.iter().map(Vec::len).collect::<Vec<_>>(), vec![1500, 1500, 1060]);
+        for (i, fragment) in fragments.iter().enumerate() {
+            let frag_iph = IpHeader::parse(fragment).unwrap();
+            assert_eq!(frag_iph.id, 4242);
+            assert_eq!(frag_iph.frag_offset, (i * 1480 / 8) as u16);
+            assert_eq!(frag_iph.flags.contains(IpFlags::MF), i < 2);
+        }
+
+        // The reassembled segment is byte-exact and passes the TCP checksum
+        let segment = reassemble(&fragments);
+        let mut expected = vec![0u8; 4000];
+        tcph.serialize(&mut expected, &iph).unwrap();
+        assert_eq!(segment, expected);
+
+        let whole_iph = IpHeader { total_len: 4020, ..iph };
+        assert_eq!(TcpHeader::parse(&segment, &whole_iph).unwrap().payload, tcph.payload);
+    }
+
+    #[test]
+    fn test_wrap_fragmented_fits_mtu() {
+        let (mut iph, tcph) = fragmentable_headers(100);
+        iph.total_len = 140;
+        let fragments = wrap_fragmented(&iph, &tcph, 1500).unwrap();
+        assert_eq!(fragments, vec![wrap(&iph, &tcph).unwrap()]);
+    }
+
+    #[test]
+    fn test_wrap_fragmented_copies_flagged_options() {
+        let (mut iph, tcph) = fragmentable_headers(3980);
+        iph.options = vec![0x01, 0x94, 0x04, 0x00, 0x00, 0x07, 0x03, 0x04]; // NOP, Router Alert, Record Route
+        let fragments = wrap_fragmented(&iph, &tcph, 1500).unwrap();
+
+        let options: Vec<Vec<u8>> = fragments.iter().map(|f| IpHeader::parse(f).unwrap().options).collect();
+        assert_eq!(options[0], iph.options);
+        assert_eq!(options[1], vec![0x94, 0x04, 0x00, 0x00]);
+        assert!(fragments[..2].iter().all(|f| (f.len() - IpHeader::parse(f).unwrap().header_len()).is_multiple_of(8)));
+        assert_eq!(reassemble(&fragments).len(), 4000);
+    }
+
+    #[test]
+    fn test_wrap_fragmented_errors() {
+        let (mut iph, tcph) = fragmentable_headers(3980);
+        assert_eq!(wrap_fragmented(&iph, &tcph, 27), Err(HeaderError::MtuTooSmall(27)));
+
+        iph.flags = IpFlags::DF;
+        assert_eq!(
+            wrap_fragmented(&iph, &tcph, 1500),
+            Err(HeaderError::FragmentationNeeded { len: 4020, mtu: 1500 })
+        );
+    }
+
+    /// The v4 fixture packets: a SYN with options and a data segment with a large payload
+    fn fixture_packets() -> Vec<Vec<u8>> {
+        let syn = [test_utils::get_ip_hex(), test_utils::get_tcp_hex()].concat();
+        let data = [
+            test_utils::get_ip_hex_with_payload(),
+            test_utils::get_tcp_hex_with_payload(),
+            test_utils::giant_payload(),
+        ]
+        .concat();
+        vec![hex::decode(syn).unwrap(), hex::decode(data).unwrap()]
+    }
+
+    #[test]
+    fn test_unpack_every_truncation() {
+        for packet in fixture_packets() {
+            for len in 0..packet.len() {
+                let err = unwrap(&packet[..len]).unwrap_err();
+                if len >= 20 {
+                    assert_eq!(err, HeaderError::TruncatedPacket { claimed: packet.len(), actual: len });
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_unpack_data_offset_past_segment_end() {
+        // Segments of 20..60 bytes whose data offset claims more header than they hold. The IP
+        // header is consistent, so only the TCP bounds check stands between them and a panic.
+        for tcp_len in 20..60 {
+            for data_offset in 5..=15u8 {
+                let header_len = data_offset as usize * 4;
+                let iph = IpHeader {
+                    version: 4,
+                    ihl: 5,
+                    total_len: (20 + tcp_len) as u16,
+                    ttl: 64,
+                    protocol: 6,
+                    ..IpHeader::default()
+                };
+                let mut packet = vec![0u8; 20 + tcp_len];
+                iph.serialize(&mut packet).unwrap();
+                packet[32] = data_offset << 4;
+
+                let results = [
+                    unwrap(&packet).map(|_| ()),
+                    unwrap_ref(&packet).map(|_| ()),
+                    unwrap_with(&packet, &ParseOptions::lenient()).map(|_| ()),
+                    TcpHeader::parse(&packet[20..], &iph).map(|_| ()),
+                ];
+                for result in results {
+                    if header_len > tcp_len {
+                        let expected = HeaderError::BufferTooSmall { expected: header_len, found: tcp_len };
+                        assert_eq!(result, Err(expected), "len {tcp_len} offset {data_offset}");
+                    } else {
+                        // Fits: the zeroed checksum is all that's wrong, and only when verified
+                        assert!(matches!(result, Ok(()) | Err(HeaderError::BadChecksum(_))));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_unpack_random_mutations_never_panic() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        for packet in fixture_packets() {
+            for _ in 0..2000 {
+                // Truncate, then corrupt a few header bytes and sometimes fix up the IP checksum so
+                // the mutation reaches the TCP parser
+                let mut mutated = packet[..rng.gen_range(0..=packet.len())].to_vec();
+                let header_end = mutated.len().min(60);
+                for _ in 0..rng.gen_range(0..4) {
+                    if header_end > 0 {
+                        mutated[rng.gen_range(0..header_end)] = rng.gen();
+                    }
+                }
+                if mutated.len() >= 20 && rng.gen_bool(0.5) {
+                    mutated[10..12].fill(0);
+                    let checksum = IpHeader::checksum(&mutated[..20]);
+                    mutated[10..12].copy_from_slice(&checksum.to_be_bytes());
+                }
+                let _ = unwrap(&mutated);
+            }
+        }
+    }
 }
@@ -0,0 +1,194 @@
+use crate::ip::ip_header::IpHeader;
+use crate::packet::checksum::{self, PseudoHeader};
+use crate::packet::errors::HeaderError;
+use bytes::Bytes;
+
+pub const PROTOCOL_UDP: u8 = 17;
+
+/// A UDP header and its payload (RFC 768). Like `TcpHeader`, serializing and parsing need the IP
+/// header for the checksum's pseudo-header.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UdpHeader {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub length: u16,   // Header and payload. Derived from the payload on serialize
+    pub checksum: u16, // 0 means the sender didn't compute one, which IPv4 allows
+    pub payload: Bytes,
+}
+
+impl UdpHeader {
+    pub const LEN: usize = 8;
+
+    /// A header between two ports, with no payload yet
+    pub fn new(src_port: u16, dst_port: u16) -> Self {
+        UdpHeader { src_port, dst_port, ..UdpHeader::default() }
+    }
+
+    /// The datagram length: 8 bytes of header plus the payload
+    pub fn datagram_len(&self) -> usize {
+        Self::LEN + self.payload.len()
+    }
+
+    /// Serialize the header and payload into `buf`, with the length and checksum filled in.
+    /// Returns `datagram_len()`.
+    pub fn serialize(&self, buf: &mut [u8], iph: &impl PseudoHeader) -> Result<usize, HeaderError> {
+        let len = self.datagram_len();
+        if len > u16::MAX as usize {
+            return Err(HeaderError::LengthMismatch { expected: u16::MAX as usize, found: len })
+        }
+        if buf.len() < len {
+            return Err(HeaderError::BufferTooSmall { expected: len, found: buf.len() })
+        }
+
+        buf[0..2].copy_from_slice(&self.src_port.to_be_bytes());
+        buf[2..4].copy_from_slice(&self.dst_port.to_be_bytes());
+        buf[4..6].copy_from_slice(&(len as u16).to_be_bytes());
+        buf[6..8].fill(0); // Set checksum to 0 initially
+        buf[8..len].copy_from_slice(&self.payload);
+
+        // A computed 0 is sent as all ones, since 0 on the wire means "no checksum"
+        let checksum = match Self::checksum(&buf[..len], iph) {
+            0 => 0xffff,
+            checksum => checksum,
+        };
+        buf[6..8].copy_from_slice(&checksum.to_be_bytes());
+
+        Ok(len)
+    }
+
+    /// Parse a UDP datagram. The buffer must hold exactly the length claimed by the IP header, and
+    /// the UDP length field must agree with it. A zero checksum is accepted as "not computed";
+    /// any other must match.
+    pub fn parse(buf: &[u8], iph: &impl PseudoHeader) -> Result<Self, HeaderError> {
+        if buf.len() < Self::LEN {
+            return Err(HeaderError::BufferTooSmall { expected: Self::LEN, found: buf.len() })
+        }
+
+        let segment_len = iph
+            .segment_len()
+            .ok_or(HeaderError::LengthMismatch { expected: 0, found: buf.len() })?;
+        if segment_len != buf.len() {
+            return Err(HeaderError::LengthMismatch { expected: segment_len, found: buf.len() })
+        }
+        let length = u16::from_be_bytes([buf[4], buf[5]]);
+        if length as usize != segment_len {
+            return Err(HeaderError::LengthMismatch { expected: segment_len, found: length as usize })
+        }
+
+        let checksum = u16::from_be_bytes([buf[6], buf[7]]);
+        if checksum != 0 && Self::checksum(buf, iph) != 0 {
+            return Err(HeaderError::BadChecksum("UDP".to_string()))
+        }
+
+        Ok(UdpHeader {
+            src_port: u16::from_be_bytes([buf[0], buf[1]]),
+            dst_port: u16::from_be_bytes([buf[2], buf[3]]),
+            length,
+            checksum,
+            payload: Bytes::copy_from_slice(&buf[Self::LEN..]),
+        })
+    }
+
+    /// Compute the checksum of a serialized datagram: the pseudo-header, then header and payload
+    pub fn checksum(data: &[u8], iph: &impl PseudoHeader) -> u16 {
+        checksum::checksum_with(iph.pseudo_header_sum(data.len()), data)
+    }
+}
+
+/// Wrap an `IpHeader` and `UdpHeader` into a packet. `iph.protocol` should be `PROTOCOL_UDP`, since
+/// the checksum covers it.
+pub fn wrap_udp(iph: &IpHeader, udph: &UdpHeader) -> Result<Vec<u8>, HeaderError> {
+    let mut packet = vec![0u8; iph.header_len() + udph.datagram_len()];
+    let ip_len = iph.serialize(&mut packet)?;
+    udph.serialize(&mut packet[ip_len..], iph)?;
+    Ok(packet)
+}
+
+/// Unwrap a packet into `IpHeader` and `UdpHeader` objects. The IP total length must fit in the
+/// packet, and trailing padding is dropped.
+pub fn unwrap_udp(packet: &[u8]) -> Result<(IpHeader, UdpHeader), HeaderError> {
+    let iph = IpHeader::parse(packet)?;
+    let header_len = iph.ihl as usize * 4;
+    let udph = UdpHeader::parse(&packet[header_len..iph.total_len as usize], &iph)?;
+    Ok((iph, udph))
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    /// A DNS query for example.com, type A, from 192.168.1.20:53211 to 8.8.8.8:53
+    fn dns_query_hex() -> String {
+        [
+            "450000394d2c000040115bbcc0a8011408080808",               // IP
+            "cfdb00350025740e",                                       // UDP
+            "1a2b01200001000000000000076578616d706c6503636f6d0000010001", // DNS
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_dns_query_round_trip() {
+        let packet = hex::decode(dns_query_hex()).unwrap();
+        let (iph, udph) = unwrap_udp(&packet).unwrap();
+        assert_eq!((iph.protocol, iph.src_ip, iph.dst_ip), (PROTOCOL_UDP, Ipv4Addr::new(192, 168, 1, 20), Ipv4Addr::new(8, 8, 8, 8)));
+        assert_eq!((udph.src_port, udph.dst_port, udph.length, udph.checksum), (53211, 53, 37, 0x740e));
+        assert_eq!(&udph.payload[12..25], b"\x07example\x03com\x00");
+
+        assert_eq!(wrap_udp(&iph, &udph).unwrap(), packet);
+
+        // The length field is derived, not copied
+        let stale = UdpHeader { length: 0, checksum: 0, ..udph.clone() };
+        assert_eq!(wrap_udp(&iph, &stale).unwrap(), packet);
+    }
+
+    #[test]
+    fn test_zero_checksum_accepted() {
+        let mut packet = hex::decode(dns_query_hex()).unwrap();
+        packet[26..28].fill(0);
+        let (_, udph) = unwrap_udp(&packet).unwrap();
+        assert_eq!(udph.checksum, 0);
+    }
+
+    #[test]
+    fn test_corrupt_checksum() {
+        let mut packet = hex::decode(dns_query_hex()).unwrap();
+        packet[40] ^= 0x01; // A bit of the query name
+        assert_eq!(unwrap_udp(&packet), Err(HeaderError::BadChecksum("UDP".to_string())));
+
+        let mut packet = hex::decode(dns_query_hex()).unwrap();
+        packet[27] ^= 0x01;
+        assert_eq!(unwrap_udp(&packet), Err(HeaderError::BadChecksum("UDP".to_string())));
+    }
+
+    #[test]
+    fn test_length_must_match_ip() {
+        // The UDP length claims one byte less than the IP header leaves for it
+        let mut packet = hex::decode(dns_query_hex()).unwrap();
+        packet[25] = 36;
+        assert_eq!(unwrap_udp(&packet), Err(HeaderError::LengthMismatch { expected: 37, found: 36 }));
+
+        let iph = IpHeader { total_len: 20 + 7, ..IpHeader::default() };
+        let udph = UdpHeader::new(1, 2);
+        assert_eq!(UdpHeader::parse(&[0; 7], &iph), Err(HeaderError::BufferTooSmall { expected: 8, found: 7 }));
+        let mut buf = [0u8; 7];
+        assert_eq!(udph.serialize(&mut buf, &iph), Err(HeaderError::BufferTooSmall { expected: 8, found: 7 }));
+    }
+
+    #[test]
+    fn test_computed_zero_sent_as_ones() {
+        // Pick a payload word that makes the checksum come out to exactly 0
+        let iph = IpHeader { version: 4, ihl: 5, total_len: 30, protocol: PROTOCOL_UDP, ..IpHeader::default() };
+        let mut udph = UdpHeader { payload: Bytes::from_static(&[0, 0]), ..UdpHeader::new(1, 2) };
+        let mut buf = [0u8; 10];
+        udph.serialize(&mut buf, &iph).unwrap();
+        let sum = u16::from_be_bytes([buf[6], buf[7]]);
+        udph.payload = Bytes::copy_from_slice(&sum.to_be_bytes());
+        udph.serialize(&mut buf, &iph).unwrap();
+        assert_eq!(&buf[6..8], &[0xff, 0xff]);
+        assert_eq!(UdpHeader::parse(&buf, &iph).unwrap().checksum, 0xffff);
+    }
+}
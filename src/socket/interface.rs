@@ -0,0 +1,98 @@
+use crate::packet::errors::InterfaceError;
+use network_interface::{Addr, NetworkInterface, NetworkInterfaceConfig};
+use std::net::Ipv4Addr;
+
+/// The interfaces on this host, with their addresses
+pub fn list() -> Result<Vec<NetworkInterface>, InterfaceError> {
+    NetworkInterface::show().map_err(|e| InterfaceError::List(e.to_string()))
+}
+
+/// The IPv4 addresses of `interface`, in the order the OS reports them
+fn ipv4_addrs(interface: &NetworkInterface) -> impl Iterator<Item = Ipv4Addr> + '_ {
+    interface.addr.iter().filter_map(|addr| match addr {
+        Addr::V4(v4) => Some(v4.ip),
+        Addr::V6(_) => None,
+    })
+}
+
+/// The local IPv4 address to send from. With `ifname`, the first address of that interface;
+/// otherwise the first non-loopback address of any interface.
+pub fn lookup_local_ip(interfaces: &[NetworkInterface], ifname: Option<&str>) -> Result<Ipv4Addr, InterfaceError> {
+    match ifname {
+        Some(name) => {
+            let interface = interfaces
+                .iter()
+                .find(|i| i.name == name)
+                .ok_or_else(|| InterfaceError::NoSuchInterface(name.to_string()))?;
+            ipv4_addrs(interface).next().ok_or_else(|| InterfaceError::AddressNotAvailable(name.to_string()))
+        }
+        None => interfaces
+            .iter()
+            .flat_map(ipv4_addrs)
+            .find(|addr| !addr.is_loopback())
+            .ok_or(InterfaceError::NoLocalAddress),
+    }
+}
+
+/// The MTU of the interface `name`, where path MTU discovery starts (see `tcp::pmtu`)
+#[cfg(target_os = "linux")]
+pub fn mtu(name: &str) -> Result<usize, InterfaceError> {
+    if name.is_empty() || name.contains('/') {
+        return Err(InterfaceError::NoSuchInterface(name.to_string()));
+    }
+    let mtu = std::fs::read_to_string(format!("/sys/class/net/{name}/mtu"))
+        .map_err(|_| InterfaceError::NoSuchInterface(name.to_string()))?;
+    mtu.trim().parse().map_err(|e| InterfaceError::List(format!("bad MTU for {name}: {e}")))
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv6Addr;
+
+    fn mock_interfaces() -> Vec<NetworkInterface> {
+        let v4 = |name, ip, index| NetworkInterface::new_afinet(name, ip, None, None, index, false);
+        let mut wg0 = v4("wg0", Ipv4Addr::new(10, 8, 0, 2), 3);
+        wg0.addr.extend(v4("wg0", Ipv4Addr::new(10, 8, 0, 3), 3).addr);
+        vec![
+            NetworkInterface::new_afinet("lo", Ipv4Addr::LOCALHOST, None, None, 1, true),
+            v4("eth0", Ipv4Addr::new(192, 168, 1, 20), 2),
+            wg0,
+            NetworkInterface::new_afinet6("eth1", Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1), None, None, 4, false),
+        ]
+    }
+
+    #[test]
+    fn test_lookup_first_non_loopback() {
+        assert_eq!(lookup_local_ip(&mock_interfaces(), None), Ok(Ipv4Addr::new(192, 168, 1, 20)));
+        let only_lo = [NetworkInterface::new_afinet("lo", Ipv4Addr::LOCALHOST, None, None, 1, true)];
+        assert_eq!(lookup_local_ip(&only_lo, None), Err(InterfaceError::NoLocalAddress));
+    }
+
+    #[test]
+    fn test_lookup_named_interface() {
+        let interfaces = mock_interfaces();
+        assert_eq!(lookup_local_ip(&interfaces, Some("wg0")), Ok(Ipv4Addr::new(10, 8, 0, 2)));
+        assert_eq!(lookup_local_ip(&interfaces, Some("lo")), Ok(Ipv4Addr::LOCALHOST));
+        assert_eq!(lookup_local_ip(&interfaces, Some("eth1")), Err(InterfaceError::AddressNotAvailable("eth1".into())));
+        assert_eq!(lookup_local_ip(&interfaces, Some("eth9")), Err(InterfaceError::NoSuchInterface("eth9".into())));
+    }
+
+    #[test]
+    fn test_list_has_loopback() {
+        let interfaces = list().unwrap();
+        let lo = interfaces.iter().find(|i| ipv4_addrs(i).any(|ip| ip == Ipv4Addr::LOCALHOST));
+        let lo = lo.unwrap_or_else(|| panic!("no loopback in {interfaces:?}"));
+        assert_eq!(lookup_local_ip(&interfaces, Some(&lo.name)), Ok(Ipv4Addr::LOCALHOST));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_mtu() {
+        assert!(mtu("lo").unwrap() >= 1500);
+        assert_eq!(mtu("no-such-if0"), Err(InterfaceError::NoSuchInterface("no-such-if0".into())));
+        assert_eq!(mtu("../lo"), Err(InterfaceError::NoSuchInterface("../lo".into())));
+    }
+}
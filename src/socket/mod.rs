@@ -1 +1,5 @@
+pub mod interface;
+pub mod packet_io;
 pub mod rawsocket;
+#[cfg(all(feature = "tun", target_os = "linux"))]
+pub mod tun;
@@ -0,0 +1,239 @@
+use crate::socket::rawsocket;
+use nix::errno::Errno;
+use nix::sys::socket::SockProtocol;
+use std::collections::VecDeque;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::os::fd::OwnedFd;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Where a connection's IP packets go and come from. The raw sockets need CAP_NET_RAW, so tests
+/// swap in `LoopbackIo` or `ScriptedIo` instead.
+pub trait PacketIo {
+    /// Send one IP packet, header included. Returns the bytes sent
+    fn send(&mut self, packet: &[u8]) -> io::Result<usize>;
+
+    /// Receive one IP packet into `buf`, waiting at most `timeout`. Returns its length. Nothing
+    /// arriving in time is an error of kind `WouldBlock`, and a packet too big for `buf` is
+    /// `EMSGSIZE`.
+    fn recv(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize>;
+}
+
+impl<T: PacketIo + ?Sized> PacketIo for Box<T> {
+    fn send(&mut self, packet: &[u8]) -> io::Result<usize> {
+        (**self).send(packet)
+    }
+
+    fn recv(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        (**self).recv(buf, timeout)
+    }
+}
+
+pub(crate) fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::WouldBlock, "no packet before the timeout")
+}
+
+/// The real network: a raw send socket with IP_HDRINCL and a raw TCP receive socket. The receive
+/// socket sees every TCP packet for this host, so filter by port, e.g. with
+/// `rawsocket::attach_port_filter` on `recv_fd`.
+#[derive(Debug)]
+pub struct RawSocketIo {
+    send_fd: OwnedFd,
+    recv_fd: OwnedFd,
+    remote: Ipv4Addr, // Where sent packets are routed
+}
+
+impl RawSocketIo {
+    /// Open both sockets. Fails with `EPERM` without CAP_NET_RAW
+    pub fn new(remote: Ipv4Addr) -> Result<Self, Errno> {
+        let send_fd = rawsocket::new_send_socket(SockProtocol::Tcp)?;
+        let recv_fd = rawsocket::new_recv_socket(SockProtocol::Tcp)?;
+        Ok(Self::from_fds(send_fd, recv_fd, remote))
+    }
+
+    /// Use sockets that are already open and configured
+    pub fn from_fds(send_fd: OwnedFd, recv_fd: OwnedFd, remote: Ipv4Addr) -> Self {
+        RawSocketIo { send_fd, recv_fd, remote }
+    }
+
+    pub fn send_fd(&self) -> &OwnedFd {
+        &self.send_fd
+    }
+
+    pub fn recv_fd(&self) -> &OwnedFd {
+        &self.recv_fd
+    }
+}
+
+impl PacketIo for RawSocketIo {
+    fn send(&mut self, packet: &[u8]) -> io::Result<usize> {
+        Ok(rawsocket::send_packet(&self.send_fd, packet, SocketAddrV4::new(self.remote, 0))?)
+    }
+
+    fn recv(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        rawsocket::recv_packet_timeout(&self.recv_fd, buf, timeout).map(|(len, _)| len)
+    }
+}
+
+/// Copy `packet` into the front of `buf`, failing like `recv_packet` if it doesn't fit
+pub(crate) fn deliver(packet: &[u8], buf: &mut [u8]) -> io::Result<usize> {
+    let dst = buf.get_mut(..packet.len()).ok_or(Errno::EMSGSIZE)?;
+    dst.copy_from_slice(packet);
+    Ok(packet.len())
+}
+
+#[derive(Debug, Default)]
+struct Wire {
+    packets: Mutex<VecDeque<Vec<u8>>>,
+    ready: Condvar,
+}
+
+/// One end of an in-memory link. What one end sends, the other receives, in order and without
+/// loss. The ends can live on different threads.
+#[derive(Debug)]
+pub struct LoopbackIo {
+    tx: Arc<Wire>,
+    rx: Arc<Wire>,
+}
+
+impl LoopbackIo {
+    /// Two ends of a new link
+    pub fn pair() -> (LoopbackIo, LoopbackIo) {
+        let (a, b) = (Arc::new(Wire::default()), Arc::new(Wire::default()));
+        (LoopbackIo { tx: a.clone(), rx: b.clone() }, LoopbackIo { tx: b, rx: a })
+    }
+
+    /// Packets sent to this end and not yet received
+    pub fn queued(&self) -> usize {
+        self.rx.packets.lock().unwrap_or_else(PoisonError::into_inner).len()
+    }
+}
+
+impl PacketIo for LoopbackIo {
+    fn send(&mut self, packet: &[u8]) -> io::Result<usize> {
+        self.tx.packets.lock().unwrap_or_else(PoisonError::into_inner).push_back(packet.to_vec());
+        self.tx.ready.notify_one();
+        Ok(packet.len())
+    }
+
+    fn recv(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let deadline = Instant::now() + timeout;
+        let mut packets = self.rx.packets.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            if let Some(packet) = packets.pop_front() {
+                return deliver(&packet, buf);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(timed_out());
+            }
+            packets = self.rx.ready.wait_timeout(packets, remaining).unwrap_or_else(PoisonError::into_inner).0;
+        }
+    }
+}
+
+/// Replays canned packets to `recv` and records everything sent. Once the script runs out,
+/// `recv` times out straight away instead of waiting.
+#[derive(Debug, Default)]
+pub struct ScriptedIo {
+    inbound: VecDeque<Vec<u8>>,
+    sent: Vec<Vec<u8>>,
+}
+
+impl ScriptedIo {
+    pub fn new(inbound: impl IntoIterator<Item = Vec<u8>>) -> Self {
+        ScriptedIo { inbound: inbound.into_iter().collect(), sent: Vec::new() }
+    }
+
+    /// Queue another packet for `recv`
+    pub fn push_inbound(&mut self, packet: Vec<u8>) {
+        self.inbound.push_back(packet);
+    }
+
+    /// Packets still to be received
+    pub fn remaining(&self) -> usize {
+        self.inbound.len()
+    }
+
+    /// Everything sent so far, oldest first
+    pub fn sent(&self) -> &[Vec<u8>] {
+        &self.sent
+    }
+
+    pub fn take_sent(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.sent)
+    }
+}
+
+impl PacketIo for ScriptedIo {
+    fn send(&mut self, packet: &[u8]) -> io::Result<usize> {
+        self.sent.push(packet.to_vec());
+        Ok(packet.len())
+    }
+
+    fn recv(&mut self, buf: &mut [u8], _timeout: Duration) -> io::Result<usize> {
+        let packet = self.inbound.pop_front().ok_or_else(timed_out)?;
+        deliver(&packet, buf)
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_loopback_pair() {
+        let (mut a, mut b) = LoopbackIo::pair();
+        let mut buf = [0u8; 8];
+        assert_eq!(a.recv(&mut buf, Duration::ZERO).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        a.send(b"one").unwrap();
+        a.send(b"two").unwrap();
+        assert_eq!((a.queued(), b.queued()), (0, 2));
+        assert_eq!(b.recv(&mut buf, Duration::ZERO).unwrap(), 3);
+        assert_eq!(&buf[..3], b"one");
+
+        // Too big for the buffer: an error, like a raw socket
+        a.send(&[0; 16]).unwrap();
+        assert_eq!(b.recv(&mut buf, Duration::ZERO).unwrap(), 3);
+        let err = b.recv(&mut buf, Duration::ZERO).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(Errno::EMSGSIZE as i32));
+    }
+
+    #[test]
+    fn test_loopback_wakes_waiting_receiver() {
+        let (mut a, mut b) = LoopbackIo::pair();
+        let receiver = thread::spawn(move || {
+            let mut buf = [0u8; 8];
+            let len = b.recv(&mut buf, Duration::from_secs(5)).unwrap();
+            buf[..len].to_vec()
+        });
+        thread::sleep(Duration::from_millis(20));
+        a.send(b"late").unwrap();
+        assert_eq!(receiver.join().unwrap(), b"late");
+
+        let t0 = Instant::now();
+        assert!(a.recv(&mut [0u8; 8], Duration::from_millis(30)).is_err());
+        assert!(t0.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_scripted_replays_and_records() {
+        let mut io: Box<dyn PacketIo> = Box::new(ScriptedIo::new([b"first".to_vec()]));
+        let mut buf = [0u8; 8];
+        assert_eq!(io.recv(&mut buf, Duration::from_secs(60)).unwrap(), 5);
+        assert_eq!(io.recv(&mut buf, Duration::from_secs(60)).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        let mut io = ScriptedIo::default();
+        io.send(b"a").unwrap();
+        io.send(b"bc").unwrap();
+        io.push_inbound(b"x".to_vec());
+        assert_eq!((io.sent(), io.remaining()), (&[b"a".to_vec(), b"bc".to_vec()][..], 1));
+        assert_eq!(io.take_sent().len(), 2);
+        assert!(io.sent().is_empty());
+    }
+}
@@ -1,25 +1,103 @@
+use crate::packet;
 use nix::errno::Errno;
-use nix::sys::socket::setsockopt;
-use nix::sys::socket::sockopt::{RcvBuf, ReceiveTimeout, ReuseAddr};
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+use nix::libc;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::socket::{getsockopt, recvmsg, sendto, setsockopt, GetSockOpt, MsgFlags, SetSockOpt, SockaddrIn};
+use nix::sys::socket::sockopt::{RcvBuf, ReceiveTimeout, ReceiveTimestamp, ReuseAddr, SendTimeout};
+#[cfg(target_os = "linux")]
+use nix::sys::socket::{sockopt::BindToDevice, sockopt::Mark};
 use nix::sys::socket::{socket, AddressFamily, SockFlag, SockProtocol, SockType};
 use nix::sys::time::{TimeVal, TimeValLike};
-use std::os::fd::OwnedFd;
-use std::time::Duration;
+#[cfg(target_os = "linux")]
+use std::ffi::OsString;
+use std::io::{self, IoSliceMut};
+use std::mem;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::time::{Duration, Instant, SystemTime};
 
-/// Get a raw send socket. Local address reuse enabled.
+/// How `new_send_socket_with` sets up a send socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendSocketOptions {
+    pub header_included: bool, // IP_HDRINCL: we write the IP header, so our ttl, id and tos are sent
+    pub reuse_addr: bool,
+}
+
+impl Default for SendSocketOptions {
+    fn default() -> Self {
+        SendSocketOptions { header_included: true, reuse_addr: true }
+    }
+}
+
+/// Get a raw send socket. Local address reuse enabled. Packets sent on it must start with their
+/// IP header, as built by `packet::wrap`.
 pub fn new_send_socket(protocol: SockProtocol) -> Result<OwnedFd, Errno> {
+    new_send_socket_with(protocol, &SendSocketOptions::default())
+}
+
+/// Like `new_send_socket`, with the options given. Without `header_included`, the kernel builds
+/// the IP header itself and packets are just the transport header and payload.
+pub fn new_send_socket_with(protocol: SockProtocol, options: &SendSocketOptions) -> Result<OwnedFd, Errno> {
     let sock_fd = socket(
         AddressFamily::Inet,
         SockType::Raw,
         SockFlag::empty(),
         protocol,
     )?;
-    setsockopt(&sock_fd, ReuseAddr, &true)?;
+    if options.reuse_addr {
+        setsockopt(&sock_fd, ReuseAddr, &true)?;
+    }
+    // Only IPPROTO_RAW implies IP_HDRINCL, so set it for every protocol
+    setsockopt(&sock_fd, IpHdrIncl, &options.header_included)?;
     Ok(sock_fd)
 }
 
-/// Get a raw recv socket. The buf size is 2 MB.
+/// Is IP_HDRINCL set on the socket?
+pub fn get_header_included(fd: &OwnedFd) -> Result<bool, Errno> {
+    getsockopt(fd, IpHdrIncl)
+}
+
+/// Send one packet to `dst`. On a raw socket the port is ignored, and with IP_HDRINCL the packet
+/// starts with its IP header, as built by `packet::wrap`.
+///
+/// macOS (like BSDs before FreeBSD 11) wants that header's total length and fragment offset in
+/// host byte order, so there they are swapped in a copy of the packet first.
+pub fn send_packet(fd: &OwnedFd, packet: &[u8], dst: SocketAddrV4) -> Result<usize, Errno> {
+    let addr = SockaddrIn::from(dst);
+    if packet::HDRINCL_HOST_BYTE_ORDER && get_header_included(fd)? {
+        let mut packet = packet.to_vec();
+        packet::to_host_byte_order(&mut packet).map_err(|_| Errno::EINVAL)?;
+        return sendto(fd.as_raw_fd(), &packet, &addr, MsgFlags::empty());
+    }
+    sendto(fd.as_raw_fd(), packet, &addr, MsgFlags::empty())
+}
+
+/// Receive one packet into `buf`. Returns its length and where it came from. A packet too big
+/// for `buf` is an `EMSGSIZE` error instead of being silently cut short; its tail is lost either
+/// way. On a non-blocking socket with nothing queued, fails with `EAGAIN`.
+pub fn recv_packet(fd: &OwnedFd, buf: &mut [u8]) -> Result<(usize, SocketAddrV4), Errno> {
+    let mut iov = [IoSliceMut::new(buf)];
+    let msg = recvmsg::<SockaddrIn>(fd.as_raw_fd(), &mut iov, None, MsgFlags::empty())?;
+    if msg.flags.contains(MsgFlags::MSG_TRUNC) {
+        return Err(Errno::EMSGSIZE);
+    }
+    let from = msg.address.ok_or(Errno::EAFNOSUPPORT)?;
+    Ok((msg.bytes, SocketAddrV4::from(from)))
+}
+
+/// Whether the kernel hands TCP and UDP packets to raw sockets. The BSDs, macOS included, keep
+/// them for their own stacks, so there a raw receive socket only sees other protocols, and TCP
+/// has to be read from a BPF device instead.
+pub const RAW_RECV_TCP: bool =
+    !cfg!(any(target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "openbsd", target_os = "netbsd", target_os = "dragonfly"));
+
+/// Get a raw recv socket. The buf size is 2 MB. Where the kernel would never deliver anything to
+/// a TCP or UDP raw socket (see `RAW_RECV_TCP`), fails with `EPROTONOSUPPORT` instead.
 pub fn new_recv_socket(protocol: SockProtocol) -> Result<OwnedFd, Errno> {
+    if !RAW_RECV_TCP && matches!(protocol, SockProtocol::Tcp | SockProtocol::Udp) {
+        return Err(Errno::EPROTONOSUPPORT);
+    }
     let sock_fd = socket(
         AddressFamily::Inet,
         SockType::Raw,
@@ -33,8 +111,798 @@ pub fn new_recv_socket(protocol: SockProtocol) -> Result<OwnedFd, Errno> {
 
 /// Set the receive timeout of a raw socket.
 pub fn set_timeout(fd: &OwnedFd, duration: Duration) -> Result<(), Errno> {
-    let timeout = TimeVal::seconds(duration.as_secs() as i64)
-        + TimeVal::microseconds(duration.subsec_micros() as i64);
-    setsockopt(&fd, ReceiveTimeout, &timeout)?;
+    setsockopt(fd, ReceiveTimeout, &to_timeval(duration))
+}
+
+/// Set the send timeout of a raw socket.
+pub fn set_send_timeout(fd: &OwnedFd, duration: Duration) -> Result<(), Errno> {
+    setsockopt(fd, SendTimeout, &to_timeval(duration))
+}
+
+/// Read back the receive timeout. Zero means blocking forever
+pub fn get_timeout(fd: &OwnedFd) -> Result<Duration, Errno> {
+    getsockopt(fd, ReceiveTimeout).map(from_timeval)
+}
+
+/// Read back the send timeout. Zero means blocking forever
+pub fn get_send_timeout(fd: &OwnedFd) -> Result<Duration, Errno> {
+    getsockopt(fd, SendTimeout).map(from_timeval)
+}
+
+/// Switch the socket between blocking and non-blocking reads and writes (O_NONBLOCK)
+pub fn set_nonblocking(fd: &OwnedFd, nonblocking: bool) -> Result<(), Errno> {
+    let mut flags = OFlag::from_bits_retain(fcntl(fd.as_raw_fd(), FcntlArg::F_GETFL)?);
+    flags.set(OFlag::O_NONBLOCK, nonblocking);
+    fcntl(fd.as_raw_fd(), FcntlArg::F_SETFL(flags)).map(drop)
+}
+
+/// Wait up to `timeout` for the socket to have something to read. Returns false on timeout
+pub fn wait_readable(fd: &OwnedFd, timeout: Duration) -> io::Result<bool> {
+    Ok(poll_readable(fd, timeout)?)
+}
+
+fn poll_readable(fd: &OwnedFd, timeout: Duration) -> Result<bool, Errno> {
+    // Round up, so a sub-millisecond wait doesn't turn into a busy poll
+    let millis = timeout.as_micros().div_ceil(1000);
+    let timeout = PollTimeout::try_from(millis).unwrap_or(PollTimeout::MAX);
+    let mut fds = [PollFd::new(fd.as_fd(), PollFlags::POLLIN)];
+    Ok(poll(&mut fds, timeout)? > 0)
+}
+
+/// Like `recv_packet`, waiting at most `timeout` for a packet. Nothing arriving in time is an
+/// error of kind `WouldBlock`. Works on blocking sockets too, but a non-blocking one can't hang if
+/// another reader takes the packet between the poll and the read.
+pub fn recv_packet_timeout(fd: &OwnedFd, buf: &mut [u8], timeout: Duration) -> io::Result<(usize, SocketAddrV4)> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match wait_readable(fd, remaining) {
+            Ok(true) => {}
+            Ok(false) => return Err(io::Error::new(io::ErrorKind::WouldBlock, "no packet before the timeout")),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+        match recv_packet(fd, buf) {
+            Err(Errno::EAGAIN | Errno::EINTR) => continue,
+            result => return result.map_err(io::Error::from),
+        }
+    }
+}
+
+/// Have the kernel stamp each received packet with its arrival time (SO_TIMESTAMP), for
+/// `recv_packet_ts`. Linux turns stamping on in the background, so the first few packets may be
+/// stamped when they're read instead.
+pub fn enable_rx_timestamps(fd: &OwnedFd) -> Result<(), Errno> {
+    setsockopt(fd, ReceiveTimestamp, &true)
+}
+
+/// Like `recv_packet`, also returning when the kernel received the packet. That's closer to the
+/// real arrival than reading the clock after the read returns. Without `enable_rx_timestamps`
+/// there's no kernel timestamp, and the time of the read is returned instead.
+pub fn recv_packet_ts(fd: &OwnedFd, buf: &mut [u8]) -> Result<(usize, SocketAddrV4, SystemTime), Errno> {
+    let mut control = [0u64; 8]; // Room for a timestamp and a few other messages, cmsghdr aligned
+    // SAFETY: all-zero is a valid sockaddr_in and msghdr; the pointers are filled in below
+    let mut addr: libc::sockaddr_in = unsafe { mem::zeroed() };
+    let mut iov = libc::iovec { iov_base: buf.as_mut_ptr() as *mut libc::c_void, iov_len: buf.len() };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut addr as *mut libc::sockaddr_in as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    // SAFETY: the header points at a live address, iovec, buffer and control buffer of the
+    // sizes given
+    let res = unsafe { libc::recvmsg(fd.as_raw_fd(), &mut msg, 0) };
+    let len = Errno::result(res)? as usize;
+    if msg.msg_flags & libc::MSG_TRUNC != 0 {
+        return Err(Errno::EMSGSIZE);
+    }
+
+    // SAFETY: the kernel wrote msg_controllen bytes of the u64 array, which is that long at least
+    let control = unsafe { std::slice::from_raw_parts(control.as_ptr() as *const u8, msg.msg_controllen as usize) };
+    let at = parse_rx_timestamp(control).unwrap_or_else(SystemTime::now);
+    let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+    Ok((len, SocketAddrV4::new(ip, u16::from_be(addr.sin_port)), at))
+}
+
+/// The SO_TIMESTAMP arrival time in the control messages of a received packet, if there is one
+fn parse_rx_timestamp(control: &[u8]) -> Option<SystemTime> {
+    // SAFETY: CMSG_LEN and CMSG_SPACE only do arithmetic
+    let header_len = unsafe { libc::CMSG_LEN(0) } as usize;
+    let mut offset = 0;
+    while offset + header_len <= control.len() {
+        // SAFETY: the header is in bounds, and read_unaligned copes with any alignment
+        let cmsg = unsafe { std::ptr::read_unaligned(control[offset..].as_ptr() as *const libc::cmsghdr) };
+        let len = cmsg.cmsg_len as usize;
+        if len < header_len || offset + len > control.len() {
+            return None; // Malformed, or cut short by MSG_CTRUNC
+        }
+
+        let data = &control[offset + header_len..offset + len];
+        if cmsg.cmsg_level == libc::SOL_SOCKET
+            && cmsg.cmsg_type == libc::SCM_TIMESTAMP
+            && data.len() >= mem::size_of::<libc::timeval>()
+        {
+            // SAFETY: checked to be long enough above
+            let tv = unsafe { std::ptr::read_unaligned(data.as_ptr() as *const libc::timeval) };
+            return Some(SystemTime::UNIX_EPOCH + from_timeval(TimeVal::new(tv.tv_sec, tv.tv_usec)));
+        }
+        offset += unsafe { libc::CMSG_SPACE((len - header_len) as libc::c_uint) } as usize;
+    }
+    None
+}
+
+/// The size of each buffer `recv_batch` receives into: a full Ethernet frame, with room to spare
+pub const BATCH_BUF_LEN: usize = 2048;
+
+/// Wait up to `timeout` for packets, then receive as many as are queued, up to one per buffer,
+/// in one `recvmmsg` call on Linux or a loop of reads elsewhere. Entry `i` of the result is the
+/// length and sender of the packet in `bufs[i]`. Empty if nothing arrived in time.
+///
+/// A packet longer than `BATCH_BUF_LEN` is cut short, and its length is reported as over
+/// `BATCH_BUF_LEN`: the full length on Linux, at least one more byte elsewhere.
+pub fn recv_batch(fd: &OwnedFd, bufs: &mut [[u8; BATCH_BUF_LEN]], timeout: Duration) -> Result<Vec<(usize, SocketAddrV4)>, Errno> {
+    if bufs.is_empty() || !poll_readable(fd, timeout)? {
+        return Ok(Vec::new());
+    }
+
+    #[cfg(target_os = "linux")]
+    return recv_batch_mmsg(fd, bufs);
+
+    #[cfg(not(target_os = "linux"))]
+    recv_batch_loop(fd, bufs)
+}
+
+#[cfg(target_os = "linux")]
+fn recv_batch_mmsg(fd: &OwnedFd, bufs: &mut [[u8; BATCH_BUF_LEN]]) -> Result<Vec<(usize, SocketAddrV4)>, Errno> {
+    // SAFETY: all-zero is a valid sockaddr_in and mmsghdr; the pointers are filled in below
+    let mut addrs: Vec<libc::sockaddr_in> = vec![unsafe { mem::zeroed() }; bufs.len()];
+    let mut iovs: Vec<libc::iovec> = bufs
+        .iter_mut()
+        .map(|buf| libc::iovec { iov_base: buf.as_mut_ptr() as *mut libc::c_void, iov_len: BATCH_BUF_LEN })
+        .collect();
+    let mut msgs: Vec<libc::mmsghdr> = vec![unsafe { mem::zeroed() }; bufs.len()];
+    for ((msg, iov), addr) in msgs.iter_mut().zip(iovs.iter_mut()).zip(addrs.iter_mut()) {
+        msg.msg_hdr.msg_name = addr as *mut libc::sockaddr_in as *mut libc::c_void;
+        msg.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+        msg.msg_hdr.msg_iov = iov;
+        msg.msg_hdr.msg_iovlen = 1;
+    }
+
+    // MSG_TRUNC makes the kernel report the full length of packets cut short
+    // SAFETY: every header points at a live address, iovec and buffer of the sizes given, and
+    // the vectors outlive the call
+    let res = unsafe {
+        libc::recvmmsg(
+            fd.as_raw_fd(),
+            msgs.as_mut_ptr(),
+            msgs.len() as libc::c_uint,
+            (libc::MSG_DONTWAIT | libc::MSG_TRUNC) as _,
+            std::ptr::null_mut(),
+        )
+    };
+    let received = match Errno::result(res) {
+        Ok(n) => n as usize,
+        Err(Errno::EAGAIN) => 0,
+        Err(e) => return Err(e),
+    };
+
+    Ok(msgs[..received]
+        .iter()
+        .zip(&addrs)
+        .map(|(msg, addr)| {
+            let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+            (msg.msg_len as usize, SocketAddrV4::new(ip, u16::from_be(addr.sin_port)))
+        })
+        .collect())
+}
+
+/// `recv_batch` for systems without `recvmmsg`: non-blocking reads until the queue is empty
+#[cfg_attr(target_os = "linux", allow(dead_code))]
+fn recv_batch_loop(fd: &OwnedFd, bufs: &mut [[u8; BATCH_BUF_LEN]]) -> Result<Vec<(usize, SocketAddrV4)>, Errno> {
+    let mut received = Vec::new();
+    for buf in bufs.iter_mut() {
+        let mut iov = [IoSliceMut::new(buf)];
+        let msg = match recvmsg::<SockaddrIn>(fd.as_raw_fd(), &mut iov, None, MsgFlags::MSG_DONTWAIT) {
+            Ok(msg) => msg,
+            Err(Errno::EAGAIN) => break,
+            Err(e) => return Err(e),
+        };
+        let len = if msg.flags.contains(MsgFlags::MSG_TRUNC) { BATCH_BUF_LEN + 1 } else { msg.bytes };
+        let from = msg.address.ok_or(Errno::EAFNOSUPPORT)?;
+        received.push((len, SocketAddrV4::from(from)));
+    }
+    Ok(received)
+}
+
+/// One classic BPF instruction, laid out like the kernel's `struct sock_filter`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SockFilter {
+    pub code: u16,
+    pub jt: u8, // Instructions to skip when a jump is taken
+    pub jf: u8, // Instructions to skip when it isn't
+    pub k: u32,
+}
+
+// Classic BPF opcodes, from linux/bpf_common.h
+const LD_B_ABS: u16 = 0x30;
+const LD_H_ABS: u16 = 0x28;
+const LD_W_ABS: u16 = 0x20;
+const LD_H_IND: u16 = 0x48;
+const LDX_B_MSH: u16 = 0xb1; // X = 4 * (packet[k] & 0xf), the IP header length
+const ALU_AND_K: u16 = 0x54;
+const JMP_JEQ_K: u16 = 0x15;
+const JMP_JSET_K: u16 = 0x45;
+const RET_K: u16 = 0x06;
+
+/// The BPF program `attach_port_filter` installs. A raw socket's filter sees the packet from the
+/// IP header on, and it keeps only unfragmented TCP/IPv4 from `remote_addr` to `local_port`.
+pub fn port_filter(local_port: u16, remote_addr: Ipv4Addr) -> Vec<SockFilter> {
+    let op = |code, k| SockFilter { code, jt: 0, jf: 0, k };
+    // Jumps to the final "drop", 13, counted from the instruction after the jump
+    let unless = |code, k, at: u8| SockFilter { code, jt: 0, jf: 12 - at, k };
+    vec![
+        op(LD_B_ABS, 0),                                          // 0: A = version and IHL
+        op(ALU_AND_K, 0xf0),                                      // 1
+        unless(JMP_JEQ_K, 0x40, 2),                               // 2: IPv4
+        op(LD_B_ABS, 9),                                          // 3: A = protocol
+        unless(JMP_JEQ_K, 6, 4),                                  // 4: TCP
+        op(LD_W_ABS, 12),                                         // 5: A = source address
+        unless(JMP_JEQ_K, u32::from(remote_addr), 6),             // 6
+        op(LD_H_ABS, 6),                                          // 7: A = flags and fragment offset
+        SockFilter { code: JMP_JSET_K, jt: 4, jf: 0, k: 0x1fff }, // 8: Not a later fragment
+        op(LDX_B_MSH, 0),                                         // 9: X = IP header length
+        op(LD_H_IND, 2),                                          // 10: A = TCP destination port
+        unless(JMP_JEQ_K, local_port as u32, 11),                 // 11
+        op(RET_K, 0x40000),                                       // 12: Accept the whole packet
+        op(RET_K, 0),                                             // 13: Drop
+    ]
+}
+
+/// Only deliver TCP packets from `remote_addr` to `local_port` to the socket (SO_ATTACH_FILTER),
+/// so a raw receive socket stops waking up for every other connection on the machine.
+/// Replaces any filter already attached. Where SO_ATTACH_FILTER doesn't exist this fails with
+/// `ENOPROTOOPT`; check received packets with `filter_accepts` instead.
+pub fn attach_port_filter(fd: &OwnedFd, local_port: u16, remote_addr: Ipv4Addr) -> Result<(), Errno> {
+    attach_filter(fd, &port_filter(local_port, remote_addr))
+}
+
+/// Attach a classic BPF program to the socket
+pub fn attach_filter(fd: &OwnedFd, program: &[SockFilter]) -> Result<(), Errno> {
+    #[cfg(target_os = "linux")]
+    {
+        let fprog = libc::sock_fprog {
+            len: program.len() as libc::c_ushort,
+            filter: program.as_ptr() as *mut libc::sock_filter, // Same layout, and the kernel copies it
+        };
+        // SAFETY: `fprog` points at `program`, which outlives the call, and the length passed is
+        // the size of `fprog`
+        let res = unsafe {
+            libc::setsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_ATTACH_FILTER,
+                &fprog as *const libc::sock_fprog as *const libc::c_void,
+                mem::size_of::<libc::sock_fprog>() as libc::socklen_t,
+            )
+        };
+        Errno::result(res).map(drop)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (fd, program);
+        Err(Errno::ENOPROTOOPT)
+    }
+}
+
+/// Remove the socket's filter. Fails with `ENOENT` if none is attached
+pub fn detach_filter(fd: &OwnedFd) -> Result<(), Errno> {
+    #[cfg(target_os = "linux")]
+    {
+        let unused: libc::c_int = 0;
+        // SAFETY: `unused` is a live c_int and the length passed is its size
+        let res = unsafe {
+            libc::setsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_DETACH_FILTER,
+                &unused as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        Errno::result(res).map(drop)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = fd;
+        Err(Errno::ENOPROTOOPT)
+    }
+}
+
+/// Run a classic BPF program over `packet` in userspace, as the kernel would on receive. Returns
+/// whether the program keeps the packet. Covers the instructions `port_filter` uses; anything
+/// else, a load past the end of the packet or a jump off the end of the program drops it.
+pub fn filter_accepts(program: &[SockFilter], packet: &[u8]) -> bool {
+    let load = |at: usize, len: usize| -> Option<u32> {
+        let bytes = packet.get(at..at.checked_add(len)?)?;
+        Some(bytes.iter().fold(0, |acc, &b| acc << 8 | b as u32))
+    };
+    let (mut a, mut x) = (0u32, 0u32);
+    let mut pc = 0;
+    while let Some(insn) = program.get(pc) {
+        let k = insn.k as usize;
+        let loaded = match insn.code {
+            LD_B_ABS => load(k, 1),
+            LD_H_ABS => load(k, 2),
+            LD_W_ABS => load(k, 4),
+            LD_H_IND => load(x as usize + k, 2),
+            LDX_B_MSH => {
+                let Some(byte) = load(k, 1) else { return false };
+                x = 4 * (byte & 0xf);
+                pc += 1;
+                continue;
+            }
+            ALU_AND_K => Some(a & insn.k),
+            JMP_JEQ_K | JMP_JSET_K => {
+                let taken = if insn.code == JMP_JEQ_K { a == insn.k } else { a & insn.k != 0 };
+                pc += 1 + if taken { insn.jt } else { insn.jf } as usize;
+                continue;
+            }
+            RET_K => return insn.k > 0,
+            _ => return false,
+        };
+        let Some(value) = loaded else { return false };
+        a = value;
+        pc += 1;
+    }
+    false
+}
+
+/// IP_HDRINCL, which nix doesn't wrap
+#[derive(Debug, Clone, Copy)]
+struct IpHdrIncl;
+
+impl SetSockOpt for IpHdrIncl {
+    type Val = bool;
+
+    fn set<F: AsFd>(&self, fd: &F, val: &bool) -> Result<(), Errno> {
+        let val = *val as libc::c_int;
+        // SAFETY: `val` is a live c_int and the length passed is its size
+        let res = unsafe {
+            libc::setsockopt(
+                fd.as_fd().as_raw_fd(),
+                libc::IPPROTO_IP,
+                libc::IP_HDRINCL,
+                &val as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        Errno::result(res).map(drop)
+    }
+}
+
+impl GetSockOpt for IpHdrIncl {
+    type Val = bool;
+
+    fn get<F: AsFd>(&self, fd: &F) -> Result<bool, Errno> {
+        let mut val: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: `val` and `len` are live and `len` holds the size of `val`
+        let res = unsafe {
+            libc::getsockopt(
+                fd.as_fd().as_raw_fd(),
+                libc::IPPROTO_IP,
+                libc::IP_HDRINCL,
+                &mut val as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        Errno::result(res).map(|_| val != 0)
+    }
+}
+
+fn to_timeval(duration: Duration) -> TimeVal {
+    TimeVal::seconds(duration.as_secs() as i64) + TimeVal::microseconds(duration.subsec_micros() as i64)
+}
+
+fn from_timeval(timeval: TimeVal) -> Duration {
+    Duration::from_secs(timeval.tv_sec() as u64) + Duration::from_micros(timeval.tv_usec() as u64)
+}
+
+/// Apply the policy-routing options of a send socket: an SO_MARK fwmark and the device to send on.
+/// `None` leaves the option untouched.
+pub fn configure_send_socket(fd: &OwnedFd, mark: Option<u32>, bind_device: Option<&str>) -> Result<(), Errno> {
+    if let Some(mark) = mark {
+        set_mark(fd, mark)?;
+    }
+    if let Some(device) = bind_device {
+        set_bind_device(fd, device)?;
+    }
     Ok(())
 }
+
+/// Mark every packet sent through the socket (SO_MARK) so policy routing can match on it.
+/// Fails with `EPERM` without CAP_NET_ADMIN, and with `ENOPROTOOPT` where SO_MARK doesn't exist.
+pub fn set_mark(fd: &OwnedFd, mark: u32) -> Result<(), Errno> {
+    #[cfg(target_os = "linux")]
+    return setsockopt(fd, Mark, &mark);
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (fd, mark);
+        Err(Errno::ENOPROTOOPT)
+    }
+}
+
+/// Read back the SO_MARK of the socket
+pub fn get_mark(fd: &OwnedFd) -> Result<u32, Errno> {
+    #[cfg(target_os = "linux")]
+    return getsockopt(fd, Mark);
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = fd;
+        Err(Errno::ENOPROTOOPT)
+    }
+}
+
+/// Only send and receive through the named interface (SO_BINDTODEVICE), e.g. to pick a VRF.
+/// Fails with `ENODEV` for an unknown interface and `ENOPROTOOPT` where the option doesn't exist.
+pub fn set_bind_device(fd: &OwnedFd, device: &str) -> Result<(), Errno> {
+    #[cfg(target_os = "linux")]
+    return setsockopt(fd, BindToDevice, &OsString::from(device));
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (fd, device);
+        Err(Errno::ENOPROTOOPT)
+    }
+}
+
+/// Read back the interface the socket is bound to. Empty when unbound
+pub fn get_bind_device(fd: &OwnedFd) -> Result<String, Errno> {
+    #[cfg(target_os = "linux")]
+    return getsockopt(fd, BindToDevice).map(|name| name.to_string_lossy().trim_end_matches('\0').to_string());
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = fd;
+        Err(Errno::ENOPROTOOPT)
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::socket::{bind, getsockname};
+    use std::thread;
+
+    /// A UDP socket, so only the option itself needs privileges
+    fn udp_socket() -> OwnedFd {
+        socket(AddressFamily::Inet, SockType::Datagram, SockFlag::empty(), None).unwrap()
+    }
+
+    #[test]
+    fn test_set_mark_readback() {
+        let fd = udp_socket();
+        match set_mark(&fd, 0x2a) {
+            Ok(()) => assert_eq!(get_mark(&fd), Ok(0x2a)),
+            Err(Errno::EPERM) => eprintln!("skipped: setting SO_MARK needs CAP_NET_ADMIN"),
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    fn test_bind_device_readback() {
+        let fd = udp_socket();
+        match configure_send_socket(&fd, None, Some("lo")) {
+            Ok(()) => assert_eq!(get_bind_device(&fd).as_deref(), Ok("lo")),
+            Err(Errno::EPERM) => eprintln!("skipped: SO_BINDTODEVICE needs CAP_NET_RAW on this kernel"),
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    fn test_bind_unknown_device() {
+        let fd = udp_socket();
+        let err = set_bind_device(&fd, "nosuchdev0").unwrap_err();
+        assert!(matches!(err, Errno::ENODEV | Errno::EPERM), "{err}");
+    }
+
+    #[test]
+    fn test_timeouts_round_trip() {
+        let fd = udp_socket();
+        assert_eq!(get_timeout(&fd), Ok(Duration::ZERO));
+
+        // The kernel keeps timeouts in jiffies and rounds up, so allow one tick at HZ=100.
+        // Sub-second parts used to be lost entirely
+        let close_to = |got: Result<Duration, Errno>, want: Duration| {
+            let got = got.unwrap();
+            assert!(got >= want && got < want + Duration::from_millis(10), "got {got:?}, want {want:?}");
+        };
+        set_timeout(&fd, Duration::from_millis(250)).unwrap();
+        close_to(get_timeout(&fd), Duration::from_millis(250));
+        set_send_timeout(&fd, Duration::from_millis(1500)).unwrap();
+        close_to(get_send_timeout(&fd), Duration::from_millis(1500));
+
+        // The fd is only borrowed, so it's still usable
+        set_timeout(&fd, Duration::ZERO).unwrap();
+        assert_eq!(get_timeout(&fd), Ok(Duration::ZERO));
+        close_to(get_send_timeout(&fd), Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn test_send_socket_header_included() {
+        match new_send_socket(SockProtocol::Tcp) {
+            Ok(fd) => assert_eq!(get_header_included(&fd), Ok(true)),
+            Err(Errno::EPERM) => return eprintln!("skipped: raw sockets need CAP_NET_RAW"),
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+
+        let options = SendSocketOptions { header_included: false, ..SendSocketOptions::default() };
+        let fd = new_send_socket_with(SockProtocol::Tcp, &options).unwrap();
+        assert_eq!(get_header_included(&fd), Ok(false));
+
+        // Only raw sockets can set the option
+        assert_eq!(setsockopt(&udp_socket(), IpHdrIncl, &true), Err(Errno::ENOPROTOOPT));
+    }
+
+    /// Two UDP sockets on loopback, and the address of each
+    fn udp_pair() -> ((OwnedFd, SocketAddrV4), (OwnedFd, SocketAddrV4)) {
+        let bound = || {
+            let fd = udp_socket();
+            bind(fd.as_raw_fd(), &SockaddrIn::from(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0))).unwrap();
+            let addr = SocketAddrV4::from(getsockname::<SockaddrIn>(fd.as_raw_fd()).unwrap());
+            (fd, addr)
+        };
+        (bound(), bound())
+    }
+
+    #[test]
+    fn test_send_and_recv_packet() {
+        let ((a, a_addr), (b, b_addr)) = udp_pair();
+        assert_eq!(send_packet(&a, b"hello", b_addr), Ok(5));
+
+        let mut buf = [0u8; 16];
+        assert_eq!(recv_packet(&b, &mut buf), Ok((5, a_addr)));
+        assert_eq!(&buf[..5], b"hello");
+
+        // Too big for the buffer: reported, not cut short
+        send_packet(&a, &[7; 32], b_addr).unwrap();
+        assert_eq!(recv_packet(&b, &mut buf), Err(Errno::EMSGSIZE));
+
+        // Too big for UDP, and nothing queued on a non-blocking socket
+        assert_eq!(send_packet(&a, &vec![0; 70_000], b_addr), Err(Errno::EMSGSIZE));
+        set_nonblocking(&b, true).unwrap();
+        assert_eq!(recv_packet(&b, &mut buf), Err(Errno::EAGAIN));
+    }
+
+    /// Append a control message to `control`, laid out and padded as the kernel would
+    fn push_cmsg(control: &mut Vec<u8>, level: i32, kind: i32, data: &[u8]) {
+        let start = control.len();
+        let mut header: libc::cmsghdr = unsafe { mem::zeroed() };
+        header.cmsg_len = unsafe { libc::CMSG_LEN(data.len() as libc::c_uint) } as _;
+        header.cmsg_level = level;
+        header.cmsg_type = kind;
+        let header_bytes: [u8; mem::size_of::<libc::cmsghdr>()] = unsafe { mem::transmute(header) };
+        control.extend_from_slice(&header_bytes);
+        control.resize(start + unsafe { libc::CMSG_LEN(0) } as usize, 0);
+        control.extend_from_slice(data);
+        control.resize(start + unsafe { libc::CMSG_SPACE(data.len() as libc::c_uint) } as usize, 0);
+    }
+
+    #[test]
+    fn test_parse_rx_timestamp() {
+        let tv = libc::timeval { tv_sec: 1_700_000_000, tv_usec: 250_000 };
+        let tv_bytes: [u8; mem::size_of::<libc::timeval>()] = unsafe { mem::transmute(tv) };
+        let want = SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_250);
+
+        // Found after an unrelated message, e.g. IP_TTL
+        let mut control = Vec::new();
+        push_cmsg(&mut control, libc::IPPROTO_IP, libc::IP_TTL, &64i32.to_ne_bytes());
+        push_cmsg(&mut control, libc::SOL_SOCKET, libc::SCM_TIMESTAMP, &tv_bytes);
+        assert_eq!(parse_rx_timestamp(&control), Some(want));
+
+        // Missing, cut short, or too short to hold a timeval
+        assert_eq!(parse_rx_timestamp(&[]), None);
+        assert_eq!(parse_rx_timestamp(&control[..control.len() - 1]), None);
+        let mut short = Vec::new();
+        push_cmsg(&mut short, libc::SOL_SOCKET, libc::SCM_TIMESTAMP, &tv_bytes[..4]);
+        assert_eq!(parse_rx_timestamp(&short), None);
+    }
+
+    #[test]
+    fn test_recv_packet_ts() {
+        let ((a, a_addr), (b, b_addr)) = udp_pair();
+        enable_rx_timestamps(&b).unwrap();
+
+        // The kernel turns stamping on in the background, and meanwhile stamps packets as they
+        // are read. Wait until a packet comes back stamped on arrival
+        let mut buf = [0u8; 16];
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            send_packet(&a, b"warmup", b_addr).unwrap();
+            thread::sleep(Duration::from_millis(5));
+            let (_, _, at) = recv_packet_ts(&b, &mut buf).unwrap();
+            if SystemTime::now().duration_since(at).unwrap_or_default() >= Duration::from_millis(5) {
+                break;
+            }
+            assert!(Instant::now() < deadline, "packets never stamped on arrival");
+        }
+
+        let before = SystemTime::now();
+        send_packet(&a, b"stamped", b_addr).unwrap();
+        thread::sleep(Duration::from_millis(20));
+
+        // Stamped on arrival, not when read
+        let (len, from, at) = recv_packet_ts(&b, &mut buf).unwrap();
+        assert_eq!((len, from), (7, a_addr));
+        assert!(at >= before - Duration::from_millis(1), "{at:?} before {before:?}");
+        assert!(SystemTime::now().duration_since(at).unwrap() >= Duration::from_millis(20));
+
+        send_packet(&a, &[0; 32], b_addr).unwrap();
+        assert_eq!(recv_packet_ts(&b, &mut buf), Err(Errno::EMSGSIZE));
+    }
+
+    #[test]
+    fn test_recv_batch() {
+        let ((a, a_addr), (b, b_addr)) = udp_pair();
+        let mut bufs = vec![[0u8; BATCH_BUF_LEN]; 8];
+        assert_eq!(recv_batch(&b, &mut bufs, Duration::from_millis(10)), Ok(vec![]));
+
+        for i in 0..5u8 {
+            send_packet(&a, &[i; 100], b_addr).unwrap();
+        }
+        send_packet(&a, &[9; 3000], b_addr).unwrap();
+
+        // All six in one call, in order. The oversized one reports its full length
+        let batch = recv_batch(&b, &mut bufs, Duration::from_secs(1)).unwrap();
+        let lens: Vec<usize> = batch.iter().map(|(len, _)| *len).collect();
+        assert_eq!(lens, [100, 100, 100, 100, 100, 3000]);
+        assert!(batch.iter().all(|(_, from)| *from == a_addr));
+        assert!((0..5).all(|i| bufs[i][..100] == [i as u8; 100]));
+        assert_eq!(bufs[5], [9; BATCH_BUF_LEN]);
+
+        // More queued than there are buffers: the rest wait for the next call
+        for i in 0..10u8 {
+            send_packet(&a, &[i], b_addr).unwrap();
+        }
+        assert_eq!(recv_batch(&b, &mut bufs, Duration::from_secs(1)).unwrap().len(), 8);
+        assert_eq!(recv_batch(&b, &mut bufs, Duration::from_secs(1)).unwrap().len(), 2);
+        assert_eq!(bufs[1][0], 9);
+    }
+
+    #[test]
+    fn test_recv_batch_loop_fallback() {
+        let ((a, a_addr), (b, b_addr)) = udp_pair();
+        let mut bufs = vec![[0u8; BATCH_BUF_LEN]; 4];
+        assert_eq!(recv_batch_loop(&b, &mut bufs), Ok(vec![]));
+
+        send_packet(&a, b"one", b_addr).unwrap();
+        send_packet(&a, &[2; 3000], b_addr).unwrap();
+        send_packet(&a, b"three", b_addr).unwrap();
+        assert!(wait_readable(&b, Duration::from_secs(1)).unwrap());
+
+        let batch = recv_batch_loop(&b, &mut bufs).unwrap();
+        assert_eq!(batch, [(3, a_addr), (BATCH_BUF_LEN + 1, a_addr), (5, a_addr)]);
+        assert_eq!(&bufs[2][..5], b"three");
+    }
+
+    #[test]
+    fn test_poll_and_nonblocking_recv() {
+        let ((a, a_addr), (b, b_addr)) = udp_pair();
+        set_nonblocking(&b, true).unwrap();
+        let mut buf = [0u8; 16];
+
+        // Nothing queued: the poll times out and a plain read doesn't block
+        assert!(!wait_readable(&b, Duration::from_millis(10)).unwrap());
+        assert_eq!(recv_packet(&b, &mut buf), Err(Errno::EAGAIN));
+        let t0 = Instant::now();
+        let err = recv_packet_timeout(&b, &mut buf, Duration::from_millis(50)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(t0.elapsed() >= Duration::from_millis(50));
+
+        send_packet(&a, b"ping", b_addr).unwrap();
+        assert!(wait_readable(&b, Duration::ZERO).unwrap());
+        assert_eq!(recv_packet_timeout(&b, &mut buf, Duration::from_secs(1)).unwrap(), (4, a_addr));
+        assert_eq!(&buf[..4], b"ping");
+
+        // Back to blocking: the timeout still applies instead of hanging
+        set_nonblocking(&b, false).unwrap();
+        let err = recv_packet_timeout(&b, &mut buf, Duration::from_millis(10)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn test_port_filter_encoding() {
+        let program = port_filter(8080, Ipv4Addr::new(10, 0, 0, 2));
+        let raw: Vec<(u16, u8, u8, u32)> = program.iter().map(|i| (i.code, i.jt, i.jf, i.k)).collect();
+        assert_eq!(
+            raw,
+            [
+                (0x30, 0, 0, 0),
+                (0x54, 0, 0, 0xf0),
+                (0x15, 0, 10, 0x40),
+                (0x30, 0, 0, 9),
+                (0x15, 0, 8, 6),
+                (0x20, 0, 0, 12),
+                (0x15, 0, 6, 0x0a000002),
+                (0x28, 0, 0, 6),
+                (0x45, 4, 0, 0x1fff),
+                (0xb1, 0, 0, 0),
+                (0x48, 0, 0, 2),
+                (0x15, 0, 1, 8080),
+                (0x06, 0, 0, 0x40000),
+                (0x06, 0, 0, 0),
+            ]
+        );
+
+        // Every jump lands on an instruction
+        for (at, i) in program.iter().enumerate().filter(|(_, i)| i.code & 0x07 == 0x05) {
+            assert!(at + 1 + (i.jt.max(i.jf) as usize) < program.len(), "instruction {at}");
+        }
+        assert_eq!(mem::size_of::<SockFilter>(), 8);
+    }
+
+    #[test]
+    fn test_port_filter_in_userspace() {
+        let program = port_filter(8080, Ipv4Addr::new(10, 0, 0, 2));
+        let packet = |src: Ipv4Addr, dst_port: u16, ihl: u8, frag_offset: u16| {
+            let tcph = crate::tcp::tcp_header::TcpHeader::new(50000, dst_port);
+            let iph = crate::ip::ip_header::IpHeader {
+                version: 4,
+                ihl,
+                total_len: (ihl as usize * 4 + 20) as u16,
+                frag_offset,
+                ttl: 64,
+                protocol: 6,
+                src_ip: src,
+                dst_ip: Ipv4Addr::new(10, 0, 0, 1),
+                options: vec![1; (ihl as usize - 5) * 4], // NOPs
+                ..Default::default()
+            };
+            packet::wrap(&iph, &tcph).unwrap()
+        };
+        let peer = Ipv4Addr::new(10, 0, 0, 2);
+
+        assert!(filter_accepts(&program, &packet(peer, 8080, 5, 0)));
+        assert!(filter_accepts(&program, &packet(peer, 8080, 7, 0))); // Port found past IP options
+        assert!(!filter_accepts(&program, &packet(peer, 8081, 5, 0)));
+        assert!(!filter_accepts(&program, &packet(Ipv4Addr::new(10, 0, 0, 3), 8080, 5, 0)));
+        assert!(!filter_accepts(&program, &packet(peer, 8080, 5, 100))); // Later fragment
+
+        // Another protocol, or cut short before the port
+        let mut udp = packet(peer, 8080, 5, 0);
+        udp[9] = 17;
+        assert!(!filter_accepts(&program, &udp));
+        assert!(!filter_accepts(&program, &packet(peer, 8080, 5, 0)[..21]));
+        assert!(!filter_accepts(&[], &packet(peer, 8080, 5, 0)));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_attach_and_detach_filter() {
+        // Any socket takes a filter, so this runs unprivileged
+        let fd = udp_socket();
+        assert_eq!(detach_filter(&fd), Err(Errno::ENOENT));
+        attach_port_filter(&fd, 8080, Ipv4Addr::LOCALHOST).unwrap();
+        assert_eq!(detach_filter(&fd), Ok(()));
+
+        // The kernel's verifier rejects a program that doesn't end in a return
+        let bad = [SockFilter { code: LD_B_ABS, jt: 0, jf: 0, k: 0 }];
+        assert_eq!(attach_filter(&fd, &bad), Err(Errno::EINVAL));
+    }
+
+    #[test]
+    fn test_configure_nothing() {
+        let fd = udp_socket();
+        assert_eq!(configure_send_socket(&fd, None, None), Ok(()));
+    }
+}
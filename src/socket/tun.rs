@@ -0,0 +1,194 @@
+// A TUN device as a `PacketIo` backend, the way CS144 runs its TCP. The kernel routes packets for
+// the interface's subnet to us instead of to its own stack, so no RST races our connections and
+// no iptables rule is needed. Linux only.
+//
+// The same device opened in TAP mode carries Ethernet frames instead; wrap it in
+// `datalink::tap::TapIo` to get IP packets, with ARP handled.
+
+use crate::datalink::ethernet::MacAddr;
+use crate::socket::packet_io::PacketIo;
+use crate::socket::rawsocket;
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::socket::{socket, AddressFamily, SockFlag, SockType};
+use std::fs::OpenOptions;
+use std::io;
+use std::mem;
+use std::net::Ipv4Addr;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::time::Duration;
+
+/// A TUN interface. Reads and writes are whole IP packets, with no Ethernet framing and no packet
+/// information prefix (IFF_NO_PI). Opened with `open_tap`, they are whole Ethernet frames.
+#[derive(Debug)]
+pub struct TunDevice {
+    fd: OwnedFd,
+    name: String, // As the kernel named it, e.g. "tun0"
+}
+
+impl TunDevice {
+    /// Create the TUN interface `name`, or attach to it if it already exists, e.g. one made
+    /// persistent with `ip tuntap add`. An empty `name` lets the kernel pick one. Needs
+    /// CAP_NET_ADMIN unless the interface is owned by the caller.
+    pub fn open(name: &str) -> io::Result<Self> {
+        Self::open_with(name, libc::IFF_TUN)
+    }
+
+    /// Like `open`, but a TAP interface, which carries Ethernet frames
+    pub fn open_tap(name: &str) -> io::Result<Self> {
+        Self::open_with(name, libc::IFF_TAP)
+    }
+
+    fn open_with(name: &str, mode: libc::c_int) -> io::Result<Self> {
+        let fd = OwnedFd::from(OpenOptions::new().read(true).write(true).open("/dev/net/tun")?);
+        let mut req = ifreq(name)?;
+        req.ifr_ifru.ifru_flags = (mode | libc::IFF_NO_PI) as libc::c_short;
+        // SAFETY: `req` is a live ifreq, which is what TUNSETIFF reads and writes
+        Errno::result(unsafe { libc::ioctl(fd.as_raw_fd(), libc::TUNSETIFF, &mut req) })?;
+        Ok(TunDevice { fd, name: ifname(&req) })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn fd(&self) -> &OwnedFd {
+        &self.fd
+    }
+
+    /// Give the interface the address `addr/prefix_len` and bring it up. The kernel then routes
+    /// the rest of that subnet through the device, so our end uses another address in it.
+    pub fn configure(&self, addr: Ipv4Addr, prefix_len: u8) -> io::Result<()> {
+        if prefix_len > 32 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "prefix length over 32"));
+        }
+        let ctl = control_socket()?;
+        let mut req = ifreq(&self.name)?;
+        req.ifr_ifru.ifru_addr = sockaddr(addr);
+        ioctl(&ctl, libc::SIOCSIFADDR, &mut req)?;
+        req.ifr_ifru.ifru_netmask = sockaddr(netmask(prefix_len));
+        ioctl(&ctl, libc::SIOCSIFNETMASK, &mut req)?;
+
+        ioctl(&ctl, libc::SIOCGIFFLAGS, &mut req)?;
+        // SAFETY: SIOCGIFFLAGS just filled in the flags
+        let flags = unsafe { req.ifr_ifru.ifru_flags };
+        req.ifr_ifru.ifru_flags = flags | (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_short;
+        ioctl(&ctl, libc::SIOCSIFFLAGS, &mut req)
+    }
+
+    /// The kernel side's hardware address. Only TAP interfaces have one; our end of the link
+    /// needs a different address of its own.
+    pub fn hw_addr(&self) -> io::Result<MacAddr> {
+        let mut req = ifreq(&self.name)?;
+        ioctl(&control_socket()?, libc::SIOCGIFHWADDR, &mut req)?;
+        // SAFETY: SIOCGIFHWADDR just filled in the hardware address
+        let data = unsafe { req.ifr_ifru.ifru_hwaddr.sa_data };
+        let mut mac = MacAddr::default();
+        for (dst, &src) in mac.0.iter_mut().zip(data.iter()) {
+            *dst = src as u8;
+        }
+        Ok(mac)
+    }
+
+    /// Set the interface MTU, the largest packet `send` may write
+    pub fn set_mtu(&self, mtu: usize) -> io::Result<()> {
+        let mut req = ifreq(&self.name)?;
+        req.ifr_ifru.ifru_mtu = libc::c_int::try_from(mtu).map_err(|_| Errno::EINVAL)?;
+        ioctl(&control_socket()?, libc::SIOCSIFMTU, &mut req)
+    }
+}
+
+impl PacketIo for TunDevice {
+    fn send(&mut self, packet: &[u8]) -> io::Result<usize> {
+        Ok(nix::unistd::write(&self.fd, packet)?)
+    }
+
+    fn recv(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        if !rawsocket::wait_readable(&self.fd, timeout)? {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "no packet before the timeout"));
+        }
+        // A TUN read hands over one whole packet (or frame), cut short if `buf` is too small
+        let len = nix::unistd::read(self.fd.as_raw_fd(), buf)?;
+        Ok(len)
+    }
+}
+
+/// A socket for interface ioctls. Any socket will do; the kernel only looks at the request
+fn control_socket() -> io::Result<OwnedFd> {
+    Ok(socket(AddressFamily::Inet, SockType::Datagram, SockFlag::SOCK_CLOEXEC, None)?)
+}
+
+fn ioctl(fd: &OwnedFd, request: libc::Ioctl, req: &mut libc::ifreq) -> io::Result<()> {
+    // SAFETY: every request used here takes a pointer to an ifreq, and `req` is a live one
+    Errno::result(unsafe { libc::ioctl(fd.as_raw_fd(), request, req as *mut libc::ifreq) })?;
+    Ok(())
+}
+
+/// An ifreq for the interface `name`, which must leave room for the terminating NUL
+fn ifreq(name: &str) -> io::Result<libc::ifreq> {
+    if name.len() >= libc::IFNAMSIZ || name.bytes().any(|b| b == 0) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("bad interface name: {name:?}")));
+    }
+    // SAFETY: all-zero is a valid ifreq: an empty name and a zeroed union
+    let mut req: libc::ifreq = unsafe { mem::zeroed() };
+    for (dst, src) in req.ifr_name.iter_mut().zip(name.bytes()) {
+        *dst = src as libc::c_char;
+    }
+    Ok(req)
+}
+
+/// The interface name in `req`, up to its NUL
+fn ifname(req: &libc::ifreq) -> String {
+    let bytes: Vec<u8> = req.ifr_name.iter().take_while(|&&c| c != 0).map(|&c| c as u8).collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn sockaddr(addr: Ipv4Addr) -> libc::sockaddr {
+    let sin = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: 0,
+        sin_addr: libc::in_addr { s_addr: u32::from(addr).to_be() },
+        sin_zero: [0; 8],
+    };
+    // SAFETY: sockaddr_in and sockaddr are both 16 bytes, and the kernel reads this as sockaddr_in
+    unsafe { mem::transmute::<libc::sockaddr_in, libc::sockaddr>(sin) }
+}
+
+/// The netmask of a `/prefix_len` subnet
+fn netmask(prefix_len: u8) -> Ipv4Addr {
+    Ipv4Addr::from(u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0))
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_netmask() {
+        assert_eq!(netmask(0), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(netmask(24), Ipv4Addr::new(255, 255, 255, 0));
+        assert_eq!(netmask(30), Ipv4Addr::new(255, 255, 255, 252));
+        assert_eq!(netmask(32), Ipv4Addr::BROADCAST);
+    }
+
+    #[test]
+    fn test_ifreq_name() {
+        let req = ifreq("tun0").unwrap();
+        assert_eq!(ifname(&req), "tun0");
+        assert_eq!(ifname(&ifreq("").unwrap()), "");
+        assert_eq!(ifname(&ifreq("fifteen_chars_x").unwrap()), "fifteen_chars_x");
+
+        // No room for the NUL, or one in the middle
+        assert_eq!(ifreq("sixteen_chars_xx").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(ifreq("tun\0x").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_sockaddr_layout() {
+        let addr = sockaddr(Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(addr.sa_family, libc::AF_INET as libc::sa_family_t);
+        assert_eq!(addr.sa_data[2..6].iter().map(|&b| b as u8).collect::<Vec<_>>(), [10, 0, 0, 1]);
+    }
+}
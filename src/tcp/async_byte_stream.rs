@@ -0,0 +1,188 @@
+use crate::tcp::byte_stream::ByteStream;
+use std::io::{self, Error, Read, Write};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+#[derive(Debug)]
+struct Shared {
+    stream: ByteStream,
+    read_waker: Option<Waker>,  // Parked reader, woken by a write or close
+    write_waker: Option<Waker>, // Parked writer, woken when a read frees space or on close
+}
+
+/// A `ByteStream` that implements `AsyncRead` and `AsyncWrite`. Clones share the same buffer,
+/// so a writer task and a reader task each hold their own handle.
+#[derive(Debug, Clone)]
+pub struct AsyncByteStream {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl AsyncByteStream {
+    /// New `AsyncByteStream` with capacity `N`
+    pub fn new(capacity: usize) -> Self {
+        AsyncByteStream::from(ByteStream::new(capacity))
+    }
+
+    /// Is the end of the byte stream reached?
+    pub fn eof(&self) -> bool {
+        self.lock().stream.eof()
+    }
+
+    /// Close the byte stream and wake a parked reader and writer. The reader gets EOF once the
+    /// buffer drains, and the writer gets the "stream closed" error
+    pub fn close(&self) {
+        let mut shared = self.lock();
+        shared.stream.close();
+        for waker in [shared.read_waker.take(), shared.write_waker.take()].into_iter().flatten() {
+            waker.wake();
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Shared> {
+        match self.shared.lock() {
+            Ok(shared) => shared,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+impl From<ByteStream> for AsyncByteStream {
+    fn from(stream: ByteStream) -> Self {
+        AsyncByteStream {
+            shared: Arc::new(Mutex::new(Shared {
+                stream,
+                read_waker: None,
+                write_waker: None,
+            })),
+        }
+    }
+}
+
+impl AsyncRead for AsyncByteStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let mut shared = self.lock();
+
+        if shared.stream.is_buffer_empty() && !shared.stream.is_closed() {
+            shared.read_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        // Reads 0 bytes at EOF, or returns the error the stream was closed with
+        let n = shared.stream.read(buf.initialize_unfilled())?;
+        buf.advance(n);
+
+        if n > 0 {
+            if let Some(waker) = shared.write_waker.take() {
+                waker.wake();
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for AsyncByteStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let mut shared = self.lock();
+
+        if shared.stream.is_closed() {
+            return Poll::Ready(Err(Error::other("stream closed")));
+        }
+        if shared.stream.remaining_capacity() == 0 && !buf.is_empty() {
+            shared.write_waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let n = shared.stream.write(buf)?;
+        if let Some(waker) = shared.read_waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(())) // no-op because this is an in-memory data structure
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_writer_and_reader_tasks() {
+        let data: Vec<u8> = (0..10_000_000).map(|i| (i % 251) as u8).collect();
+        let stream = AsyncByteStream::new(32768);
+
+        let mut writer = stream.clone();
+        let input = data.clone();
+        let producer = tokio::spawn(async move {
+            for chunk in input.chunks(1500) {
+                writer.write_all(chunk).await.unwrap();
+            }
+            writer.shutdown().await.unwrap();
+        });
+
+        let mut reader = stream;
+        let mut output = Vec::with_capacity(data.len());
+        reader.read_to_end(&mut output).await.unwrap();
+        producer.await.unwrap();
+
+        assert_eq!(output.len(), data.len());
+        assert!(output == data);
+        assert!(reader.eof());
+    }
+
+    #[tokio::test]
+    async fn test_write_after_close() {
+        let mut stream = AsyncByteStream::new(16);
+        stream.write_all(b"hello").await.unwrap();
+        stream.shutdown().await.unwrap();
+
+        let err = stream.write(b"world").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+
+        let mut out = String::new();
+        stream.read_to_string(&mut out).await.unwrap();
+        assert_eq!(out, "hello");
+    }
+
+    #[tokio::test]
+    async fn test_close_wakes_parked_writer() {
+        let stream = AsyncByteStream::new(4);
+        let mut writer = stream.clone();
+        let parked = tokio::spawn(async move { writer.write_all(b"more than four bytes").await });
+
+        // Let the writer fill the stream and park
+        while stream.lock().write_waker.is_none() {
+            tokio::task::yield_now().await;
+        }
+        stream.close();
+
+        let result = tokio::time::timeout(Duration::from_secs(5), parked).await.expect("writer still parked after close");
+        assert_eq!(result.unwrap().unwrap_err().kind(), io::ErrorKind::Other);
+    }
+
+    #[tokio::test]
+    async fn test_read_error_after_reset() {
+        let mut bs = ByteStream::new(16);
+        bs.write_all(b"abc").unwrap();
+        bs.set_error(Error::new(io::ErrorKind::ConnectionReset, "reset"));
+
+        let mut stream = AsyncByteStream::from(bs);
+        let mut buf = [0u8; 3];
+        stream.read_exact(&mut buf).await.unwrap();
+        let err = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    }
+}
@@ -1,7 +1,36 @@
 use std::collections::VecDeque;
-use std::io::{self, Error, ErrorKind, Read, Write};
+use std::fmt;
+use std::io::{self, Error, ErrorKind, IoSlice, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
 
-/// An in-order byte stream
+/// Most free space `write_from` zero-fills for a single read, so a small read from a large
+/// stream doesn't touch the whole free region
+const READ_CHUNK: usize = 4096;
+
+/// An in-order byte stream with a fixed capacity. Writes past the capacity are truncated, and
+/// reading frees space for the producer again.
+///
+/// ```
+/// use net::tcp::byte_stream::ByteStream;
+/// use std::io::{Read, Write};
+///
+/// let mut stream = ByteStream::new(8);
+/// assert_eq!(stream.write(b"hello world").unwrap(), 8); // Only the capacity is accepted
+/// assert_eq!(stream.remaining_capacity(), 0);
+///
+/// let mut buf = [0u8; 5];
+/// assert_eq!(stream.read(&mut buf).unwrap(), 5);
+/// assert_eq!(&buf, b"hello");
+///
+/// // The consumer made room, so the producer can write again before closing
+/// stream.write_all(b"!").unwrap();
+/// stream.close();
+///
+/// let mut rest = String::new();
+/// stream.read_to_string(&mut rest).unwrap();
+/// assert_eq!(rest, " wo!");
+/// assert!(stream.eof());
+/// ```
 #[derive(Debug)]
 pub struct ByteStream {
     buffer: VecDeque<u8>,
@@ -9,6 +38,86 @@ pub struct ByteStream {
     bytes_written: usize,
     bytes_read: usize,
     closed: bool,
+    error: Option<io::Error>,     // Set when the stream ended abnormally, e.g. on a reset
+    on_pressure: OnPressure,      // Capacity-pressure callback, disabled by default
+    waiters: Waiters,             // Readable/writable transition callbacks
+    low_watermark: Option<usize>, // Buffered bytes a read must fall to for a `Drained` event
+    drained: bool,                // A read crossed the low watermark since the last `take_drained`
+}
+
+/// A moment of capacity pressure, or of relief from it, in a `ByteStream` or `Reassembler`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PressureEvent {
+    /// A write only partly fit in the remaining capacity
+    WriteTruncated { requested: usize, accepted: usize },
+    /// An inserted segment was trimmed to the window: bytes already assembled and bytes past capacity
+    InsertTrimmed { dropped_low: usize, dropped_high: usize },
+    /// A pending out-of-order segment was evicted to stay under the segment cap
+    PendingEvicted { bytes: usize },
+    /// A read brought the buffered bytes from above the low watermark down to it or below
+    Drained { buffered: usize },
+}
+
+/// A capacity-pressure callback
+pub type PressureHook = Box<dyn FnMut(PressureEvent) + Send>;
+
+/// An optional `PressureHook`. Firing with no hook installed is a single branch
+#[derive(Default)]
+pub(crate) struct OnPressure(Option<PressureHook>);
+
+impl OnPressure {
+    pub(crate) fn set(&mut self, hook: Option<PressureHook>) {
+        self.0 = hook;
+    }
+
+    #[inline]
+    pub(crate) fn fire(&mut self, event: PressureEvent) {
+        if let Some(hook) = &mut self.0 {
+            hook(event);
+        }
+    }
+}
+
+/// A readiness callback
+pub type WakeHook = Box<dyn FnMut() + Send>;
+
+/// Callbacks fired on readiness transitions: empty to non-empty or closed (readable), and full to
+/// having space (writable). They run while the stream is borrowed, so they must not touch it.
+#[derive(Default)]
+pub(crate) struct Waiters {
+    readable: Option<WakeHook>,
+    writable: Option<WakeHook>,
+}
+
+impl Waiters {
+    #[inline]
+    fn wake_readable(&mut self) {
+        if let Some(hook) = &mut self.readable {
+            hook();
+        }
+    }
+
+    #[inline]
+    fn wake_writable(&mut self) {
+        if let Some(hook) = &mut self.writable {
+            hook();
+        }
+    }
+}
+
+impl fmt::Debug for Waiters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Waiters")
+            .field("readable", &self.readable.is_some())
+            .field("writable", &self.writable.is_some())
+            .finish()
+    }
+}
+
+impl fmt::Debug for OnPressure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() { "Some(PressureHook)" } else { "None" })
+    }
 }
 
 impl ByteStream {
@@ -20,21 +129,148 @@ impl ByteStream {
             bytes_written: 0,
             bytes_read: 0,
             closed: false, // It's always the producer's job to close the byte stream, never the consumer
+            error: None,
+            on_pressure: OnPressure::default(),
+            waiters: Waiters::default(),
+            low_watermark: None,
+            drained: false,
         }
     }
 
+    /// Call `hook` whenever the stream becomes readable: empty to non-empty, or closed.
+    /// Replaces any previous readable hook.
+    pub fn on_readable(&mut self, hook: impl FnMut() + Send + 'static) {
+        self.waiters.readable = Some(Box::new(hook));
+    }
+
+    /// Call `hook` whenever the stream becomes writable: full to having space.
+    /// Replaces any previous writable hook.
+    pub fn on_writable(&mut self, hook: impl FnMut() + Send + 'static) {
+        self.waiters.writable = Some(Box::new(hook));
+    }
+
+    /// Install, or with `None` remove, a callback fired whenever a write is truncated by capacity
+    pub fn set_on_pressure(&mut self, hook: Option<PressureHook>) {
+        self.on_pressure.set(hook);
+    }
+
+    /// Report a `Drained` event, and set the flag behind `take_drained`, whenever a read takes the
+    /// buffered bytes from above `low_watermark` to at or below it. `None` turns this off.
+    pub fn set_low_watermark(&mut self, low_watermark: Option<usize>) {
+        self.low_watermark = low_watermark;
+        self.drained = false;
+    }
+
+    /// Has a read crossed the low watermark since the last call?
+    pub fn take_drained(&mut self) -> bool {
+        std::mem::take(&mut self.drained)
+    }
+
     /// Remove `N` bytes from the byte stream and return the actual number of bytes popped
     pub fn pop_output(&mut self, len: usize) -> usize {
         let to_pop = len.min(self.buffer.len());
+        let was_full = self.remaining_capacity() == 0;
+        let was_buffered = self.buffer.len();
         self.buffer.drain(..to_pop);
         self.bytes_read += to_pop;
+        if was_full && self.remaining_capacity() > 0 {
+            self.waiters.wake_writable();
+        }
+        if let Some(low) = self.low_watermark {
+            if was_buffered > low && self.buffer.len() <= low {
+                self.drained = true;
+                self.on_pressure.fire(PressureEvent::Drained { buffered: self.buffer.len() });
+            }
+        }
         to_pop
     }
 
     /// Peek `N` bytes without consuming them and return a new vector of bytes peeked
     pub fn peek_output(&self, amount: usize) -> Vec<u8> {
-        let to_peek = amount.min(self.buffer.len());
-        self.buffer.iter().take(to_peek).cloned().collect()
+        let mut peeked = vec![0u8; amount.min(self.buffer.len())];
+        self.peek_into(&mut peeked);
+        peeked
+    }
+
+    /// Copy bytes into `buf` without consuming them. Zero allocation.
+    /// Returns the number of bytes copied.
+    pub fn peek_into(&self, buf: &mut [u8]) -> usize {
+        let (head, tail) = self.buffer.as_slices();
+        let to_peek = buf.len().min(self.buffer.len());
+        let from_head = to_peek.min(head.len());
+        buf[..from_head].copy_from_slice(&head[..from_head]);
+        buf[from_head..to_peek].copy_from_slice(&tail[..to_peek - from_head]);
+        to_peek
+    }
+
+    /// The buffered bytes in order, as two slices. The second slice is only non-empty when the
+    /// underlying ring has wrapped around.
+    pub fn as_slices(&self) -> (&[u8], &[u8]) {
+        self.buffer.as_slices()
+    }
+
+    /// Drain up to `max` buffered bytes straight into `w`. Zero allocation.
+    /// Stops early if `w` accepts a short write. Returns the number of bytes drained.
+    pub fn copy_to<W: Write>(&mut self, w: &mut W, max: usize) -> io::Result<usize> {
+        let mut copied = 0;
+        while copied < max && !self.buffer.is_empty() {
+            let (head, _) = self.buffer.as_slices();
+            let chunk_len = head.len().min(max - copied);
+            let n = w.write(&head[..chunk_len])?;
+            self.pop_output(n);
+            copied += n;
+            if n < chunk_len {
+                break;
+            }
+        }
+        Ok(copied)
+    }
+
+    /// Fill the remaining capacity straight from `r`, at most `READ_CHUNK` bytes per read. Zero
+    /// allocation. Reads interrupted by a signal are retried.
+    /// Returns the number of bytes written, 0 when the stream is full or `r` is at EOF.
+    pub fn write_from<R: Read>(&mut self, r: &mut R) -> io::Result<usize> {
+        if self.closed {
+            return Err(Error::other("stream closed"));
+        }
+
+        let was_empty = self.buffer.is_empty();
+        let mut written = 0;
+        while self.remaining_capacity() > 0 {
+            // Grow one chunk into the preallocated ring, read into its first free region, then trim
+            let len = self.buffer.len();
+            self.buffer.resize(len + self.remaining_capacity().min(READ_CHUNK), 0);
+            let (head, tail) = self.buffer.as_mut_slices();
+            let free = if len < head.len() { &mut head[len..] } else { &mut tail[len - head.len()..] };
+            let free_len = free.len();
+
+            let result = r.read(free);
+            let n = *result.as_ref().unwrap_or(&0);
+            self.buffer.truncate(len + n);
+            self.bytes_written += n;
+            written += n;
+
+            match result {
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) if written == 0 => return Err(e),
+                Ok(n) if n == free_len => continue,
+                _ => break,
+            }
+        }
+        if was_empty && written > 0 {
+            self.waiters.wake_readable();
+        }
+        Ok(written)
+    }
+
+    /// Write every slice in order, or nothing at all if they don't fit together.
+    /// Fails with `WriteZero` when the remaining capacity is too small.
+    pub fn write_all_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<()> {
+        let total: usize = bufs.iter().map(|buf| buf.len()).sum();
+        if !self.closed && total > self.remaining_capacity() {
+            return Err(Error::new(ErrorKind::WriteZero, "not enough capacity for all slices"));
+        }
+        self.write_vectored(bufs).map(|_| ())
     }
 
     /// The remaining capacity in the byte stream
@@ -42,9 +278,41 @@ impl ByteStream {
         self.capacity.saturating_sub(self.buffer.len())
     }
 
-    /// Close the byte stream
+    /// The maximum number of bytes the byte stream buffers
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Resize the byte stream. Growing is always allowed, but shrinking stops at the number of
+    /// bytes currently buffered so no data is discarded. Returns the new capacity.
+    pub fn set_capacity(&mut self, capacity: usize) -> usize {
+        let was_full = self.remaining_capacity() == 0;
+        self.capacity = capacity.max(self.buffer.len());
+        if was_full && self.remaining_capacity() > 0 {
+            self.waiters.wake_writable();
+        }
+        self.buffer.reserve(self.capacity - self.buffer.len()); // Keep writes allocation free
+        self.capacity
+    }
+
+    /// Close the byte stream. Wakes readers so they observe the EOF
     pub fn close(&mut self) {
-        self.closed = true;
+        if !self.closed {
+            self.closed = true;
+            self.waiters.wake_readable();
+        }
+    }
+
+    /// Close the byte stream with an error. Buffered bytes can still be read, after which
+    /// every `read` returns the error instead of signalling a clean EOF.
+    pub fn set_error(&mut self, err: io::Error) {
+        self.error = Some(err);
+        self.close();
+    }
+
+    /// The error the byte stream was closed with, if any
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
     }
 
     /// Is the byte stream closed?
@@ -76,19 +344,147 @@ impl ByteStream {
     pub fn bytes_read(&self) -> usize {
         self.bytes_read
     }
+
+    /// Split the byte stream into a writer half and a reader half that can live on different
+    /// threads. Both halves share the same buffer.
+    pub fn split(self) -> (StreamWriter, StreamReader) {
+        let shared = Arc::new(Mutex::new(self));
+        (StreamWriter { shared: shared.clone() }, StreamReader { shared })
+    }
+}
+
+/// The producer side of a byte stream. Implemented by a whole `ByteStream` and by a `StreamWriter`
+pub trait StreamWrite: Write {
+    fn remaining_capacity(&self) -> usize;
+    fn bytes_written(&self) -> usize;
+    fn close(&mut self);
+    fn set_error(&mut self, err: io::Error);
+    fn has_error(&self) -> bool;
+}
+
+impl StreamWrite for ByteStream {
+    fn remaining_capacity(&self) -> usize {
+        ByteStream::remaining_capacity(self)
+    }
+
+    fn bytes_written(&self) -> usize {
+        ByteStream::bytes_written(self)
+    }
+
+    fn close(&mut self) {
+        ByteStream::close(self)
+    }
+
+    fn set_error(&mut self, err: io::Error) {
+        ByteStream::set_error(self, err)
+    }
+
+    fn has_error(&self) -> bool {
+        self.error.is_some()
+    }
+}
+
+/// The writer half of a split `ByteStream`
+#[derive(Debug, Clone)]
+pub struct StreamWriter {
+    shared: Arc<Mutex<ByteStream>>,
+}
+
+/// The reader half of a split `ByteStream`
+#[derive(Debug, Clone)]
+pub struct StreamReader {
+    shared: Arc<Mutex<ByteStream>>,
+}
+
+/// Lock the shared stream. A panic on the other half doesn't leave the buffer inconsistent
+fn lock(shared: &Mutex<ByteStream>) -> MutexGuard<'_, ByteStream> {
+    match shared.lock() {
+        Ok(stream) => stream,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+impl StreamWriter {
+    /// Is the byte stream closed?
+    pub fn is_closed(&self) -> bool {
+        lock(&self.shared).is_closed()
+    }
+}
+
+impl StreamWrite for StreamWriter {
+    fn remaining_capacity(&self) -> usize {
+        lock(&self.shared).remaining_capacity()
+    }
+
+    fn bytes_written(&self) -> usize {
+        lock(&self.shared).bytes_written()
+    }
+
+    fn close(&mut self) {
+        lock(&self.shared).close()
+    }
+
+    fn set_error(&mut self, err: io::Error) {
+        lock(&self.shared).set_error(err)
+    }
+
+    fn has_error(&self) -> bool {
+        lock(&self.shared).error().is_some()
+    }
+}
+
+impl Write for StreamWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        lock(&self.shared).write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        lock(&self.shared).write_vectored(bufs) // One lock for all the slices
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl StreamReader {
+    /// Peek `N` bytes without consuming them and return a new vector of bytes peeked
+    pub fn peek_output(&self, amount: usize) -> Vec<u8> {
+        lock(&self.shared).peek_output(amount)
+    }
+
+    /// Remove `N` bytes from the byte stream and return the actual number of bytes popped
+    pub fn pop_output(&mut self, len: usize) -> usize {
+        lock(&self.shared).pop_output(len)
+    }
+
+    /// Is the end of the byte stream reached?
+    pub fn eof(&self) -> bool {
+        lock(&self.shared).eof()
+    }
+
+    /// The total number of bytes read
+    pub fn bytes_read(&self) -> usize {
+        lock(&self.shared).bytes_read()
+    }
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        lock(&self.shared).read(buf)
+    }
 }
 
 impl Read for ByteStream {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let to_read = buf.len().min(self.buffer.len());
+        // Copy straight out of both halves of the ring, then drop what was copied
+        let to_read = self.peek_into(buf);
 
         if to_read > 0 {
-            // Make ring buffer contiguous if not already
-            let contiguous = self.buffer.make_contiguous();
-            buf[..to_read].copy_from_slice(&contiguous[..to_read]);
-            self.buffer.drain(..to_read);
-            self.bytes_read += to_read;
+            self.pop_output(to_read);
             Ok(to_read)
+        } else if let Some(err) = &self.error {
+            Err(Error::new(err.kind(), err.to_string()))
         } else {
             Ok(0)
         }
@@ -98,15 +494,49 @@ impl Read for ByteStream {
 impl Write for ByteStream {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         if self.closed {
-            return Err(Error::new(ErrorKind::Other, "stream closed"));
+            return Err(Error::other("stream closed"));
         }
+        let was_empty = self.buffer.is_empty();
         let available = self.remaining_capacity();
         let to_write = buf.len().min(available);
+        if to_write < buf.len() {
+            self.on_pressure.fire(PressureEvent::WriteTruncated {
+                requested: buf.len(),
+                accepted: to_write,
+            });
+        }
         self.buffer.extend(&buf[..to_write]);
         self.bytes_written += to_write;
+        if was_empty && to_write > 0 {
+            self.waiters.wake_readable();
+        }
         Ok(to_write)
     }
 
+    /// Fill the slices in order, stopping mid-slice when the capacity runs out
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        if self.closed {
+            return Err(Error::other("stream closed"));
+        }
+        let requested: usize = bufs.iter().map(|buf| buf.len()).sum();
+        let was_empty = self.buffer.is_empty();
+        let mut accepted = 0;
+        for buf in bufs {
+            let to_write = buf.len().min(self.remaining_capacity());
+            self.buffer.extend(&buf[..to_write]);
+            accepted += to_write;
+        }
+
+        self.bytes_written += accepted;
+        if accepted < requested {
+            self.on_pressure.fire(PressureEvent::WriteTruncated { requested, accepted });
+        }
+        if was_empty && accepted > 0 {
+            self.waiters.wake_readable();
+        }
+        Ok(accepted)
+    }
+
     fn flush(&mut self) -> io::Result<()> {
         Ok(()) // no-op because this is an in-memory data structure
     }
@@ -118,6 +548,7 @@ impl Write for ByteStream {
 mod tests {
     use super::*;
     use std::io::{ErrorKind, Read, Write};
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     fn generate_data(size: usize) -> Vec<u8> {
         (0..size as u8).collect()
@@ -203,6 +634,7 @@ mod tests {
     fn test_write_over_capacity() {
         let capacity = 20;
         let mut bs = ByteStream::new(capacity);
+        let events = record_pressure(&mut bs);
         let data = generate_data(50);
         let n_written = bs.write(&data).unwrap();
         assert_eq!(n_written, capacity);
@@ -212,6 +644,200 @@ mod tests {
         // Write again to overflow
         let n_written = bs.write(&data).unwrap();
         assert_eq!(n_written, 0);
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                PressureEvent::WriteTruncated { requested: 50, accepted: 20 },
+                PressureEvent::WriteTruncated { requested: 50, accepted: 0 },
+            ]
+        );
+    }
+
+    /// Install a pressure hook that records every event
+    fn record_pressure(bs: &mut ByteStream) -> Arc<Mutex<Vec<PressureEvent>>> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        bs.set_on_pressure(Some(Box::new(move |event| sink.lock().unwrap().push(event))));
+        events
+    }
+
+    #[test]
+    fn test_partial_write_pressure_events() {
+        let mut bs = ByteStream::new(20);
+        let events = record_pressure(&mut bs);
+
+        bs.write_all(&generate_data(15)).unwrap(); // Fits, no event
+        assert_eq!(bs.write(&generate_data(50)).unwrap(), 5);
+        assert_eq!(bs.write(&generate_data(50)).unwrap(), 0);
+        bs.pop_output(10);
+        assert_eq!(bs.write(&generate_data(10)).unwrap(), 10);
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                PressureEvent::WriteTruncated { requested: 50, accepted: 5 },
+                PressureEvent::WriteTruncated { requested: 50, accepted: 0 },
+            ]
+        );
+
+        // Removing the hook stops the events
+        bs.set_on_pressure(None);
+        assert_eq!(bs.write(&generate_data(1)).unwrap(), 0);
+        assert_eq!(events.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_low_watermark() {
+        let mut bs = ByteStream::new(20);
+        let events = record_pressure(&mut bs);
+        bs.set_low_watermark(Some(8));
+
+        bs.write_all(&generate_data(20)).unwrap();
+        bs.pop_output(10); // 10 left, still above
+        assert!(!bs.take_drained());
+        bs.pop_output(4); // Crosses to 6
+        bs.pop_output(6); // Already below, no second event
+        assert!(bs.take_drained());
+        assert!(!bs.take_drained());
+
+        // Refilling re-arms it. Landing exactly on the watermark counts
+        bs.write_all(&generate_data(9)).unwrap();
+        bs.pop_output(1);
+        assert!(bs.take_drained());
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![PressureEvent::Drained { buffered: 6 }, PressureEvent::Drained { buffered: 8 }]
+        );
+
+        bs.set_low_watermark(None);
+        bs.write_all(&generate_data(12)).unwrap();
+        bs.pop_output(20);
+        assert!(!bs.take_drained());
+        assert_eq!(events.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_set_capacity_shrink_keeps_data() {
+        let mut bs = ByteStream::new(20);
+        bs.write_all(b"hello world").unwrap();
+
+        // Shrinking below the buffered length stops at the buffered length
+        assert_eq!(bs.set_capacity(4), 11);
+        assert_eq!(bs.capacity(), 11);
+        assert_eq!(bs.remaining_capacity(), 0);
+        assert_eq!(bs.write(b"!").unwrap(), 0);
+        assert_eq!(bs.peek_output(20), b"hello world");
+
+        // Draining frees nothing until the stream is below its new capacity
+        bs.pop_output(6);
+        assert_eq!(bs.remaining_capacity(), 6);
+    }
+
+    #[test]
+    fn test_set_capacity_grow_accepts_writes() {
+        let mut bs = ByteStream::new(5);
+        bs.write_all(b"hello").unwrap();
+        assert_eq!(bs.write(b" world").unwrap(), 0);
+
+        assert_eq!(bs.set_capacity(11), 11);
+        assert_eq!(bs.remaining_capacity(), 6);
+        bs.write_all(b" world").unwrap();
+        assert_eq!(bs.peek_output(20), b"hello world");
+    }
+
+    #[test]
+    fn test_write_vectored_capacity_ends_mid_slice() {
+        let mut bs = ByteStream::new(8);
+        let bufs = [IoSlice::new(b"abc"), IoSlice::new(b"defgh"), IoSlice::new(b"ijk")];
+        assert_eq!(bs.write_vectored(&bufs).unwrap(), 8);
+        assert_eq!(bs.bytes_written(), 8);
+        assert_eq!(bs.peek_output(8), b"abcdefgh");
+
+        bs.pop_output(2);
+        let bufs = [IoSlice::new(b"i"), IoSlice::new(b"jkl"), IoSlice::new(b"mno")];
+        assert_eq!(bs.write_vectored(&bufs).unwrap(), 2);
+        assert_eq!(bs.peek_output(8), b"cdefghij");
+        assert_eq!(bs.write_vectored(&bufs).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_write_all_vectored() {
+        let mut bs = ByteStream::new(8);
+        let header = IoSlice::new(b"hdr:");
+        bs.write_all_vectored(&[header, IoSlice::new(b"abcd")]).unwrap();
+
+        // Nothing is written when the slices don't all fit
+        bs.pop_output(4);
+        let err = bs.write_all_vectored(&[header, IoSlice::new(b"efgh")]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WriteZero);
+        assert_eq!(bs.peek_output(8), b"abcd");
+
+        bs.close();
+        assert!(bs.write_all_vectored(&[header]).is_err());
+    }
+
+    #[test]
+    fn test_split_write_vectored() {
+        let (mut writer, mut reader) = ByteStream::new(20).split();
+        let bufs = [IoSlice::new(b"hello"), IoSlice::new(b" "), IoSlice::new(b"world")];
+        assert_eq!(writer.write_vectored(&bufs).unwrap(), 11);
+
+        let mut buf = [0u8; 11];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello world");
+    }
+
+    /// Register counting readable/writable hooks
+    fn count_wakes(bs: &mut ByteStream) -> (Arc<AtomicUsize>, Arc<AtomicUsize>) {
+        let (readable, writable) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let (r, w) = (readable.clone(), writable.clone());
+        bs.on_readable(move || {
+            r.fetch_add(1, Ordering::SeqCst);
+        });
+        bs.on_writable(move || {
+            w.fetch_add(1, Ordering::SeqCst);
+        });
+        (readable, writable)
+    }
+
+    #[test]
+    fn test_wake_hooks_fire_on_transitions_only() {
+        let mut bs = ByteStream::new(8);
+        let (readable, writable) = count_wakes(&mut bs);
+        let wakes = || (readable.load(Ordering::SeqCst), writable.load(Ordering::SeqCst));
+
+        bs.write_all(b"ab").unwrap(); // Empty -> non-empty
+        bs.write_all(b"cd").unwrap();
+        assert_eq!(wakes(), (1, 0));
+
+        bs.write_all(b"efgh").unwrap(); // Now full
+        assert_eq!(bs.write(b"i").unwrap(), 0);
+        bs.pop_output(1); // Full -> has space
+        bs.pop_output(1);
+        assert_eq!(wakes(), (1, 1));
+
+        let mut buf = [0u8; 6];
+        bs.read_exact(&mut buf).unwrap(); // Drained, but it wasn't full
+        assert_eq!(wakes(), (1, 1));
+
+        bs.write_all_vectored(&[IoSlice::new(b"xyz")]).unwrap(); // Empty -> non-empty again
+        assert_eq!(bs.write_from(&mut io::Cursor::new(b"12345")).unwrap(), 5); // Fills it up
+        let mut sink = Vec::new();
+        assert_eq!(bs.copy_to(&mut sink, 2).unwrap(), 2); // Full -> has space
+        assert_eq!(wakes(), (2, 2));
+
+        bs.close(); // Readers must see the EOF
+        bs.close();
+        assert_eq!(wakes(), (3, 2));
+    }
+
+    #[test]
+    fn test_wake_readable_on_error() {
+        let mut bs = ByteStream::new(8);
+        let (readable, _) = count_wakes(&mut bs);
+        bs.set_error(Error::new(ErrorKind::ConnectionReset, "reset"));
+        assert_eq!(readable.load(Ordering::SeqCst), 1);
     }
 
     #[test]
@@ -246,6 +872,147 @@ mod tests {
         assert_eq!(peeked, b"hello world");
     }
 
+    /// Write, read, write so the buffered bytes wrap around the end of the ring
+    fn wrapped_stream() -> ByteStream {
+        let mut bs = ByteStream::new(8);
+        bs.write_all(b"xxxxxabc").unwrap();
+        bs.pop_output(5);
+        bs.write_all(b"defgh").unwrap();
+        bs
+    }
+
+    #[test]
+    fn test_as_slices_wrapped() {
+        let bs = wrapped_stream();
+        let (head, tail) = bs.as_slices();
+        assert!(!tail.is_empty());
+        assert_eq!([head, tail].concat(), b"abcdefgh");
+    }
+
+    #[test]
+    fn test_peek_into_wrapped() {
+        let bs = wrapped_stream();
+
+        let mut buf = [0u8; 16];
+        assert_eq!(bs.peek_into(&mut buf), 8);
+        assert_eq!(&buf[..8], b"abcdefgh");
+
+        let mut small = [0u8; 2];
+        assert_eq!(bs.peek_into(&mut small), 2);
+        assert_eq!(&small, b"ab");
+
+        assert_eq!(bs.peek_output(5), b"abcde");
+        assert_eq!(bs.buffer_size(), 8); // Nothing consumed
+    }
+
+    #[test]
+    fn test_read_wrapped() {
+        let mut bs = wrapped_stream();
+
+        let mut buf = [0u8; 5];
+        assert_eq!(bs.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf, b"abcde");
+
+        // Refill across the wrap point again
+        bs.write_all(b"ijklm").unwrap();
+        let mut rest = Vec::new();
+        bs.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"fghijklm");
+        assert_eq!(bs.bytes_read(), 18);
+    }
+
+    /// A writer that accepts at most `limit` bytes per call
+    struct ShortWriter {
+        data: Vec<u8>,
+        limit: usize,
+    }
+
+    impl Write for ShortWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.limit);
+            self.data.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_copy_to_wrapped() {
+        let mut bs = wrapped_stream();
+        let mut out = Vec::new();
+        assert_eq!(bs.copy_to(&mut out, 6).unwrap(), 6);
+        assert_eq!(out, b"abcdef");
+        assert_eq!(bs.bytes_read(), 11);
+
+        // Stops at the first short write
+        let mut short = ShortWriter { data: Vec::new(), limit: 1 };
+        assert_eq!(bs.copy_to(&mut short, 100).unwrap(), 1);
+        assert_eq!(short.data, b"g");
+        assert_eq!(bs.peek_output(8), b"h");
+    }
+
+    #[test]
+    fn test_write_from_fills_capacity() {
+        let mut bs = wrapped_stream();
+        bs.pop_output(6);
+
+        // The free space wraps around the end of the ring
+        let mut src = io::Cursor::new(b"0123456789".to_vec());
+        assert_eq!(bs.write_from(&mut src).unwrap(), 6);
+        assert_eq!(bs.peek_output(8), b"gh012345");
+        assert_eq!(bs.bytes_written(), 19);
+        assert_eq!(bs.write_from(&mut src).unwrap(), 0); // Full
+
+        bs.close();
+        assert!(bs.write_from(&mut src).is_err());
+    }
+
+    #[test]
+    fn test_write_from_retries_interrupted() {
+        struct Interrupting<'a> {
+            interrupts: usize,
+            src: &'a [u8],
+        }
+        impl Read for Interrupting<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.interrupts > 0 {
+                    self.interrupts -= 1;
+                    return Err(Error::from(ErrorKind::Interrupted));
+                }
+                self.src.read(buf)
+            }
+        }
+
+        let mut bs = ByteStream::new(3 * READ_CHUNK);
+        let data: Vec<u8> = (0..2 * READ_CHUNK + 100).map(|i| (i % 251) as u8).collect();
+        let mut src = Interrupting { interrupts: 2, src: &data };
+        assert_eq!(bs.write_from(&mut src).unwrap(), data.len()); // Over several chunks
+        assert_eq!(bs.peek_output(data.len()), data);
+        assert_eq!(bs.remaining_capacity(), READ_CHUNK - 100);
+    }
+
+    #[test]
+    fn test_pipe_1mb_through_copy_to_and_write_from() {
+        let data: Vec<u8> = (0..1 << 20).map(|i| (i % 251) as u8).collect();
+        let mut src = io::Cursor::new(&data[..]);
+        let mut out = Vec::new();
+        let mut bs = ByteStream::new(4096);
+
+        loop {
+            let n_in = bs.write_from(&mut src).unwrap();
+            let n_out = bs.copy_to(&mut out, 1500).unwrap();
+            if n_in == 0 && n_out == 0 {
+                break;
+            }
+        }
+        assert_eq!(out, data);
+        assert_eq!(bs.bytes_written(), data.len());
+        assert_eq!(bs.bytes_read(), data.len());
+    }
+
     #[test]
     fn test_close() {
         let mut bs = ByteStream::new(20);
@@ -276,6 +1043,76 @@ mod tests {
         assert!(bs.eof());
     }
 
+    #[test]
+    fn test_set_error() {
+        let mut bs = ByteStream::new(20);
+        bs.write_all(b"hello").unwrap();
+        bs.set_error(Error::new(ErrorKind::ConnectionReset, "reset by peer"));
+        assert!(bs.is_closed());
+        assert!(bs.write(b"world").is_err());
+
+        // Buffered data drains first
+        let mut buf = [0u8; 5];
+        bs.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        // Then the error on every read
+        for _ in 0..2 {
+            let err = bs.read(&mut buf).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+            assert_eq!(err.to_string(), "reset by peer");
+        }
+        assert_eq!(bs.error().map(|e| e.kind()), Some(ErrorKind::ConnectionReset));
+    }
+
+    #[test]
+    fn test_split_writer_closes_then_reader_drains() {
+        let (mut writer, mut reader) = ByteStream::new(20).split();
+        writer.write_all(b"hello world").unwrap();
+        assert_eq!(writer.remaining_capacity(), 9);
+        writer.close();
+        assert!(writer.write(b"!").is_err());
+
+        assert_eq!(reader.peek_output(5), b"hello");
+        assert!(!reader.eof());
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "hello world");
+        assert!(reader.eof());
+        assert_eq!(writer.remaining_capacity(), 20);
+    }
+
+    #[test]
+    fn test_split_across_threads() {
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let (mut writer, mut reader) = ByteStream::new(1024).split();
+
+        let input = data.clone();
+        let producer = std::thread::spawn(move || {
+            let mut remaining = &input[..];
+            while !remaining.is_empty() {
+                let n = writer.write(remaining).unwrap();
+                remaining = &remaining[n..];
+                if n == 0 {
+                    std::thread::yield_now();
+                }
+            }
+            writer.close();
+        });
+
+        let mut output = Vec::new();
+        let mut buf = [0u8; 700];
+        while !reader.eof() {
+            let n = reader.read(&mut buf).unwrap();
+            output.extend_from_slice(&buf[..n]);
+            if n == 0 {
+                std::thread::yield_now();
+            }
+        }
+        producer.join().unwrap();
+        assert_eq!(output, data);
+    }
+
     #[test]
     fn test_make_contiguous() {
         let mut bs = ByteStream::new(20);
@@ -303,4 +1140,83 @@ mod tests {
 
         assert!(bs.flush().is_ok()); // No-op flush
     }
+
+    /// Both ends of a stream, so one contract covers a whole `ByteStream` and its split halves
+    trait Ends {
+        fn writer(&mut self) -> &mut dyn StreamWrite;
+        fn reader(&mut self) -> &mut dyn Read;
+    }
+
+    impl Ends for ByteStream {
+        fn writer(&mut self) -> &mut dyn StreamWrite {
+            self
+        }
+
+        fn reader(&mut self) -> &mut dyn Read {
+            self
+        }
+    }
+
+    impl Ends for (StreamWriter, StreamReader) {
+        fn writer(&mut self) -> &mut dyn StreamWrite {
+            &mut self.0
+        }
+
+        fn reader(&mut self) -> &mut dyn Read {
+            &mut self.1
+        }
+    }
+
+    /// The semantics every way of reaching a `ByteStream` must agree on
+    fn check_contract<E: Ends>(new: impl Fn(usize) -> E) {
+        // Writes are truncated at the capacity, and reading frees it again
+        let mut ends = new(8);
+        assert_eq!(ends.writer().write(b"hello world").unwrap(), 8);
+        assert_eq!(ends.writer().write(b"!").unwrap(), 0);
+        assert_eq!(ends.writer().remaining_capacity(), 0);
+        let mut buf = [0u8; 5];
+        assert_eq!(ends.reader().read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf, b"hello");
+        assert_eq!(ends.writer().remaining_capacity(), 5);
+        assert_eq!(ends.writer().bytes_written(), 8);
+
+        // Vectored writes stop mid-slice
+        let bufs = [IoSlice::new(b"ab"), IoSlice::new(b"cdef")];
+        assert_eq!(ends.writer().write_vectored(&bufs).unwrap(), 5);
+        let mut out = Vec::new();
+        ends.writer().close();
+        ends.reader().read_to_end(&mut out).unwrap();
+        assert_eq!(out, b" woabcde");
+
+        // Closed: writes fail, and reads return 0 once drained
+        assert!(ends.writer().write(b"x").is_err());
+        assert_eq!(ends.reader().read(&mut buf).unwrap(), 0);
+        assert!(!ends.writer().has_error());
+
+        // Errored: buffered bytes first, then the error on every read
+        let mut ends = new(8);
+        ends.writer().write_all(b"abc").unwrap();
+        ends.writer().set_error(Error::from(ErrorKind::ConnectionReset));
+        assert!(ends.writer().has_error());
+        assert_eq!(ends.reader().read(&mut buf).unwrap(), 3);
+        for _ in 0..2 {
+            assert_eq!(ends.reader().read(&mut buf).unwrap_err().kind(), ErrorKind::ConnectionReset);
+        }
+
+        // An empty read buffer reads nothing, even with bytes buffered
+        let mut ends = new(8);
+        ends.writer().write_all(b"abc").unwrap();
+        assert_eq!(ends.reader().read(&mut []).unwrap(), 0);
+        assert_eq!(ends.writer().remaining_capacity(), 5);
+    }
+
+    #[test]
+    fn test_contract_whole_stream() {
+        check_contract(ByteStream::new);
+    }
+
+    #[test]
+    fn test_contract_split_halves() {
+        check_contract(|capacity| ByteStream::new(capacity).split());
+    }
 }
@@ -0,0 +1,78 @@
+use std::time::{Duration, Instant};
+
+/// Linux's historical `tcp_challenge_ack_limit`
+pub const DEFAULT_CHALLENGE_ACK_LIMIT: u32 = 1000;
+
+/// Caps the challenge ACKs sent per second (RFC 5961 section 7), so a flood of spoofed segments
+/// can't turn the connection into an ACK amplifier.
+#[derive(Debug, Clone)]
+pub struct ChallengeAckLimiter {
+    max_per_sec: u32,
+    window_start: Option<Instant>, // Start of the current one-second window
+    sent: u32,                     // Challenge ACKs allowed in the current window
+}
+
+impl ChallengeAckLimiter {
+    pub fn new(max_per_sec: u32) -> Self {
+        ChallengeAckLimiter { max_per_sec, window_start: None, sent: 0 }
+    }
+
+    pub fn max_per_sec(&self) -> u32 {
+        self.max_per_sec
+    }
+
+    pub fn set_max_per_sec(&mut self, max_per_sec: u32) {
+        self.max_per_sec = max_per_sec;
+    }
+
+    /// May a challenge ACK be sent at `now`? Counts it if so.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        match self.window_start {
+            Some(start) if now.saturating_duration_since(start) < Duration::from_secs(1) => {}
+            _ => {
+                self.window_start = Some(now);
+                self.sent = 0;
+            }
+        }
+
+        if self.sent < self.max_per_sec {
+            self.sent += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Default for ChallengeAckLimiter {
+    fn default() -> Self {
+        Self::new(DEFAULT_CHALLENGE_ACK_LIMIT)
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_per_second() {
+        let mut limiter = ChallengeAckLimiter::new(3);
+        let t0 = Instant::now();
+
+        let allowed = (0..10).filter(|_| limiter.allow(t0)).count();
+        assert_eq!(allowed, 3);
+        assert!(!limiter.allow(t0 + Duration::from_millis(999)));
+
+        // A new second starts a new budget
+        assert!(limiter.allow(t0 + Duration::from_secs(1)));
+        assert_eq!((0..10).filter(|_| limiter.allow(t0 + Duration::from_millis(1500))).count(), 2);
+    }
+
+    #[test]
+    fn test_zero_limit_suppresses_all() {
+        let mut limiter = ChallengeAckLimiter::new(0);
+        assert!(!limiter.allow(Instant::now()));
+    }
+}
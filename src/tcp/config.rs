@@ -0,0 +1,29 @@
+use crate::tcp::congestion::{CcAlgorithm, CubicParams};
+use crate::tcp::timestamps::{MillisClock, TsClock};
+use std::sync::Arc;
+
+/// Per-connection knobs, mostly for controlled experiments
+#[derive(Debug, Clone)]
+pub struct TcpConfig {
+    pub mss: usize,                     // Largest payload per segment
+    pub initial_cwnd_segments: u8,      // IW in segments, e.g. 1, 4 or 10 (RFC 6928)
+    pub congestion_control: CcAlgorithm,
+    pub cubic: CubicParams,             // Only used with `CcAlgorithm::Cubic`
+    pub ecn: bool,                      // Request ECN in the handshake (RFC 3168)
+    pub ts_clock: Arc<dyn TsClock>,     // Source of TSval for the timestamps option
+    pub max_pending_segments: usize,    // Cap on distinct out-of-order segments the receiver buffers
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        TcpConfig {
+            mss: 1460,
+            initial_cwnd_segments: 10,
+            congestion_control: CcAlgorithm::default(),
+            cubic: CubicParams::default(),
+            ecn: false,
+            ts_clock: Arc::new(MillisClock::new()),
+            max_pending_segments: 1024,
+        }
+    }
+}
@@ -0,0 +1,361 @@
+use crate::tcp::config::TcpConfig;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Which congestion control the sender runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CcAlgorithm {
+    None, // Window-limited only: the peer's advertised window is the sole limit
+    #[default]
+    Reno,
+    Cubic,
+}
+
+impl fmt::Display for CcAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CcAlgorithm::None => write!(f, "none"),
+            CcAlgorithm::Reno => write!(f, "reno"),
+            CcAlgorithm::Cubic => write!(f, "cubic"),
+        }
+    }
+}
+
+impl FromStr for CcAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(CcAlgorithm::None),
+            "reno" => Ok(CcAlgorithm::Reno),
+            "cubic" => Ok(CcAlgorithm::Cubic),
+            _ => Err(format!("unknown congestion control algorithm: {s}")),
+        }
+    }
+}
+
+/// A pluggable congestion control algorithm. Times are passed in, so algorithms can be driven
+/// by scripted traces.
+pub trait CongestionControl: fmt::Debug + Send {
+    fn algorithm(&self) -> CcAlgorithm;
+
+    /// `bytes_acked` new bytes were acknowledged, with `rtt` the latest round-trip sample
+    fn on_ack(&mut self, bytes_acked: usize, rtt: Duration, now: Instant);
+
+    /// A segment was lost
+    fn on_loss(&mut self, now: Instant);
+
+    /// The peer echoed a CE mark. Treated as a loss unless the algorithm knows better
+    fn on_ecn_echo(&mut self, now: Instant) {
+        self.on_loss(now);
+    }
+
+    /// Bytes allowed in flight
+    fn cwnd(&self) -> usize;
+}
+
+/// Build the congestion controller selected by `config`
+pub fn from_config(config: &TcpConfig) -> Box<dyn CongestionControl> {
+    let mss = config.mss;
+    let iw = config.initial_cwnd_segments;
+    match config.congestion_control {
+        CcAlgorithm::None => Box::new(NoCongestionControl),
+        CcAlgorithm::Reno => Box::new(Reno::new(mss, iw)),
+        CcAlgorithm::Cubic => Box::new(Cubic::new(mss, iw, config.cubic)),
+    }
+}
+
+/// No congestion window at all, for isolating flow control in experiments
+#[derive(Debug, Clone, Copy)]
+pub struct NoCongestionControl;
+
+impl CongestionControl for NoCongestionControl {
+    fn algorithm(&self) -> CcAlgorithm {
+        CcAlgorithm::None
+    }
+
+    fn on_ack(&mut self, _bytes_acked: usize, _rtt: Duration, _now: Instant) {}
+
+    fn on_loss(&mut self, _now: Instant) {}
+
+    fn cwnd(&self) -> usize {
+        usize::MAX
+    }
+}
+
+/// Slow start and congestion avoidance as in RFC 5681
+#[derive(Debug, Clone)]
+pub struct Reno {
+    mss: usize,
+    cwnd: usize,
+    ssthresh: usize,
+    acked_in_round: usize, // Bytes acked towards the next congestion avoidance increase
+}
+
+impl Reno {
+    pub fn new(mss: usize, initial_segments: u8) -> Self {
+        Reno {
+            mss,
+            cwnd: initial_segments as usize * mss,
+            ssthresh: usize::MAX,
+            acked_in_round: 0,
+        }
+    }
+
+    pub fn ssthresh(&self) -> usize {
+        self.ssthresh
+    }
+}
+
+impl CongestionControl for Reno {
+    fn algorithm(&self) -> CcAlgorithm {
+        CcAlgorithm::Reno
+    }
+
+    /// Grow by one MSS per ACK in slow start, and by one MSS per window in congestion avoidance
+    fn on_ack(&mut self, bytes_acked: usize, _rtt: Duration, _now: Instant) {
+        if self.cwnd < self.ssthresh {
+            self.cwnd += bytes_acked.min(self.mss);
+            return;
+        }
+
+        self.acked_in_round += bytes_acked;
+        if self.acked_in_round >= self.cwnd {
+            self.acked_in_round -= self.cwnd;
+            self.cwnd += self.mss;
+        }
+    }
+
+    /// Halve the window on a loss, but never below two segments
+    fn on_loss(&mut self, _now: Instant) {
+        self.ssthresh = (self.cwnd / 2).max(2 * self.mss);
+        self.cwnd = self.ssthresh;
+        self.acked_in_round = 0;
+    }
+
+    fn cwnd(&self) -> usize {
+        self.cwnd
+    }
+}
+
+/// CUBIC's constants, exposed for experiments
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CubicParams {
+    pub c: f64,                 // Scales the cubic growth, in segments per second cubed
+    pub beta: f64,              // Window kept after a loss
+    pub fast_convergence: bool, // Release bandwidth faster when the window keeps shrinking
+    pub tcp_friendliness: bool, // Never grow slower than Reno would
+}
+
+impl Default for CubicParams {
+    /// The values from RFC 9438
+    fn default() -> Self {
+        CubicParams { c: 0.4, beta: 0.7, fast_convergence: true, tcp_friendliness: true }
+    }
+}
+
+/// CUBIC (RFC 9438). The window follows a cubic function of the time since the last congestion
+/// event, flattening out around the window where that loss happened.
+#[derive(Debug, Clone)]
+pub struct Cubic {
+    params: CubicParams,
+    mss: usize,
+    cwnd: f64,                    // In segments
+    ssthresh: f64,                // In segments
+    w_max: f64,                   // Window just before the last reduction
+    k: f64,                       // Seconds until the cubic curve gets back to `w_max`
+    epoch_start: Option<Instant>, // Start of the current congestion avoidance epoch
+    w_est: f64,                   // The window Reno would have in the same epoch
+}
+
+impl Cubic {
+    pub fn new(mss: usize, initial_segments: u8, params: CubicParams) -> Self {
+        Cubic {
+            params,
+            mss,
+            cwnd: initial_segments as f64,
+            ssthresh: f64::INFINITY,
+            w_max: 0.0,
+            k: 0.0,
+            epoch_start: None,
+            w_est: 0.0,
+        }
+    }
+
+    /// The cubic window `t` seconds into the epoch
+    fn w_cubic(&self, t: f64) -> f64 {
+        self.params.c * (t - self.k).powi(3) + self.w_max
+    }
+}
+
+impl CongestionControl for Cubic {
+    fn algorithm(&self) -> CcAlgorithm {
+        CcAlgorithm::Cubic
+    }
+
+    fn on_ack(&mut self, bytes_acked: usize, rtt: Duration, now: Instant) {
+        let segments_acked = bytes_acked as f64 / self.mss as f64;
+        if self.cwnd < self.ssthresh {
+            self.cwnd += segments_acked.min(1.0);
+            return;
+        }
+
+        let epoch_start = *self.epoch_start.get_or_insert_with(|| {
+            // A new epoch starts at the first ACK of congestion avoidance
+            self.k = if self.cwnd < self.w_max {
+                ((self.w_max - self.cwnd) / self.params.c).cbrt()
+            } else {
+                self.w_max = self.cwnd;
+                0.0
+            };
+            self.w_est = self.cwnd;
+            now
+        });
+
+        // Aim for where the curve will be one RTT from now, but never more than 1.5x the window
+        let t = now.saturating_duration_since(epoch_start) + rtt;
+        let target = self.w_cubic(t.as_secs_f64()).clamp(self.cwnd, 1.5 * self.cwnd);
+        let mut cwnd = self.cwnd + (target - self.cwnd) / self.cwnd * segments_acked;
+
+        // Reno-friendly region: grow at least as fast as AIMD with the same beta would
+        if self.params.tcp_friendliness {
+            let beta = self.params.beta;
+            let alpha = 3.0 * (1.0 - beta) / (1.0 + beta);
+            self.w_est += alpha * segments_acked / self.cwnd;
+            cwnd = cwnd.max(self.w_est);
+        }
+        self.cwnd = cwnd;
+    }
+
+    fn on_loss(&mut self, _now: Instant) {
+        let beta = self.params.beta;
+        self.w_max = if self.params.fast_convergence && self.cwnd < self.w_max {
+            self.cwnd * (1.0 + beta) / 2.0
+        } else {
+            self.cwnd
+        };
+        self.ssthresh = (self.cwnd * beta).max(2.0);
+        self.cwnd = self.ssthresh;
+        self.epoch_start = None;
+    }
+
+    fn cwnd(&self) -> usize {
+        (self.cwnd * self.mss as f64) as usize
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSS: usize = 1000;
+    const RTT: Duration = Duration::from_millis(100);
+
+    /// Ack a full window one segment at a time every RTT, with losses in the given rounds.
+    /// Returns the window in segments at the end of each round.
+    fn run_trace(cc: &mut dyn CongestionControl, rounds: usize, loss_rounds: &[usize]) -> Vec<usize> {
+        let t0 = Instant::now();
+        (0..rounds)
+            .map(|round| {
+                let now = t0 + RTT * round as u32;
+                if loss_rounds.contains(&round) {
+                    cc.on_loss(now);
+                } else {
+                    for _ in 0..cc.cwnd() / MSS {
+                        cc.on_ack(MSS, RTT, now);
+                    }
+                }
+                cc.cwnd() / MSS
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_reno_slow_start_then_avoidance() {
+        let now = Instant::now();
+        let mut reno = Reno::new(1000, 2);
+        assert_eq!(reno.cwnd(), 2000);
+
+        // Slow start: one MSS per ACK
+        reno.on_ack(1000, RTT, now);
+        reno.on_ack(1000, RTT, now);
+        assert_eq!(reno.cwnd(), 4000);
+
+        reno.on_loss(now);
+        assert_eq!((reno.cwnd(), reno.ssthresh()), (2000, 2000));
+
+        // Congestion avoidance: one MSS per window's worth of ACKs
+        reno.on_ack(1000, RTT, now);
+        assert_eq!(reno.cwnd(), 2000);
+        reno.on_ack(1000, RTT, now);
+        assert_eq!(reno.cwnd(), 3000);
+    }
+
+    #[test]
+    fn test_reno_trace() {
+        let trajectory = run_trace(&mut Reno::new(MSS, 10), 12, &[3]);
+        // Doubles per RTT, halves on the loss, then one segment per RTT
+        assert_eq!(trajectory, vec![20, 40, 80, 40, 41, 42, 43, 44, 45, 46, 47, 48]);
+    }
+
+    #[test]
+    fn test_cubic_trace() {
+        let params = CubicParams { tcp_friendliness: false, ..CubicParams::default() };
+        let trajectory = run_trace(&mut Cubic::new(MSS, 10, params), 40, &[3]);
+
+        // Same slow start as Reno, but only backs off to 0.7x. Then concave growth back towards
+        // w_max = 80, fastest right after the loss and flattening out near the old window
+        assert_eq!(
+            trajectory,
+            vec![
+                20, 40, 80, 56, 57, 58, 60, 61, 63, 64, 65, 67, 68, 69, 70, 71, 72, 73, 73, 74, //
+                75, 75, 76, 76, 77, 77, 78, 78, 78, 78, 79, 79, 79, 79, 79, 79, 79, 79, 79, 79,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cubic_tcp_friendliness() {
+        // With a tiny C the cubic curve is nearly flat, so the Reno estimate takes over
+        let slow = CubicParams { c: 0.001, ..CubicParams::default() };
+        let friendly = run_trace(&mut Cubic::new(MSS, 10, slow), 20, &[3]);
+        let unfriendly = run_trace(&mut Cubic::new(MSS, 10, CubicParams { tcp_friendliness: false, ..slow }), 20, &[3]);
+
+        assert_eq!(friendly[3], 56);
+        assert!(friendly[19] > unfriendly[19]);
+        assert!(friendly[19] >= 56 + 8); // alpha ~0.53 segments per RTT over 16 RTTs
+    }
+
+    #[test]
+    fn test_cubic_fast_convergence() {
+        let now = Instant::now();
+        let mut cubic = Cubic::new(MSS, 100, CubicParams::default());
+        cubic.on_loss(now); // w_max 100, cwnd 70
+        cubic.on_loss(now); // Still below w_max: w_max = 70 * 1.7 / 2
+        assert!((cubic.w_max - 59.5).abs() < 1e-9);
+
+        let mut cubic = Cubic::new(MSS, 100, CubicParams { fast_convergence: false, ..CubicParams::default() });
+        cubic.on_loss(now);
+        cubic.on_loss(now);
+        assert!((cubic.w_max - 70.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_none_is_unlimited() {
+        let trajectory = run_trace(&mut NoCongestionControl, 1, &[0]);
+        assert_eq!(trajectory, vec![usize::MAX / MSS]);
+        assert_eq!(CcAlgorithm::None.to_string(), "none");
+    }
+
+    #[test]
+    fn test_parse_algorithm() {
+        for algorithm in [CcAlgorithm::None, CcAlgorithm::Reno, CcAlgorithm::Cubic] {
+            assert_eq!(algorithm.to_string().parse::<CcAlgorithm>(), Ok(algorithm));
+        }
+        assert_eq!("CUBIC".parse::<CcAlgorithm>(), Ok(CcAlgorithm::Cubic));
+        assert!("vegas".parse::<CcAlgorithm>().is_err());
+    }
+}
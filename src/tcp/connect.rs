@@ -0,0 +1,379 @@
+// Active open (RFC 9293 section 3.5): send a SYN, wait for the SYN-ACK that acknowledges it, and
+// answer with the final ACK. `TcpListener` is the passive side.
+
+use crate::packet::errors::HeaderError;
+use crate::packet::icmp;
+use crate::packet::TcpSegment;
+use crate::socket::packet_io::PacketIo;
+use crate::tcp::byte_stream::StreamWrite;
+use crate::tcp::flow_key::FlowKey;
+use crate::tcp::negotiation::{HandshakeOffer, Negotiated};
+use crate::tcp::sender::TcpSender;
+use crate::tcp::tcp_flags::TcpFlags;
+use crate::tcp::tcp_options::{self, TcpOption};
+use crate::tcp::wrap32::Wrap32;
+use bytes::Bytes;
+use nix::errno::Errno;
+use nix::libc;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// The longest wait for a SYN-ACK, however many times the SYN was sent (RFC 6298 rule 2.5)
+pub const MAX_SYN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How an active open offers options and retries its SYN
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectConfig {
+    pub offer: HandshakeOffer,
+    pub window: u16,           // Receive window to advertise. Never scaled in the SYN itself
+    pub syn_timeout: Duration, // Wait for the first SYN-ACK. Doubles with each retransmission
+    pub syn_retries: u32,      // Retransmissions before giving up, like Linux's tcp_syn_retries
+}
+
+impl Default for ConnectConfig {
+    fn default() -> Self {
+        ConnectConfig {
+            offer: HandshakeOffer::default(),
+            window: u16::MAX,
+            syn_timeout: Duration::from_secs(1), // The initial RTO (RFC 6298)
+            syn_retries: 6,
+        }
+    }
+}
+
+/// A completed handshake, from our side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Established {
+    pub initial_seq_num: Wrap32, // Our ISN. Data starts one past it
+    pub initial_ack_num: Wrap32, // The peer's ISN plus one: the first byte we expect
+    pub peer_offer: HandshakeOffer,
+    pub negotiated: Negotiated,
+    pub peer_window: u16, // Window in the SYN-ACK, which is never scaled
+    pub syns_sent: u32,   // 1 unless the SYN had to be retransmitted
+}
+
+/// What a packet received in SYN-SENT means for the handshake
+#[derive(Debug)]
+enum Reply {
+    Ignore,
+    SynAck(TcpSegment),
+    Reset,
+    BadAck(Wrap32), // Acknowledges something we never sent. Answered with a RST at that seq
+    Icmp(io::Error),
+}
+
+/// Open `flow` through `io`, with `sender` building the packets from its ISN. The SYN carries
+/// `config.offer`, and is sent again with exponential backoff until a SYN-ACK from `flow.remote`
+/// acknowledges it. Then the final ACK goes out, and `sender` moves past the SYN, is clamped to
+/// the negotiated MSS and is told the peer's window.
+///
+/// Fails with ETIMEDOUT once `syn_retries` retransmissions go unanswered, ECONNRESET if the peer
+/// answers with a RST, and ECONNREFUSED or ENOPROTOOPT on the matching ICMP error. Other ICMP
+/// errors may be transient, so they only replace ETIMEDOUT as the reason for giving up.
+/// Simultaneous open isn't supported: a bare SYN from the peer is ignored.
+pub fn connect<W: StreamWrite>(io: &mut impl PacketIo, sender: &mut TcpSender<W>, flow: FlowKey, config: &ConnectConfig) -> io::Result<Established> {
+    sender.set_flow(flow);
+    let isn = sender.isn();
+    let mut buf = [0u8; 2048];
+    let mut timeout = config.syn_timeout;
+    let mut soft_error = None;
+
+    for syns_sent in 1..=config.syn_retries + 1 {
+        io.send(syn_packet(sender, config)?)?;
+        let deadline = Instant::now() + timeout;
+        while let Some(len) = recv_until(io, &mut buf, deadline)? {
+            match classify(&buf[..len], &flow, isn) {
+                Reply::Ignore => {}
+                Reply::SynAck(syn_ack) => return finish(io, sender, config, syn_ack, syns_sent),
+                Reply::Reset => return Err(Errno::ECONNRESET.into()),
+                Reply::BadAck(ack_no) => {
+                    io.send(build(sender.build_control_reused(ack_no, Wrap32::new(0), TcpFlags::RST, 0, Bytes::new()))?)?;
+                }
+                Reply::Icmp(err) => match err.raw_os_error() {
+                    Some(libc::ECONNREFUSED | libc::ENOPROTOOPT) => return Err(err),
+                    _ => soft_error = Some(err),
+                },
+            }
+        }
+        timeout = (timeout * 2).min(MAX_SYN_TIMEOUT);
+    }
+    Err(soft_error.unwrap_or_else(|| Errno::ETIMEDOUT.into()))
+}
+
+/// Our SYN, with the offer's options and a fresh TSval
+fn syn_packet<'a, W: StreamWrite>(sender: &'a mut TcpSender<W>, config: &ConnectConfig) -> io::Result<&'a [u8]> {
+    let ts_val = match sender.timestamps().outgoing() {
+        TcpOption::Timestamps { val, .. } => val,
+        _ => 0,
+    };
+    let options = tcp_options::encode_options(&config.offer.options(ts_val)).into();
+    build(sender.build_control_reused(sender.isn(), Wrap32::new(0), TcpFlags::SYN, config.window, options))
+}
+
+/// Record what the SYN-ACK settled and acknowledge it
+fn finish<W: StreamWrite>(
+    io: &mut impl PacketIo,
+    sender: &mut TcpSender<W>,
+    config: &ConnectConfig,
+    syn_ack: TcpSegment,
+    syns_sent: u32,
+) -> io::Result<Established> {
+    let peer_options = syn_ack.tcph.tcp_options_partial();
+    let peer_offer = HandshakeOffer::from_options(&peer_options);
+    let negotiated = Negotiated::resolve(&config.offer, &peer_offer);
+
+    sender.syn_acked();
+    sender.set_mss(sender.mss().min(negotiated.mss as usize));
+    sender.set_peer_window(syn_ack.tcph.window as usize);
+    let mut options = Vec::new();
+    if negotiated.timestamps {
+        for option in &peer_options {
+            if let &TcpOption::Timestamps { val, .. } = option {
+                sender.timestamps().paws_accept(val); // Becomes TS.Recent, echoed in the ACK
+            }
+        }
+        options.push(sender.timestamps().outgoing());
+    }
+
+    let established = Established {
+        initial_seq_num: sender.isn(),
+        initial_ack_num: syn_ack.seq_no() + Wrap32::new(1),
+        peer_offer,
+        negotiated,
+        peer_window: syn_ack.tcph.window,
+        syns_sent,
+    };
+    let seq_no = established.initial_seq_num + Wrap32::new(1);
+    let window = config.window >> negotiated.rcv_wscale;
+    let options = tcp_options::encode_options(&options).into();
+    io.send(build(sender.build_control_reused(seq_no, established.initial_ack_num, TcpFlags::ACK, window, options))?)?;
+    Ok(established)
+}
+
+/// The SYN-SENT checks of RFC 9293 section 3.10.7.3, in order: the ACK must cover exactly our
+/// SYN, a RST only counts with such an ACK, and then a SYN-ACK completes the handshake.
+fn classify(packet: &[u8], flow: &FlowKey, isn: Wrap32) -> Reply {
+    if let Some(err) = icmp::connection_error(packet, flow) {
+        return Reply::Icmp(err);
+    }
+    let Ok(segment) = TcpSegment::parse(packet) else {
+        return Reply::Ignore;
+    };
+    if segment.src() != flow.remote || segment.dst() != flow.local {
+        return Reply::Ignore;
+    }
+
+    let flags = segment.flags();
+    if flags.contains(TcpFlags::ACK) && segment.ack_no() != isn + Wrap32::new(1) {
+        return if flags.contains(TcpFlags::RST) { Reply::Ignore } else { Reply::BadAck(segment.ack_no()) };
+    }
+    if flags.contains(TcpFlags::RST) {
+        // Without an ACK we can't tell it's about our SYN
+        return if flags.contains(TcpFlags::ACK) { Reply::Reset } else { Reply::Ignore };
+    }
+    if flags.contains(TcpFlags::SYN | TcpFlags::ACK) {
+        Reply::SynAck(segment)
+    } else {
+        Reply::Ignore
+    }
+}
+
+/// The next packet before `deadline`, or `None` once it passes. `WouldBlock` from `io` counts as
+/// the deadline passing, since a real `recv` only returns it after waiting that long.
+fn recv_until(io: &mut impl PacketIo, buf: &mut [u8], deadline: Instant) -> io::Result<Option<usize>> {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if remaining.is_zero() {
+        return Ok(None);
+    }
+    match io.recv(buf, remaining) {
+        Ok(len) => Ok(Some(len)),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn build(packet: Result<&[u8], HeaderError>) -> io::Result<&[u8]> {
+    packet.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ip::ip_header::IpHeader;
+    use crate::packet::icmp::{IcmpMessage, CODE_HOST_UNREACHABLE, CODE_PORT_UNREACHABLE, PROTOCOL_ICMP};
+    use crate::socket::packet_io::ScriptedIo;
+    use crate::tcp::byte_stream::ByteStream;
+    use crate::tcp::reassembler::Reassembler;
+    use crate::tcp::receiver::TcpReceiver;
+    use crate::tcp::tcp_header::TcpHeader;
+    use std::io::Read;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    const CLIENT: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 50000);
+    const SERVER: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 80);
+    const ISN: u32 = 1000;
+
+    fn client() -> TcpSender {
+        TcpSender::new(Wrap32::new(ISN), ByteStream::new(4096))
+    }
+
+    /// A segment from `SERVER` to `CLIENT`
+    fn server_packet(seq_no: u32, ack_no: Wrap32, flags: TcpFlags, options: &[TcpOption]) -> Vec<u8> {
+        let mut server = TcpSender::new(Wrap32::new(seq_no), ByteStream::new(16));
+        server.set_flow(FlowKey::new(SERVER, CLIENT));
+        let tcph = TcpHeader {
+            seq_no: Wrap32::new(seq_no),
+            ack_no,
+            flags,
+            window: 29200,
+            options: tcp_options::encode_options(options).into(),
+            ..TcpHeader::new(SERVER.port(), CLIENT.port())
+        };
+        server.build_packet(&tcph).unwrap()
+    }
+
+    fn syn_ack(ack_no: Wrap32) -> Vec<u8> {
+        let options = [TcpOption::Mss(1400), TcpOption::SackPermitted, TcpOption::Timestamps { val: 777, ecr: 1 }, TcpOption::WindowScale(8)];
+        server_packet(5000, ack_no, TcpFlags::SYN | TcpFlags::ACK, &options)
+    }
+
+    fn fast() -> ConnectConfig {
+        ConnectConfig { syn_timeout: Duration::from_millis(10), syn_retries: 2, ..ConnectConfig::default() }
+    }
+
+    #[test]
+    fn test_handshake() {
+        let mut io = ScriptedIo::new([syn_ack(Wrap32::new(ISN + 1))]);
+        let mut sender = client();
+        let established = connect(&mut io, &mut sender, FlowKey::new(CLIENT, SERVER), &fast()).unwrap();
+        assert_eq!((established.initial_seq_num, established.initial_ack_num), (Wrap32::new(ISN), Wrap32::new(5001)));
+        assert_eq!(established.peer_offer, HandshakeOffer { mss: 1400, window_scale: Some(8), sack_permitted: true, timestamps: true });
+        assert_eq!((established.negotiated.mss, established.negotiated.snd_wscale, established.negotiated.rcv_wscale), (1400, 8, 7));
+        assert_eq!((established.peer_window, established.syns_sent), (29200, 1));
+        assert_eq!(sender.mss(), 1400);
+
+        // The SYN used up the ISN and is acknowledged, so data starts right after it
+        assert_eq!((sender.current_seq_no(), sender.first_unacked_seq_no()), (Wrap32::new(ISN + 1), Wrap32::new(ISN + 1)));
+        assert_eq!(sender.bytes_in_flight(), 0);
+
+        let sent: Vec<TcpSegment> = io.sent().iter().map(|p| TcpSegment::parse(p).unwrap()).collect();
+        assert_eq!(sent.len(), 2);
+        let syn = &sent[0];
+        assert_eq!((syn.src(), syn.dst(), syn.flags(), syn.seq_no()), (CLIENT, SERVER, TcpFlags::SYN, Wrap32::new(ISN)));
+        let offered = HandshakeOffer::from_options(&syn.tcph.tcp_options().unwrap());
+        assert_eq!(offered, HandshakeOffer::default());
+
+        // The ACK covers the SYN-ACK, echoes its TSval and scales our window
+        let ack = &sent[1];
+        assert_eq!((ack.flags(), ack.seq_no(), ack.ack_no()), (TcpFlags::ACK, Wrap32::new(ISN + 1), Wrap32::new(5001)));
+        assert_eq!(ack.tcph.window, u16::MAX >> 7);
+        assert!(matches!(ack.tcph.tcp_options().unwrap()[..], [TcpOption::Timestamps { ecr: 777, .. }]));
+    }
+
+    #[test]
+    fn test_peer_data_accepted_after_handshake() {
+        let mut io = ScriptedIo::new([syn_ack(Wrap32::new(ISN + 1))]);
+        let mut sender = client();
+        let established = connect(&mut io, &mut sender, FlowKey::new(CLIENT, SERVER), &fast()).unwrap();
+
+        // The peer's first data segment acks our SYN, which is exactly snd_nxt
+        let mut receiver = TcpReceiver::new(established.initial_ack_num, Reassembler::new(ByteStream::new(4096)));
+        let data = TcpHeader {
+            seq_no: established.initial_ack_num,
+            ack_no: Wrap32::new(ISN + 1),
+            flags: TcpFlags::ACK | TcpFlags::PSH,
+            payload: Bytes::from_static(b"hello"),
+            ..TcpHeader::new(SERVER.port(), CLIENT.port())
+        };
+        receiver.recv_established(data, &sender, u16::MAX as u32).unwrap();
+        assert_eq!(receiver.stats().unacceptable_ack_drops, 0);
+        assert_eq!(receiver.ack_no(), Wrap32::new(5006));
+        let mut received = String::new();
+        receiver.stream_mut().read_to_string(&mut received).unwrap();
+        assert_eq!(received, "hello");
+    }
+
+    #[test]
+    fn test_syn_retransmitted_until_timeout() {
+        let mut io = ScriptedIo::new([]);
+        let err = connect(&mut io, &mut client(), FlowKey::new(CLIENT, SERVER), &fast()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        // The first SYN and two retransmissions, all with the same seq
+        let syns: Vec<TcpSegment> = io.sent().iter().map(|p| TcpSegment::parse(p).unwrap()).collect();
+        assert_eq!(syns.len(), 3);
+        assert!(syns.iter().all(|s| s.flags() == TcpFlags::SYN && s.seq_no() == Wrap32::new(ISN)));
+    }
+
+    #[test]
+    fn test_rst_resets() {
+        let mut io = ScriptedIo::new([
+            server_packet(0, Wrap32::new(1), TcpFlags::RST | TcpFlags::ACK, &[]), // Not for our SYN
+            server_packet(0, Wrap32::new(0), TcpFlags::RST, &[]),                  // Unacceptable without ACK
+            server_packet(0, Wrap32::new(ISN + 1), TcpFlags::RST | TcpFlags::ACK, &[]),
+        ]);
+        let err = connect(&mut io, &mut client(), FlowKey::new(CLIENT, SERVER), &fast()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!((io.sent().len(), io.remaining()), (1, 0));
+    }
+
+    #[test]
+    fn test_bad_ack_and_strays_ignored() {
+        let stray = {
+            let mut other = TcpSender::new(Wrap32::new(5000), ByteStream::new(16));
+            other.set_flow(FlowKey::new(SERVER, SocketAddrV4::new(*CLIENT.ip(), 40000)));
+            let tcph = TcpHeader { ack_no: Wrap32::new(ISN + 1), flags: TcpFlags::SYN | TcpFlags::ACK, ..TcpHeader::new(80, 40000) };
+            other.build_packet(&tcph).unwrap()
+        };
+        let mut io = ScriptedIo::new([stray, syn_ack(Wrap32::new(99)), syn_ack(Wrap32::new(ISN + 1))]);
+        let established = connect(&mut io, &mut client(), FlowKey::new(CLIENT, SERVER), &fast()).unwrap();
+        assert_eq!(established.initial_ack_num, Wrap32::new(5001));
+
+        // The SYN-ACK acking seq 99 gets a RST at that seq, then the real one an ACK
+        let sent: Vec<TcpSegment> = io.sent().iter().map(|p| TcpSegment::parse(p).unwrap()).collect();
+        let flags: Vec<TcpFlags> = sent.iter().map(|s| s.flags()).collect();
+        assert_eq!(flags, [TcpFlags::SYN, TcpFlags::RST, TcpFlags::ACK]);
+        assert_eq!(sent[1].seq_no(), Wrap32::new(99));
+    }
+
+    #[test]
+    fn test_icmp_errors() {
+        let icmp_packet = |code: u8, syn: &[u8]| {
+            let icmp = IcmpMessage::unreachable(code, 0, syn).to_bytes();
+            let iph = IpHeader {
+                version: 4,
+                ihl: 5,
+                total_len: (20 + icmp.len()) as u16,
+                ttl: 64,
+                protocol: PROTOCOL_ICMP,
+                src_ip: *SERVER.ip(),
+                dst_ip: *CLIENT.ip(),
+                ..IpHeader::default()
+            };
+            let mut packet = vec![0u8; 20];
+            iph.serialize(&mut packet).unwrap();
+            packet.extend(icmp);
+            packet
+        };
+        let flow = FlowKey::new(CLIENT, SERVER);
+        let syn = {
+            let mut sender = client();
+            sender.set_flow(flow);
+            syn_packet(&mut sender, &fast()).unwrap().to_vec()
+        };
+
+        // Port unreachable fails straight away, like a RST
+        let mut io = ScriptedIo::new([icmp_packet(CODE_PORT_UNREACHABLE, &syn)]);
+        let err = connect(&mut io, &mut client(), flow, &fast()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        assert_eq!(io.sent().len(), 1);
+
+        // Host unreachable keeps trying, and is the error reported at the end
+        let mut io = ScriptedIo::new([icmp_packet(CODE_HOST_UNREACHABLE, &syn)]);
+        let err = connect(&mut io, &mut client(), flow, &fast()).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EHOSTUNREACH));
+        assert_eq!(io.sent().len(), 3);
+    }
+}
@@ -0,0 +1,302 @@
+use crate::packet;
+use crate::packet::errors::HeaderError;
+use crate::tcp::flow_key::FlowKey;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddrV4;
+
+/// Which packet a full queue gives up. Either way TCP retransmits what was dropped
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DropPolicy {
+    #[default]
+    DropNewest, // Tail drop: keep what's queued, discard the arrival
+    DropOldest, // Evict from the head until the arrival fits
+}
+
+/// Bounds on the packets queued for one connection before it processes them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueLimits {
+    pub max_packets: usize,
+    pub max_bytes: usize, // Whole IP packets, headers included
+    pub policy: DropPolicy,
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        QueueLimits { max_packets: 256, max_bytes: 256 * 1500, policy: DropPolicy::default() }
+    }
+}
+
+/// Per-connection queue counters
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    pub enqueued_packets: u64,
+    pub dropped_packets: u64, // Arrivals or queued packets discarded at the limits
+    pub dropped_bytes: u64,
+    pub peak_bytes: usize,    // Most bytes queued at once
+}
+
+/// Counters for packets that never reached a queue
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DemuxStats {
+    pub unmatched: u64, // Packets for no registered connection
+}
+
+#[derive(Debug)]
+struct FlowQueue {
+    packets: VecDeque<Vec<u8>>,
+    bytes: usize,
+    limits: QueueLimits,
+    stats: QueueStats,
+}
+
+impl FlowQueue {
+    fn push(&mut self, packet: Vec<u8>) {
+        let len = packet.len();
+        if len > self.limits.max_bytes || self.limits.max_packets == 0 {
+            self.drop_packet(len);
+            return;
+        }
+
+        let full = |q: &FlowQueue| q.packets.len() >= q.limits.max_packets || q.bytes + len > q.limits.max_bytes;
+        match self.limits.policy {
+            DropPolicy::DropNewest if full(self) => {
+                self.drop_packet(len);
+                return;
+            }
+            DropPolicy::DropNewest => {}
+            DropPolicy::DropOldest => {
+                while full(self) {
+                    if let Some(oldest) = self.packets.pop_front() {
+                        self.bytes -= oldest.len();
+                        self.drop_packet(oldest.len());
+                    }
+                }
+            }
+        }
+
+        self.bytes += len;
+        self.packets.push_back(packet);
+        self.stats.enqueued_packets += 1;
+        self.stats.peak_bytes = self.stats.peak_bytes.max(self.bytes);
+    }
+
+    fn drop_packet(&mut self, len: usize) {
+        self.stats.dropped_packets += 1;
+        self.stats.dropped_bytes += len as u64;
+    }
+}
+
+/// Routes inbound TCP/IPv4 packets to per-connection queues by their 4-tuple. Each queue is
+/// bounded, so a fast sender can't grow memory faster than its connection drains it.
+#[derive(Debug, Default)]
+pub struct Demux {
+    queues: HashMap<FlowKey, FlowQueue>,
+    stats: DemuxStats,
+}
+
+impl Demux {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start queueing packets for `flow` within `limits`. Re-registering a flow keeps its queued
+    /// packets and counters and only changes the limits, which apply from the next arrival.
+    pub fn register(&mut self, flow: FlowKey, limits: QueueLimits) {
+        self.queues
+            .entry(flow)
+            .and_modify(|queue| queue.limits = limits)
+            .or_insert_with(|| FlowQueue { packets: VecDeque::new(), bytes: 0, limits, stats: QueueStats::default() });
+    }
+
+    /// Stop queueing for `flow`, discarding whatever is still queued. Returns its final counters
+    pub fn unregister(&mut self, flow: &FlowKey) -> Option<QueueStats> {
+        self.queues.remove(flow).map(|queue| queue.stats)
+    }
+
+    /// Queue an inbound packet for its connection. Returns the flow it was routed to, or `None`
+    /// if no connection is registered for it. Whether it survived the limits shows in the
+    /// queue's stats.
+    pub fn push(&mut self, packet: Vec<u8>) -> Result<Option<FlowKey>, HeaderError> {
+        let (iph, tcph) = packet::unwrap_ref(&packet)?;
+        let flow = FlowKey::new(
+            SocketAddrV4::new(iph.dst_ip(), tcph.dst_port()),
+            SocketAddrV4::new(iph.src_ip(), tcph.src_port()),
+        );
+        match self.queues.get_mut(&flow) {
+            Some(queue) => {
+                queue.push(packet);
+                Ok(Some(flow))
+            }
+            None => {
+                self.stats.unmatched += 1;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Take the oldest packet queued for `flow`
+    pub fn pop(&mut self, flow: &FlowKey) -> Option<Vec<u8>> {
+        let queue = self.queues.get_mut(flow)?;
+        let packet = queue.packets.pop_front()?;
+        queue.bytes -= packet.len();
+        Some(packet)
+    }
+
+    /// Take every packet queued for `flow`, oldest first
+    pub fn drain(&mut self, flow: &FlowKey) -> Vec<Vec<u8>> {
+        match self.queues.get_mut(flow) {
+            Some(queue) => {
+                queue.bytes = 0;
+                queue.packets.drain(..).collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// The packets and bytes queued for `flow`
+    pub fn queued(&self, flow: &FlowKey) -> Option<(usize, usize)> {
+        self.queues.get(flow).map(|queue| (queue.packets.len(), queue.bytes))
+    }
+
+    pub fn queue_stats(&self, flow: &FlowKey) -> Option<&QueueStats> {
+        self.queues.get(flow).map(|queue| &queue.stats)
+    }
+
+    pub fn stats(&self) -> &DemuxStats {
+        &self.stats
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ip::ip_header::IpHeader;
+    use crate::tcp::byte_stream::ByteStream;
+    use crate::tcp::reassembler::Reassembler;
+    use crate::tcp::receiver::TcpReceiver;
+    use crate::tcp::tcp_flags::TcpFlags;
+    use crate::tcp::tcp_header::TcpHeader;
+    use crate::tcp::wrap32::Wrap32;
+    use std::io::Read;
+    use std::net::Ipv4Addr;
+
+    fn flow() -> FlowKey {
+        FlowKey::new(
+            SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 80),
+            SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 50000),
+        )
+    }
+
+    /// A data segment from the remote end of `flow` starting at stream index `seq`
+    fn data_packet(flow: FlowKey, seq: u32, payload: &[u8]) -> Vec<u8> {
+        let iph = IpHeader {
+            version: 4,
+            ihl: 5,
+            ttl: 64,
+            protocol: 6,
+            total_len: (40 + payload.len()) as u16,
+            src_ip: *flow.remote.ip(),
+            dst_ip: *flow.local.ip(),
+            ..IpHeader::default()
+        };
+        let tcph = TcpHeader {
+            src_port: flow.remote.port(),
+            dst_port: flow.local.port(),
+            seq_no: Wrap32::new(seq),
+            data_offset: 5,
+            flags: TcpFlags::ACK,
+            window: 65535,
+            payload: payload.to_vec().into(),
+            ..TcpHeader::default()
+        };
+        packet::wrap(&iph, &tcph).unwrap()
+    }
+
+    fn seqs(packets: &[Vec<u8>]) -> Vec<u32> {
+        packets.iter().map(|p| packet::unwrap(p).unwrap().1.seq_no.value()).collect()
+    }
+
+    #[test]
+    fn test_drop_newest_at_packet_limit() {
+        let mut demux = Demux::new();
+        demux.register(flow(), QueueLimits { max_packets: 3, ..QueueLimits::default() });
+
+        for i in 0..5 {
+            assert_eq!(demux.push(data_packet(flow(), i * 10, &[0; 10])), Ok(Some(flow())));
+        }
+        let stats = *demux.queue_stats(&flow()).unwrap();
+        assert_eq!((stats.enqueued_packets, stats.dropped_packets, stats.dropped_bytes), (3, 2, 100));
+        assert_eq!(stats.peak_bytes, 150);
+        assert_eq!(seqs(&demux.drain(&flow())), [0, 10, 20]);
+        assert_eq!(demux.queued(&flow()), Some((0, 0)));
+    }
+
+    #[test]
+    fn test_drop_oldest_at_byte_limit() {
+        let mut demux = Demux::new();
+        let limits = QueueLimits { max_bytes: 200, policy: DropPolicy::DropOldest, ..QueueLimits::default() };
+        demux.register(flow(), limits);
+
+        // 40 header bytes each, so 140 + 50 fit but a third packet evicts the first
+        demux.push(data_packet(flow(), 0, &[0; 100])).unwrap();
+        demux.push(data_packet(flow(), 100, &[0; 10])).unwrap();
+        assert_eq!(demux.queued(&flow()), Some((2, 190)));
+        demux.push(data_packet(flow(), 110, &[0; 10])).unwrap();
+        assert_eq!(demux.queued(&flow()), Some((2, 100)));
+
+        // Too big for the queue on its own: dropped, and nothing evicted for it
+        demux.push(data_packet(flow(), 120, &[0; 200])).unwrap();
+        let stats = demux.queue_stats(&flow()).unwrap();
+        assert_eq!((stats.dropped_packets, stats.dropped_bytes), (2, 140 + 240));
+
+        assert_eq!(demux.pop(&flow()).map(|p| seqs(&[p])), Some(vec![100]));
+        assert_eq!(demux.queued(&flow()), Some((1, 50)));
+        assert_eq!(seqs(&demux.drain(&flow())), [110]);
+    }
+
+    #[test]
+    fn test_unmatched_and_unregister() {
+        let mut demux = Demux::new();
+        assert_eq!(demux.push(data_packet(flow(), 0, b"x")), Ok(None));
+        assert_eq!(demux.stats().unmatched, 1);
+
+        demux.register(flow(), QueueLimits::default());
+        demux.push(data_packet(flow(), 0, b"x")).unwrap();
+        assert_eq!(demux.unregister(&flow()).map(|stats| stats.enqueued_packets), Some(1));
+        assert_eq!(demux.pop(&flow()), None);
+        assert!(demux.push(vec![0x45; 10]).is_err());
+    }
+
+    #[test]
+    fn test_transfer_completes_through_drops() {
+        let data: Vec<u8> = (0..20_000).map(|i| i as u8).collect();
+        let mut demux = Demux::new();
+        let limits = QueueLimits { max_packets: 4, ..QueueLimits::default() };
+        demux.register(flow(), limits);
+        let mut receiver = TcpReceiver::new(Wrap32::new(0), Reassembler::new(ByteStream::new(data.len())));
+
+        // A sender that blasts its whole window each round, then goes back to the first
+        // unacknowledged byte, like a retransmission timeout would
+        let mut rounds = 0;
+        while (receiver.next_expected_seq_no() as usize) < data.len() {
+            let start = receiver.next_expected_seq_no() as usize;
+            for (i, chunk) in data[start..].chunks(1000).enumerate() {
+                demux.push(data_packet(flow(), (start + i * 1000) as u32, chunk)).unwrap();
+            }
+            for packet in demux.drain(&flow()) {
+                receiver.recv_packet(&packet).unwrap();
+            }
+            rounds += 1;
+            assert!(rounds < 100, "transfer stalled");
+        }
+
+        let stats = demux.queue_stats(&flow()).unwrap();
+        assert!(stats.dropped_packets > 0);
+        assert!(stats.peak_bytes <= 4 * 1040);
+        let mut received = Vec::new();
+        receiver.stream_mut().read_to_end(&mut received).unwrap();
+        assert_eq!(received, data);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::ip::ecn::Ecn;
+use crate::tcp::tcp_flags::TcpFlags;
+
+/// ECN negotiation and the ECE/CWR handshake of one connection (RFC 3168)
+#[derive(Debug, Default, Clone)]
+pub struct EcnState {
+    requested: bool,   // We asked for or offered ECN in the handshake
+    negotiated: bool,  // Both ends agreed to use ECN
+    ece_latched: bool, // A CE mark arrived. Echo ECE until the peer sends CWR
+    cwr_pending: bool, // We reacted to an ECE. Set CWR on the next new data segment
+}
+
+impl EcnState {
+    pub fn new(requested: bool) -> Self {
+        EcnState { requested, ..EcnState::default() }
+    }
+
+    pub fn is_negotiated(&self) -> bool {
+        self.negotiated
+    }
+
+    /// Flags for an active open: an ECN-setup SYN if ECN is requested
+    pub fn syn_flags(&self) -> TcpFlags {
+        if self.requested {
+            TcpFlags::SYN | TcpFlags::ECE | TcpFlags::CWR
+        } else {
+            TcpFlags::SYN
+        }
+    }
+
+    /// Passive open: agree to ECN if the peer's SYN asked for it. Returns the SYN-ACK flags
+    pub fn on_syn(&mut self, syn_flags: TcpFlags) -> TcpFlags {
+        self.negotiated = self.requested && syn_flags.is_ecn_setup_syn();
+        if self.negotiated {
+            TcpFlags::SYN | TcpFlags::ACK | TcpFlags::ECE
+        } else {
+            TcpFlags::SYN | TcpFlags::ACK
+        }
+    }
+
+    /// Active open: ECN is on only if the SYN-ACK agreed to our request
+    pub fn on_syn_ack(&mut self, syn_ack_flags: TcpFlags) {
+        self.negotiated = self.requested && syn_ack_flags.is_ecn_setup_syn_ack();
+    }
+
+    /// The codepoint to mark outgoing data packets with
+    pub fn outgoing_ecn(&self) -> Ecn {
+        if self.negotiated { Ecn::Ect0 } else { Ecn::NotEct }
+    }
+
+    /// Process an arriving segment's flags and its packet's ECN codepoint. Returns true if the
+    /// peer echoed congestion, so the congestion controller should react.
+    pub fn on_segment(&mut self, flags: TcpFlags, ecn: Ecn) -> bool {
+        if !self.negotiated {
+            return false;
+        }
+
+        // CWR ends the echo, but a CE mark on the same packet starts a new one
+        if flags.contains(TcpFlags::CWR) {
+            self.ece_latched = false;
+        }
+        if ecn == Ecn::Ce {
+            self.ece_latched = true;
+        }
+
+        flags.contains(TcpFlags::ECE)
+    }
+
+    /// We cut the window in response to an ECE. The next data segment tells the peer with CWR
+    pub fn on_window_reduced(&mut self) {
+        if self.negotiated {
+            self.cwr_pending = true;
+        }
+    }
+
+    /// ECN flags for an outgoing segment. CWR goes out once, on a segment carrying data
+    pub fn outgoing_flags(&mut self, has_data: bool) -> TcpFlags {
+        let mut flags = TcpFlags::empty();
+        if self.ece_latched {
+            flags |= TcpFlags::ECE;
+        }
+        if self.cwr_pending && has_data {
+            self.cwr_pending = false;
+            flags |= TcpFlags::CWR;
+        }
+        flags
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn negotiated_pair() -> (EcnState, EcnState) {
+        let mut client = EcnState::new(true);
+        let mut server = EcnState::new(true);
+        let syn_ack = server.on_syn(client.syn_flags());
+        client.on_syn_ack(syn_ack);
+        (client, server)
+    }
+
+    #[test]
+    fn test_negotiation() {
+        let (client, server) = negotiated_pair();
+        assert!(client.is_negotiated() && server.is_negotiated());
+        assert_eq!(client.outgoing_ecn(), Ecn::Ect0);
+
+        // Either end declining turns it off
+        let mut server = EcnState::new(false);
+        let syn_ack = server.on_syn(EcnState::new(true).syn_flags());
+        assert_eq!(syn_ack, TcpFlags::SYN | TcpFlags::ACK);
+        let mut client = EcnState::new(true);
+        client.on_syn_ack(syn_ack);
+        assert!(!client.is_negotiated());
+        assert_eq!(client.outgoing_ecn(), Ecn::NotEct);
+
+        let mut server = EcnState::new(true);
+        server.on_syn(EcnState::new(false).syn_flags());
+        assert!(!server.is_negotiated());
+    }
+
+    #[test]
+    fn test_ece_latched_until_cwr() {
+        let (mut client, mut server) = negotiated_pair();
+
+        // A CE mark makes the receiver echo ECE on every ACK until CWR arrives
+        server.on_segment(TcpFlags::ACK, Ecn::Ce);
+        for _ in 0..3 {
+            assert_eq!(server.outgoing_flags(false), TcpFlags::ECE);
+            server.on_segment(TcpFlags::ACK, Ecn::Ect0);
+        }
+
+        // The sender reacts once and sets CWR on its next data segment only
+        assert!(client.on_segment(TcpFlags::ACK | TcpFlags::ECE, Ecn::NotEct));
+        client.on_window_reduced();
+        assert_eq!(client.outgoing_flags(false), TcpFlags::empty());
+        let flags = client.outgoing_flags(true);
+        assert_eq!(flags, TcpFlags::CWR);
+        assert_eq!(client.outgoing_flags(true), TcpFlags::empty());
+
+        server.on_segment(TcpFlags::ACK | flags, Ecn::Ect0);
+        assert_eq!(server.outgoing_flags(false), TcpFlags::empty());
+
+        // CWR and a fresh CE mark on the same packet keep the echo going
+        server.on_segment(TcpFlags::ACK, Ecn::Ce);
+        server.on_segment(TcpFlags::ACK | TcpFlags::CWR, Ecn::Ce);
+        assert_eq!(server.outgoing_flags(false), TcpFlags::ECE);
+    }
+
+    #[test]
+    fn test_ignored_without_negotiation() {
+        let mut state = EcnState::new(false);
+        assert!(!state.on_segment(TcpFlags::ACK | TcpFlags::ECE, Ecn::Ce));
+        state.on_window_reduced();
+        assert_eq!(state.outgoing_flags(true), TcpFlags::empty());
+    }
+}
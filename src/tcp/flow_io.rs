@@ -0,0 +1,182 @@
+use crate::ip::ip_header::IpHeader;
+use crate::packet;
+use crate::socket::packet_io::{self, PacketIo};
+use crate::tcp::byte_stream::ByteStream;
+use crate::tcp::connect::{self, ConnectConfig, Established};
+use crate::tcp::flow_key::FlowKey;
+use crate::tcp::sender::TcpSender;
+use crate::tcp::tcp_flags::TcpFlags;
+use crate::tcp::tcp_header::TcpHeader;
+use crate::tcp::wrap32::Wrap32;
+use bytes::Bytes;
+use std::io;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FlowStats {
+    pub segments_sent: u64,
+    pub bytes_sent: u64, // Payload only, not headers
+    pub segments_received: u64,
+    pub bytes_received: u64,
+    pub discarded: u64, // Packets for other flows, or that didn't parse as TCP
+}
+
+/// One connection's view of a `PacketIo`: segments go out addressed to the flow, and only the
+/// flow's segments come back. A raw TCP socket sees every TCP packet for the host, so most of
+/// what it receives is for someone else's port.
+#[derive(Debug)]
+pub struct FlowIo<P: PacketIo> {
+    io: P,
+    flow: FlowKey,
+    sender: TcpSender, // Builds the packets and numbers their IP ids
+    window: u16,       // Window advertised in every segment sent
+    stats: FlowStats,
+}
+
+impl<P: PacketIo> FlowIo<P> {
+    /// Carry `flow` over `io`, with `sender` building the packets
+    pub fn new(io: P, flow: FlowKey, mut sender: TcpSender) -> Self {
+        sender.set_flow(flow);
+        FlowIo { io, flow, sender, window: u16::MAX, stats: FlowStats::default() }
+    }
+
+    /// Like `new`, with a sender that has no stream of its own
+    pub fn with_isn(io: P, flow: FlowKey, isn: Wrap32) -> Self {
+        Self::new(io, flow, TcpSender::new(isn, ByteStream::new(0)))
+    }
+
+    /// Do the 3-way handshake with `connect::connect`
+    pub fn connect(&mut self, config: &ConnectConfig) -> io::Result<Established> {
+        connect::connect(&mut self.io, &mut self.sender, self.flow, config)
+    }
+
+    /// Send one segment with the given numbers, payload and flags. Returns the packet length
+    pub fn send(&mut self, seq_no: Wrap32, ack_no: Wrap32, payload: &[u8], flags: TcpFlags) -> io::Result<usize> {
+        let tcph = TcpHeader {
+            seq_no,
+            ack_no,
+            flags,
+            window: self.window,
+            payload: Bytes::copy_from_slice(payload),
+            ..TcpHeader::new(self.flow.local.port(), self.flow.remote.port())
+        };
+        let packet = self.sender.build_packet(&tcph).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let len = self.io.send(&packet)?;
+        self.stats.segments_sent += 1;
+        self.stats.bytes_sent += payload.len() as u64;
+        Ok(len)
+    }
+
+    /// The next segment of this flow, from `flow.remote` to `flow.local`, waiting at most
+    /// `timeout` in all. Everything else received meanwhile is discarded. Times out with
+    /// `WouldBlock`, like `PacketIo::recv`.
+    pub fn recv_segment(&mut self, timeout: Duration) -> io::Result<(IpHeader, TcpHeader)> {
+        let deadline = Instant::now() + timeout;
+        let mut buf = [0u8; 65535];
+        loop {
+            let len = self.io.recv(&mut buf, deadline.saturating_duration_since(Instant::now()))?;
+            match packet::unwrap(&buf[..len]) {
+                Ok((iph, tcph)) if self.is_ours(&iph, &tcph) => {
+                    self.stats.segments_received += 1;
+                    self.stats.bytes_received += tcph.payload_len() as u64;
+                    return Ok((iph, tcph));
+                }
+                _ => self.stats.discarded += 1,
+            }
+            if Instant::now() >= deadline {
+                return Err(packet_io::timed_out());
+            }
+        }
+    }
+
+    fn is_ours(&self, iph: &IpHeader, tcph: &TcpHeader) -> bool {
+        iph.src_ip == *self.flow.remote.ip()
+            && tcph.src_port == self.flow.remote.port()
+            && iph.dst_ip == *self.flow.local.ip()
+            && tcph.dst_port == self.flow.local.port()
+    }
+
+    /// Advertise `window` in the segments sent from now on
+    pub fn set_window(&mut self, window: u16) {
+        self.window = window;
+    }
+
+    pub fn flow(&self) -> FlowKey {
+        self.flow
+    }
+
+    pub fn stats(&self) -> &FlowStats {
+        &self.stats
+    }
+
+    pub fn io(&self) -> &P {
+        &self.io
+    }
+
+    pub fn io_mut(&mut self) -> &mut P {
+        &mut self.io
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::socket::packet_io::ScriptedIo;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    const LOCAL: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 50000);
+    const REMOTE: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 80);
+
+    /// A segment between two endpoints with `payload`
+    fn segment(from: SocketAddrV4, to: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+        FlowIo::with_isn(ScriptedIo::default(), FlowKey::new(from, to), Wrap32::new(0))
+            .sender
+            .build_packet(&TcpHeader { payload: Bytes::copy_from_slice(payload), ..TcpHeader::new(from.port(), to.port()) })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_recv_filters_other_flows() {
+        let other_host = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 3), 80);
+        let io = ScriptedIo::new([
+            segment(REMOTE, SocketAddrV4::new(*LOCAL.ip(), 22), b"ssh"), // Another local port
+            segment(other_host, LOCAL, b"spoof"),                        // Right port, wrong host
+            segment(LOCAL, REMOTE, b"echo"),                             // Our own, seen going out
+            vec![0x45, 0, 0],                                            // Not a packet
+            segment(REMOTE, LOCAL, b"hello"),
+        ]);
+        let mut flow_io = FlowIo::with_isn(io, FlowKey::new(LOCAL, REMOTE), Wrap32::new(1));
+
+        let (iph, tcph) = flow_io.recv_segment(Duration::from_secs(1)).unwrap();
+        assert_eq!((iph.src_ip, tcph.src_port, tcph.dst_port), (*REMOTE.ip(), 80, 50000));
+        assert_eq!(&tcph.payload[..], b"hello");
+        let stats = *flow_io.stats();
+        assert_eq!((stats.segments_received, stats.bytes_received, stats.discarded), (1, 5, 4));
+
+        // Only unrelated packets left: times out after dropping them
+        flow_io.io_mut().push_inbound(segment(REMOTE, SocketAddrV4::new(*LOCAL.ip(), 22), b""));
+        let err = flow_io.recv_segment(Duration::from_secs(1)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!((flow_io.stats().discarded, flow_io.io().remaining()), (5, 0));
+    }
+
+    #[test]
+    fn test_send_counts_payload() {
+        let mut flow_io = FlowIo::with_isn(ScriptedIo::default(), FlowKey::new(LOCAL, REMOTE), Wrap32::new(1));
+        flow_io.set_window(1024);
+        let len = flow_io.send(Wrap32::new(100), Wrap32::new(200), b"GET / HTTP/1.1\r\n", TcpFlags::ACK | TcpFlags::PSH).unwrap();
+        flow_io.send(Wrap32::new(116), Wrap32::new(200), b"", TcpFlags::ACK | TcpFlags::FIN).unwrap();
+        assert_eq!(len, 40 + 16);
+
+        let (iph, tcph) = packet::unwrap(&flow_io.io().sent()[0]).unwrap();
+        assert_eq!((iph.src_ip, iph.dst_ip), (*LOCAL.ip(), *REMOTE.ip()));
+        assert_eq!((tcph.src_port, tcph.dst_port), (LOCAL.port(), REMOTE.port()));
+        assert_eq!((tcph.seq_no, tcph.ack_no, tcph.flags, tcph.window), (Wrap32::new(100), Wrap32::new(200), TcpFlags::ACK | TcpFlags::PSH, 1024));
+        assert_eq!(&tcph.payload[..], b"GET / HTTP/1.1\r\n");
+
+        let stats = flow_io.stats();
+        assert_eq!((stats.segments_sent, stats.bytes_sent), (2, 16));
+    }
+}
@@ -0,0 +1,133 @@
+use crate::ip::ip_header::IpHeader;
+use crate::tcp::tcp_header::TcpHeader;
+use std::fmt;
+use std::net::SocketAddrV4;
+
+/// The direction a packet travels relative to the local endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum Direction {
+    Inbound,  // remote -> local
+    Outbound, // local -> remote
+}
+
+/// The 4-tuple identifying a TCP connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FlowKey {
+    pub local: SocketAddrV4,
+    pub remote: SocketAddrV4,
+}
+
+impl FlowKey {
+    pub fn new(local: SocketAddrV4, remote: SocketAddrV4) -> Self {
+        FlowKey { local, remote }
+    }
+
+    /// The same flow as seen from the other endpoint
+    pub fn reversed(&self) -> Self {
+        FlowKey {
+            local: self.remote,
+            remote: self.local,
+        }
+    }
+
+    /// Does the packet belong to this flow when travelling in the given direction?
+    pub fn matches_packet(&self, iph: &IpHeader, tcph: &TcpHeader, direction: Direction) -> bool {
+        let src = SocketAddrV4::new(iph.src_ip, tcph.src_port);
+        let dst = SocketAddrV4::new(iph.dst_ip, tcph.dst_port);
+
+        match direction {
+            Direction::Inbound => src == self.remote && dst == self.local,
+            Direction::Outbound => src == self.local && dst == self.remote,
+        }
+    }
+}
+
+impl From<(SocketAddrV4, SocketAddrV4)> for FlowKey {
+    fn from((local, remote): (SocketAddrV4, SocketAddrV4)) -> Self {
+        FlowKey::new(local, remote)
+    }
+}
+
+impl fmt::Display for FlowKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} <-> {}", self.local, self.remote)
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::test_utils;
+    use std::collections::HashSet;
+    use std::net::Ipv4Addr;
+
+    fn fixture_flow() -> FlowKey {
+        // The wireshark fixture is a SYN sent from 10.110.208.106:50871 to 204.44.192.60:80
+        FlowKey::new(
+            SocketAddrV4::new(Ipv4Addr::new(10, 110, 208, 106), 50871),
+            SocketAddrV4::new(Ipv4Addr::new(204, 44, 192, 60), 80),
+        )
+    }
+
+    fn fixture_headers() -> (IpHeader, TcpHeader) {
+        let ip_bytes = hex::decode(test_utils::get_ip_hex()).unwrap();
+        let tcp_bytes = hex::decode(test_utils::get_tcp_hex()).unwrap();
+        crate::packet::unwrap(&[ip_bytes, tcp_bytes].concat()).unwrap()
+    }
+
+    #[test]
+    fn test_matches_packet_outbound() {
+        let flow = fixture_flow();
+        let (iph, tcph) = fixture_headers();
+
+        assert!(flow.matches_packet(&iph, &tcph, Direction::Outbound));
+        assert!(!flow.matches_packet(&iph, &tcph, Direction::Inbound));
+    }
+
+    #[test]
+    fn test_matches_packet_inbound() {
+        let flow = fixture_flow().reversed();
+        let (iph, tcph) = fixture_headers();
+
+        assert!(flow.matches_packet(&iph, &tcph, Direction::Inbound));
+        assert!(!flow.matches_packet(&iph, &tcph, Direction::Outbound));
+    }
+
+    #[test]
+    fn test_matches_packet_wrong_port() {
+        let flow = fixture_flow();
+        let (iph, mut tcph) = fixture_headers();
+        tcph.src_port = 50872;
+
+        assert!(!flow.matches_packet(&iph, &tcph, Direction::Outbound));
+    }
+
+    #[test]
+    fn test_reversed_roundtrip() {
+        let flow = fixture_flow();
+        assert_ne!(flow, flow.reversed());
+        assert_eq!(flow, flow.reversed().reversed());
+    }
+
+    #[test]
+    fn test_hash_consistency() {
+        let flow = fixture_flow();
+        let from_tuple = FlowKey::from((flow.local, flow.remote));
+
+        let mut set = HashSet::new();
+        set.insert(flow);
+        assert!(set.contains(&from_tuple));
+        assert!(!set.contains(&flow.reversed()));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            fixture_flow().to_string(),
+            "10.110.208.106:50871 <-> 204.44.192.60:80"
+        );
+    }
+}
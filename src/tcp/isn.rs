@@ -0,0 +1,156 @@
+// Initial sequence numbers as in RFC 6528: ISN = M + F(localip, localport, remoteip, remoteport,
+// secretkey). M is a 250 kHz clock, so a new connection on a recently used 4-tuple starts ahead
+// of the old one's sequence space. F is SipHash-2-4 keyed with a per-process secret, so an
+// off-path attacker can't predict the ISN of a connection from the ISNs of their own.
+
+use crate::tcp::flow_key::FlowKey;
+use crate::tcp::wrap32::Wrap32;
+use std::net::Ipv4Addr;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+/// The ISN clock's tick: 4 microseconds, i.e. 250 kHz
+pub const TICK: Duration = Duration::from_micros(4);
+
+/// The random key used by `generate` when no other is given. Created on first use and kept for
+/// the life of the process.
+pub fn process_secret() -> &'static [u8; 16] {
+    static SECRET: OnceLock<[u8; 16]> = OnceLock::new();
+    SECRET.get_or_init(rand::random)
+}
+
+/// The ISN for a connection from `src:src_port` to `dst:dst_port`, using the current time
+pub fn generate(src: Ipv4Addr, dst: Ipv4Addr, src_port: u16, dst_port: u16, secret: &[u8; 16]) -> Wrap32 {
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    generate_at(src, dst, src_port, dst_port, secret, now)
+}
+
+/// Like `generate`, with the clock read as `clock`. Deterministic, for tests and replays
+pub fn generate_at(src: Ipv4Addr, dst: Ipv4Addr, src_port: u16, dst_port: u16, secret: &[u8; 16], clock: Duration) -> Wrap32 {
+    let mut tuple = [0u8; 12];
+    tuple[0..4].copy_from_slice(&src.octets());
+    tuple[4..8].copy_from_slice(&dst.octets());
+    tuple[8..10].copy_from_slice(&src_port.to_be_bytes());
+    tuple[10..12].copy_from_slice(&dst_port.to_be_bytes());
+
+    let ticks = (clock.as_nanos() / TICK.as_nanos()) as u32; // Wraps every ~4.8 hours
+    Wrap32::new(ticks.wrapping_add(siphash24(secret, &tuple) as u32))
+}
+
+/// The ISN for our end of `flow`, with the process secret and the current time
+pub fn for_flow(flow: &FlowKey) -> Wrap32 {
+    generate(*flow.local.ip(), *flow.remote.ip(), flow.local.port(), flow.remote.port(), process_secret())
+}
+
+/// SipHash-2-4 (Aumasson and Bernstein, 2012) of `data` under `key`
+fn siphash24(key: &[u8; 16], data: &[u8]) -> u64 {
+    let (k0, k1) = (le_u64(&key[0..8]), le_u64(&key[8..16]));
+    let mut v = [k0 ^ 0x736f6d6570736575, k1 ^ 0x646f72616e646f6d, k0 ^ 0x6c7967656e657261, k1 ^ 0x7465646279746573];
+
+    let round = |v: &mut [u64; 4]| {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    };
+    let mut compress = |m: u64| {
+        v[3] ^= m;
+        round(&mut v);
+        round(&mut v);
+        v[0] ^= m;
+    };
+
+    // The last word holds the leftover bytes and the message length mod 256 in its top byte
+    let mut words = data.chunks_exact(8);
+    for word in &mut words {
+        compress(le_u64(word));
+    }
+    compress(le_u64(words.remainder()) | (data.len() as u64) << 56);
+
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+/// Up to 8 bytes as a little-endian word, zero padded
+fn le_u64(bytes: &[u8]) -> u64 {
+    let mut word = [0u8; 8];
+    word[..bytes.len()].copy_from_slice(bytes);
+    u64::from_le_bytes(word)
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddrV4;
+
+    const SECRET: [u8; 16] = [7; 16];
+
+    fn isn_at(src_port: u16, clock: Duration) -> Wrap32 {
+        generate_at(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2), src_port, 80, &SECRET, clock)
+    }
+
+    #[test]
+    fn test_siphash_reference_vectors() {
+        // From the appendix of the SipHash paper: key 00..0f over messages 00, 00 01, ...
+        let key: [u8; 16] = std::array::from_fn(|i| i as u8);
+        let data: Vec<u8> = (0..15).collect();
+        assert_eq!(siphash24(&key, &[]), 0x726fdb47dd0e0e31);
+        assert_eq!(siphash24(&key, &data[..8]), 0x93f5f5799a932462);
+        assert_eq!(siphash24(&key, &data), 0xa129ca6149be45e5);
+    }
+
+    #[test]
+    fn test_same_tuple_and_time_is_deterministic() {
+        let clock = Duration::from_secs(1_700_000_000);
+        assert_eq!(isn_at(50000, clock), isn_at(50000, clock));
+
+        // Another secret gives another ISN
+        let other = generate_at(Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2), 50000, 80, &[8; 16], clock);
+        assert_ne!(other, isn_at(50000, clock));
+    }
+
+    #[test]
+    fn test_different_tuples_differ() {
+        let clock = Duration::from_secs(1_700_000_000);
+        let isns: Vec<u32> = (50000..50100).map(|port| isn_at(port, clock).value()).collect();
+        let mut unique = isns.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), isns.len());
+
+        // Swapping the endpoints is a different tuple too
+        let reverse = generate_at(Ipv4Addr::new(10, 0, 0, 2), Ipv4Addr::new(10, 0, 0, 1), 80, 50000, &SECRET, clock);
+        assert_ne!(reverse, isn_at(50000, clock));
+    }
+
+    #[test]
+    fn test_clock_advances_at_250_khz() {
+        let clock = Duration::from_secs(1_700_000_000);
+        let start = isn_at(50000, clock).value();
+        assert_eq!(isn_at(50000, clock + Duration::from_micros(3)).value(), start);
+        assert_eq!(isn_at(50000, clock + TICK).value(), start.wrapping_add(1));
+        assert_eq!(isn_at(50000, clock + Duration::from_secs(1)).value(), start.wrapping_add(250_000));
+    }
+
+    #[test]
+    fn test_for_flow_uses_process_secret() {
+        let flow = FlowKey::new(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 80), SocketAddrV4::new(Ipv4Addr::LOCALHOST, 50000));
+        assert_eq!(process_secret(), process_secret());
+
+        // Only the clock separates two calls, and it can't have advanced by a second
+        let a = for_flow(&flow).value();
+        let b = for_flow(&flow).value();
+        assert!(b.wrapping_sub(a) < 250_000);
+    }
+}
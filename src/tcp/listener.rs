@@ -0,0 +1,338 @@
+use crate::ip::ip_header::IpHeader;
+use crate::packet;
+use crate::packet::errors::HeaderError;
+use crate::tcp::byte_stream::ByteStream;
+use crate::tcp::config::TcpConfig;
+use crate::tcp::flow_key::FlowKey;
+use crate::tcp::isn;
+use crate::tcp::reassembler::Reassembler;
+use crate::tcp::receiver::TcpReceiver;
+use crate::tcp::sender::TcpSender;
+use crate::tcp::tcp_flags::TcpFlags;
+use crate::tcp::tcp_header::TcpHeader;
+use crate::tcp::tcp_options::TcpOption;
+use crate::tcp::wrap32::Wrap32;
+use std::net::SocketAddrV4;
+use std::time::{Duration, Instant};
+
+/// A peer whose SYN is waiting for the application to accept or reject it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingConn {
+    pub flow: FlowKey,
+    pub syn_options: Vec<TcpOption>,
+    pub age: Duration, // Time since the SYN arrived
+}
+
+/// A connection the application accepted, with the SYN-ACK to send back
+#[derive(Debug)]
+pub struct AcceptedConn {
+    pub flow: FlowKey,
+    pub sender: TcpSender,
+    pub receiver: TcpReceiver,
+    pub syn_ack: Vec<u8>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ListenerStats {
+    pub syns_queued: u64,
+    pub duplicate_syns: u64, // Retransmitted SYNs from a peer already queued
+    pub backlog_drops: u64,  // SYNs dropped because the queue was full
+    pub accepted: u64,       // Connections given streams and buffers
+    pub rejected: u64,
+}
+
+#[derive(Debug)]
+struct PendingSyn {
+    flow: FlowKey,
+    peer_isn: Wrap32,
+    syn_options: Vec<TcpOption>,
+    arrived: Instant,
+}
+
+/// A passive open that queues SYNs instead of answering them. Only the SYN's flow, ISN and
+/// options are kept until the application calls `accept_from`, which is where the streams and
+/// reassembly buffers are allocated.
+#[derive(Debug)]
+pub struct TcpListener {
+    local: SocketAddrV4,
+    backlog: usize,  // Most SYNs waiting at once
+    capacity: usize, // Stream capacity of each accepted connection
+    config: TcpConfig,
+    queue: Vec<PendingSyn>,
+    stats: ListenerStats,
+}
+
+impl TcpListener {
+    pub fn new(local: SocketAddrV4, backlog: usize, capacity: usize) -> Self {
+        Self::with_config(local, backlog, capacity, TcpConfig::default())
+    }
+
+    pub fn with_config(local: SocketAddrV4, backlog: usize, capacity: usize, config: TcpConfig) -> Self {
+        TcpListener { local, backlog, capacity, config, queue: Vec::new(), stats: ListenerStats::default() }
+    }
+
+    /// Queue the SYN in `packet` if it's addressed to this listener. Returns whether the packet
+    /// was a SYN for us, even if the backlog had no room for it.
+    pub fn on_packet(&mut self, packet: &[u8], now: Instant) -> Result<bool, HeaderError> {
+        let (iph, tcph) = packet::unwrap(packet)?;
+        Ok(self.on_syn(&iph, &tcph, now))
+    }
+
+    /// Like `on_packet`, for headers that are already parsed
+    pub fn on_syn(&mut self, iph: &IpHeader, tcph: &TcpHeader, now: Instant) -> bool {
+        let is_syn = tcph.flags.contains(TcpFlags::SYN) && !tcph.flags.intersects(TcpFlags::ACK | TcpFlags::RST);
+        if !is_syn || iph.dst_ip != *self.local.ip() || tcph.dst_port != self.local.port() {
+            return false;
+        }
+
+        let flow = FlowKey::new(self.local, SocketAddrV4::new(iph.src_ip, tcph.src_port));
+        if self.queue.iter().any(|syn| syn.flow == flow) {
+            self.stats.duplicate_syns += 1;
+            return true;
+        }
+        if self.queue.len() >= self.backlog {
+            self.stats.backlog_drops += 1;
+            return true;
+        }
+
+        self.stats.syns_queued += 1;
+        self.queue.push(PendingSyn {
+            flow,
+            peer_isn: tcph.seq_no,
+            syn_options: tcph.tcp_options_partial(),
+            arrived: now,
+        });
+        true
+    }
+
+    /// The peers waiting to be accepted, oldest first
+    pub fn pending(&self, now: Instant) -> Vec<PendingConn> {
+        self.queue
+            .iter()
+            .map(|syn| PendingConn {
+                flow: syn.flow,
+                syn_options: syn.syn_options.clone(),
+                age: now.saturating_duration_since(syn.arrived),
+            })
+            .collect()
+    }
+
+    /// Commit resources to the peer on `flow`: allocate its streams and build the SYN-ACK.
+    /// `None` if no SYN from that peer is pending.
+    pub fn accept_from(&mut self, flow: FlowKey) -> Option<AcceptedConn> {
+        self.accept_with_isn(flow, isn::for_flow(&flow))
+    }
+
+    /// Like `accept_from`, with our ISN given instead of generated. Deterministic, for tests
+    pub fn accept_with_isn(&mut self, flow: FlowKey, isn: Wrap32) -> Option<AcceptedConn> {
+        let syn = self.take(flow)?;
+
+        let reassembler = Reassembler::new(ByteStream::new(self.capacity));
+        let mut receiver = TcpReceiver::with_config(syn.peer_isn + Wrap32::new(1), reassembler, &self.config);
+        receiver.set_syn_received();
+        let mut sender = TcpSender::with_config(isn, ByteStream::new(self.capacity), &self.config);
+        sender.set_flow(flow);
+
+        let syn_ack = TcpHeader {
+            src_port: flow.local.port(),
+            dst_port: flow.remote.port(),
+            seq_no: sender.isn(),
+            ack_no: syn.peer_isn + Wrap32::new(1),
+            data_offset: 5,
+            flags: TcpFlags::SYN | TcpFlags::ACK,
+            window: receiver.window_size(),
+            ..TcpHeader::default()
+        };
+        let syn_ack = sender.build_packet(&syn_ack).ok()?;
+        sender.syn_sent();
+
+        self.stats.accepted += 1;
+        Some(AcceptedConn { flow, sender, receiver, syn_ack })
+    }
+
+    /// Drop the pending peer on `flow`. With `with_rst`, returns the RST to send so the peer
+    /// fails fast instead of retransmitting its SYN.
+    pub fn reject(&mut self, flow: FlowKey, with_rst: bool) -> Option<Vec<u8>> {
+        let syn = self.take(flow)?;
+        self.stats.rejected += 1;
+        if !with_rst {
+            return None;
+        }
+
+        // RFC 793: a RST answering a SYN acknowledges it and uses seq 0
+        let tcph = TcpHeader {
+            src_port: flow.local.port(),
+            dst_port: flow.remote.port(),
+            seq_no: Wrap32::new(0),
+            ack_no: syn.peer_isn + Wrap32::new(1),
+            data_offset: 5,
+            flags: TcpFlags::RST | TcpFlags::ACK,
+            ..TcpHeader::default()
+        };
+        let iph = IpHeader {
+            version: 4,
+            ihl: 5,
+            total_len: 40,
+            ttl: 64,
+            protocol: 6,
+            src_ip: *flow.local.ip(),
+            dst_ip: *flow.remote.ip(),
+            ..IpHeader::default()
+        };
+        packet::wrap(&iph, &tcph).ok()
+    }
+
+    pub fn stats(&self) -> &ListenerStats {
+        &self.stats
+    }
+
+    fn take(&mut self, flow: FlowKey) -> Option<PendingSyn> {
+        let idx = self.queue.iter().position(|syn| syn.flow == flow)?;
+        Some(self.queue.remove(idx))
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const SERVER: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 80);
+
+    fn syn_packet(peer: SocketAddrV4, isn: u32) -> Vec<u8> {
+        let options = vec![2, 4, 5, 180]; // MSS 1460
+        let iph = IpHeader {
+            version: 4,
+            ihl: 5,
+            total_len: 44,
+            ttl: 64,
+            protocol: 6,
+            src_ip: *peer.ip(),
+            dst_ip: *SERVER.ip(),
+            ..IpHeader::default()
+        };
+        let tcph = TcpHeader {
+            src_port: peer.port(),
+            dst_port: SERVER.port(),
+            seq_no: Wrap32::new(isn),
+            data_offset: 6,
+            flags: TcpFlags::SYN,
+            window: 65535,
+            options: options.into(),
+            ..TcpHeader::default()
+        };
+        packet::wrap(&iph, &tcph).unwrap()
+    }
+
+    #[test]
+    fn test_accept_one_reject_other() {
+        let alice = SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 10), 40000);
+        let mallory = SocketAddrV4::new(Ipv4Addr::new(203, 0, 113, 7), 50000);
+        let t0 = Instant::now();
+
+        let mut listener = TcpListener::new(SERVER, 8, 4096);
+        assert!(listener.on_packet(&syn_packet(alice, 1000), t0).unwrap());
+        assert!(listener.on_packet(&syn_packet(mallory, 7000), t0 + Duration::from_millis(5)).unwrap());
+
+        // The application sees both peers, their options and how long they've waited
+        let pending = listener.pending(t0 + Duration::from_millis(20));
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0].flow, FlowKey::new(SERVER, alice));
+        assert_eq!(pending[0].syn_options, vec![TcpOption::Mss(1460)]);
+        assert_eq!(pending[0].age, Duration::from_millis(20));
+        assert_eq!(pending[1].age, Duration::from_millis(15));
+
+        // Accepting allocates the connection and answers with a SYN-ACK
+        let conn = listener.accept_with_isn(pending[0].flow, Wrap32::new(5000)).unwrap();
+        let (iph, syn_ack) = packet::unwrap(&conn.syn_ack).unwrap();
+        assert_eq!((iph.src_ip, iph.dst_ip), (*SERVER.ip(), *alice.ip()));
+        assert_eq!(syn_ack.flags, TcpFlags::SYN | TcpFlags::ACK);
+        assert_eq!(syn_ack.ack_no, Wrap32::new(1001));
+        assert_eq!((syn_ack.seq_no, conn.sender.isn()), (Wrap32::new(5000), Wrap32::new(5000)));
+        assert_eq!(conn.receiver.ack_no(), Wrap32::new(1001));
+
+        // Rejecting sends mallory a RST and allocates nothing
+        let rst = listener.reject(pending[1].flow, true).unwrap();
+        let (iph, rst) = packet::unwrap(&rst).unwrap();
+        assert_eq!(iph.dst_ip, *mallory.ip());
+        assert_eq!(rst.dst_port, mallory.port());
+        assert!(rst.flags.contains(TcpFlags::RST));
+        assert_eq!(rst.ack_no, Wrap32::new(7001));
+
+        let stats = listener.stats();
+        assert_eq!((stats.syns_queued, stats.accepted, stats.rejected), (2, 1, 1));
+        assert!(listener.pending(t0).is_empty());
+        assert!(listener.accept_from(pending[1].flow).is_none());
+    }
+
+    #[test]
+    fn test_duplicate_syn_after_accept() {
+        let alice = SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 10), 40000);
+        let mut listener = TcpListener::new(SERVER, 8, 4096);
+        listener.on_packet(&syn_packet(alice, 1000), Instant::now()).unwrap();
+        let mut conn = listener.accept_with_isn(FlowKey::new(SERVER, alice), Wrap32::new(5000)).unwrap();
+
+        // The SYN-ACK was lost, so alice sends her SYN again after some data
+        let data = TcpHeader { seq_no: Wrap32::new(1001), flags: TcpFlags::ACK, payload: b"hi".to_vec().into(), ..TcpHeader::default() };
+        conn.receiver.recv(data).unwrap();
+        let (_, syn) = packet::unwrap(&syn_packet(alice, 1000)).unwrap();
+        conn.receiver.recv(syn).unwrap();
+
+        assert!(conn.receiver.take_ack_pending());
+        assert_eq!(conn.receiver.ack_no(), Wrap32::new(1003));
+        assert_eq!(conn.receiver.stats().duplicate_syns, 1);
+        assert_eq!(conn.receiver.stream().peek_output(8), b"hi");
+    }
+
+    #[test]
+    fn test_data_after_accept() {
+        let alice = SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 10), 40000);
+        let mut listener = TcpListener::new(SERVER, 8, 4096);
+        listener.on_packet(&syn_packet(alice, 1000), Instant::now()).unwrap();
+        let mut conn = listener.accept_with_isn(FlowKey::new(SERVER, alice), Wrap32::new(5000)).unwrap();
+
+        // The SYN-ACK used up our ISN: our data starts after it, and alice's ACK of it is valid
+        assert_eq!(conn.sender.current_seq_no(), Wrap32::new(5001));
+        assert_eq!(conn.sender.first_unacked_seq_no(), Wrap32::new(5000));
+        let data = TcpHeader {
+            seq_no: Wrap32::new(1001),
+            ack_no: Wrap32::new(5001),
+            flags: TcpFlags::ACK,
+            payload: b"GET /".to_vec().into(),
+            ..TcpHeader::default()
+        };
+        conn.receiver.recv_established(data, &conn.sender, 65535).unwrap();
+        assert_eq!(conn.receiver.stats().unacceptable_ack_drops, 0);
+        assert_eq!(conn.receiver.stream().peek_output(8), b"GET /");
+        assert_eq!(conn.receiver.ack_no(), Wrap32::new(1006));
+    }
+
+    #[test]
+    fn test_backlog_and_duplicates() {
+        let mut listener = TcpListener::new(SERVER, 1, 4096);
+        let now = Instant::now();
+        let peer = |port| SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 10), port);
+
+        assert!(listener.on_packet(&syn_packet(peer(1), 1), now).unwrap());
+        assert!(listener.on_packet(&syn_packet(peer(1), 1), now).unwrap());
+        assert!(listener.on_packet(&syn_packet(peer(2), 1), now).unwrap());
+        assert_eq!(listener.pending(now).len(), 1);
+        assert_eq!(listener.stats().duplicate_syns, 1);
+        assert_eq!(listener.stats().backlog_drops, 1);
+
+        // A silent reject frees the slot without a RST
+        assert!(listener.reject(FlowKey::new(SERVER, peer(1)), false).is_none());
+        assert!(listener.on_packet(&syn_packet(peer(2), 1), now).unwrap());
+        assert_eq!(listener.pending(now).len(), 1);
+    }
+
+    #[test]
+    fn test_ignores_other_packets() {
+        let mut listener = TcpListener::new(SocketAddrV4::new(*SERVER.ip(), 443), 8, 4096);
+        let peer = SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 10), 40000);
+        assert!(!listener.on_packet(&syn_packet(peer, 1), Instant::now()).unwrap());
+        assert!(listener.pending(Instant::now()).is_empty());
+    }
+}
@@ -1,10 +1,28 @@
 pub mod byte_stream;
+pub mod challenge_ack;
+#[cfg(feature = "tokio")]
+pub mod async_byte_stream;
+pub mod config;
+pub mod connect;
+pub mod congestion;
 pub mod conn;
+pub mod demux;
+pub mod ecn;
+pub mod flow_io;
+pub mod flow_key;
+pub mod isn;
+pub mod listener;
+pub mod negotiation;
+pub mod pmtu;
 pub mod tcp_flags;
 pub mod tcp_header;
+pub mod tcp_options;
+pub mod timer;
+pub mod timestamps;
 pub mod reassembler;
 pub mod receiver;
+pub mod ring_buffer;
 pub mod sender;
 pub mod state;
 pub mod wrap32;
-mod states;
\ No newline at end of file
+pub mod states;
\ No newline at end of file
@@ -0,0 +1,229 @@
+use crate::ip::ip_header::IpHeader;
+use crate::packet;
+use crate::packet::errors::NegotiationError;
+use crate::tcp::tcp_flags::TcpFlags;
+use crate::tcp::tcp_header::TcpHeader;
+use crate::tcp::tcp_options::{self, TcpOption};
+use crate::tcp::wrap32::Wrap32;
+use std::net::Ipv4Addr;
+
+/// The largest window scale shift RFC 7323 allows. Larger offers are treated as 14
+pub const MAX_WINDOW_SCALE: u8 = 14;
+
+/// The MSS assumed when a SYN carries none (RFC 9293 section 3.7.1)
+pub const DEFAULT_MSS: u16 = 536;
+
+/// The handshake options one endpoint puts in its SYN or SYN-ACK
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandshakeOffer {
+    pub mss: u16,
+    pub window_scale: Option<u8>, // Shift applied to the windows this endpoint advertises
+    pub sack_permitted: bool,
+    pub timestamps: bool,
+}
+
+impl Default for HandshakeOffer {
+    fn default() -> Self {
+        HandshakeOffer { mss: 1460, window_scale: Some(7), sack_permitted: true, timestamps: true }
+    }
+}
+
+impl HandshakeOffer {
+    /// The options to send, in the order Linux uses
+    pub fn options(&self, ts_val: u32) -> Vec<TcpOption> {
+        let mut options = vec![TcpOption::Mss(self.mss)];
+        if let Some(shift) = self.window_scale {
+            options.push(TcpOption::WindowScale(shift));
+        }
+        if self.timestamps {
+            options.push(TcpOption::Timestamps { val: ts_val, ecr: 0 });
+        }
+        if self.sack_permitted {
+            options.push(TcpOption::SackPermitted);
+        }
+        options
+    }
+
+    /// Read an offer back out of received SYN or SYN-ACK options
+    pub fn from_options(options: &[TcpOption]) -> Self {
+        let mut offer = HandshakeOffer { mss: DEFAULT_MSS, window_scale: None, sack_permitted: false, timestamps: false };
+        for option in options {
+            match option {
+                TcpOption::Mss(mss) => offer.mss = *mss,
+                TcpOption::WindowScale(shift) => offer.window_scale = Some(*shift),
+                TcpOption::SackPermitted => offer.sack_permitted = true,
+                TcpOption::Timestamps { .. } => offer.timestamps = true,
+                _ => {}
+            }
+        }
+        offer
+    }
+
+    /// The offer to put in a SYN-ACK answering `syn`. Window scaling, SACK and timestamps may only
+    /// be sent back if the SYN offered them (RFC 7323 and RFC 2018).
+    pub fn reply_to(&self, syn: &HandshakeOffer) -> Self {
+        HandshakeOffer {
+            mss: self.mss,
+            window_scale: self.window_scale.filter(|_| syn.window_scale.is_some()),
+            sack_permitted: self.sack_permitted && syn.sack_permitted,
+            timestamps: self.timestamps && syn.timestamps,
+        }
+    }
+}
+
+/// What an endpoint ends up using after the handshake, from its own point of view
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Negotiated {
+    pub mss: u16,        // Largest segment either side sends: the smaller of the two MSS values
+    pub snd_wscale: u8,  // Shift applied to windows the peer advertises. 0 if scaling is off
+    pub rcv_wscale: u8,  // Shift applied to windows we advertise. 0 if scaling is off
+    pub sack: bool,
+    pub timestamps: bool,
+}
+
+impl Negotiated {
+    /// Combine what we offered with what the peer offered
+    pub fn resolve(local: &HandshakeOffer, peer: &HandshakeOffer) -> Self {
+        let (snd_wscale, rcv_wscale) = match (local.window_scale, peer.window_scale) {
+            (Some(ours), Some(theirs)) => (theirs.min(MAX_WINDOW_SCALE), ours.min(MAX_WINDOW_SCALE)),
+            _ => (0, 0), // Scaling is only on if both ends offer it
+        };
+        Negotiated {
+            mss: local.mss.min(peer.mss),
+            snd_wscale,
+            rcv_wscale,
+            sack: local.sack_permitted && peer.sack_permitted,
+            timestamps: local.timestamps && peer.timestamps,
+        }
+    }
+
+    /// The same agreement from the peer's point of view
+    pub fn mirrored(&self) -> Self {
+        Negotiated { snd_wscale: self.rcv_wscale, rcv_wscale: self.snd_wscale, ..*self }
+    }
+
+    /// Every field where `self` and `other` disagree, as `field: <a_label> x, <b_label> y`
+    pub fn diff(&self, a_label: &str, other: &Negotiated, b_label: &str) -> Vec<String> {
+        let fields = [
+            ("mss", self.mss.to_string(), other.mss.to_string()),
+            ("snd_wscale", self.snd_wscale.to_string(), other.snd_wscale.to_string()),
+            ("rcv_wscale", self.rcv_wscale.to_string(), other.rcv_wscale.to_string()),
+            ("sack", self.sack.to_string(), other.sack.to_string()),
+            ("timestamps", self.timestamps.to_string(), other.timestamps.to_string()),
+        ];
+        fields
+            .into_iter()
+            .filter(|(_, a, b)| a != b)
+            .map(|(field, a, b)| format!("{field}: {a_label} {a}, {b_label} {b}"))
+            .collect()
+    }
+}
+
+/// Run a handshake between two of our own endpoints through serialized packets, and check that
+/// both come out with the same parameters, equal to what their offers should resolve to. Returns
+/// the client's and the server's view.
+pub fn check_handshake(client: &HandshakeOffer, server: &HandshakeOffer) -> Result<(Negotiated, Negotiated), NegotiationError> {
+    // Client -> SYN. The server negotiates from what it parses
+    let syn = round_trip(TcpFlags::SYN, &client.options(1))?;
+    let server_view = Negotiated::resolve(server, &HandshakeOffer::from_options(&syn));
+
+    // Server -> SYN-ACK, answering only what the SYN offered
+    let reply = server.reply_to(&HandshakeOffer::from_options(&syn));
+    let syn_ack = round_trip(TcpFlags::SYN | TcpFlags::ACK, &reply.options(2))?;
+    let client_view = Negotiated::resolve(client, &HandshakeOffer::from_options(&syn_ack));
+
+    let expected = Negotiated::resolve(client, server);
+    let mut mismatches = client_view.diff("client", &server_view.mirrored(), "server");
+    mismatches.extend(client_view.diff("client", &expected, "configured"));
+    mismatches.extend(server_view.diff("server", &expected.mirrored(), "configured"));
+    if !mismatches.is_empty() {
+        return Err(NegotiationError::Mismatch(mismatches));
+    }
+    Ok((client_view, server_view))
+}
+
+/// Serialize a handshake segment carrying `options`, then parse its options back
+fn round_trip(flags: TcpFlags, options: &[TcpOption]) -> Result<Vec<TcpOption>, NegotiationError> {
+    let tcph = TcpHeader {
+        src_port: 50000,
+        dst_port: 80,
+        seq_no: Wrap32::new(0),
+        flags,
+        window: u16::MAX,
+        options: tcp_options::encode_options(options).into(),
+        ..TcpHeader::default()
+    };
+    let iph = IpHeader {
+        version: 4,
+        ihl: 5,
+        total_len: (20 + tcph.header_len()) as u16,
+        ttl: 64,
+        protocol: 6,
+        src_ip: Ipv4Addr::LOCALHOST,
+        dst_ip: Ipv4Addr::LOCALHOST,
+        ..IpHeader::default()
+    };
+    let (_, tcph) = packet::unwrap(&packet::wrap(&iph, &tcph)?)?;
+    Ok(tcph.tcp_options()?)
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symmetric_defaults() {
+        let (client, server) = check_handshake(&HandshakeOffer::default(), &HandshakeOffer::default()).unwrap();
+        let expected = Negotiated { mss: 1460, snd_wscale: 7, rcv_wscale: 7, sack: true, timestamps: true };
+        assert_eq!(client, expected);
+        assert_eq!(server, expected);
+    }
+
+    #[test]
+    fn test_one_side_without_sack_resolves_to_intersection() {
+        let client = HandshakeOffer { mss: 8960, window_scale: Some(9), ..HandshakeOffer::default() };
+        let server = HandshakeOffer { sack_permitted: false, window_scale: Some(2), ..HandshakeOffer::default() };
+
+        // Disagreeing configs are not a mismatch: both ends settle on the same intersection
+        let (client_view, server_view) = check_handshake(&client, &server).unwrap();
+        assert!(!client_view.sack && !server_view.sack);
+        assert_eq!(client_view, Negotiated { mss: 1460, snd_wscale: 2, rcv_wscale: 9, sack: false, timestamps: true });
+        assert_eq!(server_view, client_view.mirrored());
+
+        // Either direction
+        let (client_view, _) = check_handshake(&server, &client).unwrap();
+        assert!(!client_view.sack);
+    }
+
+    #[test]
+    fn test_scaling_and_mss_fallbacks() {
+        let client = HandshakeOffer { window_scale: None, timestamps: false, ..HandshakeOffer::default() };
+        let server = HandshakeOffer { window_scale: Some(20), ..HandshakeOffer::default() };
+        let (client_view, _) = check_handshake(&client, &server).unwrap();
+        assert_eq!((client_view.snd_wscale, client_view.rcv_wscale, client_view.timestamps), (0, 0, false));
+
+        // Oversized shifts are clamped on both sides
+        let client = HandshakeOffer { window_scale: Some(15), ..HandshakeOffer::default() };
+        let (client_view, server_view) = check_handshake(&client, &server).unwrap();
+        assert_eq!((client_view.snd_wscale, server_view.snd_wscale), (14, 14));
+
+        // No MSS option at all
+        assert_eq!(HandshakeOffer::from_options(&[]).mss, DEFAULT_MSS);
+    }
+
+    #[test]
+    fn test_mismatch_is_a_field_by_field_diff() {
+        let a = Negotiated { mss: 1460, snd_wscale: 7, rcv_wscale: 7, sack: true, timestamps: true };
+        let b = Negotiated { mss: 536, sack: false, ..a };
+        assert_eq!(a.diff("client", &b, "server"), ["mss: client 1460, server 536", "sack: client true, server false"]);
+        assert!(a.diff("client", &a.mirrored(), "server").is_empty());
+
+        let err = NegotiationError::Mismatch(a.diff("client", &b, "server"));
+        assert_eq!(
+            err.to_string(),
+            "Endpoints disagree on negotiated parameters: mss: client 1460, server 536; sack: client true, server false"
+        );
+    }
+}
@@ -0,0 +1,212 @@
+// Path MTU discovery (RFC 1191). We always send with DF, so a link narrower than our segments
+// drops them and the router says so with ICMP Fragmentation Needed. Without acting on that the
+// connection blackholes: every retransmission is the same size and is dropped the same way.
+
+use crate::packet::icmp::{IcmpMessage, CODE_FRAGMENTATION_NEEDED};
+use crate::tcp::flow_key::FlowKey;
+use crate::tcp::tcp_flags::TcpFlags;
+use crate::tcp::tcp_header::TcpHeader;
+use crate::tcp::wrap32::Wrap32;
+use std::time::{Duration, Instant};
+
+/// How long a reduced path MTU lasts before we try the interface MTU again (RFC 1191 section 6.3)
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// The smallest MTU every IPv4 link must support (RFC 791)
+pub const MIN_MTU: usize = 68;
+
+/// IPv4 and TCP headers without options
+const HEADERS_LEN: usize = 40;
+
+/// Common MTUs, for routers that report a next-hop MTU of 0 (RFC 1191 section 7)
+const PLATEAUS: [usize; 11] = [65535, 32000, 17914, 8166, 4352, 2002, 1492, 1006, 508, 296, MIN_MTU];
+
+/// The path MTU of one connection
+#[derive(Debug, Clone)]
+pub struct PathMtu {
+    interface_mtu: usize,
+    path_mtu: usize,
+    reduced_at: Option<Instant>, // When we last lowered it, for the probe timer
+}
+
+impl PathMtu {
+    /// Start from the MTU of the interface we send on, e.g. from `interface::mtu`
+    pub fn new(interface_mtu: usize) -> Self {
+        PathMtu { interface_mtu, path_mtu: interface_mtu, reduced_at: None }
+    }
+
+    pub fn path_mtu(&self) -> usize {
+        self.path_mtu
+    }
+
+    /// The largest payload that fits the path, assuming no IP or TCP options. Clamp the sender's
+    /// MSS to this and the negotiated MSS, whichever is smaller.
+    pub fn mss(&self) -> usize {
+        self.path_mtu.saturating_sub(HEADERS_LEN)
+    }
+
+    /// Act on an ICMP message received for the connection `flow`. If it's a Fragmentation Needed
+    /// for one of our segments that lowers the path MTU, returns that segment's sequence number
+    /// so it can be sent again, resegmented to the new `mss()`.
+    pub fn on_icmp(&mut self, message: &IcmpMessage, flow: &FlowKey, now: Instant) -> Option<Wrap32> {
+        let &IcmpMessage::DestinationUnreachable { code: CODE_FRAGMENTATION_NEEDED, next_hop_mtu, .. } = message else {
+            return None;
+        };
+        let quoted = message.quoted()?;
+        if quoted.src != flow.local || quoted.dst != flow.remote {
+            return None;
+        }
+        // Old routers leave the MTU out; guess the next plateau below what didn't fit
+        let mtu = match next_hop_mtu as usize {
+            0 => next_plateau(quoted.total_len as usize),
+            mtu => mtu.max(MIN_MTU),
+        };
+        // Never raise it on an ICMP, which anyone on the path could forge
+        if mtu >= self.path_mtu {
+            return None;
+        }
+        self.path_mtu = mtu;
+        self.reduced_at = Some(now);
+        Some(quoted.seq_no)
+    }
+
+    /// Call periodically. Once a reduced path MTU is `PROBE_INTERVAL` old, go back to the
+    /// interface MTU and return true; if the path is still narrow, the next ICMP lowers it again.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        match self.reduced_at {
+            Some(at) if now.duration_since(at) >= PROBE_INTERVAL => {
+                self.path_mtu = self.interface_mtu;
+                self.reduced_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The largest plateau below `len`
+fn next_plateau(len: usize) -> usize {
+    PLATEAUS.into_iter().find(|&plateau| plateau < len).unwrap_or(MIN_MTU)
+}
+
+/// Split `tcph` into segments of at most `mss` payload bytes, in order. Each keeps the header's
+/// fields with its own sequence number; PSH and FIN stay on the last one only.
+pub fn resegment(tcph: &TcpHeader, mss: usize) -> Vec<TcpHeader> {
+    if tcph.payload.len() <= mss || mss == 0 {
+        return vec![tcph.clone()];
+    }
+    let last_only = TcpFlags::PSH | TcpFlags::FIN;
+    let count = tcph.payload.len().div_ceil(mss);
+    (0..count)
+        .map(|i| {
+            let start = i * mss;
+            let end = (start + mss).min(tcph.payload.len());
+            let flags = if i + 1 == count { tcph.flags } else { tcph.flags.difference(last_only) };
+            TcpHeader {
+                seq_no: tcph.seq_no + Wrap32::new(start as u32),
+                flags,
+                payload: tcph.payload.slice(start..end),
+                ..tcph.clone()
+            }
+        })
+        .collect()
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    const LOCAL: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 50000);
+    const REMOTE: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 80);
+
+    /// The IP header and 8 TCP bytes of a `total_len` byte packet of ours with `seq_no`
+    fn quoted(total_len: u16, seq_no: u32) -> Vec<u8> {
+        let mut packet = vec![0x45, 0, 0, 0, 0, 0, 0x40, 0, 64, 6, 0, 0];
+        packet[2..4].copy_from_slice(&total_len.to_be_bytes());
+        packet.extend(LOCAL.ip().octets());
+        packet.extend(REMOTE.ip().octets());
+        packet.extend(LOCAL.port().to_be_bytes());
+        packet.extend(REMOTE.port().to_be_bytes());
+        packet.extend(seq_no.to_be_bytes());
+        packet
+    }
+
+    #[test]
+    fn test_frag_needed_lowers_mtu() {
+        let flow = FlowKey::new(LOCAL, REMOTE);
+        let mut pmtu = PathMtu::new(1500);
+        assert_eq!(pmtu.mss(), 1460);
+        let now = Instant::now();
+
+        let icmp = IcmpMessage::unreachable(CODE_FRAGMENTATION_NEEDED, 1280, &quoted(1500, 1234));
+        assert_eq!(pmtu.on_icmp(&icmp, &flow, now), Some(Wrap32::new(1234)));
+        assert_eq!((pmtu.path_mtu(), pmtu.mss()), (1280, 1240));
+
+        // Never raised by an ICMP, and ignored for other flows and other errors
+        let higher = IcmpMessage::unreachable(CODE_FRAGMENTATION_NEEDED, 1400, &quoted(1500, 1234));
+        assert_eq!(pmtu.on_icmp(&higher, &flow, now), None);
+        let lower = IcmpMessage::unreachable(CODE_FRAGMENTATION_NEEDED, 1000, &quoted(1280, 1234));
+        assert_eq!(pmtu.on_icmp(&lower, &flow.reversed(), now), None);
+        let port = IcmpMessage::unreachable(3, 1000, &quoted(1280, 1234));
+        assert_eq!(pmtu.on_icmp(&port, &flow, now), None);
+        assert_eq!(pmtu.path_mtu(), 1280);
+
+        // Silly small values are raised to the minimum
+        let tiny = IcmpMessage::unreachable(CODE_FRAGMENTATION_NEEDED, 20, &quoted(1280, 1234));
+        assert!(pmtu.on_icmp(&tiny, &flow, now).is_some());
+        assert_eq!(pmtu.path_mtu(), MIN_MTU);
+    }
+
+    #[test]
+    fn test_plateaus_without_next_hop_mtu() {
+        let flow = FlowKey::new(LOCAL, REMOTE);
+        let mut pmtu = PathMtu::new(9000);
+        let now = Instant::now();
+        for (sent, expected) in [(9000, 8166), (8166, 4352), (1500, 1492), (1492, 1006), (576, 508)] {
+            let icmp = IcmpMessage::unreachable(CODE_FRAGMENTATION_NEEDED, 0, &quoted(sent, 1));
+            pmtu.on_icmp(&icmp, &flow, now);
+            assert_eq!(pmtu.path_mtu(), expected, "after a {sent} byte packet");
+        }
+        assert_eq!(next_plateau(68), MIN_MTU);
+    }
+
+    #[test]
+    fn test_probe_after_interval() {
+        let flow = FlowKey::new(LOCAL, REMOTE);
+        let mut pmtu = PathMtu::new(1500);
+        let start = Instant::now();
+        assert!(!pmtu.poll(start + PROBE_INTERVAL)); // Nothing to probe
+
+        let icmp = IcmpMessage::unreachable(CODE_FRAGMENTATION_NEEDED, 1400, &quoted(1500, 1));
+        pmtu.on_icmp(&icmp, &flow, start);
+        assert!(!pmtu.poll(start + PROBE_INTERVAL - Duration::from_secs(1)));
+        assert_eq!(pmtu.path_mtu(), 1400);
+        assert!(pmtu.poll(start + PROBE_INTERVAL));
+        assert_eq!(pmtu.path_mtu(), 1500);
+        assert!(!pmtu.poll(start + 2 * PROBE_INTERVAL));
+    }
+
+    #[test]
+    fn test_resegment() {
+        let tcph = TcpHeader {
+            seq_no: Wrap32::new(u32::MAX - 1),
+            flags: TcpFlags::ACK | TcpFlags::PSH | TcpFlags::FIN,
+            payload: (0..25u8).collect::<Vec<u8>>().into(),
+            ..TcpHeader::new(LOCAL.port(), REMOTE.port())
+        };
+        let segments = resegment(&tcph, 10);
+        let seqs: Vec<u32> = segments.iter().map(|s| s.seq_no.value()).collect();
+        assert_eq!(seqs, [u32::MAX - 1, 8, 18]);
+        let lens: Vec<usize> = segments.iter().map(|s| s.payload_len()).collect();
+        assert_eq!(lens, [10, 10, 5]);
+        assert_eq!(segments[0].flags, TcpFlags::ACK);
+        assert_eq!(segments[2].flags, tcph.flags);
+        let rejoined: Vec<u8> = segments.iter().flat_map(|s| s.payload.to_vec()).collect();
+        assert_eq!(rejoined, tcph.payload);
+
+        assert_eq!(resegment(&tcph, 25), [tcph]);
+    }
+}
@@ -1,31 +1,135 @@
-use crate::tcp::byte_stream::ByteStream;
+use crate::tcp::byte_stream::{ByteStream, OnPressure, PressureEvent, PressureHook, StreamWrite};
+use crate::tcp::ring_buffer::RingBuffer;
+use bytes::{Bytes, BytesMut};
 use std::collections::BTreeMap;
 use std::io;
-use std::io::{Read, Write};
+use std::io::Read;
+use std::ops::Range;
+
+/// A segment's payload: shared with the caller, or borrowed from a packet buffer
+#[derive(Debug, Clone)]
+pub enum Payload<'a> {
+    Shared(Bytes),
+    Borrowed(&'a [u8]),
+}
+
+impl Payload<'_> {
+    pub fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        match self {
+            Payload::Shared(bytes) => bytes,
+            Payload::Borrowed(slice) => slice,
+        }
+    }
+
+    /// Take ownership of the payload, copying it if it was borrowed
+    pub fn into_bytes(self) -> Bytes {
+        match self {
+            Payload::Shared(bytes) => bytes,
+            Payload::Borrowed(slice) => Bytes::copy_from_slice(slice),
+        }
+    }
+}
 
+/// Puts segments that arrive out of order back together, writing bytes to the output stream as
+/// soon as they're contiguous.
+///
+/// ```
+/// use net::tcp::byte_stream::ByteStream;
+/// use net::tcp::reassembler::Reassembler;
+/// use std::io::Read;
+///
+/// let mut reassembler = Reassembler::new(ByteStream::new(64));
+///
+/// // The last segment arrives first, so it waits behind a gap
+/// reassembler.insert(6, b"world", true).unwrap();
+/// assert_eq!(reassembler.bytes_pending(), 5);
+/// assert_eq!(reassembler.gaps(), vec![0..6]);
+///
+/// // Filling the gap flushes both segments and closes the stream
+/// reassembler.insert(0, b"hello ", false).unwrap();
+/// assert_eq!(reassembler.bytes_pending(), 0);
+///
+/// let mut out = String::new();
+/// reassembler.read_to_string(&mut out).unwrap();
+/// assert_eq!(out, "hello world");
+/// assert!(reassembler.get_output().eof());
+/// ```
 #[derive(Debug)]
-pub struct Reassembler {
-    segments: BTreeMap<usize, Vec<u8>>,   // Out-of-order segments. key = start index
-    output: ByteStream,                   // The assembled ByteStream, ready to be read
+pub struct Reassembler<W: StreamWrite = ByteStream> {
+    segments: BTreeMap<usize, Bytes>,     // Out-of-order segments. key = start index
+    ring: Option<RingBuffer>,             // Alternative fixed-size storage; replaces `segments`
+    output: W,                            // The assembled ByteStream, or its writer half
     next_byte_idx: usize,                 // The next byte index expected to write
     last_byte_idx: Option<usize>,         // The last byte index, if known
+    max_segments: Option<usize>,          // Cap on the number of buffered out-of-order segments
+    segments_dropped: u64,                // Segments refused or evicted because of `max_segments`
+    inconsistent_bytes: u64,              // Overlapping bytes that differed from the buffered copy
+    strict: bool,                         // Abort on inconsistent overlaps instead of keeping the first copy
+    on_pressure: OnPressure,              // Capacity-pressure callback, disabled by default
 }
 
-impl Reassembler {
-    /// New `Reassembler` with the provided `ByteStream` as output
-    pub fn new(output: ByteStream) -> Self {
+impl<W: StreamWrite> Reassembler<W> {
+    /// New `Reassembler` with the provided `ByteStream`, or a `StreamWriter`, as output
+    pub fn new(output: W) -> Self {
         Reassembler {
             segments: BTreeMap::new(),
+            ring: None,
             output,
             next_byte_idx: 0,
             last_byte_idx: None,
+            max_segments: None,
+            segments_dropped: 0,
+            inconsistent_bytes: 0,
+            strict: false,
+            on_pressure: OnPressure::default(),
+        }
+    }
+
+    /// New `Reassembler` that buffers at most `max_segments` out-of-order segments. When the cap
+    /// is hit, the segments furthest from `next_byte_idx` are evicted first.
+    pub fn with_limits(output: W, max_segments: usize) -> Self {
+        Reassembler {
+            max_segments: Some(max_segments),
+            ..Reassembler::new(output)
+        }
+    }
+
+    /// New `Reassembler` that buffers out-of-order bytes in a ring sized to the output capacity.
+    /// A cap from `limit_segments` applies to the ring's disjoint ranges.
+    pub fn with_ring_buffer(output: W) -> Self {
+        let ring = RingBuffer::new(output.remaining_capacity());
+        Reassembler {
+            ring: Some(ring),
+            ..Reassembler::new(output)
         }
     }
 
-    /// Insert a new byte segment into the `Reassembler`
-    pub fn insert(&mut self, first_idx: usize, data: &[u8], is_last: bool) -> io::Result<()> {
+    /// Insert a new byte segment into the `Reassembler`. The ring buffer copies straight from the
+    /// slice, the tree backend copies it into a new `Bytes`; prefer `insert_bytes` there.
+    /// Returns the number of new bytes accepted, excluding duplicates and bytes beyond capacity.
+    pub fn insert(&mut self, first_idx: usize, data: &[u8], is_last: bool) -> io::Result<usize> {
+        self.insert_payload(first_idx, Payload::Borrowed(data), is_last)
+    }
+
+    /// Insert a new byte segment into the `Reassembler` without copying it.
+    /// Returns the number of new bytes accepted, excluding duplicates and bytes beyond capacity.
+    pub fn insert_bytes(&mut self, first_idx: usize, data: Bytes, is_last: bool) -> io::Result<usize> {
+        self.insert_payload(first_idx, Payload::Shared(data), is_last)
+    }
+
+    /// Insert a segment whose payload is either shared or borrowed. Borrowed payloads are only
+    /// copied if the tree backend has to keep them.
+    pub fn insert_payload(&mut self, first_idx: usize, data: Payload<'_>, is_last: bool) -> io::Result<usize> {
         if data.is_empty() && !is_last {
-            return Ok(());
+            return Ok(0);
         }
 
         // If this is the last segment, set `last_byte_idx`
@@ -35,65 +139,171 @@ impl Reassembler {
 
         if self.is_done() {
             self.output.close();
-            return Ok(());
+            return Ok(0);
+        }
+
+        // Nothing more is accepted once the stream has been aborted
+        if self.output.has_error() {
+            return Ok(0);
+        }
+
+        // Report the parts of the segment that fall outside the window
+        let last_idx = first_idx + data.len();
+        let dropped_low = self.next_byte_idx.min(last_idx).saturating_sub(first_idx);
+        let dropped_high = last_idx.saturating_sub(self.window_end().max(first_idx));
+        if dropped_low > 0 || dropped_high > 0 {
+            self.on_pressure.fire(PressureEvent::InsertTrimmed { dropped_low, dropped_high });
         }
 
         // Buffer in the new segment
-        self.insert_buffer(first_idx, &data)?;
+        let inconsistent_before = self.inconsistent_bytes;
+        let accepted = if self.ring.is_some() {
+            self.insert_ring(first_idx, data.as_slice())
+        } else {
+            self.insert_buffer(first_idx, data.into_bytes())?
+        };
+
+        if self.strict && self.inconsistent_bytes > inconsistent_before {
+            let err = || io::Error::new(io::ErrorKind::InvalidData, "inconsistent overlapping data");
+            self.abort(err());
+            return Err(err());
+        }
 
         // Write as much as possible to the output stream
         self.write_output()?;
 
-        Ok(())
+        Ok(accepted)
+    }
+
+    /// Install, or with `None` remove, a callback fired when an insert is trimmed to the window
+    /// or a pending segment is evicted
+    pub fn set_on_pressure(&mut self, hook: Option<PressureHook>) {
+        self.on_pressure.set(hook);
+    }
+
+    /// In strict mode, an overlapping segment whose bytes differ from the buffered copy aborts
+    /// reassembly and `insert` returns `InvalidData`. Otherwise the first received bytes win.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// The total number of overlapping bytes that differed from the buffered copy
+    pub fn inconsistent_bytes(&self) -> u64 {
+        self.inconsistent_bytes
+    }
+
+    /// Abort reassembly. Pending segments are discarded and the output is closed with `err`,
+    /// so readers see the error once they drain the bytes already assembled.
+    pub fn abort(&mut self, err: io::Error) {
+        self.segments.clear();
+        if let Some(ring) = &mut self.ring {
+            ring.clear();
+        }
+        self.output.set_error(err);
+    }
+
+    /// Tighten the cap on buffered out-of-order segments to `max_segments`. An existing lower cap
+    /// is kept. The ring backend counts its disjoint ranges as segments.
+    pub fn limit_segments(&mut self, max_segments: usize) {
+        self.max_segments = Some(self.max_segments.map_or(max_segments, |cap| cap.min(max_segments)));
+    }
+
+    /// Segments refused or evicted so far because the segment cap was reached
+    pub fn segments_dropped(&self) -> u64 {
+        self.segments_dropped
+    }
+
+    /// The number of out-of-order segments held in the buffer
+    pub fn segments_pending(&self) -> usize {
+        match &self.ring {
+            Some(ring) => ring.ranges(),
+            None => self.segments.len(),
+        }
     }
 
     /// The total number of bytes pending reassembly in the buffer
     pub fn bytes_pending(&self) -> usize {
-        self.segments.values().map(|segment| segment.len()).sum()
+        match &self.ring {
+            Some(ring) => ring.pending(),
+            None => self.segments.values().map(|segment| segment.len()).sum(),
+        }
     }
 
     /// Get the underlying `ByteStream` output
-    pub fn get_output(&mut self) -> &ByteStream {
+    pub fn get_output(&self) -> &W {
         &self.output
     }
 
+    /// Get mutable access to the underlying `ByteStream` output
+    pub fn output_mut(&mut self) -> &mut W {
+        &mut self.output
+    }
+
+    /// Consume the `Reassembler` and take ownership of the `ByteStream` output
+    pub fn into_output(self) -> W {
+        self.output
+    }
+
     /// Get the index of the next byte. Aka: tail of the ByteStream
     pub fn next_byte_idx(&self) -> usize {
         self.next_byte_idx
     }
 
-    /// Insert data into the buffer and merging any overlapping segments
-    fn insert_buffer(&mut self, first_idx: usize, data: &[u8]) -> io::Result<()> {
+    /// The missing byte ranges between `next_byte_idx` and the highest buffered byte
+    pub fn gaps(&self) -> Vec<Range<usize>> {
+        if let Some(ring) = &self.ring {
+            return ring.gaps(self.next_byte_idx);
+        }
+
+        let mut gaps = Vec::new();
+        let mut cursor = self.next_byte_idx;
+
+        // Segments are sorted by start index, so adjacent segments simply advance the cursor
+        for (&seg_start, seg_data) in &self.segments {
+            if seg_start > cursor {
+                gaps.push(cursor..seg_start);
+            }
+            cursor = cursor.max(seg_start + seg_data.len());
+        }
+
+        gaps
+    }
+
+    /// Insert data into the buffer and merging any overlapping segments.
+    /// Returns the number of bytes not already buffered.
+    fn insert_buffer(&mut self, first_idx: usize, data: Bytes) -> io::Result<usize> {
         let last_idx = first_idx + data.len();
 
         // Ignore the segment if it's entirely before the next expected byte
         if last_idx <= self.next_byte_idx {
-            return Ok(());
+            return Ok(0);
         }
 
         // Calculate the range of data to buffer based on incoming data and remaining capacity
         let buffer_start = first_idx.max(self.next_byte_idx);
-        let buffer_end = last_idx.min(self.next_byte_idx + self.output.remaining_capacity());
+        let buffer_end = last_idx.min(self.window_end());
 
         if buffer_start >= buffer_end {
-            return Ok(()); // No capacity to buffer
+            return Ok(0); // No capacity to buffer
         }
 
-        // Calculate the effective slice of data that fits within the buffer's capacity
+        // Calculate the effective slice of data that fits within the buffer's capacity.
+        // Slicing `Bytes` is reference counted, so trimming never copies
         let offset = buffer_start - first_idx;
-        let window = &data[offset..(buffer_end - first_idx)];
+        let window = data.slice(offset..(buffer_end - first_idx));
 
         // Set the merge range to encompass the entire new data. It may grow or shrink later on
         let mut merge_start = buffer_start;
         let mut merge_end = buffer_end;
 
-        // Find all existing segments that overlap with the new data range. Thanks OpenAI :)
+        // Find all existing segments that overlap or touch the new data range, so adjacent
+        // segments become one and count once against `max_segments`. Thanks OpenAI :)
         let overlapping_keys: Vec<usize> = self
             .segments
-            .range(..buffer_end)
+            .range(..=buffer_end)
             .filter_map(|(&seg_start, seg_data)| {
                 let seg_end = seg_start + seg_data.len();
-                if seg_end > buffer_start {
+                if seg_end >= buffer_start {
                     Some(seg_start)
                 } else {
                     None
@@ -103,12 +313,16 @@ impl Reassembler {
 
         // If there are no overlapping segments, just insert the new window directly
         if overlapping_keys.is_empty() {
-            self.segments.insert(buffer_start, Vec::from(window));
-            return Ok(());
+            if !self.make_room_for(buffer_start) {
+                return Ok(0); // The new segment is the furthest out, so it's the one dropped
+            }
+            let accepted = window.len();
+            self.segments.insert(buffer_start, window);
+            return Ok(accepted);
         }
 
         // Collect and remove overlapping segments. Update the merge range accordingly
-        let mut overlapping_segments: Vec<(usize, Vec<u8>)> = Vec::new();
+        let mut overlapping_segments: Vec<(usize, Bytes)> = Vec::new();
         for &key in &overlapping_keys {
             if let Some(seg) = self.segments.remove(&key) {
                 merge_start = merge_start.min(key);
@@ -117,33 +331,122 @@ impl Reassembler {
             }
         }
 
-        // Allocate a new buffer to hold the merged data
+        // Allocate a new buffer to hold the merged data. Only genuine overlaps pay for a copy
         let merged_len = merge_end - merge_start;
-        let mut merged = vec![0u8; merged_len];
+        let mut merged = BytesMut::zeroed(merged_len);
+
+        // Lay down the new incoming data first
+        let new_data_start = buffer_start - merge_start;
+        merged[new_data_start..new_data_start + window.len()].copy_from_slice(&window);
 
-        // Overlay existing overlapping segments onto the merged buffer
+        // Overlay existing segments on top, so the first received bytes win
         for (seg_start, seg) in &overlapping_segments {
+            let lo = (*seg_start).max(buffer_start);
+            let hi = (seg_start + seg.len()).min(buffer_end);
+            if lo < hi {
+                let old = &seg[lo - seg_start..hi - seg_start];
+                let new = &window[lo - buffer_start..hi - buffer_start];
+                self.inconsistent_bytes += old.iter().zip(new).filter(|(a, b)| a != b).count() as u64;
+            }
+
             let cut_start = seg_start - merge_start;
             merged[cut_start..cut_start + seg.len()].copy_from_slice(seg);
         }
 
-        // Overlay the new incoming data onto the merged buffer
-        let new_data_start = buffer_start - merge_start;
-        merged[new_data_start..new_data_start + window.len()].copy_from_slice(window);
+        // Buffered segments never overlap each other, so whatever the merge grew by is new
+        let existing_len: usize = overlapping_segments.iter().map(|(_, seg)| seg.len()).sum();
+        let accepted = merged_len - existing_len;
 
         // Insert the merged segment back into the BTreeMap
-        self.segments.insert(merge_start, merged.to_vec());
+        self.segments.insert(merge_start, merged.freeze());
 
         // Time complexity:
         // Worse case: O(k log n + k * m)
         //      where k = number of overlapping segments and m = avg segment size
         // Avg case: O(log n)
         //      when most segments arrive in-order
-        Ok(())
+        Ok(accepted)
+    }
+
+    /// Evict the segments furthest from `next_byte_idx` until a new segment starting at
+    /// `first_idx` fits under `max_segments`. Returns false if the new segment should be dropped.
+    fn make_room_for(&mut self, first_idx: usize) -> bool {
+        let Some(max_segments) = self.max_segments else {
+            return true;
+        };
+
+        while self.segments.len() >= max_segments {
+            match self.segments.last_key_value() {
+                Some((&last_start, last)) if last_start > first_idx => {
+                    let bytes = last.len();
+                    self.segments.remove(&last_start);
+                    self.segments_dropped += 1;
+                    self.on_pressure.fire(PressureEvent::PendingEvicted { bytes });
+                }
+                _ => {
+                    self.segments_dropped += 1;
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Copy the part of the segment that fits within the window into the ring buffer.
+    /// Returns the number of bytes not already buffered.
+    fn insert_ring(&mut self, first_idx: usize, data: &[u8]) -> usize {
+        let window_end = self.window_end();
+        let Some(ring) = &mut self.ring else {
+            return 0;
+        };
+
+        let buffer_start = first_idx.max(self.next_byte_idx);
+        let buffer_end = (first_idx + data.len()).min(window_end);
+
+        if buffer_start >= buffer_end {
+            return 0; // Already assembled, or no capacity to buffer
+        }
+
+        let (accepted, inconsistent) =
+            ring.insert(buffer_start, &data[buffer_start - first_idx..buffer_end - first_idx]);
+        self.inconsistent_bytes += inconsistent as u64;
+
+        // Only a segment touching no present range adds one, so it's the only way over the cap.
+        // Like the tree backend, evict the ranges furthest from `next_byte_idx` first
+        let Some(max_segments) = self.max_segments else {
+            return accepted;
+        };
+        while ring.ranges() > max_segments {
+            let Some(last) = ring.pop_last() else {
+                break;
+            };
+            self.segments_dropped += 1;
+            if last.start == buffer_start {
+                return 0; // The new segment is the furthest out, so it's the one dropped
+            }
+            self.on_pressure.fire(PressureEvent::PendingEvicted { bytes: last.len() });
+        }
+        accepted
+    }
+
+    /// One past the highest byte index that fits in the output's remaining capacity
+    fn window_end(&self) -> usize {
+        let capacity = self.output.remaining_capacity();
+        let capacity = self.ring.as_ref().map_or(capacity, |ring| capacity.min(ring.capacity()));
+        self.next_byte_idx + capacity
     }
 
     /// Write contiguous data from the buffer to the output `ByteStream`
     fn write_output(&mut self) -> io::Result<()> {
+        if let Some(ring) = &mut self.ring {
+            // Touching ranges are merged, so a single contiguous prefix is all there is
+            self.next_byte_idx += ring.write_prefix(self.next_byte_idx, &mut self.output)?;
+            if self.is_done() {
+                self.output.close();
+            }
+            return Ok(());
+        }
+
         while let Some(mut data) = self.segments.remove(&self.next_byte_idx) {
             let n = self.output.write(&data)?;
 
@@ -195,18 +498,32 @@ mod tests {
     use rand::seq::SliceRandom;
     use rand::{Rng, RngCore};
     use std::io::Read;
+    use std::sync::{Arc, Mutex};
 
     fn create_reassembler(capacity: usize) -> Reassembler {
         let stream = ByteStream::new(capacity);
         Reassembler::new(stream)
     }
 
+    fn create_ring_reassembler(capacity: usize) -> Reassembler {
+        let stream = ByteStream::new(capacity);
+        Reassembler::with_ring_buffer(stream)
+    }
+
     fn read_all_as_string(reassembler: &mut Reassembler) -> String {
         let mut buf = vec![];
         reassembler.read_to_end(&mut buf).unwrap();
         std::str::from_utf8(&buf).unwrap().to_owned()
     }
 
+    /// Install a pressure hook that records every event
+    fn record_pressure(reassembler: &mut Reassembler) -> Arc<Mutex<Vec<PressureEvent>>> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        reassembler.set_on_pressure(Some(Box::new(move |event| sink.lock().unwrap().push(event))));
+        events
+    }
+
     // -- Test insert and capacity --
 
     #[test]
@@ -253,14 +570,15 @@ mod tests {
     #[test]
     fn test_insert_beyond_capacity() {
         let mut ra = create_reassembler(5);
+        let events = record_pressure(&mut ra);
 
         // Insert first
-        ra.insert(0, b"Hello", false).unwrap();
+        assert_eq!(ra.insert(0, b"Hello", false).unwrap(), 5);
         assert_eq!(ra.output.bytes_written(), 5);
         assert_eq!(ra.bytes_pending(), 0);
 
         // Insert second; no-op because capacity exceeded
-        ra.insert(5, b"World", true).unwrap();
+        assert_eq!(ra.insert(5, b"World", true).unwrap(), 0);
         assert_eq!(ra.output.bytes_written(), 5);
         assert_eq!(ra.bytes_pending(), 0);
 
@@ -269,7 +587,7 @@ mod tests {
         assert_eq!("Hello", actual);
 
         // Insert third; success
-        ra.insert(5, b"World", true).unwrap();
+        assert_eq!(ra.insert(5, b"World", true).unwrap(), 5);
         assert_eq!(ra.output.bytes_written(), 10);
         assert_eq!(ra.bytes_pending(), 0);
 
@@ -278,19 +596,22 @@ mod tests {
         assert_eq!("World", actual);
 
         assert!(ra.output.eof());
+        let expected = vec![PressureEvent::InsertTrimmed { dropped_low: 0, dropped_high: 5 }];
+        assert_eq!(*events.lock().unwrap(), expected);
     }
 
     #[test]
     fn test_capacity_overlapping_inserts() {
         let mut ra = create_reassembler(1);
+        let events = record_pressure(&mut ra);
 
-        // Insert first
-        ra.insert(0, b"ab", false).unwrap();
+        // Insert first; truncated by capacity
+        assert_eq!(ra.insert(0, b"ab", false).unwrap(), 1);
         assert_eq!(ra.output.bytes_written(), 1);
         assert_eq!(ra.bytes_pending(), 0);
 
         // Insert second; no-op because capacity exceeded
-        ra.insert(0, b"ab", false).unwrap();
+        assert_eq!(ra.insert(0, b"ab", false).unwrap(), 0);
         assert_eq!(ra.output.bytes_written(), 1);
         assert_eq!(ra.bytes_pending(), 0);
 
@@ -299,8 +620,8 @@ mod tests {
         assert_eq!(ra.output.bytes_read(), 1);
         assert_eq!("a", actual);
 
-        // Insert third
-        ra.insert(0, b"abc", false).unwrap();
+        // Insert third; only "b" is new and fits
+        assert_eq!(ra.insert(0, b"abc", false).unwrap(), 1);
         assert_eq!(ra.output.bytes_written(), 2);
         assert_eq!(ra.bytes_pending(), 0);
 
@@ -308,21 +629,31 @@ mod tests {
         let actual = read_all_as_string(&mut ra);
         assert_eq!(ra.output.bytes_read(), 2);
         assert_eq!("b", actual);
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                PressureEvent::InsertTrimmed { dropped_low: 0, dropped_high: 1 },
+                PressureEvent::InsertTrimmed { dropped_low: 1, dropped_high: 1 },
+                PressureEvent::InsertTrimmed { dropped_low: 1, dropped_high: 1 },
+            ]
+        );
     }
 
     #[test]
     fn test_insert_beyond_capacity_with_different_data() {
         let mut ra = create_reassembler(2);
+        let events = record_pressure(&mut ra);
 
-        ra.insert(1, b"b", false).unwrap();
+        assert_eq!(ra.insert(1, b"b", false).unwrap(), 1);
         assert_eq!(ra.output.bytes_written(), 0);
         assert_eq!(ra.bytes_pending(), 1);
 
-        ra.insert(2, b"bX", false).unwrap();
+        assert_eq!(ra.insert(2, b"bX", false).unwrap(), 0);
         assert_eq!(ra.output.bytes_written(), 0);
         assert_eq!(ra.bytes_pending(), 1);
 
-        ra.insert(0, b"a", false).unwrap();
+        assert_eq!(ra.insert(0, b"a", false).unwrap(), 1);
         assert_eq!(ra.output.bytes_written(), 2);
         assert_eq!(ra.bytes_pending(), 0);
         let actual = read_all_as_string(&mut ra);
@@ -333,6 +664,14 @@ mod tests {
         assert_eq!(ra.bytes_pending(), 0);
         let actual = read_all_as_string(&mut ra);
         assert_eq!("c", actual);
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                PressureEvent::InsertTrimmed { dropped_low: 0, dropped_high: 2 },
+                PressureEvent::InsertTrimmed { dropped_low: 1, dropped_high: 0 },
+            ]
+        );
     }
 
     #[test]
@@ -377,6 +716,236 @@ mod tests {
         assert_eq!("", actual);
     }
 
+    // -- Test output access --
+
+    #[test]
+    fn test_into_output_after_eof() {
+        let mut ra = create_reassembler(32);
+        ra.insert(4, b"efgh", true).unwrap();
+        ra.insert(0, b"abcd", false).unwrap();
+        assert!(ra.get_output().is_closed());
+
+        // Move the stream out and keep reading from it
+        let mut stream = ra.into_output();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"abcd");
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"efgh");
+        assert!(stream.eof());
+    }
+
+    #[test]
+    fn test_output_mut() {
+        let mut ra = create_reassembler(32);
+        ra.insert(0, b"abcd", false).unwrap();
+
+        assert_eq!(ra.output_mut().pop_output(2), 2);
+        assert_eq!("cd", read_all_as_string(&mut ra));
+        assert_eq!(ra.get_output().bytes_read(), 4);
+    }
+
+    #[test]
+    fn test_split_output() {
+        let (writer, mut reader) = ByteStream::new(32).split();
+        let mut ra = Reassembler::new(writer);
+        ra.insert(3, b"def", true).unwrap();
+        ra.insert(0, b"abc", false).unwrap();
+
+        let mut out = String::new();
+        reader.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "abcdef");
+        assert!(reader.eof());
+    }
+
+    // -- Test abort --
+
+    #[test]
+    fn test_abort() {
+        for mut ra in [create_reassembler(32), create_ring_reassembler(32)] {
+            ra.insert(0, b"abcd", false).unwrap();
+            ra.insert(8, b"ijkl", false).unwrap();
+            ra.abort(io::Error::new(io::ErrorKind::ConnectionReset, "reset"));
+            assert_eq!(ra.bytes_pending(), 0);
+
+            // Late segments are ignored
+            assert_eq!(ra.insert(4, b"efgh", false).unwrap(), 0);
+
+            let mut buf = [0u8; 4];
+            ra.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"abcd");
+            let err = ra.read(&mut buf).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        }
+    }
+
+    // -- Test conflicting overlaps --
+
+    #[test]
+    fn test_conflicting_overlap_first_received_wins() {
+        for mut ra in [create_reassembler(32), create_ring_reassembler(32)] {
+            ra.insert(2, b"cdef", false).unwrap();
+            ra.insert(8, b"ij", false).unwrap();
+
+            // Differs at 3 (D), 4 (E) and 9 (J). Bytes 6..8 are new
+            assert_eq!(ra.insert(1, b"bcDEfghiJ", false).unwrap(), 3);
+            assert_eq!(ra.inconsistent_bytes(), 3);
+
+            ra.insert(0, b"a", false).unwrap();
+            assert_eq!("abcdefghij", read_all_as_string(&mut ra));
+        }
+    }
+
+    #[test]
+    fn test_consistent_overlap_not_counted() {
+        for mut ra in [create_reassembler(32), create_ring_reassembler(32)] {
+            ra.insert(2, b"cdef", false).unwrap();
+            ra.insert(0, b"abcdefg", false).unwrap();
+            assert_eq!(ra.inconsistent_bytes(), 0);
+            assert_eq!("abcdefg", read_all_as_string(&mut ra));
+        }
+    }
+
+    #[test]
+    fn test_strict_mode_aborts_on_inconsistency() {
+        for mut ra in [create_reassembler(32), create_ring_reassembler(32)] {
+            ra.set_strict(true);
+            ra.insert(0, b"ab", false).unwrap();
+            ra.insert(4, b"ef", false).unwrap();
+
+            // A consistent overlap is fine in strict mode
+            ra.insert(4, b"efg", false).unwrap();
+
+            let err = ra.insert(3, b"dX", false).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(ra.bytes_pending(), 0);
+
+            let mut buf = [0u8; 2];
+            ra.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"ab");
+            assert!(ra.read(&mut buf).is_err());
+        }
+    }
+
+    // -- Test segment limits --
+
+    /// A tree and a ring reassembler, both capped at `max_segments`
+    fn limited_reassemblers(capacity: usize, max_segments: usize) -> [Reassembler; 2] {
+        let mut ring = create_ring_reassembler(capacity);
+        ring.limit_segments(max_segments);
+        [Reassembler::with_limits(ByteStream::new(capacity), max_segments), ring]
+    }
+
+    #[test]
+    fn test_limits_many_tiny_segments() {
+        for mut ra in limited_reassemblers(65536, 64) {
+            // 10k 1-byte segments at odd offsets, none of which touch
+            for i in 0..10_000 {
+                ra.insert(2 * i + 1, b"x", false).unwrap();
+                assert!(ra.segments_pending() <= 64);
+            }
+            assert_eq!(ra.segments_pending(), 64);
+            assert_eq!(ra.bytes_pending(), 64);
+            assert_eq!(ra.segments_dropped(), 10_000 - 64);
+
+            // The survivors are the ones closest to `next_byte_idx`
+            assert_eq!(ra.gaps().last(), Some(&(126..127)));
+        }
+    }
+
+    #[test]
+    fn test_limits_adjacent_segments_count_once() {
+        for mut ra in limited_reassemblers(4096, 2) {
+            for i in 1..1000 {
+                assert_eq!(ra.insert(i, b"x", false).unwrap(), 1);
+            }
+            assert_eq!(ra.segments_pending(), 1);
+            assert_eq!(ra.segments_dropped(), 0);
+            assert_eq!(ra.gaps(), vec![0..1]);
+        }
+    }
+
+    #[test]
+    fn test_limits_evicts_furthest_first() {
+        for ra in limited_reassemblers(64, 2) {
+            check_evicts_furthest_first(ra);
+        }
+    }
+
+    fn check_evicts_furthest_first(mut ra: Reassembler) {
+        let events = record_pressure(&mut ra);
+        assert_eq!(ra.insert(10, b"kl", false).unwrap(), 2);
+        assert_eq!(ra.insert(20, b"uv", false).unwrap(), 2);
+
+        // A closer segment evicts the one at 20
+        assert_eq!(ra.insert(4, b"ef", false).unwrap(), 2);
+        assert_eq!(ra.gaps(), vec![0..4, 6..10]);
+
+        // A further segment is dropped instead
+        assert_eq!(ra.insert(30, b"ef", false).unwrap(), 0);
+        assert_eq!(ra.segments_pending(), 2);
+
+        // Overlapping existing segments merges them, which never counts against the cap
+        assert_eq!(ra.insert(5, b"fghijk", false).unwrap(), 4);
+        assert_eq!(ra.segments_pending(), 1);
+
+        ra.insert(0, b"abcd", false).unwrap();
+        assert_eq!("abcdefghijkl", read_all_as_string(&mut ra));
+
+        // Only the segment at 20 was evicted. The dropped new segment was never pending
+        assert_eq!(*events.lock().unwrap(), vec![PressureEvent::PendingEvicted { bytes: 2 }]);
+    }
+
+    #[test]
+    fn test_limits_bytes_bounded_by_capacity() {
+        let mut ra = Reassembler::with_limits(ByteStream::new(100), 1000);
+        for i in 0..10_000 {
+            ra.insert(2 * i + 1, b"x", false).unwrap();
+        }
+        assert!(ra.bytes_pending() <= 100);
+        assert_eq!(ra.segments_pending(), 50);
+    }
+
+    // -- Test zero-copy inserts --
+
+    #[test]
+    fn test_insert_bytes_pending_is_not_copied() {
+        let mut ra = create_reassembler(32);
+        let data = Bytes::from_static(b"efgh");
+
+        ra.insert_bytes(4, data.clone(), false).unwrap();
+        assert_eq!(ra.segments[&4].as_ptr(), data.as_ptr());
+    }
+
+    #[test]
+    fn test_insert_bytes_trimmed_is_not_copied() {
+        let mut ra = create_reassembler(8);
+        ra.insert(0, b"ab", false).unwrap();
+
+        // Trimmed to the 4 bytes that still fit within capacity
+        let data = Bytes::from(b"efghijklmnop".to_vec());
+        ra.insert_bytes(4, data.clone(), false).unwrap();
+        assert_eq!(ra.bytes_pending(), 4);
+        assert_eq!(ra.segments[&4].as_ptr(), data.as_ptr());
+        assert_eq!(&ra.segments[&4][..], b"efgh");
+
+        ra.insert(0, b"abcd", false).unwrap();
+        let actual = read_all_as_string(&mut ra);
+        assert_eq!("abcdefgh", actual);
+    }
+
+    #[test]
+    fn test_insert_bytes_overlap_is_merged() {
+        let mut ra = create_reassembler(32);
+        let first = Bytes::from_static(b"bcd");
+        let second = Bytes::from_static(b"cdef");
+
+        ra.insert_bytes(1, first.clone(), false).unwrap();
+        ra.insert_bytes(2, second.clone(), false).unwrap();
+        assert_eq!(&ra.segments[&1][..], b"bcdef");
+        assert_ne!(ra.segments[&1].as_ptr(), first.as_ptr());
+    }
+
     // -- Test sequential --
 
     #[test]
@@ -477,7 +1046,7 @@ mod tests {
         assert_eq!("abcd", actual);
 
         // Insert duplicate data at index 0
-        ra.insert(0, b"abcd", false).unwrap();
+        assert_eq!(ra.insert(0, b"abcd", false).unwrap(), 0);
         assert_eq!(ra.output.bytes_written(), 8);
         let actual = read_all_as_string(&mut ra);
         assert_eq!("", actual);
@@ -608,6 +1177,39 @@ mod tests {
         assert_eq!("", actual);
     }
 
+    // -- Test gaps --
+
+    #[test]
+    fn test_gaps_empty() {
+        let mut ra = create_reassembler(64);
+        assert!(ra.gaps().is_empty());
+
+        ra.insert(0, b"abcd", false).unwrap();
+        assert!(ra.gaps().is_empty());
+    }
+
+    #[test]
+    fn test_gaps_between_segments() {
+        let mut ra = create_reassembler(64);
+
+        ra.insert(10, &[b'x'; 10], false).unwrap();
+        ra.insert(30, &[b'y'; 10], false).unwrap();
+        assert_eq!(ra.gaps(), vec![0..10, 20..30]);
+
+        ra.insert(0, &[b'z'; 10], false).unwrap();
+        assert_eq!(ra.gaps(), vec![20..30]);
+    }
+
+    #[test]
+    fn test_gaps_adjacent_segments() {
+        let mut ra = create_reassembler(64);
+
+        ra.insert(10, &[b'x'; 10], false).unwrap();
+        ra.insert(20, &[b'y'; 10], false).unwrap();
+        ra.insert(35, &[b'z'; 5], false).unwrap();
+        assert_eq!(ra.gaps(), vec![0..10, 30..35]);
+    }
+
     // -- Test overlapping segments --
 
     #[test]
@@ -715,15 +1317,15 @@ mod tests {
         assert_eq!(ra.output.bytes_written(), 1);
         assert_eq!(ra.bytes_pending(), 7);
 
-        ra.insert(0, b"abcde", false).unwrap();
+        assert_eq!(ra.insert(0, b"abcde", false).unwrap(), 3);
         assert_eq!(ra.output.bytes_written(), 8);
         assert_eq!(ra.bytes_pending(), 3);
 
-        ra.insert(14, b"opqrst", false).unwrap();
+        assert_eq!(ra.insert(14, b"opqrst", false).unwrap(), 3);
         assert_eq!(ra.output.bytes_written(), 8);
         assert_eq!(ra.bytes_pending(), 6);
 
-        ra.insert(14, b"op", false).unwrap();
+        assert_eq!(ra.insert(14, b"op", false).unwrap(), 0);
         assert_eq!(ra.output.bytes_written(), 8);
         assert_eq!(ra.bytes_pending(), 6);
 
@@ -780,4 +1382,124 @@ mod tests {
             assert_eq!(payload, buf);
         }
     }
+
+    // -- Test ring buffer backend --
+
+    #[test]
+    fn test_ring_insert_beyond_capacity() {
+        let mut ra = create_ring_reassembler(5);
+        let events = record_pressure(&mut ra);
+
+        ra.insert(0, b"Hello", false).unwrap();
+        assert_eq!(ra.output.bytes_written(), 5);
+
+        // No-op because capacity exceeded
+        assert_eq!(ra.insert(5, b"World", true).unwrap(), 0);
+        assert_eq!(ra.output.bytes_written(), 5);
+        assert_eq!(ra.bytes_pending(), 0);
+        assert_eq!("Hello", read_all_as_string(&mut ra));
+
+        ra.insert(5, b"World", true).unwrap();
+        assert_eq!(ra.output.bytes_written(), 10);
+        assert_eq!("World", read_all_as_string(&mut ra));
+        assert!(ra.output.eof());
+        let expected = vec![PressureEvent::InsertTrimmed { dropped_low: 0, dropped_high: 5 }];
+        assert_eq!(*events.lock().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_ring_overlap_many_pending() {
+        let mut ra = create_ring_reassembler(32);
+
+        ra.insert(4, b"efgh", false).unwrap();
+        ra.insert(14, b"op", false).unwrap();
+        ra.insert(18, b"s", false).unwrap();
+        assert_eq!(ra.bytes_pending(), 7);
+        assert_eq!(ra.gaps(), vec![0..4, 8..14, 16..18]);
+
+        assert_eq!(ra.insert(0, b"abcde", false).unwrap(), 4);
+        assert_eq!(ra.output.bytes_written(), 8);
+        assert_eq!(ra.bytes_pending(), 3);
+
+        assert_eq!(ra.insert(14, b"opqrst", false).unwrap(), 3);
+        assert_eq!(ra.bytes_pending(), 6);
+
+        ra.insert(8, b"ijklmn", false).unwrap();
+        assert_eq!(ra.output.bytes_written(), 20);
+        assert_eq!(ra.bytes_pending(), 0);
+        assert_eq!("abcdefghijklmnopqrst", read_all_as_string(&mut ra));
+    }
+
+    #[test]
+    fn test_ring_fill_gap_with_last() {
+        let mut ra = create_ring_reassembler(4);
+
+        ra.insert(2, b"cd", true).unwrap();
+        assert_eq!(ra.output.bytes_written(), 0);
+
+        ra.insert(0, b"ab", false).unwrap();
+        assert_eq!("abcd", read_all_as_string(&mut ra));
+        assert!(ra.output.eof());
+    }
+
+    #[test]
+    fn test_ring_wraps_around() {
+        let mut ra = create_ring_reassembler(4);
+
+        for i in 0..10 {
+            // Out-of-order within the window, then fill the hole
+            ra.insert(4 * i + 2, b"cd", false).unwrap();
+            ra.insert(4 * i, b"ab", false).unwrap();
+            assert_eq!("abcd", read_all_as_string(&mut ra));
+        }
+    }
+
+    #[test]
+    fn test_ring_random_shuffle() {
+        let n_reps = 32;
+        let n_segs = 128;
+        let max_seg_len = 2048;
+        let max_offset_shift = 1023; // Maximum shift to introduce overlaps
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..n_reps {
+            let capacity = n_segs * max_seg_len;
+            let mut ra = create_ring_reassembler(capacity);
+
+            let mut segments: Vec<(usize, usize)> = Vec::with_capacity(n_segs);
+            let mut total_len = 0;
+
+            // Generate segments with possible overlaps
+            for _ in 0..n_segs {
+                let seg_len = 1 + rng.gen_range(0..max_seg_len - 1);
+                let shift = total_len.min(1 + rng.gen_range(0..max_offset_shift));
+                let start = total_len - shift;
+                let seg_size = seg_len + shift;
+                segments.push((start, seg_size));
+
+                total_len += seg_len;
+            }
+
+            // Shuffle segments to simulate out of order receives
+            segments.shuffle(&mut rng);
+
+            // Generate random data
+            let mut payload = vec![0u8; total_len];
+            rng.fill_bytes(&mut payload);
+
+            // Insert each shuffled segment into the Reassembler
+            for (start, size) in segments {
+                let slice = &payload[start..(start + size)];
+                let is_last = start + size == total_len;
+                ra.insert(start, slice, is_last)
+                    .expect("Insert into Reassembler failed");
+            }
+
+            // Read out all data
+            let mut buf = vec![];
+            ra.read_to_end(&mut buf).expect("Read to end failed");
+            assert_eq!(payload.len(), buf.len());
+            assert_eq!(payload, buf);
+        }
+    }
 }
@@ -1,33 +1,1021 @@
+use crate::ip::ip_reassembler::IpReassembler;
+use crate::packet;
+use crate::packet::errors::HeaderError;
+use crate::packet::header_ref::TcpHeaderRef;
+use crate::packet::pcap::PacketCapture;
+use crate::tcp::byte_stream::{ByteStream, StreamWrite};
+use crate::tcp::challenge_ack::ChallengeAckLimiter;
+use crate::tcp::config::TcpConfig;
 use crate::tcp::tcp_flags::TcpFlags;
 use crate::tcp::tcp_header::TcpHeader;
-use crate::tcp::reassembler::Reassembler;
+use crate::tcp::reassembler::{Payload, Reassembler};
+use crate::tcp::sender::TcpSender;
+use std::fmt;
 use std::io;
+use std::time::{Instant, SystemTime};
 use crate::tcp::wrap32::Wrap32;
 
 /// The receiver end of the `TcpConnection`
 #[derive(Debug)]
 pub struct TcpReceiver {
-    isn: Wrap32,                // Initial seq number
+    isn: Wrap32,                // Seq number of the first data byte: the peer's ISN plus one
     reassembler: Reassembler,   // Handles TCP segments
+    stats: ReceiverStats,       // Counters for debugging lossy links
+    syn_received: bool,         // Has the peer's SYN been seen?
+    ack_pending: bool,          // A SYN or a challenged segment should be (re-)acknowledged
+    strict: bool,               // Reject conflicting SYNs and inconsistent overlaps with an error
+    challenge_acks: ChallengeAckLimiter,
+    capture: PacketCapture,     // Records every raw packet received, when enabled
+    mss: usize,                 // Sizes the free space a read must open up for a window update
+    last_rx_at: Option<SystemTime>, // Kernel arrival time of the latest packet, when known
+}
+
+/// What to do with an incoming RST (RFC 5961 section 3.2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RstDisposition {
+    Reset,        // Its seq is exactly the next expected one
+    ChallengeAck, // In the window but not exact. A legitimate peer answers with an exact RST
+    Drop,         // Outside the window
+}
+
+/// Receiver-side counters, useful for debugging lossy links
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReceiverStats {
+    pub segments_received: u64,     // Every segment handed to the receiver
+    pub bytes_delivered: u64,       // Bytes written in-order to the output stream
+    pub duplicate_segments: u64,    // Segments that carried no new data
+    pub out_of_order_segments: u64, // Segments buffered ahead of a gap
+    pub bad_checksum_drops: u64,    // Packets dropped because a checksum failed
+    pub out_of_window_drops: u64,   // Segments entirely beyond the receive window
+    pub duplicate_syns: u64,        // Retransmitted SYNs carrying the known ISN
+    pub conflicting_syns: u64,      // SYNs carrying a different ISN, which are dropped
+    pub inconsistent_bytes: u64,    // Overlapping bytes that differed from the buffered copy
+    pub syn_count: u64,
+    pub fin_count: u64,
+    pub rst_resets: u64,                // RSTs that reset the connection
+    pub rst_challenged: u64,            // In-window RSTs answered with a challenge ACK
+    pub rst_out_of_window_drops: u64,   // RSTs outside the window, dropped silently
+    pub challenge_acks_suppressed: u64, // Challenge ACKs withheld by the rate limiter
+    pub syn_challenged: u64,            // SYNs on a synchronized connection, answered with a challenge ACK
+    pub unacceptable_ack_drops: u64,    // Segments whose ACK field was outside what we ever sent
+    pub no_ack_drops: u64,              // Segments without the ACK bit, dropped silently
+    pub segment_cap_drops: u64,         // Out-of-order segments dropped at `max_pending_segments`
+    pub window_updates: u64,            // ACKs requested because a read reopened a small window
 }
 
 impl TcpReceiver {
     pub fn new(isn: Wrap32, reassembler: Reassembler) -> Self {
-        TcpReceiver {
+        Self::with_config(isn, reassembler, &TcpConfig::default())
+    }
+
+    /// New `TcpReceiver` whose reassembler buffers at most `config.max_pending_segments`
+    /// out-of-order segments, on top of any cap it already has
+    pub fn with_config(isn: Wrap32, mut reassembler: Reassembler, config: &TcpConfig) -> Self {
+        reassembler.limit_segments(config.max_pending_segments);
+        let mut receiver = TcpReceiver {
             isn,
             reassembler,
+            stats: ReceiverStats::default(),
+            syn_received: false,
+            ack_pending: false,
+            strict: false,
+            challenge_acks: ChallengeAckLimiter::default(),
+            capture: PacketCapture::default(),
+            mss: config.mss,
+            last_rx_at: None,
+        };
+        receiver.arm_window_update();
+        receiver
+    }
+
+    /// Watch the stream for a read that opens the window to `min(MSS, capacity / 2)` or more, the
+    /// receiver-side silly window avoidance threshold of RFC 1122 section 4.2.3.3. The peer may be
+    /// blocked on our small window with nothing in flight, so no inbound segment would prompt the
+    /// update.
+    fn arm_window_update(&mut self) {
+        let stream = self.reassembler.output_mut();
+        let threshold = self.mss.min(stream.capacity() / 2);
+        stream.set_low_watermark(Some(stream.capacity() - threshold));
+    }
+
+    /// Record every raw packet received from now on, including ones later dropped
+    pub fn set_capture(&mut self, capture: PacketCapture) {
+        self.capture = capture;
+    }
+
+    pub fn capture(&self) -> &PacketCapture {
+        &self.capture
+    }
+
+    /// Cap the challenge ACKs sent per second. Defaults to `DEFAULT_CHALLENGE_ACK_LIMIT`
+    pub fn set_challenge_ack_limit(&mut self, max_per_sec: u32) {
+        self.challenge_acks.set_max_per_sec(max_per_sec);
+    }
+
+    /// The peer's SYN was already handled elsewhere, e.g. by `TcpListener`. Any SYN from now on
+    /// is a retransmission or a conflict
+    pub fn set_syn_received(&mut self) {
+        self.syn_received = true;
+    }
+
+    /// In strict mode a conflicting SYN or an inconsistent overlap makes `recv` return an error,
+    /// so the caller can reset the connection. Otherwise they are dropped and counted.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+        self.reassembler.set_strict(strict);
+    }
+
+    /// Was a SYN or a challenged segment received, or did the application reopen a small window,
+    /// since the last call? The caller should answer with a SYN-ACK or ACK. Retransmitted SYNs set
+    /// this again without touching the stream.
+    pub fn take_ack_pending(&mut self) -> bool {
+        let window_update = self.reassembler.output_mut().take_drained();
+        if window_update {
+            self.stats.window_updates += 1;
         }
+        std::mem::take(&mut self.ack_pending) | window_update
     }
 
     pub fn recv(&mut self, tcph: TcpHeader) -> io::Result<()> {
+        self.recv_segment(tcph.seq_no, tcph.flags, Payload::Shared(tcph.payload))
+    }
+
+    /// Receive a segment borrowed from a packet buffer. Its payload is copied straight into the
+    /// reassembler, so a ring-buffer receiver allocates nothing per segment.
+    pub fn recv_ref(&mut self, tcph: &TcpHeaderRef<'_>) -> io::Result<()> {
+        self.recv_segment(tcph.seq_no(), tcph.flags(), Payload::Borrowed(tcph.payload()))
+    }
+
+    fn recv_segment(&mut self, seq_no: Wrap32, flags: TcpFlags, payload: Payload<'_>) -> io::Result<()> {
         let checkpoint = self.reassembler.next_byte_idx() as u64;
-        let abs_seq_no = tcph.seq_no.unwrap(self.isn, checkpoint);
-        
-        let is_last = tcph.flags.contains(TcpFlags::FIN);
-        self.reassembler.insert(abs_seq_no as usize, &tcph.payload, is_last)
+        let mut abs_seq_no = seq_no.unwrap(self.isn, checkpoint);
+
+        self.stats.segments_received += 1;
+        if flags.contains(TcpFlags::RST) {
+            match self.rst_disposition(abs_seq_no) {
+                RstDisposition::Reset => {
+                    self.stats.rst_resets += 1;
+                    let err = io::Error::new(io::ErrorKind::ConnectionReset, "connection reset by peer");
+                    self.reassembler.abort(err);
+                }
+                RstDisposition::ChallengeAck => {
+                    self.stats.rst_challenged += 1;
+                    self.challenge_ack(Instant::now());
+                }
+                RstDisposition::Drop => self.stats.rst_out_of_window_drops += 1,
+            }
+            return Ok(());
+        }
+        if flags.contains(TcpFlags::SYN) {
+            self.stats.syn_count += 1;
+
+            // The SYN uses up the peer's ISN, so any data it carries is the first byte
+            if !self.syn_received {
+                self.isn = seq_no + Wrap32::new(1);
+            } else if seq_no + Wrap32::new(1) != self.isn {
+                // A SYN with a different ISN is never a retransmission of the one we know
+                self.stats.conflicting_syns += 1;
+                if self.strict {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "SYN with a conflicting ISN"));
+                }
+                return Ok(());
+            } else {
+                self.stats.duplicate_syns += 1;
+            }
+            abs_seq_no = 0;
+            self.syn_received = true;
+            self.ack_pending = true;
+        }
+        if flags.contains(TcpFlags::FIN) {
+            self.stats.fin_count += 1;
+        }
+
+        // Snapshot the reassembler so the outcome of the insert can be classified
+        let next_idx = self.reassembler.next_byte_idx();
+        let window_end = next_idx + self.reassembler.get_output().remaining_capacity();
+        let written = self.reassembler.get_output().bytes_written();
+        let inconsistent = self.reassembler.inconsistent_bytes();
+        let cap_drops = self.reassembler.segments_dropped();
+
+        let is_last = flags.contains(TcpFlags::FIN);
+        let has_payload = !payload.is_empty();
+        let result = self.reassembler.insert_payload(abs_seq_no as usize, payload, is_last);
+        self.stats.inconsistent_bytes += self.reassembler.inconsistent_bytes() - inconsistent;
+        self.stats.segment_cap_drops += self.reassembler.segments_dropped() - cap_drops;
+        let accepted = result?;
+
+        let delivered = self.reassembler.get_output().bytes_written() - written;
+        self.stats.bytes_delivered += delivered as u64;
+
+        if has_payload {
+            let first_idx = abs_seq_no as usize;
+            if first_idx >= window_end {
+                self.stats.out_of_window_drops += 1;
+            } else if accepted == 0 {
+                self.stats.duplicate_segments += 1;
+            } else if first_idx > next_idx {
+                self.stats.out_of_order_segments += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Receive a segment on a synchronized connection, after RFC 5961's checks against blind
+    /// injection. A SYN is answered with a challenge ACK instead of touching the connection, and a
+    /// segment is dropped unless its ACK field lies in `[snd_una - max_window, snd_nxt]` of `sender`.
+    /// `max_window` is the largest window the peer has advertised. A segment without the ACK bit is
+    /// dropped silently, as RFC 793 says, since there's no ACK field to challenge.
+    pub fn recv_established<W: StreamWrite>(
+        &mut self,
+        tcph: TcpHeader,
+        sender: &TcpSender<W>,
+        max_window: u32,
+    ) -> io::Result<()> {
+        if tcph.flags.contains(TcpFlags::RST) {
+            return self.recv(tcph);
+        }
+
+        if tcph.flags.contains(TcpFlags::SYN) {
+            self.stats.segments_received += 1;
+            self.stats.syn_count += 1;
+            self.stats.syn_challenged += 1;
+            self.challenge_ack(Instant::now());
+            return Ok(());
+        }
+
+        let snd_una = sender.first_unacked_seq_no();
+        let snd_nxt = sender.current_seq_no();
+        if !tcph.flags.contains(TcpFlags::ACK) {
+            self.stats.segments_received += 1;
+            self.stats.no_ack_drops += 1;
+            return Ok(());
+        }
+        if !acceptable_ack(tcph.ack_no, snd_una, snd_nxt, max_window) {
+            self.stats.segments_received += 1;
+            self.stats.unacceptable_ack_drops += 1;
+            self.challenge_ack(Instant::now());
+            return Ok(());
+        }
+
+        self.recv(tcph)
+    }
+
+    /// Classify an RST by its absolute seq number. Only an exact match on the next expected byte
+    /// resets the connection, so a blind attacker has to guess one number instead of a window.
+    pub fn rst_disposition(&self, abs_seq_no: u64) -> RstDisposition {
+        let next_idx = self.reassembler.next_byte_idx() as u64;
+        let window_end = next_idx + self.reassembler.get_output().remaining_capacity() as u64;
+
+        if abs_seq_no == next_idx {
+            RstDisposition::Reset
+        } else if abs_seq_no > next_idx && abs_seq_no < window_end {
+            RstDisposition::ChallengeAck
+        } else {
+            RstDisposition::Drop
+        }
+    }
+
+    /// Ask the caller to send an ACK for the current state, unless the rate limit is used up
+    fn challenge_ack(&mut self, now: Instant) {
+        if self.challenge_acks.allow(now) {
+            self.ack_pending = true;
+        } else {
+            self.stats.challenge_acks_suppressed += 1;
+        }
+    }
+
+    /// Parse a raw IP packet and receive its TCP segment. Packets with a bad checksum are
+    /// counted and dropped.
+    pub fn recv_packet(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.capture.record(packet);
+        match packet::unwrap_ref(packet) {
+            Ok((_, tcph)) => self.recv_ref(&tcph),
+            Err(HeaderError::BadChecksum(_)) => {
+                self.stats.bad_checksum_drops += 1;
+                Ok(())
+            }
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+
+    /// Like `recv_packet`, for a packet the kernel stamped as arriving `at`, as returned by
+    /// `rawsocket::recv_packet_ts`. The time is kept for RTT samples, see `last_rx_at`.
+    pub fn recv_packet_at(&mut self, packet: &[u8], at: SystemTime) -> io::Result<()> {
+        self.last_rx_at = Some(at);
+        self.recv_packet(packet)
+    }
+
+    /// When the latest packet given to `recv_packet_at` arrived
+    pub fn last_rx_at(&self) -> Option<SystemTime> {
+        self.last_rx_at
     }
-    
+
+    /// Like `recv_packet`, but IP fragments are buffered in `defrag` until their datagram is
+    /// complete, and the reassembled segment is received.
+    pub fn recv_packet_defragmented(&mut self, packet: &[u8], defrag: &mut IpReassembler) -> io::Result<()> {
+        self.capture.record(packet);
+        let result = defrag
+            .push(packet, Instant::now())
+            .and_then(|datagram| datagram.map(|(iph, segment)| TcpHeader::parse(&segment, &iph)).transpose());
+        match result {
+            Ok(Some(tcph)) => self.recv(tcph),
+            Ok(None) => Ok(()), // Waiting for more fragments
+            Err(HeaderError::BadChecksum(_)) => {
+                self.stats.bad_checksum_drops += 1;
+                Ok(())
+            }
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+
+    /// The window to advertise: the free space in the stream, capped at what fits in the header
+    pub fn window_size(&self) -> u16 {
+        self.reassembler.get_output().remaining_capacity().min(u16::MAX as usize) as u16
+    }
+
+    /// Resize the receive buffer on a live connection. Shrinking never discards buffered bytes.
+    /// A ring-buffer reassembler still only buffers out-of-order bytes up to its original size.
+    /// Returns the recomputed window to advertise.
+    pub fn set_window(&mut self, capacity: usize) -> u16 {
+        self.reassembler.output_mut().set_capacity(capacity);
+        self.arm_window_update();
+        self.window_size()
+    }
+
     pub fn next_expected_seq_no(&self) -> u64 {
         self.reassembler.next_byte_idx() as u64
     }
+
+    /// The on-wire ACK number for the next expected byte. Wraps past zero like any seq number
+    pub fn ack_no(&self) -> Wrap32 {
+        Wrap32::wrap(self.next_expected_seq_no(), self.isn)
+    }
+
+    /// Get the assembled `ByteStream`
+    pub fn stream(&self) -> &ByteStream {
+        self.reassembler.get_output()
+    }
+
+    /// Get mutable access to the assembled `ByteStream`, e.g. to read from it
+    pub fn stream_mut(&mut self) -> &mut ByteStream {
+        self.reassembler.output_mut()
+    }
+
+    /// Consume the receiver and hand the assembled `ByteStream` to the application
+    pub fn into_stream(self) -> ByteStream {
+        self.reassembler.into_output()
+    }
+
+    /// Get the receiver-side counters
+    pub fn stats(&self) -> &ReceiverStats {
+        &self.stats
+    }
+
+    /// Reset all receiver-side counters to zero
+    pub fn reset_stats(&mut self) {
+        self.stats = ReceiverStats::default();
+    }
+}
+
+/// Is `ack_no` in `[snd_una - max_window, snd_nxt]` (RFC 5961 section 5.2)? Compared as offsets
+/// from the lower bound, so the range may straddle the wraparound.
+fn acceptable_ack(ack_no: Wrap32, snd_una: Wrap32, snd_nxt: Wrap32, max_window: u32) -> bool {
+    let lower = snd_una.value().wrapping_sub(max_window);
+    ack_no.value().wrapping_sub(lower) <= snd_nxt.value().wrapping_sub(lower)
+}
+
+impl fmt::Display for ReceiverStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "segments={} delivered={}B duplicate={} out_of_order={} bad_checksum={} \
+            out_of_window={} inconsistent={}B syn={} duplicate_syn={} conflicting_syn={} fin={} \
+            rst={} rst_challenged={} rst_out_of_window={} challenge_acks_suppressed={} \
+            syn_challenged={} unacceptable_ack={} no_ack={} segment_cap={} window_updates={}",
+            self.segments_received,
+            self.bytes_delivered,
+            self.duplicate_segments,
+            self.out_of_order_segments,
+            self.bad_checksum_drops,
+            self.out_of_window_drops,
+            self.inconsistent_bytes,
+            self.syn_count,
+            self.duplicate_syns,
+            self.conflicting_syns,
+            self.fin_count,
+            self.rst_resets,
+            self.rst_challenged,
+            self.rst_out_of_window_drops,
+            self.challenge_acks_suppressed,
+            self.syn_challenged,
+            self.unacceptable_ack_drops,
+            self.no_ack_drops,
+            self.segment_cap_drops,
+            self.window_updates,
+        )
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ip::ip_flags::IpFlags;
+    use crate::ip::ip_header::IpHeader;
+    use crate::packet::test_utils;
+    use std::io::Read;
+
+    fn create_receiver(capacity: usize) -> TcpReceiver {
+        let reassembler = Reassembler::new(ByteStream::new(capacity));
+        TcpReceiver::new(Wrap32::new(0), reassembler)
+    }
+
+    fn segment(seq_no: u32, payload: &[u8], flags: TcpFlags) -> TcpHeader {
+        TcpHeader {
+            seq_no: Wrap32::new(seq_no),
+            flags,
+            payload: payload.to_vec().into(),
+            ..TcpHeader::default()
+        }
+    }
+
+    #[test]
+    fn test_stats_duplicates_and_gaps() {
+        let mut rx = create_receiver(32);
+
+        rx.recv(segment(0, b"abcd", TcpFlags::ACK)).unwrap(); // In order
+        rx.recv(segment(0, b"abcd", TcpFlags::ACK)).unwrap(); // Duplicate of assembled data
+        rx.recv(segment(8, b"ijkl", TcpFlags::ACK)).unwrap(); // Gap at 4..8
+        rx.recv(segment(8, b"ijkl", TcpFlags::ACK)).unwrap(); // Duplicate of buffered data
+        rx.recv(segment(64, b"zz", TcpFlags::ACK)).unwrap(); // Beyond the window
+        rx.recv(segment(4, b"efgh", TcpFlags::ACK)).unwrap(); // Fills the gap
+        rx.recv(segment(12, b"", TcpFlags::FIN)).unwrap(); // Pure FIN
+
+        let stats = rx.stats();
+        assert_eq!(stats.segments_received, 7);
+        assert_eq!(stats.bytes_delivered, 12);
+        assert_eq!(stats.duplicate_segments, 2);
+        assert_eq!(stats.out_of_order_segments, 1);
+        assert_eq!(stats.out_of_window_drops, 1);
+        assert_eq!(stats.bad_checksum_drops, 0);
+        assert_eq!(stats.syn_count, 0);
+        assert_eq!(stats.fin_count, 1);
+    }
+
+    #[test]
+    fn test_set_window() {
+        let mut rx = create_receiver(8);
+        rx.recv(segment(0, b"abcdef", TcpFlags::ACK)).unwrap();
+        assert_eq!(rx.window_size(), 2);
+
+        // Shrinking below what's buffered closes the window without losing data
+        assert_eq!(rx.set_window(4), 0);
+        rx.recv(segment(6, b"gh", TcpFlags::ACK)).unwrap();
+        assert_eq!(rx.stats().out_of_window_drops, 1);
+        assert_eq!(rx.stream().peek_output(8), b"abcdef");
+
+        // Growing opens it again
+        assert_eq!(rx.set_window(100_000), u16::MAX);
+        rx.recv(segment(6, b"gh", TcpFlags::ACK)).unwrap();
+        assert_eq!(rx.stream().peek_output(8), b"abcdefgh");
+    }
+
+    #[test]
+    fn test_read_after_zero_window_requests_window_update() {
+        let mut rx = create_receiver(4096);
+        rx.recv(segment(0, &[7; 4096], TcpFlags::ACK)).unwrap();
+        assert_eq!(rx.window_size(), 0);
+        assert!(!rx.take_ack_pending());
+
+        // The peer is blocked and sends nothing. A small read isn't worth advertising
+        let mut buf = vec![0u8; 4096];
+        rx.stream_mut().read_exact(&mut buf[..100]).unwrap();
+        assert!(!rx.take_ack_pending());
+
+        // Draining the rest is, on the very next poll and only once
+        rx.stream_mut().read_exact(&mut buf[100..]).unwrap();
+        assert!(rx.take_ack_pending());
+        assert_eq!(rx.window_size(), 4096);
+        assert!(!rx.take_ack_pending());
+        assert_eq!(rx.stats().window_updates, 1);
+    }
+
+    #[test]
+    fn test_window_update_threshold_follows_set_window() {
+        // Half of a 1000 byte window is less than an MSS
+        let mut rx = create_receiver(4096);
+        rx.set_window(1000);
+        rx.recv(segment(0, &[7; 1000], TcpFlags::ACK)).unwrap();
+
+        rx.stream_mut().pop_output(499);
+        assert!(!rx.take_ack_pending());
+        rx.stream_mut().pop_output(1);
+        assert!(rx.take_ack_pending());
+    }
+
+    #[test]
+    fn test_read_stream() {
+        let mut rx = create_receiver(32);
+        rx.recv(segment(0, b"hello", TcpFlags::ACK)).unwrap();
+
+        let mut buf = [0u8; 5];
+        rx.stream_mut().read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        rx.recv(segment(5, b"world", TcpFlags::FIN)).unwrap();
+        let mut stream = rx.into_stream();
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "world");
+        assert!(stream.eof());
+    }
+
+    #[test]
+    fn test_rst_aborts_stream() {
+        let mut rx = create_receiver(32);
+        rx.recv(segment(0, b"hello", TcpFlags::ACK)).unwrap();
+        rx.recv(segment(10, b"later", TcpFlags::ACK)).unwrap();
+        rx.recv(segment(5, b"", TcpFlags::RST)).unwrap();
+
+        let mut buf = [0u8; 5];
+        rx.stream_mut().read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+        let err = rx.stream_mut().read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(rx.stats().rst_resets, 1);
+    }
+
+    #[test]
+    fn test_rst_dispositions() {
+        let mut rx = create_receiver(32);
+        rx.recv(segment(0, b"hello", TcpFlags::ACK)).unwrap();
+
+        assert_eq!(rx.rst_disposition(5), RstDisposition::Reset);
+        assert_eq!(rx.rst_disposition(6), RstDisposition::ChallengeAck);
+        assert_eq!(rx.rst_disposition(31), RstDisposition::ChallengeAck);
+        assert_eq!(rx.rst_disposition(32), RstDisposition::Drop); // 5 + 27 free bytes
+        assert_eq!(rx.rst_disposition(4), RstDisposition::Drop);
+    }
+
+    #[test]
+    fn test_in_window_rst_gets_challenge_ack() {
+        let mut rx = create_receiver(32);
+        rx.recv(segment(0, b"hello", TcpFlags::ACK)).unwrap();
+
+        rx.recv(segment(20, b"", TcpFlags::RST)).unwrap();
+        assert!(rx.take_ack_pending());
+        assert_eq!(rx.stats().rst_challenged, 1);
+
+        // The stream is untouched, and the peer's exact RST still resets it
+        rx.recv(segment(5, b"world", TcpFlags::ACK)).unwrap();
+        assert_eq!(rx.stream().peek_output(16), b"helloworld");
+        rx.recv(segment(10, b"", TcpFlags::RST)).unwrap();
+        assert_eq!(rx.stats().rst_resets, 1);
+        let err = rx.stream_mut().read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    }
+
+    #[test]
+    fn test_out_of_window_rst_is_dropped() {
+        let mut rx = create_receiver(32);
+        rx.recv(segment(0, b"hello", TcpFlags::ACK)).unwrap();
+
+        rx.recv(segment(1000, b"", TcpFlags::RST)).unwrap();
+        rx.recv(segment(2, b"", TcpFlags::RST)).unwrap();
+        assert!(!rx.take_ack_pending());
+        assert_eq!(rx.stats().rst_out_of_window_drops, 2);
+        assert_eq!(rx.stats().rst_resets, 0);
+
+        rx.recv(segment(5, b"!", TcpFlags::ACK)).unwrap();
+        assert_eq!(rx.stream().peek_output(8), b"hello!");
+    }
+
+    #[test]
+    fn test_challenge_ack_rate_limit_under_rst_flood() {
+        let mut rx = create_receiver(1024);
+        rx.set_challenge_ack_limit(10);
+
+        let mut acks = 0;
+        for seq_no in 1..=500 {
+            rx.recv(segment(seq_no, b"", TcpFlags::RST)).unwrap();
+            acks += rx.take_ack_pending() as u32;
+        }
+
+        assert_eq!(acks, 10);
+        assert_eq!(rx.stats().rst_challenged, 500);
+        assert_eq!(rx.stats().challenge_acks_suppressed, 490);
+        assert_eq!(rx.stats().rst_resets, 0);
+        assert!(rx.stream().error().is_none());
+    }
+
+    #[test]
+    fn test_stats_inconsistent_overlap() {
+        let mut rx = create_receiver(32);
+        rx.recv(segment(4, b"efgh", TcpFlags::ACK)).unwrap();
+        rx.recv(segment(2, b"cdEFgH", TcpFlags::ACK)).unwrap();
+        assert_eq!(rx.stats().inconsistent_bytes, 3);
+
+        rx.recv(segment(0, b"ab", TcpFlags::ACK)).unwrap();
+        let mut rest = String::new();
+        rx.stream_mut().read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "abcdefgh");
+    }
+
+    #[test]
+    fn test_stats_bad_checksum() {
+        let mut rx = create_receiver(32);
+
+        let ip_bytes = hex::decode(test_utils::get_ip_hex()).unwrap();
+        let mut tcp_bytes = hex::decode(test_utils::get_tcp_hex()).unwrap();
+        tcp_bytes[10] = 0xff; // Corrupt a byte
+        let packet = [ip_bytes, tcp_bytes].concat();
+
+        rx.recv_packet(&packet).unwrap();
+        assert_eq!(rx.stats().bad_checksum_drops, 1);
+        assert_eq!(rx.stats().segments_received, 0);
+    }
+
+    #[test]
+    fn test_recv_packet_at_keeps_arrival_time() {
+        let mut rx = create_receiver(64);
+        assert_eq!(rx.last_rx_at(), None);
+
+        let packet = hex::decode([test_utils::get_ip_hex(), test_utils::get_tcp_hex()].concat()).unwrap();
+        let at = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        rx.recv_packet_at(&packet, at).unwrap();
+        assert_eq!(rx.last_rx_at(), Some(at));
+        assert_eq!(rx.stats().syn_count, 1);
+
+        // Plain `recv_packet` has no kernel time, so the last one stays
+        rx.recv_packet(&packet).unwrap();
+        assert_eq!(rx.last_rx_at(), Some(at));
+    }
+
+    #[test]
+    fn test_recv_packet_defragmented() {
+        let mut rx = create_receiver(4096);
+        let mut defrag = IpReassembler::default();
+
+        let iph = IpHeader { version: 4, ihl: 5, id: 7, flags: IpFlags::empty(), ttl: 64, protocol: 6, ..IpHeader::default() };
+        let data: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+        let tcph = TcpHeader { data_offset: 5, ..segment(0, &data, TcpFlags::ACK) };
+        let fragments = packet::wrap_fragmented(&iph, &tcph, 1500).unwrap();
+        assert_eq!(fragments.len(), 3);
+
+        for i in [1, 2, 0] {
+            assert_eq!(rx.stats().segments_received, 0);
+            rx.recv_packet_defragmented(&fragments[i], &mut defrag).unwrap();
+        }
+        assert_eq!(rx.stats().segments_received, 1);
+        assert_eq!(rx.stream().peek_output(4096), data);
+    }
+
+    #[test]
+    fn test_duplicate_syn_is_idempotent() {
+        let mut rx = create_receiver(32);
+        rx.recv(segment(0, b"", TcpFlags::SYN)).unwrap();
+        assert!(rx.take_ack_pending());
+        assert!(!rx.take_ack_pending());
+
+        rx.recv(segment(1, b"abcd", TcpFlags::ACK)).unwrap();
+        rx.recv(segment(9, b"ij", TcpFlags::ACK)).unwrap();
+
+        // The retransmitted SYN asks for another ACK and leaves the stream alone
+        rx.recv(segment(0, b"", TcpFlags::SYN)).unwrap();
+        assert!(rx.take_ack_pending());
+        assert_eq!(rx.stats().duplicate_syns, 1);
+        assert_eq!(rx.next_expected_seq_no(), 4);
+        assert_eq!(rx.ack_no(), Wrap32::new(5));
+        assert_eq!(rx.stream().buffer_size(), 4);
+        assert_eq!(rx.reassembler.bytes_pending(), 2);
+    }
+
+    #[test]
+    fn test_conflicting_syn_is_dropped() {
+        let mut rx = create_receiver(32);
+        rx.recv(segment(0, b"", TcpFlags::SYN)).unwrap();
+        rx.recv(segment(1, b"abcd", TcpFlags::ACK)).unwrap();
+        rx.take_ack_pending();
+
+        rx.recv(segment(1000, b"wxyz", TcpFlags::SYN)).unwrap();
+        assert!(!rx.take_ack_pending());
+        assert_eq!(rx.stats().conflicting_syns, 1);
+        assert_eq!(rx.stats().duplicate_syns, 0);
+        assert_eq!(rx.next_expected_seq_no(), 4);
+        assert_eq!(rx.stream().peek_output(8), b"abcd");
+    }
+
+    #[test]
+    fn test_first_syn_sets_isn() {
+        let mut rx = create_receiver(32);
+        rx.set_strict(true);
+
+        // Only a SYN after the first one can conflict. The SYN uses up seq 1000 and its data
+        // starts at 1001
+        rx.recv(segment(1000, b"ab", TcpFlags::SYN)).unwrap();
+        assert!(rx.take_ack_pending());
+        assert_eq!(rx.ack_no(), Wrap32::new(1003));
+        rx.recv(segment(1003, b"cd", TcpFlags::ACK)).unwrap();
+        assert_eq!(rx.stream().peek_output(8), b"abcd");
+        assert_eq!(rx.ack_no(), Wrap32::new(1005));
+        assert_eq!((rx.stats().conflicting_syns, rx.stats().duplicate_syns), (0, 0));
+
+        // A retransmission of it is a duplicate whose data is already assembled
+        rx.recv(segment(1000, b"ab", TcpFlags::SYN)).unwrap();
+        assert_eq!(rx.stats().duplicate_syns, 1);
+        assert_eq!(rx.ack_no(), Wrap32::new(1005));
+
+        assert!(rx.recv(segment(0, b"", TcpFlags::SYN)).is_err());
+        assert_eq!(rx.stats().conflicting_syns, 1);
+    }
+
+    #[test]
+    fn test_conflicting_syn_strict() {
+        let mut rx = create_receiver(32);
+        rx.set_strict(true);
+        rx.recv(segment(0, b"", TcpFlags::SYN)).unwrap();
+
+        let err = rx.recv(segment(1000, b"", TcpFlags::SYN)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(rx.stats().conflicting_syns, 1);
+    }
+
+    /// A sender that has sent 100 bytes after `isn`, of which the peer acknowledged 60
+    fn create_sender(isn: u32) -> TcpSender {
+        let mut sender = TcpSender::new(Wrap32::new(isn), ByteStream::new(1024));
+        sender.send(&[0u8; 100]).unwrap();
+        sender.acknowledge(Wrap32::new(isn.wrapping_add(60)));
+        sender
+    }
+
+    fn data_segment(seq_no: u32, ack_no: u32, payload: &[u8]) -> TcpHeader {
+        TcpHeader { ack_no: Wrap32::new(ack_no), ..segment(seq_no, payload, TcpFlags::ACK) }
+    }
+
+    #[test]
+    fn test_acceptable_ack_range() {
+        let (una, nxt) = (Wrap32::new(1060), Wrap32::new(1100));
+        assert!(acceptable_ack(Wrap32::new(1060), una, nxt, 0));
+        assert!(acceptable_ack(Wrap32::new(1100), una, nxt, 0));
+        assert!(!acceptable_ack(Wrap32::new(1101), una, nxt, 0));
+        assert!(!acceptable_ack(Wrap32::new(1059), una, nxt, 0));
+        assert!(acceptable_ack(Wrap32::new(1000), una, nxt, 60));
+        assert!(!acceptable_ack(Wrap32::new(999), una, nxt, 60));
+
+        // The range straddles the wraparound
+        let (una, nxt) = (Wrap32::new(10), Wrap32::new(50));
+        assert!(acceptable_ack(Wrap32::new(u32::MAX - 5), una, nxt, 20));
+        assert!(!acceptable_ack(Wrap32::new(u32::MAX - 15), una, nxt, 20));
+    }
+
+    #[test]
+    fn test_established_spoofed_syn_gets_challenge_ack() {
+        let sender = create_sender(1000);
+        let mut rx = create_receiver(32);
+        rx.recv(segment(0, b"", TcpFlags::SYN)).unwrap();
+        rx.take_ack_pending();
+        rx.recv_established(data_segment(1, 1060, b"hello"), &sender, 0).unwrap();
+
+        // Neither a conflicting nor a duplicate SYN touches the connection
+        for seq_no in [0, 5, 4000] {
+            rx.recv_established(segment(seq_no, b"junk", TcpFlags::SYN), &sender, 0).unwrap();
+            assert!(rx.take_ack_pending());
+        }
+        assert_eq!(rx.stats().syn_challenged, 3);
+        assert_eq!(rx.stats().conflicting_syns, 0);
+        assert_eq!(rx.next_expected_seq_no(), 5);
+        assert_eq!(rx.stream().peek_output(16), b"hello");
+        assert!(rx.stream().error().is_none());
+    }
+
+    #[test]
+    fn test_established_drops_data_with_wild_ack() {
+        let sender = create_sender(1000);
+        let mut rx = create_receiver(32);
+
+        rx.recv_established(data_segment(0, 1060, b"ab"), &sender, 0).unwrap(); // snd_una
+        rx.recv_established(data_segment(2, 1100, b"cd"), &sender, 0).unwrap(); // snd_nxt
+        rx.recv_established(data_segment(4, 1101, b"XX"), &sender, 0).unwrap(); // Acks unsent data
+        rx.recv_established(data_segment(4, 7, b"XX"), &sender, 0).unwrap(); // Way behind
+        rx.recv_established(data_segment(4, 1050, b"XX"), &sender, 0).unwrap(); // Behind snd_una
+        assert_eq!(rx.stats().unacceptable_ack_drops, 3);
+        assert!(rx.take_ack_pending());
+
+        // No ACK field to challenge: dropped without an ACK
+        rx.recv_established(segment(4, b"XX", TcpFlags::empty()), &sender, 0).unwrap();
+        assert_eq!((rx.stats().no_ack_drops, rx.stats().unacceptable_ack_drops), (1, 3));
+        assert!(!rx.take_ack_pending());
+
+        // Old ACKs within the peer's max window are still fine
+        rx.recv_established(data_segment(4, 1050, b"ef"), &sender, 64).unwrap();
+        assert_eq!(rx.stats().unacceptable_ack_drops, 3);
+        assert_eq!(rx.stream().peek_output(16), b"abcdef");
+    }
+
+    #[test]
+    fn test_established_challenge_acks_share_rate_limit() {
+        let sender = create_sender(1000);
+        let mut rx = create_receiver(1024);
+        rx.set_challenge_ack_limit(4);
+
+        let mut acks = 0;
+        for i in 1..=30 {
+            let tcph = match i % 3 {
+                0 => segment(i, b"", TcpFlags::SYN),
+                1 => segment(i, b"", TcpFlags::RST),
+                _ => data_segment(i, 0xdead_beef, b"x"),
+            };
+            rx.recv_established(tcph, &sender, 0).unwrap();
+            acks += rx.take_ack_pending() as u32;
+        }
+
+        assert_eq!(acks, 4);
+        assert_eq!(rx.stats().challenge_acks_suppressed, 26);
+        assert_eq!(rx.stats().syn_challenged, 10);
+        assert_eq!(rx.stats().rst_challenged, 10);
+        assert_eq!(rx.stats().unacceptable_ack_drops, 10);
+        assert_eq!(rx.next_expected_seq_no(), 0);
+    }
+
+    const WRAP_ISN: u32 = 0xFFFF_FFF0;
+
+    fn wrapping_receiver(capacity: usize) -> TcpReceiver {
+        let reassembler = Reassembler::new(ByteStream::new(capacity));
+        TcpReceiver::new(Wrap32::new(WRAP_ISN), reassembler)
+    }
+
+    #[test]
+    fn test_segment_wraps_seq_space() {
+        // 16 bytes fit before u32::MAX, the other 48 land after zero
+        let payload: Vec<u8> = (0..64).collect();
+        let mut rx = wrapping_receiver(128);
+        rx.recv(segment(WRAP_ISN, &payload, TcpFlags::ACK)).unwrap();
+
+        assert_eq!(rx.stream().peek_output(64), payload);
+        assert_eq!(rx.next_expected_seq_no(), 64);
+        assert_eq!(rx.ack_no(), Wrap32::new(0x30));
+        assert_eq!(rx.stats().out_of_order_segments, 0);
+
+        // A retransmission straddling the wrap is a pure duplicate
+        rx.recv(segment(WRAP_ISN + 8, &payload[8..40], TcpFlags::ACK)).unwrap();
+        assert_eq!(rx.stats().duplicate_segments, 1);
+        assert_eq!(rx.ack_no(), Wrap32::new(0x30));
+    }
+
+    #[test]
+    fn test_wrapped_segments_out_of_order() {
+        let payload: Vec<u8> = (0..64).collect();
+        let mut rx = wrapping_receiver(128);
+
+        // The half after the wrap arrives first and waits at index 32
+        rx.recv(segment(0x10, &payload[32..], TcpFlags::FIN)).unwrap();
+        assert_eq!(rx.stats().out_of_order_segments, 1);
+        assert_eq!(rx.ack_no(), Wrap32::new(WRAP_ISN));
+
+        rx.recv(segment(WRAP_ISN, &payload[..32], TcpFlags::ACK)).unwrap();
+        assert_eq!(rx.stream().peek_output(64), payload);
+        assert!(rx.stream().is_closed());
+        assert_eq!(rx.ack_no(), Wrap32::new(0x30));
+    }
+
+    #[test]
+    fn test_wrapped_segment_trimmed_to_window() {
+        let payload: Vec<u8> = (0..64).collect();
+        let mut rx = wrapping_receiver(40);
+
+        // Only the first 40 bytes fit, ending 24 bytes past zero
+        rx.recv(segment(WRAP_ISN, &payload, TcpFlags::ACK)).unwrap();
+        assert_eq!(rx.stream().peek_output(64), &payload[..40]);
+        assert_eq!(rx.ack_no(), Wrap32::new(0x18));
+        assert_eq!(rx.window_size(), 0);
+    }
+
+    /// An in-memory capture file that the test can read back while the capture still holds it
+    #[derive(Clone, Default)]
+    struct SharedBuf(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_capture_both_directions() {
+        use crate::packet::pcap::PcapReader;
+
+        let file = SharedBuf::default();
+        let capture = PacketCapture::to_writer(file.clone()).unwrap();
+        let mut sender = TcpSender::new(Wrap32::new(0), ByteStream::new(1024));
+        let mut rx = create_receiver(1024);
+        sender.set_capture(capture.clone());
+        rx.set_capture(capture);
+
+        // Move three segments from the sender to the receiver, plus one corrupted copy
+        let mut sent = Vec::new();
+        for (seq_no, payload) in [(0, b"abc"), (3, b"def"), (6, b"ghi")] {
+            let tcph = TcpHeader { data_offset: 5, ..segment(seq_no, payload, TcpFlags::ACK) };
+            let packet = sender.build_packet(&tcph).unwrap();
+            rx.recv_packet(&packet).unwrap();
+            sent.push(packet);
+        }
+        let mut corrupted = sent[0].clone();
+        *corrupted.last_mut().unwrap() ^= 0xff;
+        rx.recv_packet(&corrupted).unwrap();
+
+        assert_eq!(rx.stream().peek_output(9), b"abcdefghi");
+        assert_eq!(rx.capture().capture_count(), 7);
+        assert_eq!(sender.capture().capture_count(), 7);
+
+        // Each packet shows up once on the way out and once on the way in
+        rx.capture().flush().unwrap();
+        let bytes = file.0.lock().unwrap().clone();
+        let mut reader = PcapReader::new(io::Cursor::new(bytes)).unwrap();
+        let mut records = Vec::new();
+        while let Some((_, record)) = reader.next_record().unwrap() {
+            records.push(record.to_vec());
+        }
+        let expected = [&sent[0], &sent[0], &sent[1], &sent[1], &sent[2], &sent[2], &corrupted];
+        assert_eq!(records.iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn test_capture_disabled_by_default() {
+        let mut rx = create_receiver(32);
+        rx.recv_packet(&hex::decode([test_utils::get_ip_hex(), test_utils::get_tcp_hex()].concat()).unwrap()).unwrap();
+        assert!(!rx.capture().is_enabled());
+        assert_eq!(rx.capture().capture_count(), 0);
+    }
+
+    #[test]
+    fn test_segment_cap_against_tiny_segment_flood() {
+        let config = TcpConfig { max_pending_segments: 1024, ..TcpConfig::default() };
+        let reassembler = Reassembler::new(ByteStream::new(1 << 16));
+        let mut rx = TcpReceiver::with_config(Wrap32::new(0), reassembler, &config);
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+
+        // 10k 1-byte segments at alternating indexes, none of which coalesce
+        for i in (1..data.len()).step_by(2) {
+            rx.recv(segment(i as u32, &data[i..i + 1], TcpFlags::ACK)).unwrap();
+            assert!(rx.reassembler.segments_pending() <= 1024);
+        }
+        assert_eq!(rx.reassembler.segments_pending(), 1024);
+        assert_eq!(rx.reassembler.bytes_pending(), 1024);
+        assert_eq!(rx.stats().segment_cap_drops, 10_000 - 1024);
+        assert_eq!(rx.ack_no(), Wrap32::new(0)); // Still acking rcv_nxt
+
+        // Legitimate in-order data still completes the stream
+        for (i, chunk) in data.chunks(1000).enumerate() {
+            rx.recv(segment((i * 1000) as u32, chunk, TcpFlags::ACK)).unwrap();
+        }
+        rx.recv(segment(data.len() as u32, b"", TcpFlags::FIN)).unwrap();
+
+        let mut out = Vec::new();
+        rx.stream_mut().read_to_end(&mut out).unwrap();
+        assert_eq!(out, data);
+        assert_eq!(rx.reassembler.segments_pending(), 0);
+    }
+
+    #[test]
+    fn test_segment_cap_merges_adjacent_segments() {
+        let mut rx = create_receiver(4096);
+
+        // 1999 adjacent bytes behind a gap at 0 are one pending segment, far under the cap
+        for i in 1..2000 {
+            rx.recv(segment(i, b"x", TcpFlags::ACK)).unwrap();
+        }
+        assert_eq!(rx.reassembler.segments_pending(), 1);
+        assert_eq!(rx.stats().segment_cap_drops, 0);
+
+        rx.recv(segment(0, b"x", TcpFlags::ACK)).unwrap();
+        assert_eq!(rx.stream().buffer_size(), 2000);
+    }
+
+    #[test]
+    fn test_segment_cap_keeps_tighter_limit() {
+        let reassembler = Reassembler::with_limits(ByteStream::new(1024), 4);
+        let mut rx = TcpReceiver::new(Wrap32::new(0), reassembler);
+        for i in 0..10 {
+            rx.recv(segment(2 * i + 1, b"x", TcpFlags::ACK)).unwrap();
+        }
+        assert_eq!(rx.reassembler.segments_pending(), 4);
+        assert_eq!(rx.stats().segment_cap_drops, 6);
+    }
+
+    #[test]
+    fn test_stats_reset() {
+        let mut rx = create_receiver(32);
+        rx.recv(segment(0, b"", TcpFlags::SYN)).unwrap();
+        assert_eq!(rx.stats().syn_count, 1);
+
+        rx.reset_stats();
+        assert_eq!(*rx.stats(), ReceiverStats::default());
+    }
 }
@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+use std::ops::Range;
+
+/// Fixed-size reassembly storage. Bytes live at `index % capacity` and an interval set
+/// tracks which absolute indexes are present.
+#[derive(Debug)]
+pub struct RingBuffer {
+    buf: Box<[u8]>,
+    ranges: BTreeMap<usize, usize>, // Present byte ranges. key = start index, value = end index
+    pending: usize,                 // Total bytes covered by `ranges`
+}
+
+impl RingBuffer {
+    /// New `RingBuffer` holding at most `capacity` bytes
+    pub fn new(capacity: usize) -> Self {
+        RingBuffer {
+            buf: vec![0u8; capacity].into_boxed_slice(),
+            ranges: BTreeMap::new(),
+            pending: 0,
+        }
+    }
+
+    /// The size of the ring
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// The total number of bytes present in the ring
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    /// The number of disjoint present ranges
+    pub fn ranges(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Discard every present range
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+        self.pending = 0;
+    }
+
+    /// Copy `data` into the ring at absolute index `first_idx`. The caller must ensure the
+    /// whole range lies within one ring length of the lowest pending index.
+    /// Bytes already present are kept (first received wins) and compared against `data`.
+    /// Returns (bytes that were not already present, present bytes that differ from `data`).
+    pub fn insert(&mut self, first_idx: usize, data: &[u8]) -> (usize, usize) {
+        if data.is_empty() {
+            return (0, 0);
+        }
+        let pending_before = self.pending;
+        let last_idx = first_idx + data.len();
+
+        // Only fill the holes between present ranges
+        let mut present: Vec<(usize, usize)> = self
+            .ranges
+            .range(..last_idx)
+            .rev()
+            .take_while(|(_, &end)| end > first_idx)
+            .map(|(&start, &end)| (start.max(first_idx), end.min(last_idx)))
+            .collect();
+        present.reverse();
+
+        let mut cursor = first_idx;
+        let mut inconsistent = 0;
+        for (lo, hi) in present {
+            if cursor < lo {
+                self.copy_in(cursor, &data[cursor - first_idx..lo - first_idx]);
+            }
+            inconsistent += self.count_mismatches(lo, &data[lo - first_idx..hi - first_idx]);
+            cursor = hi;
+        }
+        if cursor < last_idx {
+            self.copy_in(cursor, &data[cursor - first_idx..]);
+        }
+
+        // Merge with every present range that overlaps or touches the new one
+        let mut merge_start = first_idx;
+        let mut merge_end = last_idx;
+        let touching: Vec<(usize, usize)> = self
+            .ranges
+            .range(..=last_idx)
+            .rev()
+            .take_while(|(_, &end)| end >= first_idx)
+            .map(|(&start, &end)| (start, end))
+            .collect();
+
+        for (start, end) in touching {
+            self.ranges.remove(&start);
+            self.pending -= end - start;
+            merge_start = merge_start.min(start);
+            merge_end = merge_end.max(end);
+        }
+
+        self.ranges.insert(merge_start, merge_end);
+        self.pending += merge_end - merge_start;
+        (self.pending - pending_before, inconsistent)
+    }
+
+    /// Discard the highest present range and return it
+    pub fn pop_last(&mut self) -> Option<Range<usize>> {
+        let (start, end) = self.ranges.pop_last()?;
+        self.pending -= end - start;
+        Some(start..end)
+    }
+
+    /// Write the range starting at `next_idx`, if present, into `output`.
+    /// Returns the number of bytes written.
+    pub fn write_prefix(&mut self, next_idx: usize, output: &mut impl Write) -> io::Result<usize> {
+        let Some(end) = self.ranges.remove(&next_idx) else {
+            return Ok(0);
+        };
+
+        let mut written = 0;
+        let mut idx = next_idx;
+        while idx < end {
+            let pos = idx % self.capacity();
+            let chunk_len = (end - idx).min(self.capacity() - pos);
+            let n = output.write(&self.buf[pos..pos + chunk_len])?;
+            written += n;
+            idx += n;
+            if n < chunk_len {
+                break; // Output is full
+            }
+        }
+
+        if idx < end {
+            self.ranges.insert(idx, end);
+        }
+        self.pending -= written;
+        Ok(written)
+    }
+
+    /// The missing byte ranges between `next_idx` and the highest present byte
+    pub fn gaps(&self, next_idx: usize) -> Vec<Range<usize>> {
+        let mut gaps = Vec::new();
+        let mut cursor = next_idx;
+        for (&start, &end) in &self.ranges {
+            if start > cursor {
+                gaps.push(cursor..start);
+            }
+            cursor = cursor.max(end);
+        }
+        gaps
+    }
+
+    /// Count the bytes in the ring starting at `first_idx` that differ from `data`
+    fn count_mismatches(&self, first_idx: usize, data: &[u8]) -> usize {
+        data.iter()
+            .enumerate()
+            .filter(|&(i, &b)| self.buf[(first_idx + i) % self.capacity()] != b)
+            .count()
+    }
+
+    /// Copy `data` into the ring, wrapping around the end if necessary
+    fn copy_in(&mut self, first_idx: usize, data: &[u8]) {
+        let pos = first_idx % self.capacity();
+        let head_len = data.len().min(self.capacity() - pos);
+        self.buf[pos..pos + head_len].copy_from_slice(&data[..head_len]);
+        self.buf[..data.len() - head_len].copy_from_slice(&data[head_len..]);
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tcp::byte_stream::ByteStream;
+    use std::io::Read;
+
+    fn drain(output: &mut ByteStream) -> Vec<u8> {
+        let mut buf = vec![];
+        output.read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_insert_merges_ranges() {
+        let mut ring = RingBuffer::new(16);
+        ring.insert(2, b"cd");
+        ring.insert(6, b"gh");
+        assert_eq!(ring.pending(), 4);
+        assert_eq!(ring.gaps(0), vec![0..2, 4..6]);
+
+        // Touching on both sides
+        ring.insert(4, b"ef");
+        assert_eq!(ring.pending(), 6);
+        assert_eq!(ring.gaps(0), vec![0..2]);
+
+        // Overlapping an existing range
+        assert_eq!(ring.insert(1, b"bcd"), (1, 0));
+        assert_eq!(ring.pending(), 7);
+        assert_eq!(ring.gaps(0), vec![0..1]);
+    }
+
+    #[test]
+    fn test_insert_keeps_first_received() {
+        let mut ring = RingBuffer::new(8);
+        let mut output = ByteStream::new(8);
+
+        ring.insert(2, b"cd");
+        assert_eq!(ring.insert(0, b"abXYe"), (3, 2));
+        ring.write_prefix(0, &mut output).unwrap();
+        assert_eq!(drain(&mut output), b"abcde");
+    }
+
+    #[test]
+    fn test_write_prefix() {
+        let mut ring = RingBuffer::new(8);
+        let mut output = ByteStream::new(8);
+
+        ring.insert(2, b"cd");
+        assert_eq!(ring.write_prefix(0, &mut output).unwrap(), 0);
+
+        ring.insert(0, b"ab");
+        assert_eq!(ring.write_prefix(0, &mut output).unwrap(), 4);
+        assert_eq!(ring.pending(), 0);
+        assert_eq!(drain(&mut output), b"abcd");
+    }
+
+    #[test]
+    fn test_write_prefix_wraps_around() {
+        let mut ring = RingBuffer::new(8);
+        let mut output = ByteStream::new(8);
+
+        ring.insert(0, b"abcdef");
+        assert_eq!(ring.write_prefix(0, &mut output).unwrap(), 6);
+        assert_eq!(drain(&mut output), b"abcdef");
+
+        // Indexes 6..12 occupy ring slots 6, 7, 0, 1, 2, 3
+        ring.insert(6, b"ghijkl");
+        assert_eq!(ring.write_prefix(6, &mut output).unwrap(), 6);
+        assert_eq!(drain(&mut output), b"ghijkl");
+    }
+
+    #[test]
+    fn test_write_prefix_partial() {
+        let mut ring = RingBuffer::new(8);
+        let mut output = ByteStream::new(3);
+
+        ring.insert(0, b"abcde");
+        assert_eq!(ring.write_prefix(0, &mut output).unwrap(), 3);
+        assert_eq!(ring.pending(), 2);
+        assert_eq!(drain(&mut output), b"abc");
+
+        assert_eq!(ring.write_prefix(3, &mut output).unwrap(), 2);
+        assert_eq!(drain(&mut output), b"de");
+    }
+}
@@ -1,31 +1,73 @@
+use bytes::Bytes;
 use std::io;
-use std::io::Write;
+use std::time::{Duration, Instant, SystemTime};
 use crate::ip::ip_header::IpHeader;
 use crate::packet;
-use crate::tcp::byte_stream::ByteStream;
+use crate::packet::errors::HeaderError;
+use crate::packet::pcap::PacketCapture;
+use crate::tcp::byte_stream::{ByteStream, StreamWrite};
+use crate::tcp::config::TcpConfig;
+use crate::tcp::congestion::{self, CcAlgorithm, CongestionControl};
+use crate::tcp::flow_key::FlowKey;
+use crate::tcp::tcp_flags::TcpFlags;
 use crate::tcp::tcp_header::TcpHeader;
+use crate::tcp::timestamps::Timestamps;
 use crate::tcp::wrap32::Wrap32;
 
+/// The largest packet `build_packet_reused` builds: a standard Ethernet MTU
+pub const MAX_PACKET_LEN: usize = 1500;
+
+/// Sender-side counters, for the metrics exporter
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SenderStats {
+    pub segments_sent: u64, // Packets built by `build_packet` and friends
+    pub bytes_sent: u64,    // Payload only, not headers
+}
+
 /// The sender end of the `TcpConnection`
 #[derive(Debug)]
-pub struct TcpSender {
+pub struct TcpSender<W: StreamWrite = ByteStream> {
     isn: Wrap32,            // Initial seq number
     unacked_seq_no: Wrap32, // First unack'ed seq number
     next_seq_no: Wrap32,    // Next seq number to send
-    stream: ByteStream,
+    stream: W,
     reused_tcp: TcpHeader,
     reused_ip: IpHeader,
+    reused_buf: Box<[u8; MAX_PACKET_LEN]>, // Scratch space for `build_packet_reused`
+    mss: usize,
+    cc: Box<dyn CongestionControl>,
+    peer_window: usize, // Last window advertised by the peer
+    ecn_holdoff: usize, // Bytes still to be acked before another ECN echo may cut the window
+    ip_id: u16,         // IP identification for the next packet built
+    timestamps: Timestamps,
+    rtt_probe: Option<(Wrap32, SystemTime)>, // End seq and send time of the data being timed
+    capture: PacketCapture, // Records every packet built, when enabled
+    stats: SenderStats,
 }
 
-impl TcpSender {
-    pub fn new(isn: Wrap32, stream: ByteStream) -> Self {
+impl<W: StreamWrite> TcpSender<W> {
+    pub fn new(isn: Wrap32, stream: W) -> Self {
+        Self::with_config(isn, stream, &TcpConfig::default())
+    }
+
+    pub fn with_config(isn: Wrap32, stream: W, config: &TcpConfig) -> Self {
         TcpSender {
             isn,
             unacked_seq_no: isn,
             next_seq_no: isn,
             stream,
             reused_tcp: TcpHeader::default(),
-            reused_ip: IpHeader::default(),
+            reused_ip: IpHeader { version: 4, ihl: 5, ttl: 64, protocol: 6, ..IpHeader::default() },
+            reused_buf: Box::new([0; MAX_PACKET_LEN]),
+            mss: config.mss,
+            cc: congestion::from_config(config),
+            peer_window: u16::MAX as usize,
+            ecn_holdoff: 0,
+            ip_id: rand::random(),
+            timestamps: Timestamps::new(config.ts_clock.clone()),
+            rtt_probe: None,
+            capture: PacketCapture::default(),
+            stats: SenderStats::default(),
         }
     }
 
@@ -40,11 +82,118 @@ impl TcpSender {
     }
 
     pub fn acknowledge(&mut self, ack_no: Wrap32) {
+        self.acknowledge_at(ack_no, Duration::ZERO, Instant::now());
+    }
+
+    /// Like `acknowledge`, with the latest RTT sample and the arrival time for the congestion
+    /// controller
+    pub fn acknowledge_at(&mut self, ack_no: Wrap32, rtt: Duration, now: Instant) {
         if ack_no > self.unacked_seq_no {
+            let newly_acked = ack_no.value().wrapping_sub(self.unacked_seq_no.value()) as usize;
             self.unacked_seq_no = ack_no;
+            self.ecn_holdoff = self.ecn_holdoff.saturating_sub(newly_acked);
+            self.cc.on_ack(newly_acked, rtt, now);
+        }
+    }
+
+    /// A segment was lost. Shrinks the congestion window unless congestion control is off. The
+    /// RTT probe is abandoned, since the ACK may be for the retransmission (Karn's algorithm)
+    pub fn on_loss(&mut self) {
+        self.rtt_probe = None;
+        self.cc.on_loss(Instant::now());
+    }
+
+    /// Time the data sent so far, which left at `sent_at`. Once it's all acknowledged,
+    /// `rtt_sample` measures the round trip. Only one probe runs at a time, so this does nothing
+    /// while another is outstanding.
+    pub fn start_rtt_probe(&mut self, sent_at: SystemTime) {
+        if self.rtt_probe.is_none() && self.bytes_in_flight() > 0 {
+            self.rtt_probe = Some((self.next_seq_no, sent_at));
+        }
+    }
+
+    /// The RTT sample for an ACK of `ack_no`. With the timestamps option the peer's TSecr
+    /// `ts_ecr` gives one on every ACK. Without it, an ACK that completes the RTT probe gives one
+    /// from the probe's send time and `rx_at`, the kernel's arrival time of the ACK (see
+    /// `TcpReceiver::last_rx_at`).
+    pub fn rtt_sample(&mut self, ack_no: Wrap32, ts_ecr: Option<u32>, rx_at: SystemTime) -> Option<Duration> {
+        let probe = match self.rtt_probe {
+            Some((end, _)) if ack_no >= end => self.rtt_probe.take(),
+            _ => None,
+        };
+        match ts_ecr {
+            Some(ts_ecr) => Some(self.timestamps.rtt_sample(ts_ecr)),
+            None => probe.and_then(|(_, sent_at)| rx_at.duration_since(sent_at).ok()),
+        }
+    }
+
+    /// The peer echoed a CE mark. Reacts at most once per window of data (RFC 3168 section
+    /// 6.1.2). Returns true if the window was cut, so CWR should be sent
+    pub fn on_ecn_echo(&mut self) -> bool {
+        if self.cc.algorithm() == CcAlgorithm::None || self.ecn_holdoff > 0 {
+            return false;
+        }
+        self.cc.on_ecn_echo(Instant::now());
+        self.ecn_holdoff = self.cc.cwnd();
+        true
+    }
+
+    /// Record the window the peer advertised in its latest segment
+    pub fn set_peer_window(&mut self, window: usize) {
+        self.peer_window = window;
+    }
+
+    /// Bytes sent but not yet acknowledged
+    pub fn bytes_in_flight(&self) -> usize {
+        self.next_seq_no.value().wrapping_sub(self.unacked_seq_no.value()) as usize
+    }
+
+    /// How many more bytes may be sent now: the smaller of the congestion and peer windows,
+    /// minus what's in flight
+    pub fn send_window(&self) -> usize {
+        self.cc.cwnd().min(self.peer_window).saturating_sub(self.bytes_in_flight())
+    }
+
+    pub fn cwnd(&self) -> usize {
+        self.cc.cwnd()
+    }
+
+    pub fn congestion_control(&self) -> CcAlgorithm {
+        self.cc.algorithm()
+    }
+
+    pub fn mss(&self) -> usize {
+        self.mss
+    }
+
+    /// Change the largest payload per segment, e.g. to clamp it to the path MTU. Only affects how
+    /// the caller cuts segments from now on; nothing already sent is touched.
+    pub fn set_mss(&mut self, mss: usize) {
+        self.mss = mss;
+    }
+
+    /// TSval generation and PAWS, driven by the configured `TsClock`
+    pub fn timestamps(&mut self) -> &mut Timestamps {
+        &mut self.timestamps
+    }
+
+    pub fn isn(&self) -> Wrap32 {
+        self.isn
+    }
+
+    /// Our SYN or SYN-ACK went out: it uses up the ISN, so data starts one past it
+    pub fn syn_sent(&mut self) {
+        if self.next_seq_no == self.isn {
+            self.next_seq_no = self.isn + Wrap32::new(1);
         }
     }
 
+    /// Our SYN went out and was acknowledged
+    pub fn syn_acked(&mut self) {
+        self.syn_sent();
+        self.acknowledge(self.isn + Wrap32::new(1));
+    }
+
     pub fn current_seq_no(&self) -> Wrap32 {
         self.next_seq_no
     }
@@ -53,8 +202,294 @@ impl TcpSender {
         self.unacked_seq_no
     }
 
+    /// Override the IP identification of the next packet built. It starts at a random value
+    pub fn set_ip_id(&mut self, ip_id: u16) {
+        self.ip_id = ip_id;
+    }
+
+    /// Address the packets this sender builds from `flow.local` to `flow.remote`
+    pub fn set_flow(&mut self, flow: FlowKey) {
+        self.reused_ip.src_ip = *flow.local.ip();
+        self.reused_ip.dst_ip = *flow.remote.ip();
+        self.reused_tcp.src_port = flow.local.port();
+        self.reused_tcp.dst_port = flow.remote.port();
+    }
+
+    /// Wrap `tcph` in an IP packet with the next IP identification
+    pub fn build_packet(&mut self, tcph: &TcpHeader) -> Result<Vec<u8>, HeaderError> {
+        self.next_ip_header(tcph);
+        let packet = packet::wrap(&self.reused_ip, tcph)?;
+        self.capture.record(&packet);
+        self.count_sent(tcph);
+        Ok(packet)
+    }
+
+    /// Like `build_packet`, into a scratch buffer the sender keeps, so nothing is allocated per
+    /// packet. The packet is only valid until the next call. Packets over 1500 bytes are a
+    /// `BufferTooSmall` error.
+    pub fn build_packet_reused(&mut self, tcph: &TcpHeader) -> Result<&[u8], HeaderError> {
+        self.next_ip_header(tcph);
+        let len = packet::wrap_into(&self.reused_ip, tcph, &mut self.reused_buf[..])?;
+        self.capture.record(&self.reused_buf[..len]);
+        self.count_sent(tcph);
+        Ok(&self.reused_buf[..len])
+    }
+
+    /// Build a header-only segment such as a SYN, ACK or RST from the sender's header template,
+    /// into the scratch buffer like `build_packet_reused`. The ports come from `set_flow` and only
+    /// the fields given here change between calls, so nothing is allocated beyond `options`.
+    pub fn build_control_reused(
+        &mut self,
+        seq_no: Wrap32,
+        ack_no: Wrap32,
+        flags: TcpFlags,
+        window: u16,
+        options: Bytes,
+    ) -> Result<&[u8], HeaderError> {
+        let mut tcph = std::mem::take(&mut self.reused_tcp);
+        tcph.seq_no = seq_no;
+        tcph.ack_no = ack_no;
+        tcph.flags = flags;
+        tcph.window = window;
+        tcph.options = options;
+        let len = self.build_packet_reused(&tcph).map(|packet| packet.len());
+        self.reused_tcp = tcph;
+        Ok(&self.reused_buf[..len?])
+    }
+
+    /// Point the reused IP header at a segment like `tcph`, using up the next IP identification
+    fn next_ip_header(&mut self, tcph: &TcpHeader) {
+        let tcp_len = tcph.header_len() + tcph.payload_len();
+        self.reused_ip.id = self.ip_id;
+        self.reused_ip.total_len = (self.reused_ip.header_len() + tcp_len) as u16;
+        self.ip_id = self.ip_id.wrapping_add(1);
+    }
+
+    fn count_sent(&mut self, tcph: &TcpHeader) {
+        self.stats.segments_sent += 1;
+        self.stats.bytes_sent += tcph.payload_len() as u64;
+    }
+
+    /// Get the sender-side counters
+    pub fn stats(&self) -> &SenderStats {
+        &self.stats
+    }
+
+    /// Record every packet built from now on. Share a clone with the receiver to capture both
+    /// directions in one file.
+    pub fn set_capture(&mut self, capture: PacketCapture) {
+        self.capture = capture;
+    }
+
+    pub fn capture(&self) -> &PacketCapture {
+        &self.capture
+    }
+
     pub fn send_syn(&mut self) -> io::Result<()> {
-        let data = packet::wrap(&self.reused_ip, &self.reused_tcp).unwrap();
+        let tcph = std::mem::take(&mut self.reused_tcp);
+        let data = self.build_packet(&tcph);
+        self.reused_tcp = tcph;
+        let data = data.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
         self.send(&data)
     }
-}
\ No newline at end of file
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tcp::tcp_options::TcpOption;
+
+    fn create_sender(initial_cwnd_segments: u8, congestion_control: CcAlgorithm) -> TcpSender {
+        let config = TcpConfig { mss: 1000, initial_cwnd_segments, congestion_control, ..TcpConfig::default() };
+        TcpSender::with_config(Wrap32::new(0), ByteStream::new(1 << 20), &config)
+    }
+
+    /// Send full segments until the window is used up. Returns how many went out
+    fn send_flight(sender: &mut TcpSender) -> usize {
+        let mut segments = 0;
+        while sender.send_window() >= sender.mss() {
+            sender.send(&[0u8; 1000]).unwrap();
+            segments += 1;
+        }
+        segments
+    }
+
+    #[test]
+    fn test_initial_window_first_flight() {
+        for iw in [1, 4, 10] {
+            let mut sender = create_sender(iw, CcAlgorithm::Reno);
+            assert_eq!(send_flight(&mut sender), iw as usize);
+            assert_eq!(sender.bytes_in_flight(), iw as usize * 1000);
+        }
+
+        // The peer's window caps the first flight too
+        let mut sender = create_sender(10, CcAlgorithm::Reno);
+        sender.set_peer_window(3500);
+        assert_eq!(send_flight(&mut sender), 3);
+    }
+
+    #[test]
+    fn test_no_congestion_control_ignores_loss() {
+        let mut sender = create_sender(1, CcAlgorithm::None);
+        sender.set_peer_window(8000);
+
+        // Lose something every round: only the advertised window limits the rate
+        for _ in 0..10 {
+            assert_eq!(send_flight(&mut sender), 8);
+            sender.on_loss();
+            sender.acknowledge(sender.current_seq_no());
+        }
+    }
+
+    #[test]
+    fn test_reno_backs_off_on_loss() {
+        let mut sender = create_sender(4, CcAlgorithm::Reno);
+        sender.set_peer_window(64_000);
+
+        assert_eq!(send_flight(&mut sender), 4);
+        sender.acknowledge(sender.current_seq_no()); // Slow start grows by one MSS per ACK
+        assert_eq!(send_flight(&mut sender), 5);
+
+        sender.on_loss();
+        assert_eq!(sender.cwnd(), 2500);
+
+        // Congestion avoidance: one MSS per window's worth of acked bytes
+        sender.acknowledge(sender.current_seq_no());
+        assert_eq!(sender.cwnd(), 3500);
+        assert_eq!(send_flight(&mut sender), 3);
+    }
+
+    #[test]
+    fn test_rtt_sample_from_rx_timestamps() {
+        let mut sender = create_sender(10, CcAlgorithm::Reno);
+        let sent_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        sender.start_rtt_probe(sent_at); // Nothing in flight to time
+        sender.send(&[0; 2000]).unwrap();
+        sender.start_rtt_probe(sent_at);
+        sender.send(&[0; 1000]).unwrap();
+        sender.start_rtt_probe(sent_at + Duration::from_millis(5)); // Already timing one
+
+        // A partial ACK doesn't complete the probe; the one covering it does, once
+        let rx_at = sent_at + Duration::from_millis(42);
+        assert_eq!(sender.rtt_sample(Wrap32::new(1000), None, rx_at), None);
+        assert_eq!(sender.rtt_sample(Wrap32::new(3000), None, rx_at), Some(Duration::from_millis(42)));
+        assert_eq!(sender.rtt_sample(Wrap32::new(3000), None, rx_at), None);
+
+        // A loss abandons the probe
+        sender.send(&[0; 1000]).unwrap();
+        sender.start_rtt_probe(sent_at);
+        sender.on_loss();
+        assert_eq!(sender.rtt_sample(Wrap32::new(4000), None, rx_at), None);
+    }
+
+    #[test]
+    fn test_rtt_sample_prefers_timestamps_option() {
+        let mut sender = create_sender(10, CcAlgorithm::Reno);
+        let sent_at = SystemTime::now();
+        sender.send(&[0; 1000]).unwrap();
+        sender.start_rtt_probe(sent_at);
+
+        // The TSecr sample wins, and the probe is still used up
+        let TcpOption::Timestamps { val, .. } = sender.timestamps().outgoing() else { unreachable!() };
+        let rtt = sender.rtt_sample(Wrap32::new(1000), Some(val), sent_at + Duration::from_secs(60));
+        assert!(rtt.is_some_and(|rtt| rtt < Duration::from_secs(1)), "{rtt:?}");
+        assert_eq!(sender.rtt_sample(Wrap32::new(1000), None, sent_at + Duration::from_secs(60)), None);
+    }
+
+    #[test]
+    fn test_ecn_echo_once_per_window() {
+        let mut sender = create_sender(8, CcAlgorithm::Reno);
+        assert!(sender.on_ecn_echo());
+        assert_eq!(sender.cwnd(), 4000);
+
+        // Further echoes within the same window are ignored
+        sender.send(&[0u8; 4000]).unwrap();
+        assert!(!sender.on_ecn_echo());
+        sender.acknowledge(Wrap32::new(3000));
+        assert!(!sender.on_ecn_echo());
+        sender.acknowledge(Wrap32::new(4000));
+        assert!(sender.on_ecn_echo());
+
+        assert!(!create_sender(8, CcAlgorithm::None).on_ecn_echo());
+    }
+
+    #[test]
+    fn test_cubic_sender() {
+        let mut sender = create_sender(10, CcAlgorithm::Cubic);
+        assert_eq!(sender.congestion_control(), CcAlgorithm::Cubic);
+        assert_eq!(send_flight(&mut sender), 10);
+
+        sender.on_loss();
+        assert_eq!(sender.cwnd(), 7000);
+    }
+
+    #[test]
+    fn test_ip_id_increments_and_wraps() {
+        let mut sender = create_sender(10, CcAlgorithm::Reno);
+        sender.set_ip_id(u16::MAX - 1);
+
+        let tcph = TcpHeader { data_offset: 5, payload: b"hi".to_vec().into(), ..TcpHeader::default() };
+        let ids: Vec<u16> = (0..3)
+            .map(|_| {
+                let packet = sender.build_packet(&tcph).unwrap();
+                let (iph, parsed) = packet::unwrap(&packet).unwrap();
+                assert_eq!(parsed.payload, &b"hi"[..]);
+                iph.id
+            })
+            .collect();
+        assert_eq!(ids, vec![u16::MAX - 1, u16::MAX, 0]);
+    }
+
+    #[test]
+    fn test_build_packet_reused() {
+        let mut sender = create_sender(10, CcAlgorithm::Reno);
+        sender.set_ip_id(7);
+
+        for i in 0..1000u32 {
+            let payload = vec![i as u8; (i % 1460) as usize];
+            let tcph = TcpHeader { seq_no: Wrap32::new(i), payload: payload.clone().into(), ..TcpHeader::default() };
+            let packet = sender.build_packet_reused(&tcph).unwrap();
+            let (iph, parsed) = packet::unwrap(packet).unwrap();
+            assert_eq!((iph.id, parsed.seq_no), (7 + i as u16, Wrap32::new(i)));
+            assert_eq!(parsed.payload, payload);
+        }
+
+        let payload_bytes: u64 = (0..1000).map(|i| i % 1460).sum();
+        assert_eq!(*sender.stats(), SenderStats { segments_sent: 1000, bytes_sent: payload_bytes });
+
+        // One byte over the scratch buffer
+        let tcph = TcpHeader { payload: vec![0; 1461].into(), ..TcpHeader::default() };
+        let result = sender.build_packet_reused(&tcph);
+        assert!(matches!(result, Err(HeaderError::BufferTooSmall { .. })), "{result:?}");
+        assert_eq!(sender.stats().segments_sent, 1000); // Not counted
+        assert_eq!(sender.build_packet(&tcph).map(|packet| packet.len()), Ok(1501));
+        assert_eq!(*sender.stats(), SenderStats { segments_sent: 1001, bytes_sent: payload_bytes + 1461 });
+    }
+
+    #[test]
+    fn test_build_control_reused_matches_build_packet() {
+        let flow = FlowKey::new("10.0.0.1:50000".parse().unwrap(), "10.0.0.2:80".parse().unwrap());
+        let mut sender = create_sender(10, CcAlgorithm::Reno);
+        sender.set_flow(flow);
+        let options = Bytes::from_static(&[2, 4, 5, 180]); // MSS 1460
+
+        for (i, flags) in [TcpFlags::SYN, TcpFlags::ACK, TcpFlags::RST | TcpFlags::ACK].into_iter().enumerate() {
+            let (seq_no, ack_no) = (Wrap32::new(1000 + i as u32), Wrap32::new(7 * i as u32));
+            let tcph = TcpHeader { seq_no, ack_no, flags, window: 512, options: options.clone(), ..TcpHeader::new(50000, 80) };
+            sender.set_ip_id(9);
+            let expected = sender.build_packet(&tcph).unwrap();
+            sender.set_ip_id(9);
+            assert_eq!(sender.build_control_reused(seq_no, ack_no, flags, 512, options.clone()).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_send_syn_with_default_header() {
+        // The reused header once had data offset 0. It used to panic while serializing, then to
+        // be rejected. Now serializing derives the offset from the options
+        let mut sender = create_sender(10, CcAlgorithm::Reno);
+        assert!(sender.send_syn().is_ok());
+    }
+}
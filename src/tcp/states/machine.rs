@@ -0,0 +1,231 @@
+use crate::tcp::tcp_flags::TcpFlags;
+use std::fmt;
+
+/// The connection states of RFC 793 section 3.2
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynRcvd,
+    SynSent,
+    Established,
+    CloseWait,
+    LastAck,
+    FinWait1,
+    FinWait2,
+    Closing,
+    TimeWait,
+}
+
+impl TcpState {
+    pub const ALL: [TcpState; 11] = [
+        TcpState::Closed,
+        TcpState::Listen,
+        TcpState::SynRcvd,
+        TcpState::SynSent,
+        TcpState::Established,
+        TcpState::CloseWait,
+        TcpState::LastAck,
+        TcpState::FinWait1,
+        TcpState::FinWait2,
+        TcpState::Closing,
+        TcpState::TimeWait,
+    ];
+
+    /// The state `event` moves to, or `None` if the table has no such transition
+    pub fn on(self, event: Event) -> Option<TcpState> {
+        transition(self, event).map(|t| t.to)
+    }
+}
+
+impl fmt::Display for TcpState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TcpState::Closed => "CLOSED",
+            TcpState::Listen => "LISTEN",
+            TcpState::SynRcvd => "SYN-RECEIVED",
+            TcpState::SynSent => "SYN-SENT",
+            TcpState::Established => "ESTABLISHED",
+            TcpState::CloseWait => "CLOSE-WAIT",
+            TcpState::LastAck => "LAST-ACK",
+            TcpState::FinWait1 => "FIN-WAIT-1",
+            TcpState::FinWait2 => "FIN-WAIT-2",
+            TcpState::Closing => "CLOSING",
+            TcpState::TimeWait => "TIME-WAIT",
+        };
+        f.write_str(name)
+    }
+}
+
+/// What drives a transition: a user call, an arriving segment or a timer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Event {
+    Listen,
+    Connect,
+    Close,
+    RecvSyn,
+    RecvSynAck,
+    RecvAck,
+    RecvFin,
+    RecvFinAck, // A FIN that also acknowledges our FIN
+    RecvRst,
+    Timeout,    // 2MSL in TIME-WAIT
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Event::Listen => "listen",
+            Event::Connect => "connect",
+            Event::Close => "close",
+            Event::RecvSyn => "rcv SYN",
+            Event::RecvSynAck => "rcv SYN,ACK",
+            Event::RecvAck => "rcv ACK",
+            Event::RecvFin => "rcv FIN",
+            Event::RecvFinAck => "rcv FIN,ACK",
+            Event::RecvRst => "rcv RST",
+            Event::Timeout => "2MSL timeout",
+        };
+        f.write_str(name)
+    }
+}
+
+/// One edge of the state machine and the flags sent while taking it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub from: TcpState,
+    pub event: Event,
+    pub to: TcpState,
+    pub sends: TcpFlags,
+}
+
+const fn edge(from: TcpState, event: Event, to: TcpState, sends: TcpFlags) -> Transition {
+    Transition { from, event, to, sends }
+}
+
+const NONE: TcpFlags = TcpFlags::empty();
+const SYN_ACK: TcpFlags = TcpFlags::SYN.union(TcpFlags::ACK);
+
+/// Every transition the state machine allows. Both validation and `dot_graph` read this table
+pub static TRANSITIONS: &[Transition] = &[
+    // Opening
+    edge(TcpState::Closed, Event::Listen, TcpState::Listen, NONE),
+    edge(TcpState::Closed, Event::Connect, TcpState::SynSent, TcpFlags::SYN),
+    edge(TcpState::Listen, Event::RecvSyn, TcpState::SynRcvd, SYN_ACK),
+    edge(TcpState::Listen, Event::Connect, TcpState::SynSent, TcpFlags::SYN),
+    edge(TcpState::Listen, Event::Close, TcpState::Closed, NONE),
+    edge(TcpState::SynSent, Event::RecvSynAck, TcpState::Established, TcpFlags::ACK),
+    edge(TcpState::SynSent, Event::RecvSyn, TcpState::SynRcvd, SYN_ACK),
+    edge(TcpState::SynSent, Event::Close, TcpState::Closed, NONE),
+    edge(TcpState::SynRcvd, Event::RecvAck, TcpState::Established, NONE),
+    edge(TcpState::SynRcvd, Event::RecvRst, TcpState::Listen, NONE),
+    edge(TcpState::SynRcvd, Event::Close, TcpState::FinWait1, TcpFlags::FIN),
+    // Passive close
+    edge(TcpState::Established, Event::RecvFin, TcpState::CloseWait, TcpFlags::ACK),
+    edge(TcpState::CloseWait, Event::Close, TcpState::LastAck, TcpFlags::FIN),
+    edge(TcpState::LastAck, Event::RecvAck, TcpState::Closed, NONE),
+    // Active close
+    edge(TcpState::Established, Event::Close, TcpState::FinWait1, TcpFlags::FIN),
+    edge(TcpState::FinWait1, Event::RecvAck, TcpState::FinWait2, NONE),
+    edge(TcpState::FinWait1, Event::RecvFin, TcpState::Closing, TcpFlags::ACK),
+    edge(TcpState::FinWait1, Event::RecvFinAck, TcpState::TimeWait, TcpFlags::ACK),
+    edge(TcpState::FinWait2, Event::RecvFin, TcpState::TimeWait, TcpFlags::ACK),
+    edge(TcpState::Closing, Event::RecvAck, TcpState::TimeWait, NONE),
+    edge(TcpState::TimeWait, Event::Timeout, TcpState::Closed, NONE),
+];
+
+/// Look up the transition for `event` in state `from`
+pub fn transition(from: TcpState, event: Event) -> Option<&'static Transition> {
+    TRANSITIONS.iter().find(|t| t.from == from && t.event == event)
+}
+
+/// A Graphviz DOT description of `TRANSITIONS`. Render with `dot -Tsvg`
+pub fn dot_graph() -> String {
+    let mut dot = String::from("digraph tcp {\n    node [shape=box];\n");
+    for state in TcpState::ALL {
+        dot.push_str(&format!("    {state:?} [label=\"{state}\"];\n"));
+    }
+    for t in TRANSITIONS {
+        let label = if t.sends.is_empty() {
+            t.event.to_string()
+        } else {
+            format!("{} / snd {}", t.event, t.sends)
+        };
+        dot.push_str(&format!("    {:?} -> {:?} [label=\"{label}\"];\n", t.from, t.to));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Walk `events` from `start`, checking each step is in the table and sends the right flags
+    fn walk(start: TcpState, steps: &[(Event, TcpState, TcpFlags)]) {
+        let mut state = start;
+        for &(event, to, sends) in steps {
+            let t = transition(state, event).unwrap_or_else(|| panic!("no transition {state} --{event}-->"));
+            assert_eq!((t.to, t.sends), (to, sends), "{state} --{event}-->");
+            state = to;
+        }
+    }
+
+    #[test]
+    fn test_scenarios_are_in_table() {
+        // Active open and active close
+        walk(TcpState::Closed, &[
+            (Event::Connect, TcpState::SynSent, TcpFlags::SYN),
+            (Event::RecvSynAck, TcpState::Established, TcpFlags::ACK),
+            (Event::Close, TcpState::FinWait1, TcpFlags::FIN),
+            (Event::RecvAck, TcpState::FinWait2, NONE),
+            (Event::RecvFin, TcpState::TimeWait, TcpFlags::ACK),
+            (Event::Timeout, TcpState::Closed, NONE),
+        ]);
+
+        // Passive open and passive close
+        walk(TcpState::Closed, &[
+            (Event::Listen, TcpState::Listen, NONE),
+            (Event::RecvSyn, TcpState::SynRcvd, SYN_ACK),
+            (Event::RecvAck, TcpState::Established, NONE),
+            (Event::RecvFin, TcpState::CloseWait, TcpFlags::ACK),
+            (Event::Close, TcpState::LastAck, TcpFlags::FIN),
+            (Event::RecvAck, TcpState::Closed, NONE),
+        ]);
+
+        // Simultaneous open and simultaneous close
+        walk(TcpState::SynSent, &[
+            (Event::RecvSyn, TcpState::SynRcvd, SYN_ACK),
+            (Event::RecvAck, TcpState::Established, NONE),
+            (Event::Close, TcpState::FinWait1, TcpFlags::FIN),
+            (Event::RecvFin, TcpState::Closing, TcpFlags::ACK),
+            (Event::RecvAck, TcpState::TimeWait, NONE),
+        ]);
+
+        assert_eq!(TcpState::FinWait1.on(Event::RecvFinAck), Some(TcpState::TimeWait));
+        assert_eq!(TcpState::Established.on(Event::RecvSyn), None);
+        assert_eq!(TcpState::Closed.on(Event::RecvAck), None);
+    }
+
+    #[test]
+    fn test_table_is_deterministic() {
+        let mut seen = HashSet::new();
+        for t in TRANSITIONS {
+            assert!(seen.insert((t.from, t.event)), "duplicate {} --{}-->", t.from, t.event);
+        }
+    }
+
+    #[test]
+    fn test_dot_graph() {
+        let dot = dot_graph();
+        assert!(dot.starts_with("digraph tcp {"));
+        assert!(dot.contains("    SynRcvd [label=\"SYN-RECEIVED\"];\n"));
+        assert!(dot.contains("    Closed -> SynSent [label=\"connect / snd SYN\"];\n"));
+        assert!(dot.contains("    Listen -> SynRcvd [label=\"rcv SYN / snd SYN,ACK\"];\n"));
+        assert!(dot.contains("    TimeWait -> Closed [label=\"2MSL timeout\"];\n"));
+        assert_eq!(dot.matches(" -> ").count(), TRANSITIONS.len());
+    }
+}
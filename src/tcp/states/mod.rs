@@ -1,3 +1,6 @@
+// Typestate markers for `TcpConn`. Unused until the state machine is wired up
+#![allow(dead_code)]
+
 mod closed;
 mod listen;
 mod syn_rcvd;
@@ -8,4 +11,12 @@ mod last_ack;
 mod fin_wait1;
 mod fin_wait2;
 mod closing;
-mod time_wait;
\ No newline at end of file
+mod time_wait;
+pub mod machine;
+
+// -- Re-export public structs --
+
+pub use crate::tcp::states::machine::dot_graph;
+pub use crate::tcp::states::machine::Event;
+pub use crate::tcp::states::machine::TcpState;
+pub use crate::tcp::states::machine::TRANSITIONS;
\ No newline at end of file
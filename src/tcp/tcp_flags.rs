@@ -1,8 +1,11 @@
+use crate::packet::errors::ParseFlagsError;
 use bitflags::bitflags;
+use std::fmt;
+use std::str::FromStr;
 
 bitflags! {
     // Bit positions [ CWR, ECE, URG, ACK, PSH, RST, SYN, FIN ]
-    #[derive(Debug, Clone, Copy, PartialEq)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct TcpFlags: u8 {
         const CWR = 1 << 7;
         const ECE = 1 << 6;
@@ -15,10 +18,111 @@ bitflags! {
     }
 }
 
+impl TcpFlags {
+    /// An ECN-setup SYN carries both ECE and CWR (RFC 3168 section 6.1.1)
+    pub fn is_ecn_setup_syn(self) -> bool {
+        self.contains(TcpFlags::SYN | TcpFlags::ECE | TcpFlags::CWR) && !self.contains(TcpFlags::ACK)
+    }
+
+    /// An ECN-setup SYN-ACK carries ECE but not CWR
+    pub fn is_ecn_setup_syn_ack(self) -> bool {
+        self.contains(TcpFlags::SYN | TcpFlags::ACK | TcpFlags::ECE) && !self.contains(TcpFlags::CWR)
+    }
+}
+
+/// Mnemonics in the order they're printed, handshake and teardown flags first
+const DISPLAY_ORDER: [(TcpFlags, &str); 8] = [
+    (TcpFlags::SYN, "SYN"),
+    (TcpFlags::FIN, "FIN"),
+    (TcpFlags::RST, "RST"),
+    (TcpFlags::PSH, "PSH"),
+    (TcpFlags::ACK, "ACK"),
+    (TcpFlags::URG, "URG"),
+    (TcpFlags::ECE, "ECE"),
+    (TcpFlags::CWR, "CWR"),
+];
+
+/// Compact form for logs, e.g. "SYN,ACK". No flags prints as "."
+impl fmt::Display for TcpFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_names(f, DISPLAY_ORDER.iter().filter(|(flag, _)| self.contains(*flag)).map(|(_, name)| *name))
+    }
+}
+
+/// Parse "SYN|ACK", "syn,ack" or "." for none. Case-insensitive
+impl FromStr for TcpFlags {
+    type Err = ParseFlagsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_names(s)
+    }
+}
+
+/// Join flag names with commas, or "." if there are none
+pub(crate) fn write_names<'a>(f: &mut fmt::Formatter<'_>, names: impl Iterator<Item = &'a str>) -> fmt::Result {
+    let mut empty = true;
+    for name in names {
+        if !empty {
+            f.write_str(",")?;
+        }
+        f.write_str(name)?;
+        empty = false;
+    }
+    if empty {
+        f.write_str(".")?;
+    }
+    Ok(())
+}
+
+/// Serialize as a list of names, e.g. `["SYN", "ACK"]`
+#[cfg(feature = "serde")]
+impl serde::Serialize for TcpFlags {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(DISPLAY_ORDER.iter().filter(|(flag, _)| self.contains(*flag)).map(|(_, name)| *name))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for TcpFlags {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_names(deserializer)
+    }
+}
+
+/// Read a list of flag names, rejecting any name that isn't a flag of `B`
+#[cfg(feature = "serde")]
+pub(crate) fn deserialize_names<'de, D, B>(deserializer: D) -> Result<B, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    B: bitflags::Flags,
+{
+    let names: Vec<String> = serde::Deserialize::deserialize(deserializer)?;
+    names.iter().try_fold(B::empty(), |acc, name| match B::from_name(&name.to_ascii_uppercase()) {
+        Some(flag) => Ok(acc.union(flag)),
+        None => Err(serde::de::Error::custom(ParseFlagsError::UnknownFlag(name.clone()))),
+    })
+}
+
+/// Parse flag names separated by '|' or ','. "." or a blank string is no flags
+pub(crate) fn parse_names<B: bitflags::Flags>(s: &str) -> Result<B, ParseFlagsError> {
+    let s = s.trim();
+    if s.is_empty() || s == "." {
+        return Ok(B::empty());
+    }
+    s.split(['|', ',']).try_fold(B::empty(), |acc, token| {
+        let token = token.trim();
+        match B::from_name(&token.to_ascii_uppercase()) {
+            Some(flag) => Ok(acc.union(flag)),
+            None => Err(ParseFlagsError::UnknownFlag(token.to_string())),
+        }
+    })
+}
+
 // -- Unit tests --
 
 #[cfg(test)]
 mod tests {
+    use crate::packet::errors::ParseFlagsError;
     use crate::tcp::tcp_flags::TcpFlags;
 
     #[test]
@@ -42,4 +146,48 @@ mod tests {
             | TcpFlags::CWR;
         assert_eq!(combined.bits(), 0b11111111);
     }
+
+    #[test]
+    fn test_ecn_setup() {
+        let syn = TcpFlags::SYN | TcpFlags::ECE | TcpFlags::CWR;
+        assert!(syn.is_ecn_setup_syn());
+        assert!(!syn.is_ecn_setup_syn_ack());
+        assert!(!(TcpFlags::SYN | TcpFlags::ECE).is_ecn_setup_syn());
+
+        let syn_ack = TcpFlags::SYN | TcpFlags::ACK | TcpFlags::ECE;
+        assert!(syn_ack.is_ecn_setup_syn_ack());
+        assert!(!(syn_ack | TcpFlags::CWR).is_ecn_setup_syn_ack());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(TcpFlags::empty().to_string(), ".");
+        assert_eq!(TcpFlags::SYN.to_string(), "SYN");
+        assert_eq!((TcpFlags::ACK | TcpFlags::SYN).to_string(), "SYN,ACK");
+        assert_eq!((TcpFlags::FIN | TcpFlags::PSH | TcpFlags::ACK).to_string(), "FIN,PSH,ACK");
+        assert_eq!(TcpFlags::all().to_string(), "SYN,FIN,RST,PSH,ACK,URG,ECE,CWR");
+    }
+
+    #[test]
+    fn test_from_str() {
+        let syn_ack = TcpFlags::SYN | TcpFlags::ACK;
+        assert_eq!("SYN|ACK".parse(), Ok(syn_ack));
+        assert_eq!("syn,ack".parse(), Ok(syn_ack));
+        assert_eq!(" Ack | Syn ".parse(), Ok(syn_ack));
+        assert_eq!(".".parse(), Ok(TcpFlags::empty()));
+        assert_eq!("".parse(), Ok(TcpFlags::empty()));
+
+        assert_eq!("SYN,BOGUS".parse::<TcpFlags>(), Err(ParseFlagsError::UnknownFlag("BOGUS".into())));
+        assert_eq!("SYN,".parse::<TcpFlags>(), Err(ParseFlagsError::UnknownFlag("".into())));
+    }
+
+    #[test]
+    fn test_round_trip_every_combination() {
+        for bits in 0..=u8::MAX {
+            let flags = TcpFlags::from_bits_retain(bits);
+            let printed = flags.to_string();
+            assert_eq!(printed.parse(), Ok(flags), "{printed}");
+            assert_eq!(printed.to_lowercase().replace(',', "|").parse(), Ok(flags), "{printed}");
+        }
+    }
 }
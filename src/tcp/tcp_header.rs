@@ -1,9 +1,54 @@
-use crate::ip::ip_header::IpHeader;
 use crate::tcp::tcp_flags::TcpFlags;
+use crate::tcp::tcp_options::{self, TcpOption};
 use crate::packet::errors::HeaderError;
+use crate::packet::checksum::{self, PseudoHeader};
+use crate::packet::header_ref::TcpHeaderRef;
+use crate::packet::parse_options::{ChecksumStatus, ParseOptions};
 use crate::tcp::wrap32::Wrap32;
+use bytes::Bytes;
 
+/// A TCP header and its payload. Serializing and parsing need the IP header for the checksum's
+/// pseudo-header.
+///
+/// ```
+/// use bytes::Bytes;
+/// use net::ip::ip_header::IpHeader;
+/// use net::tcp::tcp_flags::TcpFlags;
+/// use net::tcp::tcp_header::TcpHeader;
+/// use net::tcp::tcp_options::TcpOption;
+/// use net::tcp::wrap32::Wrap32;
+/// use std::net::Ipv4Addr;
+///
+/// let iph = IpHeader {
+///     version: 4,
+///     ihl: 5,
+///     total_len: 44,
+///     protocol: 6,
+///     src_ip: Ipv4Addr::new(10, 0, 0, 1),
+///     dst_ip: Ipv4Addr::new(10, 0, 0, 2),
+///     ..IpHeader::default()
+/// };
+/// let syn = TcpHeader {
+///     src_port: 50000,
+///     dst_port: 80,
+///     seq_no: Wrap32::new(1000),
+///     data_offset: 6, // 20 bytes plus a 4 byte MSS option
+///     flags: TcpFlags::SYN,
+///     window: 65535,
+///     options: Bytes::from_static(&[2, 4, 0x05, 0xb4]),
+///     ..TcpHeader::default()
+/// };
+///
+/// let mut buf = [0u8; 24];
+/// assert_eq!(syn.serialize(&mut buf, &iph).unwrap(), 24);
+///
+/// let parsed = TcpHeader::parse(&buf, &iph).unwrap();
+/// assert_eq!(parsed.flags, TcpFlags::SYN);
+/// assert_eq!(parsed.seq_no, Wrap32::new(1000));
+/// assert_eq!(parsed.tcp_options().unwrap(), vec![TcpOption::Mss(1460)]);
+/// ```
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(deny_unknown_fields))]
 pub struct TcpHeader {
     pub src_port: u16,
     pub dst_port: u16,
@@ -15,16 +60,54 @@ pub struct TcpHeader {
     pub window: u16,
     pub checksum: u16,
     pub urgent: u16,
-    pub options: Vec<u8>,
-    pub payload: Vec<u8>, // Append payload to end of TCP header for ease of use
+    pub options: Bytes,
+    pub payload: Bytes, // Shared, not copied, when a header is cloned or handed to the receiver
 }
 
 impl TcpHeader {
-    /// Convert a `TCPHeader` into a byte vector.
-    pub fn serialize(&self, buf: &mut [u8], iph: &IpHeader) -> Result<usize, HeaderError> {
-        let header_len = self.data_offset as usize * 4; // 20 + options
+    /// A default header between two ports
+    pub fn new(src_port: u16, dst_port: u16) -> Self {
+        TcpHeader { src_port, dst_port, ..TcpHeader::default() }
+    }
+
+    /// The serialized header length: 20 bytes plus the options padded to a multiple of 4
+    pub fn header_len(&self) -> usize {
+        20 + self.options.len().next_multiple_of(4)
+    }
+
+    pub fn payload_len(&self) -> usize {
+        self.payload.len()
+    }
+
+    /// The sequence space the segment occupies: its payload, plus one each for SYN and FIN
+    pub fn seq_len(&self) -> usize {
+        seq_len(self.flags, self.payload.len())
+    }
+
+    /// Convert a `TCPHeader` into a byte vector of size `header_len()` plus the payload. The data
+    /// offset is derived from the options, so `self.data_offset` is ignored.
+    pub fn serialize(&self, buf: &mut [u8], iph: &impl PseudoHeader) -> Result<usize, HeaderError> {
+        let data_offset = self.header_len() / 4;
+        if data_offset > 15 {
+            return Err(HeaderError::InvalidDataOffset(data_offset.min(u8::MAX as usize) as u8))
+        }
+        self.serialize_with_offset(buf, iph, data_offset as u8)
+    }
+
+    /// Like `serialize`, but writes `self.data_offset` as is, e.g. to build a malformed segment on
+    /// purpose. Options shorter than the offset are zero padded.
+    pub fn serialize_raw(&self, buf: &mut [u8], iph: &impl PseudoHeader) -> Result<usize, HeaderError> {
+        self.serialize_with_offset(buf, iph, self.data_offset)
+    }
+
+    fn serialize_with_offset(&self, buf: &mut [u8], iph: &impl PseudoHeader, data_offset: u8) -> Result<usize, HeaderError> {
+        let header_len = data_offset as usize * 4; // 20 + options
         let total_len = header_len + self.payload.len(); // 20 + options + payload
 
+        if header_len < 20 + self.options.len() {
+            return Err(HeaderError::InvalidDataOffset(data_offset))
+        }
+
         if buf.len() < total_len {
             return Err(HeaderError::BufferTooSmall { expected: total_len, found: buf.len() })
         }
@@ -33,15 +116,15 @@ impl TcpHeader {
         buf[2..4].copy_from_slice(&self.dst_port.to_be_bytes());
         buf[4..8].copy_from_slice(&self.seq_no.value().to_be_bytes());
         buf[8..12].copy_from_slice(&self.ack_no.value().to_be_bytes());
-        buf[12] = (self.data_offset << 4) | self.reserved;
+        buf[12] = (data_offset << 4) | self.reserved;
         buf[13] = self.flags.bits();
         buf[14..16].copy_from_slice(&self.window.to_be_bytes());
         buf[16..18].fill(0); // Set checksum to 0 initially
         buf[18..20].copy_from_slice(&self.urgent.to_be_bytes());
 
-        if !self.options.is_empty() {
-            buf[20..header_len].copy_from_slice(&self.options);
-        }
+        // Zeroes after the options read as End of Options List
+        buf[20..20 + self.options.len()].copy_from_slice(&self.options);
+        buf[20 + self.options.len()..header_len].fill(0);
 
         if !self.payload.is_empty() {
             buf[header_len..total_len].copy_from_slice(&self.payload);
@@ -54,93 +137,51 @@ impl TcpHeader {
     }
 
     /// Convert a byte vector into a `TCPHeader`.
-    pub fn parse(buf: &[u8], iph: &IpHeader) -> Result<Self, HeaderError> {
-        if buf.len() < 20 {
-            return Err(HeaderError::BufferTooSmall { expected: 20, found: buf.len() })
-        }
-
-        let src_port = u16::from_be_bytes([buf[0], buf[1]]);
-        let dst_port = u16::from_be_bytes([buf[2], buf[3]]);
-        let seq_no = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
-        let ack_no = u32::from_be_bytes([buf[8], buf[9], buf[10], buf[11]]);
-        let data_offset = buf[12] >> 4;
-        let reserved = buf[12] & 0x0f;
-        let flags = TcpFlags::from_bits_truncate(buf[13]);
-        let window = u16::from_be_bytes([buf[14], buf[15]]);
-        let checksum = u16::from_be_bytes([buf[16], buf[17]]);
-        let urgent = u16::from_be_bytes([buf[18], buf[19]]);
-
-        let header_len = data_offset as usize * 4;
-        if buf.len() < header_len {
-            return Err(HeaderError::BufferTooSmall { expected: header_len, found: buf.len() })
-        }
+    /// The buffer must hold exactly the TCP segment length claimed by the IPv4 or IPv6 header.
+    pub fn parse(buf: &[u8], iph: &impl PseudoHeader) -> Result<Self, HeaderError> {
+        TcpHeaderRef::parse(buf, iph).map(|tcph| tcph.to_owned())
+    }
 
-        let options = if header_len > 20 {
-            buf[20..header_len].to_vec()
-        } else {
-            Vec::new()
-        };
+    /// Like `parse`, but a bad checksum is only an error if `options.verify_tcp_checksum` is set
+    pub fn parse_with(
+        buf: &[u8],
+        iph: &impl PseudoHeader,
+        options: &ParseOptions,
+    ) -> Result<(Self, ChecksumStatus), HeaderError> {
+        TcpHeaderRef::parse_with(buf, iph, options).map(|(tcph, status)| (tcph.to_owned(), status))
+    }
 
-        let payload = if buf.len() > header_len {
-            buf[header_len..].to_vec()
-        } else {
-            Vec::new()
-        };
+    /// Parse the raw options into typed options. Options never cause `parse` to reject a
+    /// segment, so callers can decide whether a malformed options area matters.
+    pub fn tcp_options(&self) -> Result<Vec<TcpOption>, HeaderError> {
+        tcp_options::parse_options(&self.options)
+    }
 
-        if Self::checksum(&buf[..(header_len + payload.len())], iph) != 0 {
-            return Err(HeaderError::BadChecksum("TCP".to_string()))
-        }
+    /// The options before any malformed one, for callers that would rather use a peer's MSS
+    /// and window scale than drop them over a bad option further along
+    pub fn tcp_options_partial(&self) -> Vec<TcpOption> {
+        tcp_options::parse_options_partial(&self.options).0
+    }
 
-        Ok(TcpHeader {
-            src_port,
-            dst_port,
-            seq_no: Wrap32::new(seq_no),
-            ack_no: Wrap32::new(ack_no),
-            data_offset,
-            reserved,
-            flags,
-            window,
-            checksum,
-            urgent,
-            options,
-            payload,
-        })
+    /// Set the window of an already-serialized segment in `buf`, fixing the checksum
+    /// incrementally (RFC 1624) instead of summing the whole payload again
+    pub fn patch_window(buf: &mut [u8], window: u16) -> Result<(), HeaderError> {
+        checksum::patch(buf, 16, 14, &window.to_be_bytes())
     }
 
     /// Compute the checksum for a `TCPHeader`.
-    pub fn checksum(data: &[u8], iph: &IpHeader) -> u16 {
-        let mut sum: u32 = 0;
-
-        // Pseudo-header
-        let src_bytes = iph.src_ip.octets();
-        let dst_bytes = iph.dst_ip.octets();
-
-        sum += ((src_bytes[0] as u32) << 8) | (src_bytes[1] as u32);
-        sum += ((src_bytes[2] as u32) << 8) | (src_bytes[3] as u32);
-        sum += ((dst_bytes[0] as u32) << 8) | (dst_bytes[1] as u32);
-        sum += ((dst_bytes[2] as u32) << 8) | (dst_bytes[3] as u32);
-
-        // Add protocol and TCP segment length
-        sum += iph.protocol as u32;
-        sum += data.len() as u32;
-
-        // Sum the TCP Header and payload
-        sum += data
-            .chunks(2)
-            .map(|chunk| {
-                if chunk.len() == 2 {
-                    u16::from_be_bytes([chunk[0], chunk[1]]) as u32
-                } else {
-                    (chunk[0] as u32) << 8
-                }
-            }).sum::<u32>();
+    pub fn checksum(data: &[u8], iph: &impl PseudoHeader) -> u16 {
+        Self::checksum_with_len(data, iph, data.len())
+    }
 
-        // Fold the carry bits
-        let folded = (sum & 0xffff) + (sum >> 16);
-        !folded as u16
+    /// Compute the checksum for a `TCPHeader` using an explicit pseudo-header segment length.
+    pub fn checksum_with_len(data: &[u8], iph: &impl PseudoHeader, segment_len: usize) -> u16 {
+        // Pseudo-header: addresses, protocol and TCP segment length. Then the header and payload
+        checksum::checksum_with(iph.pseudo_header_sum(segment_len), data)
     }
 }
 
+/// A bare 20 byte header with no flags set and the largest unscaled window, ready to serialize
 impl Default for TcpHeader {
     fn default() -> Self {
         TcpHeader {
@@ -148,23 +189,30 @@ impl Default for TcpHeader {
             dst_port: 0,
             seq_no: Wrap32::new(0),
             ack_no: Wrap32::new(0),
-            data_offset: 0,
+            data_offset: 5,
             reserved: 0,
-            flags: TcpFlags::ACK,
-            window: 0,
+            flags: TcpFlags::empty(),
+            window: u16::MAX,
             checksum: 0,
             urgent: 0,
-            options: vec![],
-            payload: vec![],
+            options: Bytes::new(),
+            payload: Bytes::new(),
         }
     }
 }
 
+/// SYN and FIN each take one sequence number on top of the payload (RFC 9293 section 3.4)
+pub(crate) fn seq_len(flags: TcpFlags, payload_len: usize) -> usize {
+    payload_len + flags.contains(TcpFlags::SYN) as usize + flags.contains(TcpFlags::FIN) as usize
+}
+
 // -- Unit tests --
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ip::ip_header::IpHeader;
+    use crate::ip::ipv6_header::Ipv6Header;
     use crate::packet::test_utils;
 
     #[test]
@@ -180,13 +228,12 @@ mod tests {
             window: 65535,
             checksum: 37527,
             urgent: 0,
-            options: hex::decode("020405b4010303060101080abb6879f80000000004020000").unwrap(),
-            payload: vec![],
+            options: hex::decode("020405b4010303060101080abb6879f80000000004020000").unwrap().into(),
+            payload: Bytes::new(),
         };
 
         // Get the IP header in order to build TCP header
-        let ip_bytes = hex::decode(test_utils::get_ip_hex()).unwrap();
-        let iph = IpHeader::parse(&ip_bytes).unwrap();
+        let iph = test_utils::get_ip_header();
         let mut buf = vec![0u8; 1024];
         let n = tcp_header.serialize(&mut buf, &iph).unwrap();
 
@@ -201,8 +248,7 @@ mod tests {
 
     #[test]
     fn test_tcp_header_from_bytes() {
-        let ip_bytes = hex::decode(test_utils::get_ip_hex()).unwrap();
-        let iph = IpHeader::parse(&ip_bytes).unwrap();
+        let iph = test_utils::get_ip_header();
 
         let tcp_bytes = hex::decode(test_utils::get_tcp_hex()).unwrap();
         let tcph = TcpHeader::parse(&tcp_bytes, &iph).unwrap();
@@ -221,6 +267,295 @@ mod tests {
             tcph.options,
             hex::decode("020405b4010303060101080abb6879f80000000004020000").unwrap()
         );
-        assert_eq!(tcph.payload, [])
+        assert_eq!(tcph.payload, Vec::<u8>::new())
+    }
+
+    #[test]
+    fn test_tcp_header_checksum_double_carry() {
+        // The 32-bit sum is 0xbfffa. Folding once gives 0x10005, which still carries
+        let iph = IpHeader {
+            total_len: 40,
+            ihl: 5,
+            protocol: 6,
+            src_ip: "255.255.255.255".parse().unwrap(),
+            dst_ip: "255.255.255.255".parse().unwrap(),
+            ..IpHeader::default()
+        };
+        let tcp_header = TcpHeader {
+            src_port: 0xffff,
+            dst_port: 0xffff,
+            seq_no: Wrap32::new(u32::MAX),
+            ack_no: Wrap32::new(u32::MAX),
+            data_offset: 5,
+            window: 0xffff,
+            urgent: 0xafdb,
+            ..TcpHeader::default()
+        };
+
+        let mut buf = [0u8; 20];
+        tcp_header.serialize(&mut buf, &iph).unwrap();
+        assert_eq!(TcpHeader::checksum(&buf, &iph), 0);
+        assert!(TcpHeader::parse(&buf, &iph).is_ok());
+    }
+
+    #[test]
+    fn test_tcp_header_checksum_v6() {
+        let packet = hex::decode(test_utils::get_ipv6_syn_hex()).unwrap();
+        let ip6h = Ipv6Header::parse(&packet).unwrap();
+        assert_eq!(TcpHeader::checksum(&packet[40..], &ip6h), 0);
+
+        // The same segment under an IPv4 pseudo-header doesn't validate
+        let iph = IpHeader { ihl: 5, total_len: 60, protocol: 6, ..IpHeader::default() };
+        assert_ne!(TcpHeader::checksum(&packet[40..], &iph), 0);
+    }
+
+    #[test]
+    fn test_tcp_header_checksum_counts_payload_once() {
+        // The pseudo-header length is the whole segment. Adding the payload length on top of it
+        // again gives a checksum the receiver rejects
+        let tcph = TcpHeader {
+            data_offset: 5,
+            flags: TcpFlags::ACK | TcpFlags::PSH,
+            payload: b"hello".to_vec().into(),
+            ..TcpHeader::default()
+        };
+        let iph = IpHeader { ihl: 5, total_len: 45, protocol: 6, ..IpHeader::default() };
+        let packet = hex::decode(test_utils::get_ipv6_syn_hex()).unwrap();
+        let ip6h = Ipv6Header { payload_len: 25, ..Ipv6Header::parse(&packet).unwrap() };
+
+        let mut buf = [0u8; 25];
+        tcph.serialize(&mut buf, &iph).unwrap();
+        assert_eq!(TcpHeader::checksum(&buf, &iph), 0);
+        assert_ne!(TcpHeader::checksum_with_len(&buf, &iph, buf.len() + tcph.payload.len()), 0);
+        assert_eq!(TcpHeader::parse(&buf, &iph).unwrap().payload, &b"hello"[..]);
+
+        tcph.serialize(&mut buf, &ip6h).unwrap();
+        assert_eq!(TcpHeader::checksum(&buf, &ip6h), 0);
+        assert!(TcpHeader::parse(&buf, &ip6h).is_ok());
+    }
+
+    #[test]
+    fn test_seq_len() {
+        let cases = [
+            (TcpFlags::ACK, &b""[..], 0),
+            (TcpFlags::SYN, b"", 1),
+            (TcpFlags::FIN | TcpFlags::ACK, b"", 1),
+            (TcpFlags::SYN | TcpFlags::FIN, b"", 2),
+            (TcpFlags::ACK | TcpFlags::PSH, b"hello", 5),
+            (TcpFlags::SYN, b"hello", 6),
+            (TcpFlags::FIN | TcpFlags::ACK, b"hello", 6),
+        ];
+        for (flags, payload, expected) in cases {
+            let tcph = TcpHeader { flags, payload: Bytes::copy_from_slice(payload), ..TcpHeader::default() };
+            assert_eq!(tcph.payload_len(), payload.len());
+            assert_eq!(tcph.seq_len(), expected, "{flags:?}");
+
+            // The borrowed view agrees
+            let mut buf = vec![0u8; tcph.header_len() + payload.len()];
+            let iph = IpHeader { ihl: 5, total_len: 20 + buf.len() as u16, protocol: 6, ..IpHeader::default() };
+            tcph.serialize(&mut buf, &iph).unwrap();
+            let (view, _) = TcpHeaderRef::parse_with(&buf, &iph, &ParseOptions::lenient()).unwrap();
+            assert_eq!((view.payload_len(), view.seq_len()), (payload.len(), expected));
+        }
+    }
+
+    #[test]
+    fn test_clone_shares_payload() {
+        let payload = Bytes::from(vec![7u8; 1460]);
+        let tcph = TcpHeader { data_offset: 5, payload: payload.clone(), ..TcpHeader::default() };
+        let copy = tcph.clone();
+
+        // Cloning bumps a refcount instead of copying the 1460 bytes
+        assert_eq!(tcph.payload.as_ptr(), payload.as_ptr());
+        assert_eq!(copy.payload.as_ptr(), payload.as_ptr());
+        assert_eq!(copy, tcph);
+    }
+
+    #[test]
+    fn test_tcp_header_typed_options() {
+        let iph = test_utils::get_ip_header();
+
+        let tcp_bytes = hex::decode(test_utils::get_tcp_hex()).unwrap();
+        let tcph = TcpHeader::parse(&tcp_bytes, &iph).unwrap();
+
+        let options = tcph.tcp_options().unwrap();
+        assert_eq!(options[0], TcpOption::Mss(1460));
+        assert_eq!(options.len(), 4);
+    }
+
+    #[test]
+    fn test_tcp_header_buffer_longer_than_ip_len() {
+        let iph = test_utils::get_ip_header();
+
+        // Trailing junk after the segment claimed by the IP header
+        let mut tcp_bytes = hex::decode(test_utils::get_tcp_hex()).unwrap();
+        tcp_bytes.extend_from_slice(&[0, 0, 0, 0]);
+
+        let err = TcpHeader::parse(&tcp_bytes, &iph).unwrap_err();
+        assert_eq!(err, HeaderError::LengthMismatch { expected: 44, found: 48 });
+    }
+
+    #[test]
+    fn test_tcp_header_buffer_shorter_than_ip_len() {
+        let iph = test_utils::get_ip_header();
+
+        // Segment cut short of the length claimed by the IP header
+        let tcp_bytes = hex::decode(test_utils::get_tcp_hex()).unwrap();
+        let truncated = &tcp_bytes[..40];
+
+        let err = TcpHeader::parse(truncated, &iph).unwrap_err();
+        assert_eq!(err, HeaderError::LengthMismatch { expected: 44, found: 40 });
+    }
+
+    #[test]
+    fn test_tcp_header_ip_len_smaller_than_ip_header() {
+        let mut iph = test_utils::get_ip_header();
+        iph.total_len = 12;
+
+        let tcp_bytes = hex::decode(test_utils::get_tcp_hex()).unwrap();
+        let err = TcpHeader::parse(&tcp_bytes, &iph).unwrap_err();
+        assert_eq!(err, HeaderError::LengthMismatch { expected: 0, found: 44 });
+    }
+
+    #[test]
+    fn test_serialize_raw_rejects_data_offset_too_small() {
+        let iph = test_utils::get_ip_header();
+        let mut buf = [0u8; 64];
+
+        // Used to panic on an out-of-range slice instead of returning an error
+        let tcph = TcpHeader { data_offset: 5, options: vec![2, 4, 5, 180].into(), ..TcpHeader::default() };
+        assert_eq!(tcph.serialize_raw(&mut buf, &iph), Err(HeaderError::InvalidDataOffset(5)));
+        let tcph = TcpHeader { data_offset: 0, ..TcpHeader::default() };
+        assert_eq!(tcph.serialize_raw(&mut buf, &iph), Err(HeaderError::InvalidDataOffset(0)));
+    }
+
+    #[test]
+    fn test_default_header_serializes() {
+        let tcph = TcpHeader::default();
+        let mut buf = [0u8; 20];
+        assert_eq!(tcph.serialize_raw(&mut buf, &IpHeader::default()), Ok(20));
+        assert_eq!(tcph.serialize(&mut buf, &IpHeader::default()), Ok(20));
+        assert_eq!(TcpHeader::checksum(&buf, &IpHeader::default()), 0);
+
+        // Parses back unchanged once the IP header accounts for it
+        let iph = IpHeader { ihl: 5, total_len: 40, protocol: 6, ..IpHeader::default() };
+        tcph.serialize(&mut buf, &iph).unwrap();
+        let parsed = TcpHeader::parse(&buf, &iph).unwrap();
+        assert_eq!(parsed, TcpHeader { checksum: parsed.checksum, ..tcph });
+        assert_eq!((parsed.data_offset, parsed.flags, parsed.window), (5, TcpFlags::empty(), u16::MAX));
+
+        let tcph = TcpHeader::new(50000, 80);
+        assert_eq!((tcph.src_port, tcph.dst_port, tcph.header_len()), (50000, 80, 20));
+    }
+
+    #[test]
+    fn test_serialize_raw_pads_short_options() {
+        let iph = test_utils::get_ip_header();
+        let mut buf = [0xffu8; 64];
+
+        let tcph = TcpHeader { data_offset: 7, options: vec![1, 1, 4, 2].into(), ..TcpHeader::default() };
+        assert_eq!(tcph.serialize_raw(&mut buf, &iph), Ok(28));
+        assert_eq!(&buf[20..28], &[1, 1, 4, 2, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_serialize_derives_data_offset() {
+        let iph = test_utils::get_ip_header();
+        let mut buf = [0xffu8; 80];
+
+        // A stale or missing data offset no longer matters
+        for (stale, options, header_len) in [(0, vec![], 20), (5, vec![2, 4, 5, 180], 24), (15, vec![1, 1, 4], 24)] {
+            let tcph = TcpHeader { data_offset: stale, options: options.into(), payload: b"hi".to_vec().into(), ..TcpHeader::default() };
+            assert_eq!(tcph.header_len(), header_len);
+            assert_eq!(tcph.serialize(&mut buf, &iph), Ok(header_len + 2));
+            assert_eq!(buf[12] >> 4, header_len as u8 / 4);
+            assert_eq!(buf[header_len..header_len + 2], *b"hi");
+        }
+        assert_eq!(&buf[20..24], &[1, 1, 4, 0]); // Padded with End of Options List
+
+        let tcph = TcpHeader { options: vec![1; 40].into(), ..TcpHeader::default() };
+        assert_eq!(tcph.serialize(&mut buf, &iph), Ok(60));
+        let tcph = TcpHeader { options: vec![1; 41].into(), ..TcpHeader::default() };
+        assert_eq!(tcph.serialize(&mut buf, &iph), Err(HeaderError::InvalidDataOffset(16)));
+    }
+
+    #[test]
+    fn test_tcp_header_patch_window() {
+        let packet = [
+            test_utils::get_ip_hex_with_payload(),
+            test_utils::get_tcp_hex_with_payload(),
+            test_utils::giant_payload(),
+        ]
+        .concat();
+        let packet = hex::decode(packet).unwrap();
+        let iph = IpHeader::parse(&packet).unwrap();
+        let mut buf = packet[20..].to_vec();
+
+        for window in [0, 1, 0x00ff, 0x7fff, 0x8000, 0xfffe, 0xffff, 0, 0xffff, 4096] {
+            TcpHeader::patch_window(&mut buf, window).unwrap();
+            assert_eq!(TcpHeader::checksum(&buf, &iph), 0, "window {window:#06x}");
+        }
+
+        // Matches a full re-serialization
+        let mut tcph = TcpHeader::parse(&buf, &iph).unwrap();
+        assert_eq!(tcph.window, 4096);
+        tcph.checksum = 0;
+        let mut expected = vec![0u8; buf.len()];
+        tcph.serialize(&mut expected, &iph).unwrap();
+        assert_eq!(buf, expected);
+
+        assert_eq!(TcpHeader::patch_window(&mut buf[..17], 1), Err(HeaderError::BufferTooSmall { expected: 18, found: 17 }));
+    }
+
+    #[test]
+    fn test_parse_rejects_data_offset_below_5() {
+        let iph = IpHeader { ihl: 5, total_len: 60, protocol: 6, ..IpHeader::default() };
+        for data_offset in 0..5 {
+            let mut buf = [0u8; 40];
+            buf[12] = data_offset << 4;
+            let checksum = TcpHeader::checksum(&buf, &iph);
+            buf[16..18].copy_from_slice(&checksum.to_be_bytes());
+
+            assert_eq!(TcpHeader::parse(&buf, &iph), Err(HeaderError::InvalidDataOffset(data_offset)));
+            assert_eq!(TcpHeaderRef::parse(&buf, &iph), Err(HeaderError::InvalidDataOffset(data_offset)));
+            let lenient = ParseOptions::lenient();
+            assert_eq!(TcpHeader::parse_with(&buf, &iph, &lenient), Err(HeaderError::InvalidDataOffset(data_offset)));
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_truncated_options() {
+        // Every data offset past what the buffer holds, and every buffer too short for the
+        // fixed header
+        for len in 0..60usize {
+            for data_offset in 5..=15u8 {
+                let mut buf = vec![0u8; len];
+                if len > 12 {
+                    buf[12] = data_offset << 4;
+                }
+                let iph = IpHeader { ihl: 5, total_len: (20 + len) as u16, protocol: 6, ..IpHeader::default() };
+                let header_len = data_offset as usize * 4;
+                match TcpHeader::parse_with(&buf, &iph, &ParseOptions::lenient()) {
+                    Ok((tcph, _)) => {
+                        assert!(len >= header_len);
+                        assert_eq!(tcph.options.len(), header_len - 20);
+                    }
+                    Err(HeaderError::BufferTooSmall { expected, found }) => {
+                        assert_eq!(found, len);
+                        assert_eq!(expected, if len < 20 { 20 } else { header_len });
+                    }
+                    Err(e) => panic!("len {len} data offset {data_offset}: {e}"),
+                }
+            }
+        }
+
+        // An option whose length runs past the options area parses, but isn't typed
+        let tcph = TcpHeader { data_offset: 6, options: vec![1, 1, 2, 4].into(), ..TcpHeader::default() };
+        let iph = IpHeader { ihl: 5, total_len: 44, protocol: 6, ..IpHeader::default() };
+        let mut buf = [0u8; 24];
+        tcph.serialize(&mut buf, &iph).unwrap();
+        buf[22..24].copy_from_slice(&[2, 6]);
+        let (tcph, _) = TcpHeader::parse_with(&buf, &iph, &ParseOptions::lenient()).unwrap();
+        assert!(matches!(tcph.tcp_options(), Err(HeaderError::MalformedOption { kind: 2, .. })));
     }
 }
@@ -0,0 +1,298 @@
+use crate::packet::errors::HeaderError;
+use crate::tcp::wrap32::Wrap32;
+use std::fmt;
+
+// Option kinds. RFC 9293 and the IANA TCP option registry
+pub const KIND_EOL: u8 = 0;
+pub const KIND_NOP: u8 = 1;
+pub const KIND_MSS: u8 = 2;
+pub const KIND_WINDOW_SCALE: u8 = 3;
+pub const KIND_SACK_PERMITTED: u8 = 4;
+pub const KIND_SACK: u8 = 5;
+pub const KIND_TIMESTAMPS: u8 = 8;
+
+/// A typed TCP option. Padding (EOL and NOP) is not represented.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TcpOption {
+    Mss(u16),
+    WindowScale(u8),
+    SackPermitted,
+    Sack(Vec<(Wrap32, Wrap32)>), // (left edge, right edge) blocks
+    Timestamps { val: u32, ecr: u32 },
+    Unknown { kind: u8, data: Vec<u8> }, // Unrecognized kinds, or known kinds with an odd length
+}
+
+impl TcpOption {
+    /// Decode a length-bearing option from its kind and data (the bytes after the length byte)
+    fn decode(kind: u8, data: &[u8]) -> Self {
+        match (kind, data.len()) {
+            (KIND_MSS, 2) => TcpOption::Mss(u16::from_be_bytes([data[0], data[1]])),
+            (KIND_WINDOW_SCALE, 1) => TcpOption::WindowScale(data[0]),
+            (KIND_SACK_PERMITTED, 0) => TcpOption::SackPermitted,
+            (KIND_SACK, n) if n > 0 && n % 8 == 0 => {
+                let blocks = data
+                    .chunks_exact(8)
+                    .map(|b| {
+                        let left = u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+                        let right = u32::from_be_bytes([b[4], b[5], b[6], b[7]]);
+                        (Wrap32::new(left), Wrap32::new(right))
+                    })
+                    .collect();
+                TcpOption::Sack(blocks)
+            }
+            (KIND_TIMESTAMPS, 8) => TcpOption::Timestamps {
+                val: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+                ecr: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            },
+            _ => TcpOption::Unknown { kind, data: data.to_vec() },
+        }
+    }
+
+    /// Append the kind, length and data bytes of the option
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        buf.extend([0, 0]); // Kind and length, filled in below
+        let kind = match self {
+            TcpOption::Mss(mss) => {
+                buf.extend(mss.to_be_bytes());
+                KIND_MSS
+            }
+            TcpOption::WindowScale(shift) => {
+                buf.push(*shift);
+                KIND_WINDOW_SCALE
+            }
+            TcpOption::SackPermitted => KIND_SACK_PERMITTED,
+            TcpOption::Sack(blocks) => {
+                for (left, right) in blocks {
+                    buf.extend(left.value().to_be_bytes());
+                    buf.extend(right.value().to_be_bytes());
+                }
+                KIND_SACK
+            }
+            TcpOption::Timestamps { val, ecr } => {
+                buf.extend(val.to_be_bytes());
+                buf.extend(ecr.to_be_bytes());
+                KIND_TIMESTAMPS
+            }
+            TcpOption::Unknown { kind, data } => {
+                buf.extend(data);
+                *kind
+            }
+        };
+        buf[start] = kind;
+        buf[start + 1] = (buf.len() - start) as u8;
+    }
+}
+
+/// tcpdump's spelling, e.g. "mss 1460", "TS val 1 ecr 0" or "unknown-253 0xabcd"
+impl fmt::Display for TcpOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TcpOption::Mss(mss) => write!(f, "mss {mss}"),
+            TcpOption::WindowScale(shift) => write!(f, "wscale {shift}"),
+            TcpOption::SackPermitted => f.write_str("sackOK"),
+            TcpOption::Sack(blocks) => {
+                write!(f, "sack {}", blocks.len())?;
+                for (left, right) in blocks {
+                    write!(f, " {{{}:{}}}", left.value(), right.value())?;
+                }
+                Ok(())
+            }
+            TcpOption::Timestamps { val, ecr } => write!(f, "TS val {val} ecr {ecr}"),
+            TcpOption::Unknown { kind, data } => write!(f, "unknown-{kind} 0x{}", hex::encode(data)),
+        }
+    }
+}
+
+/// Parse the options area of a TCP header into typed options.
+///
+/// Unknown kinds become `TcpOption::Unknown` and missing final padding is tolerated. Only
+/// structurally impossible encodings are errors: a length byte of 0 or 1, or an option
+/// running past the end of the options area.
+pub fn parse_options(buf: &[u8]) -> Result<Vec<TcpOption>, HeaderError> {
+    match parse_options_partial(buf) {
+        (options, None) => Ok(options),
+        (_, Some(e)) => Err(e),
+    }
+}
+
+/// Like `parse_options`, but a malformed option only stops the parse: the options before it
+/// are returned along with the error. Nothing after a bad length byte can be trusted, since
+/// it's unknown where the next option starts.
+pub fn parse_options_partial(buf: &[u8]) -> (Vec<TcpOption>, Option<HeaderError>) {
+    let mut options = Vec::new();
+    let mut i = 0;
+
+    while i < buf.len() {
+        let kind = buf[i];
+        match kind {
+            KIND_EOL => break,
+            KIND_NOP => i += 1,
+            _ => {
+                let len = buf.get(i + 1).map_or(0, |&len| len as usize);
+                if len < 2 || i + len > buf.len() {
+                    return (options, Some(HeaderError::MalformedOption { kind, offset: i }));
+                }
+
+                options.push(TcpOption::decode(kind, &buf[i + 2..i + len]));
+                i += len;
+            }
+        }
+    }
+
+    (options, None)
+}
+
+/// Encode typed options into an options area, the inverse of `parse_options`. The result isn't
+/// padded, since `TcpHeader::serialize` pads the options to a multiple of 4.
+pub fn encode_options(options: &[TcpOption]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for option in options {
+        option.encode_into(&mut buf);
+    }
+    buf
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_syn_options() {
+        // Options from the wireshark SYN fixture
+        let buf = hex::decode("020405b4010303060101080abb6879f80000000004020000").unwrap();
+        let options = parse_options(&buf).unwrap();
+
+        assert_eq!(
+            options,
+            vec![
+                TcpOption::Mss(1460),
+                TcpOption::WindowScale(6),
+                TcpOption::Timestamps { val: 0xbb6879f8, ecr: 0 },
+                TcpOption::SackPermitted,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_sack_blocks() {
+        let buf = hex::decode("0101050a0000000a00000014").unwrap();
+        let options = parse_options(&buf).unwrap();
+
+        assert_eq!(options, vec![TcpOption::Sack(vec![(Wrap32::new(10), Wrap32::new(20))])]);
+    }
+
+    #[test]
+    fn test_parse_unknown_experimental_kinds() {
+        // Middlebox-style RFC 6994 experiments (kinds 253 and 254) around an MSS
+        let buf = hex::decode("fd04abcd020405b4fe06010203040101").unwrap();
+        let options = parse_options(&buf).unwrap();
+
+        assert_eq!(
+            options,
+            vec![
+                TcpOption::Unknown { kind: 253, data: vec![0xab, 0xcd] },
+                TcpOption::Mss(1460),
+                TcpOption::Unknown { kind: 254, data: vec![1, 2, 3, 4] },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_keeps_options_before_malformed() {
+        // A valid MSS, then an option claiming length 0
+        let buf = hex::decode("020405b40800").unwrap();
+        assert!(matches!(parse_options(&buf), Err(HeaderError::MalformedOption { kind: 8, offset: 4 })));
+
+        let (options, err) = parse_options_partial(&buf);
+        assert_eq!(options, vec![TcpOption::Mss(1460)]);
+        assert!(matches!(err, Some(HeaderError::MalformedOption { kind: 8, offset: 4 })));
+    }
+
+    #[test]
+    fn test_display() {
+        let options = [
+            TcpOption::Mss(1460),
+            TcpOption::WindowScale(6),
+            TcpOption::SackPermitted,
+            TcpOption::Sack(vec![(Wrap32::new(10), Wrap32::new(20)), (Wrap32::new(30), Wrap32::new(40))]),
+            TcpOption::Timestamps { val: 7, ecr: 0 },
+            TcpOption::Unknown { kind: 253, data: vec![0xab, 0xcd] },
+        ];
+        let shown: Vec<String> = options.iter().map(|o| o.to_string()).collect();
+        assert_eq!(shown, ["mss 1460", "wscale 6", "sackOK", "sack 2 {10:20} {30:40}", "TS val 7 ecr 0", "unknown-253 0xabcd"]);
+    }
+
+    #[test]
+    fn test_parse_known_kind_with_odd_length() {
+        // MSS with a 3 byte length is kept as unknown instead of rejecting the segment
+        let buf = hex::decode("020305").unwrap();
+        let options = parse_options(&buf).unwrap();
+
+        assert_eq!(options, vec![TcpOption::Unknown { kind: KIND_MSS, data: vec![5] }]);
+    }
+
+    #[test]
+    fn test_parse_missing_final_padding() {
+        // MSS + window scale is 7 bytes; the trailing NOP/EOL pad byte is missing
+        let buf = hex::decode("020405b4030307").unwrap();
+        let options = parse_options(&buf).unwrap();
+
+        assert_eq!(options, vec![TcpOption::Mss(1460), TcpOption::WindowScale(7)]);
+    }
+
+    #[test]
+    fn test_parse_stops_at_eol() {
+        let buf = hex::decode("0402000204ffff").unwrap();
+        let options = parse_options(&buf).unwrap();
+
+        assert_eq!(options, vec![TcpOption::SackPermitted]);
+    }
+
+    #[test]
+    fn test_parse_zero_length_is_error() {
+        let buf = hex::decode("01fe000000").unwrap();
+        let err = parse_options(&buf).unwrap_err();
+        assert_eq!(err, HeaderError::MalformedOption { kind: 254, offset: 1 });
+    }
+
+    #[test]
+    fn test_parse_length_one_is_error() {
+        let buf = hex::decode("0201").unwrap();
+        let err = parse_options(&buf).unwrap_err();
+        assert_eq!(err, HeaderError::MalformedOption { kind: KIND_MSS, offset: 0 });
+    }
+
+    #[test]
+    fn test_parse_length_past_end_is_error() {
+        let buf = hex::decode("0101080a00000001").unwrap();
+        let err = parse_options(&buf).unwrap_err();
+        assert_eq!(err, HeaderError::MalformedOption { kind: KIND_TIMESTAMPS, offset: 2 });
+    }
+
+    #[test]
+    fn test_parse_missing_length_byte_is_error() {
+        let buf = hex::decode("01010102").unwrap();
+        let err = parse_options(&buf).unwrap_err();
+        assert_eq!(err, HeaderError::MalformedOption { kind: KIND_MSS, offset: 3 });
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let options = vec![
+            TcpOption::Mss(1460),
+            TcpOption::WindowScale(6),
+            TcpOption::Timestamps { val: 0xbb6879f8, ecr: 0 },
+            TcpOption::SackPermitted,
+            TcpOption::Sack(vec![(Wrap32::new(10), Wrap32::new(20))]),
+            TcpOption::Unknown { kind: 253, data: vec![0xab, 0xcd] },
+        ];
+        let buf = encode_options(&options);
+        assert_eq!(buf.len(), 4 + 3 + 10 + 2 + 10 + 4);
+        assert_eq!(&buf[..7], &hex::decode("020405b4030306").unwrap()[..]);
+        assert_eq!(parse_options(&buf).unwrap(), options);
+        assert!(encode_options(&[]).is_empty());
+    }
+}
@@ -0,0 +1,190 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::time::Instant;
+
+/// The timers a connection runs. Each can be armed at most once at a time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TimerId {
+    Retransmit,
+    DelayedAck,
+    Persist,
+    Keepalive,
+    TimeWait,
+}
+
+impl TimerId {
+    pub const ALL: [TimerId; 5] =
+        [TimerId::Retransmit, TimerId::DelayedAck, TimerId::Persist, TimerId::Keepalive, TimerId::TimeWait];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Deadlines for every connection timer in one min-heap. Arming is O(log n) and cancelling is
+/// O(1): a cancelled or re-armed entry stays in the heap and is skipped when it surfaces, since
+/// its generation no longer matches the live one.
+#[derive(Debug, Default)]
+pub struct TimerQueue {
+    heap: BinaryHeap<Reverse<(Instant, u64, TimerId)>>,
+    armed: [Option<(Instant, u64)>; 5], // Live deadline and generation for each timer
+    generation: u64,
+}
+
+impl TimerQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Arm `id` to fire at `deadline`, replacing any deadline it already had
+    pub fn arm(&mut self, id: TimerId, deadline: Instant) {
+        self.generation += 1;
+        self.armed[id.index()] = Some((deadline, self.generation));
+        self.heap.push(Reverse((deadline, self.generation, id)));
+    }
+
+    /// Disarm `id`. Returns whether it was armed
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        self.armed[id.index()].take().is_some()
+    }
+
+    pub fn is_armed(&self, id: TimerId) -> bool {
+        self.armed[id.index()].is_some()
+    }
+
+    pub fn deadline(&self, id: TimerId) -> Option<Instant> {
+        self.armed[id.index()].map(|(deadline, _)| deadline)
+    }
+
+    /// The earliest live deadline, i.e. when the connection next needs to wake up
+    pub fn next_wakeup(&mut self) -> Option<Instant> {
+        self.discard_stale();
+        self.heap.peek().map(|Reverse((deadline, _, _))| *deadline)
+    }
+
+    /// Pop the next timer due at `now`, disarming it. Timers come out in deadline order, and
+    /// timers with the same deadline in the order they were armed.
+    pub fn pop_due(&mut self, now: Instant) -> Option<TimerId> {
+        self.discard_stale();
+        let Reverse((deadline, _, id)) = *self.heap.peek()?;
+        if deadline > now {
+            return None;
+        }
+        self.heap.pop();
+        self.armed[id.index()] = None;
+        Some(id)
+    }
+
+    /// Every timer due at `now`, in firing order
+    pub fn drain_due(&mut self, now: Instant) -> Vec<TimerId> {
+        std::iter::from_fn(|| self.pop_due(now)).collect()
+    }
+
+    /// Drop heap entries left behind by `cancel` and re-arming
+    fn discard_stale(&mut self) {
+        while let Some(Reverse((_, generation, id))) = self.heap.peek() {
+            if self.armed[id.index()].is_some_and(|(_, live)| live == *generation) {
+                break;
+            }
+            self.heap.pop();
+        }
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    #[test]
+    fn test_deadline_ordering() {
+        let t0 = Instant::now();
+        let mut timers = TimerQueue::new();
+        timers.arm(TimerId::Keepalive, t0 + ms(7200));
+        timers.arm(TimerId::Retransmit, t0 + ms(1000));
+        timers.arm(TimerId::DelayedAck, t0 + ms(40));
+        timers.arm(TimerId::Persist, t0 + ms(1000));
+
+        assert_eq!(timers.next_wakeup(), Some(t0 + ms(40)));
+        assert_eq!(timers.pop_due(t0 + ms(39)), None);
+        assert_eq!(timers.drain_due(t0 + ms(1000)), vec![TimerId::DelayedAck, TimerId::Retransmit, TimerId::Persist]);
+        assert_eq!(timers.next_wakeup(), Some(t0 + ms(7200)));
+        assert!(timers.is_armed(TimerId::Keepalive));
+        assert!(!timers.is_armed(TimerId::Retransmit));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let t0 = Instant::now();
+        let mut timers = TimerQueue::new();
+        timers.arm(TimerId::Retransmit, t0 + ms(200));
+        timers.arm(TimerId::TimeWait, t0 + ms(60_000));
+
+        assert!(timers.cancel(TimerId::Retransmit));
+        assert!(!timers.cancel(TimerId::Retransmit));
+        assert_eq!(timers.next_wakeup(), Some(t0 + ms(60_000)));
+        assert_eq!(timers.pop_due(t0 + ms(1000)), None);
+
+        assert!(timers.cancel(TimerId::TimeWait));
+        assert_eq!(timers.next_wakeup(), None);
+    }
+
+    #[test]
+    fn test_rearm_replaces_deadline() {
+        let t0 = Instant::now();
+        let mut timers = TimerQueue::new();
+
+        // Pushed back: the old deadline must not fire
+        timers.arm(TimerId::Retransmit, t0 + ms(200));
+        timers.arm(TimerId::Retransmit, t0 + ms(400));
+        assert_eq!(timers.deadline(TimerId::Retransmit), Some(t0 + ms(400)));
+        assert_eq!(timers.pop_due(t0 + ms(300)), None);
+        assert_eq!(timers.drain_due(t0 + ms(400)), vec![TimerId::Retransmit]);
+
+        // Pulled in: fires once, at the new deadline
+        timers.arm(TimerId::Persist, t0 + ms(5000));
+        timers.arm(TimerId::Persist, t0 + ms(500));
+        assert_eq!(timers.drain_due(t0 + ms(10_000)), vec![TimerId::Persist]);
+        assert_eq!(timers.next_wakeup(), None);
+    }
+
+    #[test]
+    fn test_cancel_then_rearm_same_deadline() {
+        // A stale entry with the same deadline must not shadow the re-armed one
+        let t0 = Instant::now();
+        let mut timers = TimerQueue::new();
+        timers.arm(TimerId::DelayedAck, t0 + ms(40));
+        timers.cancel(TimerId::DelayedAck);
+        timers.arm(TimerId::DelayedAck, t0 + ms(40));
+
+        assert_eq!(timers.drain_due(t0 + ms(40)), vec![TimerId::DelayedAck]);
+        assert_eq!(timers.pop_due(t0 + ms(40)), None);
+    }
+
+    #[test]
+    fn test_rearm_from_fired_timer() {
+        // Re-arming a timer while handling it schedules the next firing, not a duplicate
+        let t0 = Instant::now();
+        let mut timers = TimerQueue::new();
+        timers.arm(TimerId::Keepalive, t0 + ms(100));
+
+        let mut fired = Vec::new();
+        let mut now = t0;
+        for _ in 0..3 {
+            now += ms(100);
+            while let Some(id) = timers.pop_due(now) {
+                fired.push((id, now));
+                timers.arm(id, now + ms(100));
+            }
+        }
+        assert_eq!(fired.len(), 3);
+        assert_eq!(timers.next_wakeup(), Some(t0 + ms(400)));
+        assert!(TimerId::ALL.iter().filter(|&&id| timers.is_armed(id)).eq([&TimerId::Keepalive]));
+    }
+}
@@ -0,0 +1,186 @@
+use crate::tcp::tcp_options::TcpOption;
+use crate::tcp::wrap32::Wrap32;
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The source of TSval for the timestamps option (RFC 7323). Injectable so tests can start near
+/// the wraparound or run the clock unusually fast or slow.
+pub trait TsClock: fmt::Debug + Send + Sync {
+    fn now_ts(&self) -> u32;
+
+    /// How long one tick of this clock lasts, to turn TSecr echoes into RTT samples
+    fn tick(&self) -> Duration {
+        Duration::from_millis(1)
+    }
+}
+
+/// Milliseconds since the clock was created, plus a fixed offset
+#[derive(Debug, Clone)]
+pub struct MillisClock {
+    start: Instant,
+    offset: u32,
+}
+
+impl MillisClock {
+    pub fn new() -> Self {
+        Self::with_offset(0)
+    }
+
+    /// Start counting at `offset`, e.g. a random value so TSval doesn't leak uptime
+    pub fn with_offset(offset: u32) -> Self {
+        MillisClock { start: Instant::now(), offset }
+    }
+}
+
+impl Default for MillisClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TsClock for MillisClock {
+    fn now_ts(&self) -> u32 {
+        self.offset.wrapping_add(self.start.elapsed().as_millis() as u32)
+    }
+}
+
+/// The timestamp state of one connection: TSval generation, TS.Recent and PAWS
+#[derive(Debug, Clone)]
+pub struct Timestamps {
+    clock: Arc<dyn TsClock>,
+    ts_recent: Option<u32>, // Latest TSval from the peer, echoed back as TSecr
+}
+
+impl Timestamps {
+    pub fn new(clock: Arc<dyn TsClock>) -> Self {
+        Timestamps { clock, ts_recent: None }
+    }
+
+    pub fn ts_recent(&self) -> Option<u32> {
+        self.ts_recent
+    }
+
+    /// The timestamps option for an outgoing segment
+    pub fn outgoing(&self) -> TcpOption {
+        TcpOption::Timestamps { val: self.clock.now_ts(), ecr: self.ts_recent.unwrap_or(0) }
+    }
+
+    /// PAWS (RFC 7323 section 5): reject a segment whose TSval is older than TS.Recent, using the
+    /// same serial arithmetic as seq numbers. Accepted TSvals advance TS.Recent.
+    pub fn paws_accept(&mut self, ts_val: u32) -> bool {
+        if let Some(recent) = self.ts_recent {
+            if Wrap32::serial_cmp(ts_val, recent) == Ordering::Less {
+                return false;
+            }
+        }
+        self.ts_recent = Some(ts_val);
+        true
+    }
+
+    /// An RTT sample from the TSecr the peer echoed back, measured on our own clock
+    pub fn rtt_sample(&self, ts_ecr: u32) -> Duration {
+        self.clock.tick() * self.clock.now_ts().wrapping_sub(ts_ecr)
+    }
+}
+
+impl Default for Timestamps {
+    fn default() -> Self {
+        Self::new(Arc::new(MillisClock::new()))
+    }
+}
+
+// -- Unit tests --
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering as AtomicOrdering};
+
+    /// A clock that only moves when told to
+    #[derive(Debug)]
+    struct ManualClock(AtomicU32);
+
+    impl ManualClock {
+        fn advance(&self, ticks: u32) {
+            let now = self.0.load(AtomicOrdering::Relaxed);
+            self.0.store(now.wrapping_add(ticks), AtomicOrdering::Relaxed);
+        }
+    }
+
+    impl TsClock for ManualClock {
+        fn now_ts(&self) -> u32 {
+            self.0.load(AtomicOrdering::Relaxed)
+        }
+    }
+
+    fn val(option: TcpOption) -> (u32, u32) {
+        match option {
+            TcpOption::Timestamps { val, ecr } => (val, ecr),
+            other => panic!("expected timestamps, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_millis_clock() {
+        let clock = MillisClock::with_offset(u32::MAX);
+        assert!(clock.now_ts() == u32::MAX || clock.now_ts() < 1000);
+    }
+
+    #[test]
+    fn test_tsval_wraps_mid_connection() {
+        for start in [(1u32 << 31) - 5, u32::MAX - 5] {
+            let clock = Arc::new(ManualClock(AtomicU32::new(start)));
+            let local = Timestamps::new(clock.clone());
+            let mut peer = Timestamps::default();
+
+            // TSval keeps increasing across 2^31 and 2^32, and PAWS keeps accepting it
+            for _ in 0..10 {
+                let (ts_val, _) = val(local.outgoing());
+                assert!(peer.paws_accept(ts_val));
+                clock.advance(1);
+            }
+            assert_eq!(peer.ts_recent(), Some(start.wrapping_add(9)));
+
+            // An old duplicate from before the wrap is rejected
+            assert!(!peer.paws_accept(start));
+            assert!(peer.paws_accept(start.wrapping_add(9)));
+        }
+    }
+
+    #[test]
+    fn test_rtt_sample_across_wrap() {
+        let clock = Arc::new(ManualClock(AtomicU32::new(u32::MAX - 10)));
+        let mut local = Timestamps::new(clock.clone());
+        let mut peer = Timestamps::default();
+
+        // The peer echoes our TSval back in its TSecr
+        let (ts_val, _) = val(local.outgoing());
+        assert!(peer.paws_accept(ts_val));
+        let (_, ts_ecr) = val(peer.outgoing());
+        assert_eq!(ts_ecr, u32::MAX - 10);
+
+        clock.advance(31);
+        assert_eq!(clock.now_ts(), 20);
+        assert_eq!(local.rtt_sample(ts_ecr), Duration::from_millis(31));
+        assert!(local.paws_accept(7));
+    }
+
+    #[test]
+    fn test_slow_clock_ticks() {
+        #[derive(Debug)]
+        struct SlowClock;
+        impl TsClock for SlowClock {
+            fn now_ts(&self) -> u32 {
+                3
+            }
+            fn tick(&self) -> Duration {
+                Duration::from_millis(100)
+            }
+        }
+
+        let local = Timestamps::new(Arc::new(SlowClock));
+        assert_eq!(local.rtt_sample(1), Duration::from_millis(200));
+    }
+}
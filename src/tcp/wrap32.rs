@@ -1,7 +1,25 @@
 use std::cmp::Ordering;
 use std::ops::Add;
 
+/// A 32-bit sequence number as it appears on the wire. `wrap` and `unwrap` convert to and from
+/// 64-bit absolute stream indexes, given the initial sequence number.
+///
+/// ```
+/// use net::tcp::wrap32::Wrap32;
+///
+/// let isn = Wrap32::new(u32::MAX - 1);
+/// let seq_no = Wrap32::wrap(5, isn); // Index 5 is past the wraparound
+/// assert_eq!(seq_no.value(), 3);
+///
+/// // The same seq number stands for index 5 in every lap of the 32-bit space. The checkpoint,
+/// // usually the last index seen, picks the closest one
+/// let lap = 1u64 << 32;
+/// assert_eq!(seq_no.unwrap(isn, 0), 5);
+/// assert_eq!(seq_no.unwrap(isn, 3 * lap), 3 * lap + 5);
+/// assert_eq!(Wrap32::wrap(3 * lap + 5, isn), seq_no);
+/// ```
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
 pub struct Wrap32 {
     value: u32,
 }
@@ -35,6 +53,19 @@ impl Wrap32 {
         // Calculate the absolute sequence number
         relative + k * Self::WRAP_SIZE
     }
+
+    /// Compare two 32-bit serial numbers (RFC 1982), so `a` is greater if it's less than half the
+    /// space ahead of `b`. Shared by seq numbers and TCP timestamps.
+    pub fn serial_cmp(a: u32, b: u32) -> Ordering {
+        let diff = a.wrapping_sub(b);
+        if diff == 0 {
+            Ordering::Equal
+        } else if diff < Wrap32::HALF_WRAP as u32 {
+            Ordering::Greater
+        } else {
+            Ordering::Less
+        }
+    }
 }
 
 impl Add for Wrap32 {
@@ -47,13 +78,7 @@ impl Add for Wrap32 {
 
 impl PartialOrd for Wrap32 {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        // Need to handle the wraparound case
-        let diff = self.value.wrapping_sub(other.value);
-        if diff < Wrap32::HALF_WRAP as u32 {
-            Some(Ordering::Greater)
-        } else {
-            Some(Ordering::Less)
-        }
+        Some(Wrap32::serial_cmp(self.value, other.value))
     }
 }
 
@@ -184,6 +209,7 @@ mod tests {
 
     // -- Test compare --
 
+    #[allow(clippy::bool_assert_comparison)] // Checks the operators themselves, not just Eq
     #[test]
 
     fn test_equality() {
@@ -214,6 +240,16 @@ mod tests {
 
     // -- Test roundtrip --
 
+    #[test]
+    fn test_serial_cmp() {
+        assert_eq!(Wrap32::serial_cmp(5, 5), Ordering::Equal);
+        assert_eq!(Wrap32::serial_cmp(6, 5), Ordering::Greater);
+        assert_eq!(Wrap32::serial_cmp(2, u32::MAX - 2), Ordering::Greater); // Across the wrap
+        assert_eq!(Wrap32::serial_cmp(u32::MAX - 2, 2), Ordering::Less);
+        assert!(Wrap32::new(7) >= Wrap32::new(7));
+        assert_eq!(Wrap32::new(7).partial_cmp(&Wrap32::new(7)), Some(Ordering::Equal));
+    }
+
     #[test]
     fn test_roundtrip() {
         fn check_roundtrip(isn: Wrap32, value: u64, checkpoint: u64) {
@@ -0,0 +1,86 @@
+// Snapshot tests over the golden packet corpus in tests/golden. Each `.hex` packet has a line in
+// manifest.txt listing its expected parsed fields. Regenerate the manifest with
+// `cargo run --bin golden_manifest -- --write` after adding packets, and review the diff.
+
+#[path = "../src/bin/golden_manifest.rs"]
+#[allow(dead_code)]
+mod golden_manifest;
+
+use golden_manifest::{fields, is_fragment, read_hex};
+use net::ip::ip_header::IpHeader;
+use net::packet;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+/// Parse manifest.txt into `name -> {key -> value}`
+fn load_manifest() -> HashMap<String, HashMap<String, String>> {
+    let text = fs::read_to_string(golden_dir().join("manifest.txt")).unwrap();
+    text.lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut parts = line.split_whitespace();
+            let name = parts.next().unwrap().to_string();
+            let expected = parts
+                .map(|pair| {
+                    let (k, v) = pair.split_once('=').unwrap();
+                    (k.to_string(), v.to_string())
+                })
+                .collect();
+            (name, expected)
+        })
+        .collect()
+}
+
+#[test]
+fn test_golden_fields() {
+    let manifest = load_manifest();
+    assert!(manifest.len() >= 12);
+
+    for (name, expected) in &manifest {
+        let packet = read_hex(&golden_dir().join(format!("{name}.hex"))).unwrap();
+        let actual: HashMap<String, String> = fields(&packet)
+            .unwrap_or_else(|e| panic!("{name}: {e}"))
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect();
+
+        assert_eq!(&actual, expected, "{name}: parsed fields differ from the manifest");
+    }
+}
+
+#[test]
+fn test_golden_roundtrip() {
+    for name in load_manifest().keys() {
+        let packet = read_hex(&golden_dir().join(format!("{name}.hex"))).unwrap();
+        let iph = IpHeader::parse(&packet).unwrap();
+
+        let rebuilt = if is_fragment(&iph) {
+            // Only the IP header of a fragment can be re-serialized on its own
+            let mut rebuilt = vec![0u8; iph.header_len()];
+            iph.serialize(&mut rebuilt).unwrap();
+            rebuilt.extend_from_slice(&packet[iph.header_len()..]);
+            rebuilt
+        } else {
+            let (iph, tcph) = packet::unwrap(&packet).unwrap();
+            packet::wrap(&iph, &tcph).unwrap()
+        };
+
+        assert_eq!(hex::encode(&rebuilt), hex::encode(&packet), "{name}: re-serialized bytes differ");
+    }
+}
+
+#[test]
+fn test_golden_every_packet_in_manifest() {
+    let manifest = load_manifest();
+    for entry in fs::read_dir(golden_dir()).unwrap() {
+        let file_name = entry.unwrap().file_name().into_string().unwrap();
+        if let Some(name) = file_name.strip_suffix(".hex") {
+            assert!(manifest.contains_key(name), "{name}.hex is missing from manifest.txt");
+        }
+    }
+}
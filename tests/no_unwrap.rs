@@ -0,0 +1,116 @@
+// Library code parses untrusted packets, so it must return errors instead of panicking. This test
+// scans src/ for `unwrap()` and `expect(` outside `#[cfg(test)]` items and the binaries. Justified
+// cases go in tests/unwrap_allowlist.txt.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn crate_root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Every library source file. `src/bin` and `src/main.rs` are binaries and may panic
+fn library_sources(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            if path.file_name().is_some_and(|name| name != "bin") {
+                library_sources(&path, files);
+            }
+        } else if path.extension().is_some_and(|ext| ext == "rs") && !path.ends_with("src/main.rs") {
+            files.push(path);
+        }
+    }
+}
+
+/// Trimmed lines of `source` that call `unwrap()` or `expect(`, skipping comments and any item
+/// marked `#[cfg(test)]` or `#[cfg(all(test, ...))]`
+fn offending_lines(source: &str) -> Vec<&str> {
+    let mut offending = Vec::new();
+    let mut in_test_item = false;
+    let mut depth = 0i32;
+
+    for line in source.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("#[cfg(test)]") || trimmed.starts_with("#[cfg(all(test,") {
+            in_test_item = true;
+            depth = 0;
+            continue;
+        }
+        if in_test_item {
+            // Skip until the braces of the test item balance again
+            depth += line.matches('{').count() as i32 - line.matches('}').count() as i32;
+            if depth <= 0 && (line.contains('}') || trimmed.ends_with(';')) {
+                in_test_item = false;
+            }
+            continue;
+        }
+        if trimmed.starts_with("//") {
+            continue;
+        }
+        if trimmed.contains(".unwrap()") || trimmed.contains(".expect(") {
+            offending.push(trimmed);
+        }
+    }
+    offending
+}
+
+#[test]
+fn test_no_unwrap_in_library_code() {
+    let root = crate_root();
+    let allowlist = fs::read_to_string(root.join("tests/unwrap_allowlist.txt")).unwrap();
+    let allowed: HashSet<&str> = allowlist
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+
+    let mut files = Vec::new();
+    library_sources(&root.join("src"), &mut files);
+    files.sort();
+    assert!(files.len() > 20, "only found {} source files", files.len());
+
+    let mut violations = Vec::new();
+    for file in files {
+        let relative = file.strip_prefix(&root).unwrap().to_string_lossy().replace('\\', "/");
+        let source = fs::read_to_string(&file).unwrap();
+        for line in offending_lines(&source) {
+            let entry = format!("{relative}: {line}");
+            if !allowed.contains(entry.as_str()) {
+                violations.push(entry);
+            }
+        }
+    }
+
+    assert!(
+        violations.is_empty(),
+        "unwrap()/expect( in library code. Return an error, or justify it in tests/unwrap_allowlist.txt:\n{}",
+        violations.join("\n")
+    );
+}
+
+#[test]
+fn test_scanner_skips_test_modules() {
+    let source = "\
+fn parse() -> u8 {
+    // A comment may say .unwrap()
+    bytes.first().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    fn helper() {
+        x.unwrap();
+    }
+}
+
+#[cfg(test)]
+use foo::bar;
+
+fn later() {
+    y.expect(\"msg\");
+}
+";
+    assert_eq!(offending_lines(source), vec!["bytes.first().unwrap()", "y.expect(\"msg\");"]);
+}
@@ -0,0 +1,167 @@
+// Handshakes and transfers over the in-memory `PacketIo` implementations, so the connection
+// logic runs without raw sockets or root.
+
+use net::packet::TcpSegment;
+use net::socket::packet_io::{LoopbackIo, PacketIo, ScriptedIo};
+use net::tcp::byte_stream::ByteStream;
+use net::tcp::connect::{self, ConnectConfig};
+use net::tcp::flow_key::FlowKey;
+use net::tcp::listener::TcpListener;
+use net::tcp::reassembler::Reassembler;
+use net::tcp::receiver::TcpReceiver;
+use net::tcp::sender::TcpSender;
+use net::tcp::tcp_flags::TcpFlags;
+use net::tcp::tcp_header::TcpHeader;
+use net::tcp::wrap32::Wrap32;
+use std::io::Read;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CLIENT: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 50000);
+const SERVER: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 80);
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn recv_segment(io: &mut impl PacketIo) -> TcpSegment {
+    let mut buf = [0u8; 2048];
+    let len = io.recv(&mut buf, TIMEOUT).unwrap();
+    TcpSegment::parse(&buf[..len]).unwrap()
+}
+
+/// A header-only segment from the client with the given flags and numbers
+fn client_header(seq_no: Wrap32, ack_no: Wrap32, flags: TcpFlags) -> TcpHeader {
+    TcpHeader { seq_no, ack_no, flags, ..TcpHeader::new(CLIENT.port(), SERVER.port()) }
+}
+
+#[test]
+fn test_handshake_over_loopback() {
+    let (mut client_io, mut server_io) = LoopbackIo::pair();
+    let mut listener = TcpListener::new(SERVER, 8, 4096);
+    let client_isn = Wrap32::new(1000);
+    let mut client = TcpSender::new(client_isn, ByteStream::new(4096));
+    client.set_flow(FlowKey::new(CLIENT, SERVER));
+
+    // Client -> SYN
+    let syn = client.build_packet(&client_header(client_isn, Wrap32::new(0), TcpFlags::SYN)).unwrap();
+    client_io.send(&syn).unwrap();
+
+    // Server queues it, accepts, and answers with a SYN-ACK
+    let mut buf = [0u8; 2048];
+    let len = server_io.recv(&mut buf, TIMEOUT).unwrap();
+    assert!(listener.on_packet(&buf[..len], Instant::now()).unwrap());
+    let flow = FlowKey::new(SERVER, CLIENT);
+    let mut conn = listener.accept_with_isn(flow, Wrap32::new(5000)).unwrap();
+    server_io.send(&conn.syn_ack).unwrap();
+
+    // Client -> ACK
+    let syn_ack = recv_segment(&mut client_io);
+    assert_eq!(syn_ack.flags(), TcpFlags::SYN | TcpFlags::ACK);
+    assert_eq!((syn_ack.src(), syn_ack.dst()), (SERVER, CLIENT));
+    assert_eq!(syn_ack.ack_no(), client_isn + Wrap32::new(1));
+    let ack = client_header(client_isn + Wrap32::new(1), syn_ack.seq_no() + Wrap32::new(1), TcpFlags::ACK);
+    client_io.send(&client.build_packet(&ack).unwrap()).unwrap();
+
+    let ack = recv_segment(&mut server_io);
+    assert_eq!(ack.ack_no(), Wrap32::new(5001));
+    conn.receiver.recv(ack.tcph).unwrap();
+    assert_eq!(conn.receiver.ack_no(), client_isn + Wrap32::new(1));
+    assert_eq!((client_io.queued(), server_io.queued()), (0, 0));
+}
+
+#[test]
+fn test_transfer_between_threads() {
+    let data: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+    let (mut client_io, mut server_io) = LoopbackIo::pair();
+    let isn = Wrap32::new(u32::MAX - 3000); // Wraps mid-transfer
+
+    let server = thread::spawn(move || {
+        let mut receiver = TcpReceiver::new(isn + Wrap32::new(1), Reassembler::new(ByteStream::new(16_384)));
+        let mut acker = TcpSender::new(Wrap32::new(0), ByteStream::new(16));
+        acker.set_flow(FlowKey::new(SERVER, CLIENT));
+        while !receiver.stream().is_closed() {
+            let segment = recv_segment(&mut server_io);
+            receiver.recv(segment.tcph).unwrap();
+            let ack = TcpHeader { ack_no: receiver.ack_no(), flags: TcpFlags::ACK, ..TcpHeader::new(80, 50000) };
+            server_io.send(&acker.build_packet(&ack).unwrap()).unwrap();
+        }
+        let mut received = Vec::new();
+        receiver.stream_mut().read_to_end(&mut received).unwrap();
+        received
+    });
+
+    // Send in 1000 byte segments, FIN on the last, waiting for each ACK. The receiver acks
+    // stream bytes only, not the FIN
+    let mut client = TcpSender::new(isn, ByteStream::new(16_384));
+    client.set_flow(FlowKey::new(CLIENT, SERVER));
+    let mut seq_no = isn + Wrap32::new(1);
+    let chunks: Vec<&[u8]> = data.chunks(1000).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let flags = if i + 1 == chunks.len() { TcpFlags::ACK | TcpFlags::FIN } else { TcpFlags::ACK };
+        let tcph = TcpHeader { payload: chunk.to_vec().into(), ..client_header(seq_no, Wrap32::new(1), flags) };
+        seq_no = seq_no + Wrap32::new(chunk.len() as u32);
+        client_io.send(&client.build_packet(&tcph).unwrap()).unwrap();
+        assert_eq!(recv_segment(&mut client_io).ack_no(), seq_no);
+    }
+
+    assert_eq!(server.join().unwrap(), data);
+}
+
+#[test]
+fn test_scripted_out_of_order_segments() {
+    let isn = Wrap32::new(7);
+    let segment = |offset: u32, payload: &[u8], flags: TcpFlags| {
+        let tcph = TcpHeader { payload: payload.to_vec().into(), ..client_header(isn + Wrap32::new(1 + offset), Wrap32::new(1), flags) };
+        let mut sender = TcpSender::new(isn, ByteStream::new(16));
+        sender.set_flow(FlowKey::new(CLIENT, SERVER));
+        sender.build_packet(&tcph).unwrap()
+    };
+    let mut io = ScriptedIo::new([
+        segment(6, b"world", TcpFlags::ACK | TcpFlags::FIN),
+        segment(0, b"hello ", TcpFlags::ACK),
+        segment(0, b"hello ", TcpFlags::ACK), // Retransmitted
+    ]);
+
+    // Receive until the script runs dry, acknowledging each segment
+    let mut receiver = TcpReceiver::new(isn + Wrap32::new(1), Reassembler::new(ByteStream::new(64)));
+    let mut acker = TcpSender::new(Wrap32::new(0), ByteStream::new(16));
+    acker.set_flow(FlowKey::new(SERVER, CLIENT));
+    let mut buf = [0u8; 2048];
+    while let Ok(len) = io.recv(&mut buf, TIMEOUT) {
+        receiver.recv_packet(&buf[..len]).unwrap();
+        let ack = TcpHeader { ack_no: receiver.ack_no(), flags: TcpFlags::ACK, ..TcpHeader::new(80, 50000) };
+        io.send(&acker.build_packet(&ack).unwrap()).unwrap();
+    }
+
+    // The first ACK repeats the next expected seq; once the gap fills, it covers every byte
+    let acks: Vec<Wrap32> = io.sent().iter().map(|p| TcpSegment::parse(p).unwrap().ack_no()).collect();
+    assert_eq!(acks, [isn + Wrap32::new(1), isn + Wrap32::new(12), isn + Wrap32::new(12)]);
+    let mut received = String::new();
+    receiver.stream_mut().read_to_string(&mut received).unwrap();
+    assert_eq!(received, "hello world");
+    assert_eq!(receiver.stats().duplicate_segments, 1);
+}
+
+#[test]
+fn test_connect_to_listener_after_lost_syn() {
+    let (mut client_io, mut server_io) = LoopbackIo::pair();
+    let server = thread::spawn(move || {
+        let mut buf = [0u8; 2048];
+        server_io.recv(&mut buf, TIMEOUT).unwrap(); // Lost on the way
+
+        // The retransmission gets through and is accepted
+        let mut listener = TcpListener::new(SERVER, 8, 4096);
+        let len = server_io.recv(&mut buf, TIMEOUT).unwrap();
+        assert!(listener.on_packet(&buf[..len], Instant::now()).unwrap());
+        let mut conn = listener.accept_with_isn(FlowKey::new(SERVER, CLIENT), Wrap32::new(5000)).unwrap();
+        server_io.send(&conn.syn_ack).unwrap();
+        conn.receiver.recv(recv_segment(&mut server_io).tcph).unwrap();
+        conn.receiver.ack_no()
+    });
+
+    let mut client = TcpSender::new(Wrap32::new(1000), ByteStream::new(4096));
+    let config = ConnectConfig { syn_timeout: Duration::from_millis(50), ..ConnectConfig::default() };
+    let established = connect::connect(&mut client_io, &mut client, FlowKey::new(CLIENT, SERVER), &config).unwrap();
+    assert_eq!(established.syns_sent, 2);
+    assert_eq!((established.initial_seq_num, established.initial_ack_num), (Wrap32::new(1000), Wrap32::new(5001)));
+    assert_eq!(server.join().unwrap(), Wrap32::new(1001));
+}
@@ -0,0 +1,127 @@
+// Path MTU discovery against a simulated narrow link: the far end of a `LoopbackIo` plays a
+// router with a 1000 byte next hop, dropping anything bigger and answering with ICMP
+// Fragmentation Needed, like a real router does for DF packets.
+
+use net::ip::ip_header::IpHeader;
+use net::packet::icmp::{IcmpMessage, CODE_FRAGMENTATION_NEEDED, PROTOCOL_ICMP};
+use net::packet::TcpSegment;
+use net::socket::packet_io::{LoopbackIo, PacketIo};
+use net::tcp::byte_stream::ByteStream;
+use net::tcp::flow_key::FlowKey;
+use net::tcp::pmtu::{self, PathMtu, PROBE_INTERVAL};
+use net::tcp::sender::TcpSender;
+use net::tcp::tcp_flags::TcpFlags;
+use net::tcp::tcp_header::TcpHeader;
+use net::tcp::wrap32::Wrap32;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::{Duration, Instant};
+
+const CLIENT: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 50000);
+const SERVER: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 1, 2), 80);
+const ROUTER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 254);
+const NEXT_HOP_MTU: usize = 1000;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The router: forward what fits, or answer with Fragmentation Needed quoting what doesn't.
+/// Returns the packet if it was forwarded.
+fn route(router: &mut LoopbackIo) -> Option<Vec<u8>> {
+    let mut buf = [0u8; 2048];
+    let len = router.recv(&mut buf, TIMEOUT).unwrap();
+    if len <= NEXT_HOP_MTU {
+        return Some(buf[..len].to_vec());
+    }
+    let icmp = IcmpMessage::unreachable(CODE_FRAGMENTATION_NEEDED, NEXT_HOP_MTU as u16, &buf[..len]).to_bytes();
+    let iph = IpHeader {
+        version: 4,
+        ihl: 5,
+        total_len: (20 + icmp.len()) as u16,
+        ttl: 64,
+        protocol: PROTOCOL_ICMP,
+        src_ip: ROUTER,
+        dst_ip: *CLIENT.ip(),
+        ..IpHeader::default()
+    };
+    let mut reply = vec![0u8; 20];
+    iph.serialize(&mut reply).unwrap();
+    reply.extend(icmp);
+    router.send(&reply).unwrap();
+    None
+}
+
+#[test]
+fn test_resegments_after_frag_needed() {
+    let (mut client_io, mut router) = LoopbackIo::pair();
+    let flow = FlowKey::new(CLIENT, SERVER);
+    let mut sender = TcpSender::new(Wrap32::new(0), ByteStream::new(4096));
+    sender.set_flow(flow);
+    let mut pmtu = PathMtu::new(1500);
+    sender.set_mss(sender.mss().min(pmtu.mss()));
+
+    // A full-size segment, too big for the next hop
+    let data: Vec<u8> = (0..sender.mss()).map(|i| i as u8).collect();
+    let segment = TcpHeader {
+        seq_no: Wrap32::new(1),
+        ack_no: Wrap32::new(1),
+        flags: TcpFlags::ACK | TcpFlags::PSH,
+        payload: data.clone().into(),
+        ..TcpHeader::new(CLIENT.port(), SERVER.port())
+    };
+    client_io.send(&sender.build_packet(&segment).unwrap()).unwrap();
+    assert_eq!(route(&mut router), None);
+
+    // The client sees the ICMP error for its segment and clamps the MSS
+    let mut buf = [0u8; 2048];
+    let len = client_io.recv(&mut buf, TIMEOUT).unwrap();
+    let iph = IpHeader::parse(&buf[..len]).unwrap();
+    assert_eq!((iph.protocol, iph.src_ip), (PROTOCOL_ICMP, ROUTER));
+    let icmp = IcmpMessage::parse(&buf[iph.header_len()..len]).unwrap();
+    assert_eq!(pmtu.on_icmp(&icmp, &flow, Instant::now()), Some(segment.seq_no));
+    assert_eq!(pmtu.path_mtu(), NEXT_HOP_MTU);
+    sender.set_mss(sender.mss().min(pmtu.mss()));
+    assert_eq!(sender.mss(), 960);
+
+    // The retransmission, resegmented, gets through
+    for piece in pmtu::resegment(&segment, sender.mss()) {
+        client_io.send(&sender.build_packet(&piece).unwrap()).unwrap();
+    }
+    let mut received = Vec::new();
+    let mut flags = Vec::new();
+    while received.len() < data.len() {
+        let forwarded = TcpSegment::parse(&route(&mut router).expect("dropped after resegmenting")).unwrap();
+        assert_eq!(forwarded.seq_no(), segment.seq_no + Wrap32::new(received.len() as u32));
+        flags.push(forwarded.flags());
+        received.extend_from_slice(forwarded.payload());
+    }
+    assert_eq!(received, data);
+    assert_eq!(flags, [TcpFlags::ACK, TcpFlags::ACK | TcpFlags::PSH]);
+    assert_eq!(client_io.queued(), 0);
+}
+
+#[test]
+fn test_probes_upward_after_interval() {
+    let (mut client_io, mut router) = LoopbackIo::pair();
+    let flow = FlowKey::new(CLIENT, SERVER);
+    let mut sender = TcpSender::new(Wrap32::new(0), ByteStream::new(4096));
+    sender.set_flow(flow);
+    let mut pmtu = PathMtu::new(1500);
+    let start = Instant::now();
+
+    let big = TcpHeader { payload: vec![0u8; 1200].into(), ..TcpHeader::new(CLIENT.port(), SERVER.port()) };
+    client_io.send(&sender.build_packet(&big).unwrap()).unwrap();
+    assert_eq!(route(&mut router), None);
+    let mut buf = [0u8; 2048];
+    let len = client_io.recv(&mut buf, TIMEOUT).unwrap();
+    let icmp = IcmpMessage::parse(&buf[20..len]).unwrap();
+    assert!(pmtu.on_icmp(&icmp, &flow, start).is_some());
+
+    // Ten minutes on, we try the full size again; the path is still narrow, so it comes down again
+    assert!(!pmtu.poll(start + PROBE_INTERVAL / 2));
+    assert!(pmtu.poll(start + PROBE_INTERVAL));
+    assert_eq!(pmtu.mss(), 1460);
+    client_io.send(&sender.build_packet(&big).unwrap()).unwrap();
+    assert_eq!(route(&mut router), None);
+    let len = client_io.recv(&mut buf, TIMEOUT).unwrap();
+    let icmp = IcmpMessage::parse(&buf[20..len]).unwrap();
+    assert!(pmtu.on_icmp(&icmp, &flow, start + PROBE_INTERVAL).is_some());
+    assert_eq!(pmtu.path_mtu(), NEXT_HOP_MTU);
+}
@@ -0,0 +1,118 @@
+// Sends a hand-built packet through a raw socket on loopback and reads it back, to check that
+// IP_HDRINCL keeps our IP header. Needs CAP_NET_RAW, so it's ignored by default:
+// `sudo -E cargo test --test raw_loopback -- --ignored`
+
+use net::ip::ip_header::IpHeader;
+use net::packet;
+use net::socket::rawsocket;
+use net::tcp::tcp_flags::TcpFlags;
+use net::tcp::tcp_header::TcpHeader;
+use net::tcp::wrap32::Wrap32;
+use nix::sys::socket::SockProtocol;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::{Duration, Instant, SystemTime};
+
+#[test]
+#[ignore = "needs CAP_NET_RAW"]
+fn test_crafted_ip_header_survives_loopback() {
+    let send_fd = rawsocket::new_send_socket(SockProtocol::Tcp).unwrap();
+    let recv_fd = rawsocket::new_recv_socket(SockProtocol::Tcp).unwrap();
+    rawsocket::set_timeout(&recv_fd, Duration::from_millis(200)).unwrap();
+
+    // Values the kernel would never pick itself
+    let src_port = 40000 + (std::process::id() % 20000) as u16;
+    let tcph = TcpHeader { seq_no: Wrap32::new(0xc0ffee), flags: TcpFlags::SYN, ..TcpHeader::new(src_port, 9) };
+    let iph = IpHeader {
+        version: 4,
+        ihl: 5,
+        tos: 0x28,
+        total_len: (20 + tcph.header_len()) as u16,
+        id: 0x1234,
+        ttl: 42,
+        protocol: 6,
+        src_ip: Ipv4Addr::LOCALHOST,
+        dst_ip: Ipv4Addr::LOCALHOST,
+        ..IpHeader::default()
+    };
+    let sent = packet::wrap(&iph, &tcph).unwrap();
+    assert_eq!(rawsocket::send_packet(&send_fd, &sent, SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)), Ok(sent.len()));
+
+    // Other loopback traffic shows up too, so look for our port
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let mut buf = [0u8; 65535];
+    while Instant::now() < deadline {
+        let Ok((n, _)) = rawsocket::recv_packet(&recv_fd, &mut buf) else { continue };
+        let Ok((got_iph, got_tcph)) = packet::unwrap(&buf[..n]) else { continue };
+        if got_tcph.src_port != src_port || got_tcph.seq_no != tcph.seq_no {
+            continue;
+        }
+        assert_eq!((got_iph.ttl, got_iph.id, got_iph.tos), (42, 0x1234, 0x28));
+        return;
+    }
+    panic!("never saw our packet on loopback");
+}
+
+#[test]
+#[ignore = "needs CAP_NET_RAW"]
+fn test_port_filter_drops_other_ports() {
+    let send_fd = rawsocket::new_send_socket(SockProtocol::Tcp).unwrap();
+    let recv_fd = rawsocket::new_recv_socket(SockProtocol::Tcp).unwrap();
+    let local_port = 20000 + (std::process::id() % 20000) as u16;
+    rawsocket::attach_port_filter(&recv_fd, local_port, Ipv4Addr::LOCALHOST).unwrap();
+
+    // One packet to another port, then one to ours. Only the second gets through
+    for (dst_port, seq) in [(local_port + 1, 1), (local_port, 2)] {
+        let tcph = TcpHeader { seq_no: Wrap32::new(seq), flags: TcpFlags::SYN, ..TcpHeader::new(9, dst_port) };
+        let iph = IpHeader {
+            version: 4,
+            ihl: 5,
+            total_len: (20 + tcph.header_len()) as u16,
+            ttl: 64,
+            protocol: 6,
+            src_ip: Ipv4Addr::LOCALHOST,
+            dst_ip: Ipv4Addr::LOCALHOST,
+            ..IpHeader::default()
+        };
+        let dst = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+        rawsocket::send_packet(&send_fd, &packet::wrap(&iph, &tcph).unwrap(), dst).unwrap();
+    }
+
+    let mut buf = [0u8; 65535];
+    let (n, _) = rawsocket::recv_packet_timeout(&recv_fd, &mut buf, Duration::from_secs(2)).unwrap();
+    let (_, tcph) = packet::unwrap(&buf[..n]).unwrap();
+    assert_eq!((tcph.dst_port, tcph.seq_no), (local_port, Wrap32::new(2)));
+}
+
+#[test]
+#[ignore = "needs CAP_NET_RAW"]
+fn test_rx_timestamps_on_raw_socket() {
+    let send_fd = rawsocket::new_send_socket(SockProtocol::Tcp).unwrap();
+    let recv_fd = rawsocket::new_recv_socket(SockProtocol::Tcp).unwrap();
+    let src_port = 30000 + (std::process::id() % 20000) as u16;
+    rawsocket::attach_port_filter(&recv_fd, 9, Ipv4Addr::LOCALHOST).unwrap();
+    rawsocket::enable_rx_timestamps(&recv_fd).unwrap();
+
+    let tcph = TcpHeader { flags: TcpFlags::SYN, ..TcpHeader::new(src_port, 9) };
+    let iph = IpHeader {
+        version: 4,
+        ihl: 5,
+        total_len: (20 + tcph.header_len()) as u16,
+        ttl: 64,
+        protocol: 6,
+        src_ip: Ipv4Addr::LOCALHOST,
+        dst_ip: Ipv4Addr::LOCALHOST,
+        ..IpHeader::default()
+    };
+    let before = SystemTime::now();
+    let dst = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0);
+    rawsocket::send_packet(&send_fd, &packet::wrap(&iph, &tcph).unwrap(), dst).unwrap();
+    std::thread::sleep(Duration::from_millis(20));
+
+    // The kernel stamped it on arrival, well before this read
+    assert!(rawsocket::wait_readable(&recv_fd, Duration::from_secs(2)).unwrap());
+    let mut buf = [0u8; 65535];
+    let (n, _, at) = rawsocket::recv_packet_ts(&recv_fd, &mut buf).unwrap();
+    assert_eq!(packet::unwrap(&buf[..n]).unwrap().1.src_port, src_port);
+    assert!(at >= before - Duration::from_millis(1));
+    assert!(SystemTime::now().duration_since(at).unwrap() >= Duration::from_millis(20));
+}
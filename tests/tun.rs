@@ -0,0 +1,124 @@
+// Talks TCP to the kernel's own stack through a TUN device: we play the peer at 10.77.0.2 and a
+// std `TcpListener` on the interface's address is the server. The TAP test does the same over
+// Ethernet, resolving the kernel's MAC with ARP and answering its requests for ours. Needs /dev/net/tun and
+// CAP_NET_ADMIN, so it's ignored by default:
+// `sudo -E cargo test --features tun --test tun -- --ignored`
+#![cfg(all(feature = "tun", target_os = "linux"))]
+
+use net::datalink::ethernet::MacAddr;
+use net::datalink::tap::TapIo;
+use net::packet::TcpSegment;
+use net::socket::packet_io::PacketIo;
+use net::socket::tun::TunDevice;
+use net::tcp::byte_stream::ByteStream;
+use net::tcp::flow_key::FlowKey;
+use net::tcp::sender::TcpSender;
+use net::tcp::tcp_flags::TcpFlags;
+use net::tcp::tcp_header::TcpHeader;
+use net::tcp::wrap32::Wrap32;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SERVER_IP: Ipv4Addr = Ipv4Addr::new(10, 77, 0, 1);
+const CLIENT: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 77, 0, 2), 40000);
+
+/// The next TCP segment the kernel sends us from `server`, skipping anything else on the device
+fn recv_from(tun: &mut impl PacketIo, server: SocketAddrV4) -> TcpSegment {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut buf = [0u8; 2048];
+    while Instant::now() < deadline {
+        let Ok(len) = tun.recv(&mut buf, Duration::from_millis(100)) else { continue };
+        match TcpSegment::parse(&buf[..len]) {
+            Ok(segment) if segment.src() == server => return segment,
+            _ => continue, // IPv6 router solicitations and the like
+        }
+    }
+    panic!("nothing from {server}");
+}
+
+#[test]
+#[ignore = "needs /dev/net/tun and CAP_NET_ADMIN"]
+fn test_handshake_and_echo_through_tun() {
+    if !Path::new("/dev/net/tun").exists() {
+        return eprintln!("skipped: no /dev/net/tun");
+    }
+    let mut tun = TunDevice::open("").unwrap(); // Let the kernel pick a free name
+    tun.configure(SERVER_IP, 24).unwrap();
+    let listener = TcpListener::bind((SERVER_IP, 0)).unwrap();
+    let server = SocketAddrV4::new(SERVER_IP, listener.local_addr().unwrap().port());
+    let echo = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).unwrap();
+        stream.write_all(&buf).unwrap();
+        buf
+    });
+
+    let isn = Wrap32::new(1000);
+    let mut sender = TcpSender::new(isn, ByteStream::new(4096));
+    sender.set_flow(FlowKey::new(CLIENT, server));
+    let header = |seq_no, ack_no, flags| TcpHeader { seq_no, ack_no, flags, ..TcpHeader::new(CLIENT.port(), server.port()) };
+
+    // SYN, SYN-ACK
+    tun.send(&sender.build_packet(&header(isn, Wrap32::new(0), TcpFlags::SYN)).unwrap()).unwrap();
+    let syn_ack = recv_from(&mut tun, server);
+    assert_eq!(syn_ack.flags(), TcpFlags::SYN | TcpFlags::ACK);
+    assert_eq!(syn_ack.ack_no(), isn + Wrap32::new(1));
+
+    // ACK carrying the data, and the echo comes back
+    let server_next = syn_ack.seq_no() + Wrap32::new(1);
+    let data = TcpHeader { payload: b"hello"[..].into(), ..header(isn + Wrap32::new(1), server_next, TcpFlags::ACK | TcpFlags::PSH) };
+    tun.send(&sender.build_packet(&data).unwrap()).unwrap();
+    assert_eq!(&echo.join().unwrap(), b"hello");
+    let reply = loop {
+        let segment = recv_from(&mut tun, server);
+        if !segment.payload().is_empty() {
+            break segment;
+        }
+    };
+    assert_eq!((reply.seq_no(), reply.payload()), (server_next, &b"hello"[..]));
+
+    // Reset rather than linger, since nothing will answer the kernel's FIN
+    let rst = header(isn + Wrap32::new(6), server_next + Wrap32::new(5), TcpFlags::RST);
+    tun.send(&sender.build_packet(&rst).unwrap()).unwrap();
+}
+
+#[test]
+#[ignore = "needs /dev/net/tun and CAP_NET_ADMIN"]
+fn test_handshake_through_tap() {
+    if !Path::new("/dev/net/tun").exists() {
+        return eprintln!("skipped: no /dev/net/tun");
+    }
+    let server_ip = Ipv4Addr::new(10, 78, 0, 1);
+    let client = SocketAddrV4::new(Ipv4Addr::new(10, 78, 0, 2), 40000);
+    let device = TunDevice::open_tap("").unwrap();
+    device.configure(server_ip, 24).unwrap();
+    let kernel_mac = device.hw_addr().unwrap();
+    let our_mac = MacAddr([0x02, 0, 0, 0x4e, 0, 0x02]);
+    assert_ne!(kernel_mac, our_mac);
+    let mut tap = TapIo::new(device, our_mac, *client.ip(), 24, None);
+
+    let listener = TcpListener::bind((server_ip, 0)).unwrap();
+    let server = SocketAddrV4::new(server_ip, listener.local_addr().unwrap().port());
+    let accept = thread::spawn(move || listener.accept().map(|(_, peer)| peer));
+
+    // The SYN waits on ARP for the kernel's address, and goes out once the reply is read
+    let isn = Wrap32::new(1000);
+    let mut sender = TcpSender::new(isn, ByteStream::new(4096));
+    sender.set_flow(FlowKey::new(client, server));
+    let header = |seq_no, ack_no, flags| TcpHeader { seq_no, ack_no, flags, ..TcpHeader::new(client.port(), server.port()) };
+    tap.send(&sender.build_packet(&header(isn, Wrap32::new(0), TcpFlags::SYN)).unwrap()).unwrap();
+    let syn_ack = recv_from(&mut tap, server);
+    assert_eq!(syn_ack.flags(), TcpFlags::SYN | TcpFlags::ACK);
+    assert_eq!(tap.arp().lookup(server_ip, Instant::now()), Some(kernel_mac));
+
+    let ack = header(isn + Wrap32::new(1), syn_ack.seq_no() + Wrap32::new(1), TcpFlags::ACK);
+    tap.send(&sender.build_packet(&ack).unwrap()).unwrap();
+    assert_eq!(accept.join().unwrap().unwrap(), SocketAddr::V4(client));
+
+    let rst = header(isn + Wrap32::new(1), syn_ack.seq_no() + Wrap32::new(1), TcpFlags::RST);
+    tap.send(&sender.build_packet(&rst).unwrap()).unwrap();
+}